@@ -0,0 +1,96 @@
+/*
+ * C interface to census2csv
+ *
+ * Link against libcensus2csv (cdylib or staticlib). Tables returned by
+ * census_read must be released with census_free, and all pointers within a
+ * table are invalidated when it is freed. Internal errors are reported as
+ * failures, like any other, rather than unwinding into the caller.
+ */
+#ifndef CENSUS2CSV_H
+#define CENSUS2CSV_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct census_table {
+    size_t channels;
+    /* One entry per PSM passing filters */
+    size_t psm_count;
+    const char *const *psm_accessions;
+    const char *const *psm_sequences;
+    /* psm_count x channels, row-major */
+    const uint32_t *psm_values;
+    /* One entry per protein passing filters */
+    size_t protein_count;
+    const char *const *protein_accessions;
+    /* protein_count x channels, row-major, summed across PSMs */
+    const double *protein_values;
+} census_table;
+
+/*
+ * Read and filter `path`. `input_format` accepts the values of
+ * --input-format, and defaults to census if NULL. `filter_json` is the
+ * contents of a filter.json file, or NULL for no filtering. Returns NULL on
+ * failure.
+ */
+census_table *census_read(const char *path, const char *input_format, const char *filter_json);
+
+#ifndef ARROW_C_DATA_INTERFACE
+#define ARROW_C_DATA_INTERFACE
+
+#define ARROW_FLAG_DICTIONARY_ORDERED 1
+#define ARROW_FLAG_NULLABLE 2
+#define ARROW_FLAG_MAP_KEYS_SORTED 4
+
+struct ArrowSchema {
+    const char *format;
+    const char *name;
+    const char *metadata;
+    int64_t flags;
+    int64_t n_children;
+    struct ArrowSchema **children;
+    struct ArrowSchema *dictionary;
+    void (*release)(struct ArrowSchema *);
+    void *private_data;
+};
+
+struct ArrowArray {
+    int64_t length;
+    int64_t null_count;
+    int64_t offset;
+    int64_t n_buffers;
+    int64_t n_children;
+    const void **buffers;
+    struct ArrowArray **children;
+    struct ArrowArray *dictionary;
+    void (*release)(struct ArrowArray *);
+    void *private_data;
+};
+
+#endif /* ARROW_C_DATA_INTERFACE */
+
+/*
+ * Read and filter `path` as for census_read, and export `table` ("protein",
+ * "protein-average", or "psm"; "protein" if NULL) through the Arrow C data
+ * interface, as a struct array with one child per column. Returns 0 on
+ * success, after which the caller owns `array` and `schema` and must call
+ * their release callbacks, or -1 on failure.
+ */
+int census_read_arrow(const char *path, const char *input_format, const char *filter_json,
+                      const char *table, struct ArrowArray *array, struct ArrowSchema *schema);
+
+/* Description of the last error on the calling thread, or NULL if none can
+ * be reported */
+const char *census_last_error(void);
+
+void census_free(census_table *table);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,172 @@
+//! Python bindings for census2csv
+//!
+//! Datasets are exposed as opaque objects that can be filtered, merged, and
+//! converted to dictionaries of columns, which pandas accepts directly, or
+//! to pyarrow record batches.
+use census2csv_core::arrow::RecordBatch;
+use census2csv_core::census_proteomics::Dataset as Data;
+use census2csv_core::convert::Table;
+use census2csv_core::frame::{Frame, Values};
+use census2csv_core::{duplicates, filter, fractions, input, rollup};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Filters, stored as JSON and parsed each time they are applied, since the
+/// parsed filter borrows from its source
+#[pyclass]
+#[derive(Clone)]
+struct Filter {
+    json: String,
+}
+
+#[pymethods]
+impl Filter {
+    /// Parse a filter from a JSON string in the filter.json format
+    #[staticmethod]
+    fn from_json(json: String) -> PyResult<Filter> {
+        serde_json::from_str::<filter::Filter>(&json)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Filter { json })
+    }
+
+    /// Read a filter.json file
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Filter> {
+        let json = std::fs::read_to_string(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        Filter::from_json(json)
+    }
+
+    fn to_json(&self) -> String {
+        self.json.clone()
+    }
+}
+
+#[pyclass]
+struct Dataset {
+    data: Data,
+}
+
+fn copy(data: &Data) -> Data {
+    Data {
+        proteins: data.proteins.clone(),
+        channels: data.channels,
+    }
+}
+
+fn to_dict(py: Python<'_>, frame: Frame) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    for (name, values) in frame.columns {
+        match values {
+            Values::Text(v) => dict.set_item(name, v)?,
+            Values::Integer(v) => dict.set_item(name, v)?,
+            Values::Number(v) => dict.set_item(name, v)?,
+            Values::Boolean(v) => dict.set_item(name, v)?,
+        }
+    }
+    Ok(dict.to_object(py))
+}
+
+#[pymethods]
+impl Dataset {
+    #[getter]
+    fn channels(&self) -> u8 {
+        self.data.channels
+    }
+
+    fn __len__(&self) -> usize {
+        self.data.proteins.len()
+    }
+
+    /// Apply `filter`, returning a new dataset
+    fn filter(&self, filter: &Filter) -> PyResult<Dataset> {
+        let f = serde_json::from_str::<filter::Filter>(&filter.json)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Dataset {
+            data: f.filter_dataset(copy(&self.data)),
+        })
+    }
+
+    /// Dictionary of columns with one row per protein, summing (or
+    /// averaging) channel values across PSMs
+    #[pyo3(signature = (average = false))]
+    fn proteins(&self, py: Python<'_>, average: bool) -> PyResult<PyObject> {
+        let frame = if average {
+            Frame::proteins(&self.data, &rollup::Mean)
+        } else {
+            Frame::proteins(&self.data, &rollup::Sum)
+        };
+        to_dict(py, frame)
+    }
+
+    /// Dictionary of columns with one row per PSM
+    fn psms(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_dict(py, Frame::psms(&self.data))
+    }
+
+    /// pyarrow RecordBatch of `table` (protein, protein-average, or psm),
+    /// handed over through the Arrow C data interface without copying
+    #[pyo3(signature = (table = "protein"))]
+    fn to_arrow(&self, py: Python<'_>, table: &str) -> PyResult<PyObject> {
+        let table = table.parse::<Table>().map_err(PyValueError::new_err)?;
+        let (array, schema) = RecordBatch::from(&table.frame(&self.data)).export();
+        let (mut array, mut schema) = (Box::new(array), Box::new(schema));
+        let batch = py.import("pyarrow").and_then(|pa| {
+            pa.getattr("RecordBatch")?.call_method1(
+                "_import_from_c",
+                (
+                    &mut *array as *mut _ as usize,
+                    &mut *schema as *mut _ as usize,
+                ),
+            )
+        });
+        // pyarrow takes ownership on success, clearing the release callbacks
+        unsafe {
+            if let Some(release) = array.release {
+                release(&mut *array);
+            }
+            if let Some(release) = schema.release {
+                release(&mut *schema);
+            }
+        }
+        Ok(batch?.to_object(py))
+    }
+}
+
+/// Read an input file, in any format accepted by `--input-format`
+#[pyfunction]
+#[pyo3(signature = (path, input_format = "census"))]
+fn read_census(path: &str, input_format: &str) -> PyResult<Dataset> {
+    let format = input_format
+        .parse::<input::InputFormat>()
+        .map_err(PyValueError::new_err)?;
+    let data = input::read(path, format).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(Dataset { data })
+}
+
+/// Combine fractions of the same experiment, as `--combine-fractions` does
+#[pyfunction]
+fn combine_fractions(datasets: Vec<PyRef<Dataset>>) -> PyResult<Dataset> {
+    let data = fractions::combine(datasets.iter().map(|d| copy(&d.data)).collect())
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(Dataset { data })
+}
+
+/// Combine protein blocks that share an accession, as `--duplicates merge`
+/// does
+#[pyfunction]
+fn merge_duplicates(dataset: &Dataset) -> Dataset {
+    Dataset {
+        data: duplicates::merge(copy(&dataset.data)),
+    }
+}
+
+#[pymodule]
+fn census2csv(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Filter>()?;
+    m.add_class::<Dataset>()?;
+    m.add_function(wrap_pyfunction!(read_census, m)?)?;
+    m.add_function(wrap_pyfunction!(combine_fractions, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_duplicates, m)?)?;
+    Ok(())
+}
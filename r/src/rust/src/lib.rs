@@ -0,0 +1,70 @@
+//! R bindings for census2csv
+//!
+//! Each function reads and filters an input file and returns a named list of
+//! columns, which the R wrappers convert to a data.frame.
+use census2csv_core::census_proteomics::Dataset;
+use census2csv_core::filter::Filter;
+use census2csv_core::frame::{Frame, Values};
+use census2csv_core::{input, rollup};
+use extendr_api::prelude::*;
+
+fn read(path: &str, input_format: &str, filter: &str) -> Result<Dataset> {
+    let format = input_format
+        .parse::<input::InputFormat>()
+        .map_err(Error::Other)?;
+    let data = input::read(path, format).map_err(|e| Error::Other(e.to_string()))?;
+    if filter.is_empty() {
+        return Ok(data);
+    }
+    let filter = serde_json::from_str::<Filter>(filter)
+        .map_err(|e| Error::Other(format!("invalid filter: {}", e)))?;
+    Ok(filter.filter_dataset(data))
+}
+
+fn to_list(frame: Frame) -> Result<List> {
+    let names = frame
+        .columns
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<String>>();
+    // R integers are 32-bit, so integer columns are returned as doubles
+    let values = frame
+        .columns
+        .into_iter()
+        .map(|(_, values)| match values {
+            Values::Text(v) => v.into_robj(),
+            Values::Integer(v) => v
+                .into_iter()
+                .map(|x| x as f64)
+                .collect::<Vec<f64>>()
+                .into_robj(),
+            Values::Number(v) => v.into_robj(),
+            Values::Boolean(v) => v.into_robj(),
+        })
+        .collect::<Vec<Robj>>();
+    List::from_names_and_values(names, values)
+}
+
+/// @export
+#[extendr]
+fn proteins_impl(path: &str, input_format: &str, filter: &str, average: bool) -> Result<List> {
+    let data = read(path, input_format, filter)?;
+    let frame = if average {
+        Frame::proteins(&data, &rollup::Mean)
+    } else {
+        Frame::proteins(&data, &rollup::Sum)
+    };
+    to_list(frame)
+}
+
+/// @export
+#[extendr]
+fn psms_impl(path: &str, input_format: &str, filter: &str) -> Result<List> {
+    to_list(Frame::psms(&read(path, input_format, filter)?))
+}
+
+extendr_module! {
+    mod census2csv;
+    fn proteins_impl;
+    fn psms_impl;
+}
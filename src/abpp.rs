@@ -0,0 +1,126 @@
+//! Competition ratios for activity-based protein profiling experiments
+//!
+//! In competitive isoTOP-ABPP screens, each treated channel is paired with a
+//! control channel, and the ratio of control to treated intensity measures
+//! how strongly a compound blocks probe labeling at a site. Ratios are capped,
+//! since a treated intensity near zero would otherwise produce arbitrarily
+//! large ratios.
+use std::path::Path;
+use std::str::FromStr;
+
+/// Default cap on competition ratios
+pub const CAP: f64 = 20.0;
+
+/// Pairs of 1-indexed control and treated channels
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pairs {
+    pub pairs: Vec<(usize, usize)>,
+    pub cap: f64,
+}
+
+fn parse_pair(control: &str, treated: &str) -> Result<(usize, usize), String> {
+    let channel = |s: &str| match s.trim().parse::<usize>() {
+        Ok(c) if c > 0 => Ok(c),
+        _ => Err(format!("invalid channel {}", s.trim())),
+    };
+    Ok((channel(control)?, channel(treated)?))
+}
+
+/// Read a tab-delimited file with a control and treated channel on each
+/// line. Blank lines, lines beginning with `#`, and a header line are
+/// skipped.
+fn read_pairs<P: AsRef<Path>>(path: P) -> Result<Vec<(usize, usize)>, String> {
+    let path = path.as_ref();
+    let file = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut pairs = Vec::new();
+    for (idx, line) in file.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (control, treated) = (fields.next().unwrap_or(""), fields.next().unwrap_or(""));
+        match parse_pair(control, treated) {
+            Ok(pair) => pairs.push(pair),
+            Err(_) if pairs.is_empty() && control.trim().parse::<usize>().is_err() => {}
+            Err(e) => return Err(format!("{} line {}: {}", path.display(), idx + 1, e)),
+        }
+    }
+    Ok(pairs)
+}
+
+impl FromStr for Pairs {
+    type Err = String;
+
+    /// Parse `control:treated` pairs separated by commas, such as `1:4,2:5`,
+    /// or the path of a tab-delimited file of pairs if `s` contains no `:`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = if s.contains(':') {
+            s.split(',')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (control, treated) = pair
+                        .split_once(':')
+                        .ok_or_else(|| format!("expected control:treated, found {}", pair))?;
+                    parse_pair(control, treated)
+                })
+                .collect::<Result<Vec<_>, String>>()?
+        } else {
+            read_pairs(s)?
+        };
+        if pairs.is_empty() {
+            return Err("no channel pairs given".into());
+        }
+        Ok(Pairs { pairs, cap: CAP })
+    }
+}
+
+impl Pairs {
+    /// Check that every channel exists in a dataset with `channels` channels
+    pub fn check(&self, channels: u8) -> Result<(), String> {
+        match self
+            .pairs
+            .iter()
+            .flat_map(|&(c, t)| [c, t])
+            .find(|&c| c > channels as usize)
+        {
+            Some(c) => Err(format!(
+                "channel {} is out of range for {} channels",
+                c, channels
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Capped control to treated ratio of each pair, or `None` if neither
+    /// channel has any intensity
+    pub fn ratios(&self, values: &[u32]) -> Vec<Option<f64>> {
+        self.pairs
+            .iter()
+            .map(|&(c, t)| {
+                let control = *values.get(c - 1)? as f64;
+                let treated = *values.get(t - 1)? as f64;
+                match (control > 0.0, treated > 0.0) {
+                    (false, false) => None,
+                    (_, false) => Some(self.cap),
+                    _ => Some((control / treated).min(self.cap)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Median of the ratios that are defined, if any
+pub fn median(ratios: &[Option<f64>]) -> Option<f64> {
+    let mut defined = ratios.iter().flatten().copied().collect::<Vec<f64>>();
+    if defined.is_empty() {
+        return None;
+    }
+    defined.sort_by(|a, b| a.total_cmp(b));
+    let n = defined.len();
+    Some(if n % 2 == 1 {
+        defined[n / 2]
+    } else {
+        (defined[n / 2 - 1] + defined[n / 2]) / 2.0
+    })
+}
@@ -0,0 +1,159 @@
+//! Left-join external annotation columns onto CSV files produced by
+//! census2csv
+//!
+//! The annotation file is tab-delimited with a header row. Every column other
+//! than the join key is appended to each row of the CSV, leaving the fields
+//! empty when a row has no matching annotation.
+use crate::diagnostics;
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// Annotation columns, keyed on the value of the join column
+struct Annotations {
+    columns: Vec<String>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+fn read_annotations<P: AsRef<Path>>(path: P, on: &str) -> std::io::Result<Annotations> {
+    let file = fs::read_to_string(path)?;
+    let mut lines = file.lines();
+    let header = lines
+        .next()
+        .ok_or_else(|| invalid("empty annotation file".into()))?
+        .split('\t')
+        .collect::<Vec<&str>>();
+    let key = header
+        .iter()
+        .position(|h| *h == on)
+        .ok_or_else(|| invalid(format!("annotation file has no column named {}", on)))?;
+
+    let columns = header
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != key)
+        .map(|(_, h)| h.replace(",", ";"))
+        .collect::<Vec<String>>();
+
+    let mut rows = HashMap::new();
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let values = (0..header.len())
+            .filter(|idx| *idx != key)
+            .map(|idx| fields.get(idx).copied().unwrap_or("").replace(",", ";"))
+            .collect::<Vec<String>>();
+        // Keep the first annotation seen for a given key
+        if let Some(k) = fields.get(key) {
+            rows.entry(k.to_string()).or_insert(values);
+        }
+    }
+    Ok(Annotations { columns, rows })
+}
+
+/// Append the columns from the tab-delimited file `annotations` to every row
+/// of `input`, matching rows where the `on` column is equal in both files.
+pub fn annotate<P: AsRef<Path>, Q: AsRef<Path>, W: Write>(
+    input: P,
+    annotations: Q,
+    on: &str,
+    mut out: W,
+) -> std::io::Result<()> {
+    let ann = read_annotations(annotations, on)?;
+    let file = fs::read_to_string(input)?;
+    let mut lines = file.lines();
+
+    let mut key = None;
+    let mut matched = 0;
+    let mut total = 0;
+    for line in lines.by_ref() {
+        // Pass through any comment lines preceding the header
+        if line.starts_with('#') {
+            writeln!(out, "{}", line)?;
+            continue;
+        }
+        key = line.split(',').position(|h| h == on);
+        if key.is_none() {
+            return Err(invalid(format!("CSV file has no column named {}", on)));
+        }
+        writeln!(out, "{},{}", line, ann.columns.join(","))?;
+        break;
+    }
+    let key = key.ok_or_else(|| invalid("empty CSV file".into()))?;
+
+    let empty = vec![String::new(); ann.columns.len()];
+    for line in lines {
+        total += 1;
+        let values = match line.split(',').nth(key).and_then(|k| ann.rows.get(k)) {
+            Some(values) => {
+                matched += 1;
+                values
+            }
+            None => &empty,
+        };
+        writeln!(out, "{},{}", line, values.join(","))?;
+    }
+
+    diagnostics::info(format!(
+        "{} of {} rows matched an annotation",
+        matched, total
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const CSV: &str = "\
+# census2csv 0.1
+accession,description,channel_1
+P12345,Serum albumin,100
+Q67890,Unknown,200
+P12345,Serum albumin,300";
+
+    const ANNOTATIONS: &str = "\
+gene\taccession\tpathway, curated
+ALB\tP12345\ttransport, plasma
+ALB2\tP12345\tduplicate
+";
+
+    fn run(test: &str, on: &str) -> std::io::Result<String> {
+        let dir = fixture::dir(test, &[("out.csv", CSV), ("ann.tsv", ANNOTATIONS)]);
+        let mut out = Vec::new();
+        annotate(dir.join("out.csv"), dir.join("ann.tsv"), on, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn left_join() {
+        assert_eq!(
+            run("annotate", "accession").unwrap(),
+            "\
+# census2csv 0.1
+accession,description,channel_1,gene,pathway; curated
+P12345,Serum albumin,100,ALB,transport; plasma
+Q67890,Unknown,200,,
+P12345,Serum albumin,300,ALB,transport; plasma
+"
+        );
+    }
+
+    #[test]
+    fn missing_key_column() {
+        let err = run("annotate-missing", "gene").err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "CSV file has no column named gene");
+
+        let err = run("annotate-missing-tsv", "channel_1").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "annotation file has no column named channel_1"
+        );
+    }
+}
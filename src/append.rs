@@ -0,0 +1,148 @@
+//! Appending the rows of each run to a single table
+//!
+//! With `--append`, the rows converted from each input are added to one CSV
+//! or TSV table in long form, after a leading `run` column naming the input,
+//! so that newly acquired runs can be added to the table without converting
+//! earlier runs again. Runs already in the table are skipped, and the
+//! columns of each run must match the header of the table.
+use crate::meta::Column;
+use crate::writer::{Delimited, RecordWriter, Row, Rows};
+use std::collections::HashSet;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Name of the run converted from `input`, which is its file stem
+pub fn run_name(input: &str) -> String {
+    Path::new(input)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| input.to_string())
+}
+
+/// A table that runs are appended to
+pub struct Target {
+    path: PathBuf,
+    tsv: bool,
+    /// Column names of the table, if it has been started
+    header: Option<Vec<String>>,
+    /// Runs already in the table
+    runs: HashSet<String>,
+}
+
+impl Target {
+    /// Open the table at `path`, reading the header and runs of an existing
+    /// table
+    pub fn open<P: AsRef<Path>>(path: P, tsv: bool) -> std::io::Result<Target> {
+        let path = path.as_ref().to_path_buf();
+        let sep = if tsv { '\t' } else { ',' };
+        let mut header = None;
+        let mut runs = HashSet::new();
+        if path.exists() {
+            let text = fs::read_to_string(&path)?;
+            let mut lines = text.lines();
+            if let Some(line) = lines.next() {
+                let names = line.split(sep).map(String::from).collect::<Vec<_>>();
+                if names.first().map(String::as_str) != Some("run") {
+                    return Err(invalid(
+                        "the table does not start with a run column, and was not written by --append",
+                    ));
+                }
+                header = Some(names);
+            }
+            for line in lines {
+                if let Some(run) = line.split(sep).next() {
+                    runs.insert(run.to_string());
+                }
+            }
+        }
+        Ok(Target {
+            path,
+            tsv,
+            header,
+            runs,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Is `run` already in the table?
+    pub fn contains(&self, run: &str) -> bool {
+        self.runs.contains(run)
+    }
+
+    /// Writer appending the rows of `run` to the table
+    pub fn writer(&mut self, run: &str) -> std::io::Result<Writer<'_>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let file = BufWriter::new(file);
+        let out = if self.tsv {
+            Delimited::tsv(file)
+        } else {
+            Delimited::csv(file)
+        };
+        Ok(Writer {
+            target: self,
+            out,
+            run: run.to_string(),
+            row: Rows::default(),
+        })
+    }
+}
+
+/// Appends the rows of one run to a `Target`
+pub struct Writer<'a> {
+    target: &'a mut Target,
+    out: Delimited<BufWriter<fs::File>>,
+    run: String,
+    /// Row being written, with the run prepended
+    row: Rows,
+}
+
+impl RecordWriter for Writer<'_> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        let mut all = vec![Column::new(
+            "run",
+            "string",
+            "Run the row was converted from, named by the stem of its input file",
+        )];
+        all.extend_from_slice(columns);
+        let names = all.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        match &self.target.header {
+            Some(header) if *header != names => Err(invalid(format!(
+                "the columns of run {} do not match the header of {}",
+                self.run,
+                self.target.path.display()
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                self.target.header = Some(names);
+                self.out.header(&all)
+            }
+        }
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        self.row.clear();
+        self.row.push(&self.run);
+        for field in row.iter() {
+            self.row.push(field);
+        }
+        self.row.end();
+        self.out.record(self.row.get(0))
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.out.finish()?;
+        self.target.runs.insert(self.run.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,887 @@
+//! Arrow columnar data, for exchange with pyarrow, polars, and other
+//! Arrow-based tools
+//!
+//! A `RecordBatch` holds typed, nullable columns in the Arrow memory layout.
+//! It can be written as an Arrow IPC file (also known as Feather v2), or
+//! handed to another library in the same process, without copying, through
+//! the Arrow C data interface. No Arrow implementation is linked, so the
+//! flatbuffer metadata of IPC files is encoded here directly.
+use crate::frame::{Frame, Values};
+use std::ffi::{c_void, CString};
+use std::io::prelude::*;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// Logical type of an `Array`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DataType {
+    Utf8,
+    Int64,
+    Float64,
+    Boolean,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Buffers {
+    Utf8 {
+        offsets: Vec<i32>,
+        data: Vec<u8>,
+    },
+    Int64(Vec<i64>),
+    Float64(Vec<f64>),
+    /// Bit-packed, least significant bit first
+    Boolean(Vec<u8>),
+}
+
+/// A column of values, any of which may be null
+#[derive(Clone, Debug, PartialEq)]
+pub struct Array {
+    len: usize,
+    null_count: usize,
+    /// One bit per value, set for non-null values. Empty if no value is null.
+    validity: Vec<u8>,
+    buffers: Buffers,
+}
+
+fn bitmap<I: Iterator<Item = bool>>(bits: I, len: usize) -> Vec<u8> {
+    let mut out = vec![0u8; len.div_ceil(8)];
+    for (idx, bit) in bits.enumerate() {
+        if bit {
+            out[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+    out
+}
+
+/// Separate values from their validity, substituting the default for nulls
+fn split_nulls<T: Default, I: IntoIterator<Item = Option<T>>>(
+    values: I,
+) -> (Vec<T>, Vec<u8>, usize) {
+    let mut valid = Vec::new();
+    let values = values
+        .into_iter()
+        .map(|v| {
+            valid.push(v.is_some());
+            v.unwrap_or_default()
+        })
+        .collect::<Vec<T>>();
+    let null_count = valid.iter().filter(|v| !**v).count();
+    let validity = if null_count > 0 {
+        bitmap(valid.into_iter(), values.len())
+    } else {
+        Vec::new()
+    };
+    (values, validity, null_count)
+}
+
+/// View a slice of plain numeric values as little-endian bytes
+fn bytes<T: Copy>(values: &[T]) -> &[u8] {
+    // Every supported target is little-endian, and the element types are
+    // integers and floats without padding
+    unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    }
+}
+
+impl Array {
+    pub fn utf8<S: AsRef<str>, I: IntoIterator<Item = Option<S>>>(values: I) -> Array {
+        let mut offsets = vec![0];
+        let mut data = Vec::new();
+        let mut valid = Vec::new();
+        for value in values {
+            if let Some(s) = &value {
+                data.extend_from_slice(s.as_ref().as_bytes());
+            }
+            valid.push(value.is_some());
+            offsets.push(data.len() as i32);
+        }
+        let len = valid.len();
+        let null_count = valid.iter().filter(|v| !**v).count();
+        Array {
+            len,
+            null_count,
+            validity: if null_count > 0 {
+                bitmap(valid.into_iter(), len)
+            } else {
+                Vec::new()
+            },
+            buffers: Buffers::Utf8 { offsets, data },
+        }
+    }
+
+    pub fn int64<I: IntoIterator<Item = Option<i64>>>(values: I) -> Array {
+        let (values, validity, null_count) = split_nulls(values);
+        Array {
+            len: values.len(),
+            null_count,
+            validity,
+            buffers: Buffers::Int64(values),
+        }
+    }
+
+    pub fn float64<I: IntoIterator<Item = Option<f64>>>(values: I) -> Array {
+        let (values, validity, null_count) = split_nulls(values);
+        Array {
+            len: values.len(),
+            null_count,
+            validity,
+            buffers: Buffers::Float64(values),
+        }
+    }
+
+    pub fn boolean<I: IntoIterator<Item = Option<bool>>>(values: I) -> Array {
+        let (values, validity, null_count) = split_nulls(values);
+        Array {
+            len: values.len(),
+            null_count,
+            validity,
+            buffers: Buffers::Boolean(bitmap(values.iter().copied(), values.len())),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.null_count
+    }
+
+    pub fn data_type(&self) -> DataType {
+        match self.buffers {
+            Buffers::Utf8 { .. } => DataType::Utf8,
+            Buffers::Int64(_) => DataType::Int64,
+            Buffers::Float64(_) => DataType::Float64,
+            Buffers::Boolean(_) => DataType::Boolean,
+        }
+    }
+
+    /// Buffers in the order of the Arrow columnar format, starting with the
+    /// validity bitmap
+    fn raw_buffers(&self) -> Vec<&[u8]> {
+        let mut out = vec![self.validity.as_slice()];
+        match &self.buffers {
+            Buffers::Utf8 { offsets, data } => {
+                out.push(bytes(offsets));
+                out.push(data);
+            }
+            Buffers::Int64(v) => out.push(bytes(v)),
+            Buffers::Float64(v) => out.push(bytes(v)),
+            Buffers::Boolean(v) => out.push(v),
+        }
+        out
+    }
+}
+
+/// Named columns of equal length
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordBatch {
+    pub columns: Vec<(String, Array)>,
+}
+
+impl From<&Frame> for RecordBatch {
+    fn from(frame: &Frame) -> RecordBatch {
+        let columns = frame
+            .columns
+            .iter()
+            .map(|(name, values)| {
+                let array = match values {
+                    Values::Text(v) => Array::utf8(v.iter().map(Some)),
+                    Values::Integer(v) => Array::int64(v.iter().copied().map(Some)),
+                    Values::Number(v) => Array::float64(v.iter().copied().map(Some)),
+                    Values::Boolean(v) => Array::boolean(v.iter().copied().map(Some)),
+                };
+                (name.clone(), array)
+            })
+            .collect();
+        RecordBatch { columns }
+    }
+}
+
+/// Minimal flatbuffer builder. As in the reference implementation, the
+/// buffer is built back to front, so that objects are written before the
+/// tables that refer to them, and positions are measured from the end of
+/// the buffer.
+#[derive(Default)]
+struct Builder {
+    buf: Vec<u8>,
+}
+
+/// Table field value
+enum Slot {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    /// Position of a previously written object
+    Offset(usize),
+}
+
+impl Slot {
+    fn size(&self) -> usize {
+        match self {
+            Slot::Bool(_) | Slot::U8(_) => 1,
+            Slot::I16(_) => 2,
+            Slot::I32(_) | Slot::Offset(_) => 4,
+            Slot::I64(_) => 8,
+        }
+    }
+}
+
+impl Builder {
+    /// Pad so that an object of `size` bytes written next is aligned
+    fn pad(&mut self, size: usize, align: usize) {
+        while (self.buf.len() + size) % align != 0 {
+            self.buf.insert(0, 0);
+        }
+    }
+
+    fn prepend(&mut self, bytes: &[u8], align: usize) -> usize {
+        self.pad(bytes.len(), align);
+        self.buf.splice(0..0, bytes.iter().copied());
+        self.buf.len()
+    }
+
+    fn uoffset(&mut self, target: usize) -> usize {
+        self.pad(4, 4);
+        let value = (self.buf.len() + 4 - target) as u32;
+        self.prepend(&value.to_le_bytes(), 4)
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        self.pad(s.len() + 1, 4);
+        self.buf.splice(0..0, s.bytes().chain(Some(0)));
+        self.prepend(&(s.len() as u32).to_le_bytes(), 4)
+    }
+
+    /// Vector of `count` structs, already encoded as `data`
+    fn structs(&mut self, data: &[u8], count: usize) -> usize {
+        self.pad(data.len(), 8);
+        self.buf.splice(0..0, data.iter().copied());
+        self.prepend(&(count as u32).to_le_bytes(), 4)
+    }
+
+    fn offsets(&mut self, targets: &[usize]) -> usize {
+        for &target in targets.iter().rev() {
+            self.uoffset(target);
+        }
+        self.prepend(&(targets.len() as u32).to_le_bytes(), 4)
+    }
+
+    /// Table with one entry per field id, with its vtable written directly
+    /// before it
+    fn table(&mut self, slots: &[Option<Slot>]) -> usize {
+        let start = self.buf.len();
+        let mut order = (0..slots.len())
+            .filter(|&i| slots[i].is_some())
+            .collect::<Vec<usize>>();
+        // Smallest fields are written first, so they end up last
+        order.sort_by_key(|&i| slots[i].as_ref().map(Slot::size));
+        let mut positions = vec![0; slots.len()];
+        for i in order {
+            positions[i] = match slots[i].as_ref().unwrap() {
+                Slot::Bool(v) => self.prepend(&[*v as u8], 1),
+                Slot::U8(v) => self.prepend(&[*v], 1),
+                Slot::I16(v) => self.prepend(&v.to_le_bytes(), 2),
+                Slot::I32(v) => self.prepend(&v.to_le_bytes(), 4),
+                Slot::I64(v) => self.prepend(&v.to_le_bytes(), 8),
+                Slot::Offset(target) => self.uoffset(*target),
+            };
+        }
+
+        let vtable_len = 4 + 2 * slots.len();
+        let table = self.prepend(&(vtable_len as i32).to_le_bytes(), 4);
+        let mut vtable = Vec::with_capacity(vtable_len);
+        vtable.extend_from_slice(&(vtable_len as u16).to_le_bytes());
+        vtable.extend_from_slice(&((table - start) as u16).to_le_bytes());
+        for (slot, pos) in slots.iter().zip(&positions) {
+            let offset = if slot.is_some() { table - pos } else { 0 };
+            vtable.extend_from_slice(&(offset as u16).to_le_bytes());
+        }
+        self.prepend(&vtable, 2);
+        table
+    }
+
+    fn finish(mut self, root: usize) -> Vec<u8> {
+        self.pad(4, 8);
+        self.uoffset(root);
+        self.buf
+    }
+}
+
+/// Arrow IPC metadata version 5
+const METADATA_V5: i16 = 4;
+
+const MAGIC: &[u8] = b"ARROW1";
+
+fn schema(b: &mut Builder, columns: &[(String, Array)]) -> usize {
+    let fields = columns
+        .iter()
+        .map(|(name, array)| {
+            let name = b.string(name);
+            // Type union ids and tables from Schema.fbs
+            let (type_id, ty) = match array.data_type() {
+                DataType::Int64 => (2, b.table(&[Some(Slot::I32(64)), Some(Slot::Bool(true))])),
+                DataType::Float64 => (3, b.table(&[Some(Slot::I16(2))])),
+                DataType::Utf8 => (5, b.table(&[])),
+                DataType::Boolean => (6, b.table(&[])),
+            };
+            let children = b.offsets(&[]);
+            b.table(&[
+                Some(Slot::Offset(name)),
+                Some(Slot::Bool(true)),
+                Some(Slot::U8(type_id)),
+                Some(Slot::Offset(ty)),
+                None,
+                Some(Slot::Offset(children)),
+            ])
+        })
+        .collect::<Vec<usize>>();
+    let fields = b.offsets(&fields);
+    b.table(&[Some(Slot::I16(0)), Some(Slot::Offset(fields))])
+}
+
+/// Encode a Message flatbuffer, whose header is built by `header`
+fn message<F: FnOnce(&mut Builder) -> usize>(
+    header_type: u8,
+    body_len: usize,
+    header: F,
+) -> Vec<u8> {
+    let mut b = Builder::default();
+    let header = header(&mut b);
+    let msg = b.table(&[
+        Some(Slot::I16(METADATA_V5)),
+        Some(Slot::U8(header_type)),
+        Some(Slot::Offset(header)),
+        Some(Slot::I64(body_len as i64)),
+    ]);
+    b.finish(msg)
+}
+
+/// Write an encapsulated message, returning the length of its metadata
+/// including the prefix
+fn write_message<W: Write>(out: &mut W, meta: &[u8], body: &[u8]) -> std::io::Result<usize> {
+    out.write_all(&[0xff; 4])?;
+    out.write_all(&(meta.len() as i32).to_le_bytes())?;
+    out.write_all(meta)?;
+    out.write_all(body)?;
+    Ok(8 + meta.len())
+}
+
+impl RecordBatch {
+    /// Number of rows
+    pub fn rows(&self) -> usize {
+        self.columns.first().map(|(_, a)| a.len()).unwrap_or(0)
+    }
+
+    /// Write as an Arrow IPC file containing a single record batch
+    pub fn write_ipc<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        let mut body = Vec::new();
+        let (mut nodes, mut buffers) = (Vec::new(), Vec::new());
+        for (_, array) in &self.columns {
+            nodes.extend_from_slice(&(array.len as i64).to_le_bytes());
+            nodes.extend_from_slice(&(array.null_count as i64).to_le_bytes());
+            for buf in array.raw_buffers() {
+                buffers.extend_from_slice(&(body.len() as i64).to_le_bytes());
+                buffers.extend_from_slice(&(buf.len() as i64).to_le_bytes());
+                body.extend_from_slice(buf);
+                body.resize(body.len().div_ceil(8) * 8, 0);
+            }
+        }
+
+        let schema_msg = message(1, 0, |b| schema(b, &self.columns));
+        let batch_msg = message(3, body.len(), |b| {
+            let count = self.columns.len();
+            let nodes = b.structs(&nodes, count);
+            let buffers = b.structs(&buffers, buffers.len() / 16);
+            b.table(&[
+                Some(Slot::I64(self.rows() as i64)),
+                Some(Slot::Offset(nodes)),
+                Some(Slot::Offset(buffers)),
+            ])
+        });
+
+        out.write_all(MAGIC)?;
+        out.write_all(&[0, 0])?;
+        let batch_offset = 8 + write_message(&mut out, &schema_msg, &[])?;
+        let meta_len = write_message(&mut out, &batch_msg, &body)?;
+        // End-of-stream marker
+        out.write_all(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0])?;
+
+        let mut block = Vec::new();
+        block.extend_from_slice(&(batch_offset as i64).to_le_bytes());
+        block.extend_from_slice(&(meta_len as i32).to_le_bytes());
+        block.extend_from_slice(&[0; 4]);
+        block.extend_from_slice(&(body.len() as i64).to_le_bytes());
+
+        let mut b = Builder::default();
+        let schema = schema(&mut b, &self.columns);
+        let dictionaries = b.structs(&[], 0);
+        let batches = b.structs(&block, 1);
+        let footer = b.table(&[
+            Some(Slot::I16(METADATA_V5)),
+            Some(Slot::Offset(schema)),
+            Some(Slot::Offset(dictionaries)),
+            Some(Slot::Offset(batches)),
+        ]);
+        let footer = b.finish(footer);
+        out.write_all(&footer)?;
+        out.write_all(&(footer.len() as i32).to_le_bytes())?;
+        out.write_all(MAGIC)?;
+        out.flush()
+    }
+}
+
+/// `ArrowSchema` of the Arrow C data interface
+#[repr(C)]
+pub struct ArrowSchema {
+    pub format: *const c_char,
+    pub name: *const c_char,
+    pub metadata: *const c_char,
+    pub flags: i64,
+    pub n_children: i64,
+    pub children: *mut *mut ArrowSchema,
+    pub dictionary: *mut ArrowSchema,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    pub private_data: *mut c_void,
+}
+
+/// `ArrowArray` of the Arrow C data interface
+#[repr(C)]
+pub struct ArrowArray {
+    pub length: i64,
+    pub null_count: i64,
+    pub offset: i64,
+    pub n_buffers: i64,
+    pub n_children: i64,
+    pub buffers: *mut *const c_void,
+    pub children: *mut *mut ArrowArray,
+    pub dictionary: *mut ArrowArray,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    pub private_data: *mut c_void,
+}
+
+const ARROW_FLAG_NULLABLE: i64 = 2;
+
+struct SchemaData {
+    format: CString,
+    name: CString,
+    children: Vec<*mut ArrowSchema>,
+}
+
+struct ArrayData {
+    /// Owner of the buffers, which is absent for the parent struct array
+    _array: Option<Array>,
+    buffers: Vec<*const c_void>,
+    children: Vec<*mut ArrowArray>,
+}
+
+unsafe extern "C" fn release_schema(schema: *mut ArrowSchema) {
+    let schema = &mut *schema;
+    let data = Box::from_raw(schema.private_data as *mut SchemaData);
+    for child in data.children {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    schema.release = None;
+}
+
+unsafe extern "C" fn release_array(array: *mut ArrowArray) {
+    let array = &mut *array;
+    let data = Box::from_raw(array.private_data as *mut ArrayData);
+    for child in data.children {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    array.release = None;
+}
+
+fn export_schema(format: &str, name: &str, flags: i64, children: Vec<ArrowSchema>) -> ArrowSchema {
+    let mut data = Box::new(SchemaData {
+        format: CString::new(format).unwrap_or_default(),
+        name: CString::new(name.replace('\0', " ")).unwrap_or_default(),
+        children: children
+            .into_iter()
+            .map(|c| Box::into_raw(Box::new(c)))
+            .collect(),
+    });
+    ArrowSchema {
+        format: data.format.as_ptr(),
+        name: data.name.as_ptr(),
+        metadata: ptr::null(),
+        flags,
+        n_children: data.children.len() as i64,
+        children: data.children.as_mut_ptr(),
+        dictionary: ptr::null_mut(),
+        release: Some(release_schema),
+        private_data: Box::into_raw(data) as *mut c_void,
+    }
+}
+
+fn export_array(
+    length: usize,
+    null_count: usize,
+    array: Option<Array>,
+    children: Vec<ArrowArray>,
+) -> ArrowArray {
+    let buffers = match &array {
+        Some(array) => {
+            let mut buffers = array
+                .raw_buffers()
+                .into_iter()
+                .map(|b| b.as_ptr() as *const c_void)
+                .collect::<Vec<_>>();
+            if array.null_count == 0 {
+                buffers[0] = ptr::null();
+            }
+            buffers
+        }
+        None => vec![ptr::null()],
+    };
+    let mut data = Box::new(ArrayData {
+        _array: array,
+        buffers,
+        children: children
+            .into_iter()
+            .map(|c| Box::into_raw(Box::new(c)))
+            .collect(),
+    });
+    ArrowArray {
+        length: length as i64,
+        null_count: null_count as i64,
+        offset: 0,
+        n_buffers: data.buffers.len() as i64,
+        n_children: data.children.len() as i64,
+        buffers: data.buffers.as_mut_ptr(),
+        children: data.children.as_mut_ptr(),
+        dictionary: ptr::null_mut(),
+        release: Some(release_array),
+        private_data: Box::into_raw(data) as *mut c_void,
+    }
+}
+
+impl RecordBatch {
+    /// Export through the Arrow C data interface, as a struct array with one
+    /// child per column. Buffers are not copied, and are freed when the
+    /// consumer calls the release callbacks.
+    pub fn export(self) -> (ArrowArray, ArrowSchema) {
+        let rows = self.rows();
+        let mut arrays = Vec::with_capacity(self.columns.len());
+        let mut fields = Vec::with_capacity(self.columns.len());
+        for (name, array) in self.columns {
+            let format = match array.data_type() {
+                DataType::Utf8 => "u",
+                DataType::Int64 => "l",
+                DataType::Float64 => "g",
+                DataType::Boolean => "b",
+            };
+            fields.push(export_schema(
+                format,
+                &name,
+                ARROW_FLAG_NULLABLE,
+                Vec::new(),
+            ));
+            arrays.push(export_array(
+                array.len,
+                array.null_count,
+                Some(array),
+                Vec::new(),
+            ));
+        }
+        (
+            export_array(rows, 0, None, arrays),
+            export_schema("+s", "", 0, fields),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+    use std::ffi::CStr;
+
+    /// Table in a flatbuffer, for reading back what `Builder` wrote
+    #[derive(Copy, Clone)]
+    struct Table<'a> {
+        buf: &'a [u8],
+        pos: usize,
+    }
+
+    fn u32_at(buf: &[u8], pos: usize) -> usize {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    fn i64_at(buf: &[u8], pos: usize) -> i64 {
+        i64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+    }
+
+    impl<'a> Table<'a> {
+        fn root(buf: &'a [u8]) -> Table<'a> {
+            Table {
+                buf,
+                pos: u32_at(buf, 0),
+            }
+        }
+
+        /// Position of field `id`, if it is present
+        fn field(&self, id: usize) -> Option<usize> {
+            let soffset = i32::from_le_bytes(self.buf[self.pos..self.pos + 4].try_into().unwrap());
+            let vtable = (self.pos as i64 - soffset as i64) as usize;
+            let len = u16::from_le_bytes([self.buf[vtable], self.buf[vtable + 1]]) as usize;
+            let entry = vtable + 4 + 2 * id;
+            match entry + 2 <= vtable + len {
+                true => match u16::from_le_bytes([self.buf[entry], self.buf[entry + 1]]) {
+                    0 => None,
+                    off => Some(self.pos + off as usize),
+                },
+                false => None,
+            }
+        }
+
+        fn u8(&self, id: usize) -> u8 {
+            self.buf[self.field(id).unwrap()]
+        }
+
+        fn i16(&self, id: usize) -> i16 {
+            let pos = self.field(id).unwrap();
+            i16::from_le_bytes([self.buf[pos], self.buf[pos + 1]])
+        }
+
+        fn i32(&self, id: usize) -> i32 {
+            u32_at(self.buf, self.field(id).unwrap()) as i32
+        }
+
+        fn i64(&self, id: usize) -> i64 {
+            i64_at(self.buf, self.field(id).unwrap())
+        }
+
+        fn target(&self, id: usize) -> usize {
+            let pos = self.field(id).unwrap();
+            pos + u32_at(self.buf, pos)
+        }
+
+        fn table(&self, id: usize) -> Table<'a> {
+            Table {
+                buf: self.buf,
+                pos: self.target(id),
+            }
+        }
+
+        fn string(&self, id: usize) -> &'a str {
+            let pos = self.target(id);
+            let len = u32_at(self.buf, pos);
+            std::str::from_utf8(&self.buf[pos + 4..pos + 4 + len]).unwrap()
+        }
+
+        fn tables(&self, id: usize) -> Vec<Table<'a>> {
+            let pos = self.target(id);
+            (0..u32_at(self.buf, pos))
+                .map(|i| pos + 4 + 4 * i)
+                .map(|p| Table {
+                    buf: self.buf,
+                    pos: p + u32_at(self.buf, p),
+                })
+                .collect()
+        }
+
+        /// Vector of structs of `size` bytes
+        fn structs(&self, id: usize, size: usize) -> Vec<&'a [u8]> {
+            let pos = self.target(id);
+            (0..u32_at(self.buf, pos))
+                .map(|i| &self.buf[pos + 4 + size * i..pos + 4 + size * (i + 1)])
+                .collect()
+        }
+    }
+
+    fn batch() -> RecordBatch {
+        RecordBatch {
+            columns: vec![
+                (
+                    "accession".into(),
+                    Array::utf8([Some("P12345"), None, Some("Q6")]),
+                ),
+                ("count".into(), Array::int64([Some(3), Some(-1), None])),
+                (
+                    "ratio".into(),
+                    Array::float64([Some(0.5), Some(2.0), Some(f64::NAN)]),
+                ),
+                (
+                    "unique".into(),
+                    Array::boolean([Some(true), None, Some(false)]),
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        let array = Array::utf8([Some("ab"), None, Some(""), Some("c")]);
+        assert_eq!((array.len(), array.null_count()), (4, 1));
+        assert_eq!(array.data_type(), DataType::Utf8);
+        let buffers = array.raw_buffers();
+        assert_eq!(buffers[0], [0b1101]);
+        assert_eq!(buffers[1], bytes(&[0i32, 2, 2, 2, 3]));
+        assert_eq!(buffers[2], b"abc");
+
+        // Without nulls there is no validity bitmap
+        let array = Array::int64((0..10).map(Some));
+        assert_eq!(array.null_count(), 0);
+        assert!(array.raw_buffers()[0].is_empty());
+        let array = Array::boolean((0..10).map(|i| if i == 9 { None } else { Some(i % 3 == 0) }));
+        assert_eq!(array.raw_buffers(), [&[0xff, 0b01][..], &[0b0100_1001, 0]]);
+        assert!(Array::float64(None).is_empty());
+    }
+
+    #[test]
+    fn ipc_file() {
+        let batch = batch();
+        let mut file = Vec::new();
+        batch.write_ipc(&mut file).unwrap();
+        assert_eq!(&file[..8], b"ARROW1\0\0");
+        assert_eq!(&file[file.len() - 6..], MAGIC);
+        let footer_len = u32_at(&file, file.len() - 10);
+        let footer = &file[file.len() - 10 - footer_len..file.len() - 10];
+        let footer = Table::root(footer);
+        assert_eq!(footer.i16(0), METADATA_V5);
+
+        let fields = footer.table(1).tables(1);
+        let types = fields
+            .iter()
+            .map(|f| (f.string(0), f.u8(1), f.u8(2)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                ("accession", 1, 5),
+                ("count", 1, 2),
+                ("ratio", 1, 3),
+                ("unique", 1, 6)
+            ]
+        );
+        let int = fields[1].table(3);
+        assert_eq!((int.i32(0), int.u8(1)), (64, 1));
+        assert_eq!(fields[2].table(3).i16(0), 2);
+
+        // The schema message, which must match the footer's schema
+        assert_eq!(&file[8..12], [0xff; 4]);
+        let schema_len = u32_at(&file, 12);
+        let schema = Table::root(&file[16..16 + schema_len]);
+        assert_eq!((schema.i16(0), schema.u8(1)), (METADATA_V5, 1));
+        assert_eq!(schema.table(2).tables(1).len(), 4);
+
+        let blocks = footer.structs(3, 24);
+        assert_eq!(blocks.len(), 1);
+        let offset = i64_at(blocks[0], 0) as usize;
+        let meta_len = u32_at(blocks[0], 8);
+        let body_len = i64_at(blocks[0], 16) as usize;
+        assert_eq!(offset, 16 + schema_len);
+        assert_eq!((offset % 8, meta_len % 8, body_len % 8), (0, 0, 0));
+        assert_eq!(&file[offset..offset + 4], [0xff; 4]);
+        let message = Table::root(&file[offset + 8..offset + meta_len]);
+        assert_eq!((message.u8(1), message.i64(3)), (3, body_len as i64));
+        let record = message.table(2);
+        assert_eq!(record.i64(0), 3);
+        let nodes = record
+            .structs(1, 16)
+            .iter()
+            .map(|n| (i64_at(n, 0), i64_at(n, 8)))
+            .collect::<Vec<_>>();
+        assert_eq!(nodes, [(3, 1), (3, 1), (3, 0), (3, 1)]);
+
+        let body = &file[offset + meta_len..offset + meta_len + body_len];
+        let buffers = record
+            .structs(2, 16)
+            .iter()
+            .map(|b| {
+                let (start, len) = (i64_at(b, 0) as usize, i64_at(b, 8) as usize);
+                assert_eq!(start % 8, 0);
+                &body[start..start + len]
+            })
+            .collect::<Vec<_>>();
+        let expected = batch
+            .columns
+            .iter()
+            .flat_map(|(_, a)| a.raw_buffers())
+            .collect::<Vec<_>>();
+        assert_eq!(buffers, expected);
+        assert_eq!(buffers[2], b"P12345Q6");
+        // An end-of-stream marker follows the batch
+        let end = offset + meta_len + body_len;
+        assert_eq!(&file[end..end + 8], [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn c_data_interface() {
+        let (mut array, mut schema) = batch().export();
+        unsafe {
+            assert_eq!(CStr::from_ptr(schema.format).to_str(), Ok("+s"));
+            assert_eq!((schema.n_children, array.n_children), (4, 4));
+            assert_eq!((array.length, array.null_count, array.n_buffers), (3, 0, 1));
+            assert!((*array.buffers).is_null());
+
+            let children = std::slice::from_raw_parts(schema.children, 4);
+            let fields = children
+                .iter()
+                .map(|&c| {
+                    let c = &*c;
+                    assert_eq!(c.flags, ARROW_FLAG_NULLABLE);
+                    (
+                        CStr::from_ptr(c.name).to_str().unwrap(),
+                        CStr::from_ptr(c.format).to_str().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                fields,
+                [
+                    ("accession", "u"),
+                    ("count", "l"),
+                    ("ratio", "g"),
+                    ("unique", "b")
+                ]
+            );
+
+            let columns = std::slice::from_raw_parts(array.children, 4);
+            let strings = &*columns[0];
+            assert_eq!(
+                (strings.length, strings.null_count, strings.n_buffers),
+                (3, 1, 3)
+            );
+            let buffers = std::slice::from_raw_parts(strings.buffers, 3);
+            assert_eq!(*(buffers[0] as *const u8), 0b101);
+            let offsets = std::slice::from_raw_parts(buffers[1] as *const i32, 4);
+            assert_eq!(offsets, [0, 6, 6, 8]);
+            let data = std::slice::from_raw_parts(buffers[2] as *const u8, 8);
+            assert_eq!(data, b"P12345Q6");
+            let ratios = &*columns[2];
+            let buffers = std::slice::from_raw_parts(ratios.buffers, 2);
+            // A column without nulls has no validity buffer
+            assert!(buffers[0].is_null());
+            let values = std::slice::from_raw_parts(buffers[1] as *const f64, 3);
+            assert_eq!(&values[..2], [0.5, 2.0]);
+            assert!(values[2].is_nan());
+
+            // As consumers may, move a child out and release it separately
+            let first = &mut *columns[1];
+            let moved = std::ptr::read(first);
+            first.release = None;
+            let mut moved = moved;
+            (moved.release.unwrap())(&mut moved);
+            assert!(moved.release.is_none());
+
+            (array.release.unwrap())(&mut array);
+            (schema.release.unwrap())(&mut schema);
+            assert!(array.release.is_none() && schema.release.is_none());
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Write datasets back out in census_out format
+//!
+//! Only the fields retained by the census parser are written, but the output
+//! can be read by census2csv and other tools that accept census files. Any
+//! columns the parser discards are left empty.
+use crate::labels::Plex;
+use census_proteomics::*;
+use std::io::prelude::*;
+
+/// Write `data` in census_out format
+pub fn write_census<W: Write>(data: &Dataset, mut file: W) -> std::io::Result<()> {
+    let mz = match Plex::detect(data.channels) {
+        Some(plex) => plex.masses().iter().map(|m| format!("{:.6}", m)).collect(),
+        None => (1..=data.channels)
+            .map(|i| format!("channel_{}", i))
+            .collect::<Vec<String>>(),
+    };
+
+    writeln!(
+        file,
+        "H\tcensus_out file written by census2csv {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(
+        file,
+        "H\tPLINE\tLOCUS\tSPEC_COUNT\tSEQ_COUNT\tSEQ_COV\tMOLWT\tDESCRIPTION"
+    )?;
+    writeln!(
+        file,
+        "H\tSLINE\tUNIQUE\tSEQUENCE\t{}\tCS\tPURITY\tFILENAME\tXCORR\tDELTACN\tSCAN",
+        mz.iter()
+            .map(|m| format!("m/z_{}_int\tnorm_m/z_{}_int", m, m))
+            .collect::<Vec<String>>()
+            .join("\t")
+    )?;
+
+    for prot in &data.proteins {
+        writeln!(
+            file,
+            "P\t{}\t{}\t{}\t{:.1}%\t{}\t{}",
+            prot.accession,
+            prot.spectral_count,
+            prot.sequence_count,
+            prot.sequence_coverage,
+            prot.molecular_weight,
+            prot.description
+        )?;
+
+        for pep in &prot.peptides {
+            let total = pep.values.iter().sum::<u32>().max(1) as f64;
+            writeln!(
+                file,
+                "S\t{}\t{}\t{}\t\t{}\t\t\t\t{}",
+                if pep.unique { "U" } else { "" },
+                pep.sequence,
+                pep.values
+                    .iter()
+                    .map(|v| format!("{}\t{:.4}", v, *v as f64 / total))
+                    .collect::<Vec<String>>()
+                    .join("\t"),
+                pep.purity,
+                pep.scan
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use census2csv::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t2\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t300\t0.5
+S\t\tR.SEQUENCER.A\t0\t0.5\t0\t0.5
+P\tReverse_Q00000\t1\t1\t5.0%\t10\t1000\tDecoy
+S\tU\tK.DECOYK.R\t7\t0.5\t8\t0.5";
+
+    #[test]
+    fn round_trip() {
+        let data = input::parse_census(FILE).unwrap();
+        let mut out = Vec::new();
+        write_census(&data, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("m/z_126.127726_int\tnorm_m/z_126.127726_int"));
+        assert!(text.contains("S\tU\tK.PEPTIDEK.L\t100\t0.2500\t300\t0.7500\t"));
+
+        let read = input::parse_census(&text).unwrap();
+        assert_eq!(read.channels, data.channels);
+        assert_eq!(read.proteins.len(), 2);
+        for (a, b) in read.proteins.iter().zip(&data.proteins) {
+            assert_eq!(a.accession, b.accession);
+            assert_eq!(a.description, b.description);
+            assert_eq!(a.spectral_count, b.spectral_count);
+            assert_eq!(a.sequence_count, b.sequence_count);
+            assert_eq!(a.peptides.len(), b.peptides.len());
+            for (p, q) in a.peptides.iter().zip(&b.peptides) {
+                assert_eq!(p.sequence, q.sequence);
+                assert_eq!(p.unique, q.unique);
+                assert_eq!(p.values, q.values);
+            }
+        }
+    }
+
+    #[test]
+    fn generic_channels() {
+        let data = Dataset {
+            proteins: Vec::new(),
+            channels: 3,
+        };
+        let mut out = Vec::new();
+        write_census(&data, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("m/z_channel_3_int\tnorm_m/z_channel_3_int\tCS"));
+    }
+}
@@ -0,0 +1,241 @@
+//! Checkpointed output of very long tables
+//!
+//! With `--checkpoint`, rows are written to the output in chunks, each of
+//! which is synced to disk before a progress record next to the output,
+//! `<output>.progress`, is updated with the number of rows and bytes
+//! written so far. Only complete rows are written, so a killed job leaves a
+//! valid partial table, and `--resume` continues it from the last
+//! checkpoint: the output is truncated to the recorded length, and the rows
+//! already written are skipped. The progress record is removed once the
+//! table is complete.
+use crate::meta::Column;
+use crate::writer::{Delimited, RecordWriter, Row};
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "# census2csv progress";
+
+/// Path of the progress record of `output`
+pub fn progress_path(output: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.progress", output.display()))
+}
+
+/// Rows and bytes of an output that were synced to disk
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Progress {
+    rows: u64,
+    bytes: u64,
+}
+
+/// Progress recorded for `output` by a run with the same `settings`, if any
+fn read_progress(output: &Path, settings: &str) -> std::io::Result<Option<Progress>> {
+    let path = progress_path(output);
+    if !path.exists() || !output.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path)?;
+    let mut lines = text.lines();
+    if lines.next() != Some(format!("{} {}", HEADER, settings).as_str()) {
+        return Ok(None);
+    }
+    let mut fields = lines.next().unwrap_or_default().split('\t');
+    match (
+        fields.next().and_then(|s| s.parse().ok()),
+        fields.next().and_then(|s| s.parse().ok()),
+    ) {
+        (Some(rows), Some(bytes)) => Ok(Some(Progress { rows, bytes })),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("malformed progress record {}", path.display()),
+        )),
+    }
+}
+
+/// Writes CSV or TSV to a file in synced chunks of rows
+pub struct Writer {
+    out: Delimited<Vec<u8>>,
+    file: fs::File,
+    progress: PathBuf,
+    settings: String,
+    /// Rows per checkpoint
+    every: usize,
+    /// Rows written since the last checkpoint
+    pending: usize,
+    /// State at the last checkpoint
+    synced: Progress,
+    /// Rows written by an earlier run, which are not written again
+    skip: u64,
+    resumed: bool,
+}
+
+impl Writer {
+    /// Create `output`, or, if `resume` is true and a run with the same
+    /// `settings` was interrupted, continue it from its last checkpoint
+    pub fn create(
+        output: &Path,
+        tsv: bool,
+        settings: &str,
+        every: usize,
+        resume: bool,
+    ) -> std::io::Result<Writer> {
+        let progress = match resume {
+            true => read_progress(output, settings)?,
+            false => None,
+        };
+        let file = match progress {
+            Some(p) => {
+                let mut file = fs::OpenOptions::new().write(true).open(output)?;
+                file.set_len(p.bytes)?;
+                file.seek(std::io::SeekFrom::End(0))?;
+                file
+            }
+            None => fs::File::create(output)?,
+        };
+        let out = if tsv {
+            Delimited::tsv(Vec::new())
+        } else {
+            Delimited::csv(Vec::new())
+        };
+        Ok(Writer {
+            out,
+            file,
+            progress: progress_path(output),
+            settings: settings.to_string(),
+            every: every.max(1),
+            pending: 0,
+            synced: progress.unwrap_or_default(),
+            skip: progress.map_or(0, |p| p.rows),
+            resumed: progress.is_some(),
+        })
+    }
+
+    /// Does the output continue an interrupted run? Header comments, and
+    /// the header itself, were then written by the earlier run.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// The output file, for writing header comments before any rows
+    pub fn file(&mut self) -> &mut fs::File {
+        &mut self.file
+    }
+
+    /// Write buffered rows, sync them to disk, and record the progress
+    fn checkpoint(&mut self) -> std::io::Result<()> {
+        let buf = self.out.get_mut();
+        self.file.write_all(buf)?;
+        buf.clear();
+        self.file.sync_data()?;
+        self.synced = Progress {
+            rows: self.synced.rows + self.pending as u64,
+            bytes: self.file.stream_position()?,
+        };
+        self.pending = 0;
+        // Written aside and renamed, so the record is never half-written
+        let tmp = self.progress.with_extension("progress.tmp");
+        fs::write(
+            &tmp,
+            format!(
+                "{} {}\n{}\t{}\n",
+                HEADER, self.settings, self.synced.rows, self.synced.bytes
+            ),
+        )?;
+        fs::rename(&tmp, &self.progress)
+    }
+}
+
+impl RecordWriter for Writer {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        if self.resumed {
+            return Ok(());
+        }
+        self.out.header(columns)?;
+        self.checkpoint()
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return Ok(());
+        }
+        self.out.record(row)?;
+        self.pending += 1;
+        if self.pending >= self.every {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.checkpoint()?;
+        fs::remove_file(&self.progress)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+    use crate::writer::Rows;
+
+    fn write(
+        path: &Path,
+        rows: &Rows,
+        every: usize,
+        resume: bool,
+        finish: bool,
+    ) -> std::io::Result<()> {
+        let mut out = Writer::create(path, false, "test", every, resume)?;
+        out.header(&[Column::new("n", "integer", "Row number")])?;
+        for row in rows.iter() {
+            out.record(row)?;
+        }
+        if finish {
+            out.finish()?;
+        }
+        Ok(())
+    }
+
+    fn rows(n: usize) -> Rows {
+        let mut rows = Rows::default();
+        (0..n).for_each(|i| rows.record([i.to_string()]));
+        rows
+    }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let dir = fixture::dir("checkpoint", &[]);
+        let path = dir.join("out.csv");
+
+        write(&path, &rows(5), 2, false, true).unwrap();
+        let full = fs::read_to_string(&path).unwrap();
+        assert_eq!(full, "n\n0\n1\n2\n3\n4\n");
+        assert!(!progress_path(&path).exists());
+
+        // Interrupted after 3 rows, with a checkpoint after 2 and half of a
+        // row written after it
+        write(&path, &rows(3), 2, false, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "n\n0\n1\n");
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"2")
+            .unwrap();
+        assert!(progress_path(&path).exists());
+
+        write(&path, &rows(5), 2, true, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), full);
+        assert!(!progress_path(&path).exists());
+
+        // Progress recorded with different settings is not resumed
+        write(&path, &rows(3), 2, false, false).unwrap();
+        let mut out = Writer::create(&path, false, "other", 2, true).unwrap();
+        assert!(!out.resumed());
+        out.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//! Parsing of command line arguments shared by the conversion and the
+//! subcommands
+use crate::diagnostics::{self, Exit};
+use crate::{filter_file, manifest, qvalues, remote};
+use census2csv::filter::{Filter, PeptideFilter};
+use census2csv::input::InputFormat;
+use census2csv::{labels, parallel};
+use clap::{Arg, ArgMatches};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Report `message` and exit with a usage error
+pub fn usage<M: Display>(message: M) -> ! {
+    diagnostics::error(message);
+    diagnostics::exit(Exit::Usage);
+}
+
+/// Parse the value of `--<name>`, if given, exiting with a usage error if it
+/// is invalid
+pub fn parse<T>(matches: &ArgMatches, name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    matches.value_of(name).map(|value| match value.parse() {
+        Ok(value) => value,
+        Err(e) => usage(format!("Invalid value for --{}: {}", name, e)),
+    })
+}
+
+/// Parse the value of `--<name>`, if given, exiting with a usage error
+/// naming the `expected` value if it is invalid or rejected by `valid`
+pub fn parse_valid<T, F>(matches: &ArgMatches, name: &str, expected: &str, valid: F) -> Option<T>
+where
+    T: FromStr,
+    F: Fn(&T) -> bool,
+{
+    matches.value_of(name).map(|value| match value.parse() {
+        Ok(value) if valid(&value) => value,
+        _ => usage(format!(
+            "Invalid value for --{}: expected {}",
+            name, expected
+        )),
+    })
+}
+
+/// `--input-format` of subcommands reading census2csv inputs
+pub fn input_format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("input-format")
+        .help("Input format, as for the top-level --input-format")
+        .long("input-format")
+        .value_name("FORMAT")
+        .takes_value(true)
+}
+
+/// Format of the inputs, from `--input-format`, census by default
+pub fn input_format(matches: &ArgMatches) -> InputFormat {
+    parse(matches, "input-format").unwrap_or(InputFormat::Census)
+}
+
+/// Number of threads, from `--threads`, or one per core. Empty values, as
+/// from an empty environment variable, are treated as unset.
+pub fn threads(matches: &ArgMatches) -> usize {
+    match matches.value_of("threads").filter(|n| !n.is_empty()) {
+        Some(_) => parse_valid(matches, "threads", "a positive integer", |n| *n > 0).unwrap(),
+        None => parallel::default_threads(),
+    }
+}
+
+/// Condition of each channel, from `--conditions`, indexed by channel
+pub fn conditions(matches: &ArgMatches) -> Vec<Option<String>> {
+    match matches
+        .value_of("conditions")
+        .map(manifest::read_conditions)
+    {
+        Some(Ok(pairs)) => {
+            let channels = pairs.iter().map(|(ch, _)| *ch).max().unwrap_or(0);
+            (1..=channels)
+                .map(|ch| {
+                    pairs
+                        .iter()
+                        .find(|(c, _)| *c == ch)
+                        .map(|(_, name)| name.clone())
+                })
+                .collect()
+        }
+        Some(Err(e)) => usage(format!("Invalid value for --conditions: {}", e)),
+        None => Vec::new(),
+    }
+}
+
+/// Time point of each channel, from `--time-points`, indexed by channel
+pub fn time_points(matches: &ArgMatches) -> Vec<Option<f64>> {
+    match matches
+        .value_of("time-points")
+        .map(manifest::read_conditions)
+    {
+        Some(Ok(pairs)) => {
+            let channels = pairs.iter().map(|(ch, _)| *ch).max().unwrap_or(0);
+            let mut times = vec![None; channels];
+            for (ch, time) in pairs {
+                match time.parse::<f64>() {
+                    Ok(t) if t.is_finite() => times[ch - 1] = Some(t),
+                    _ => usage(format!(
+                        "Invalid value for --time-points: {} is not a number",
+                        time
+                    )),
+                }
+            }
+            times
+        }
+        Some(Err(e)) => usage(format!("Invalid value for --time-points: {}", e)),
+        None => Vec::new(),
+    }
+}
+
+/// Parse a size in bytes, with an optional K, M, G, or T suffix (powers of
+/// 1024) and an optional trailing B
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        Some('T') => (&digits[..digits.len() - 1], 40),
+        _ => (digits, 0),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size {}", s))
+}
+
+/// Contents of the filter file at `path`, resolving the filter set `set`.
+/// Empty paths, as from an empty environment variable, are treated as
+/// unset.
+pub fn read_filter(path: Option<&str>, set: Option<&str>) -> Option<String> {
+    let path = path.filter(|path| !path.is_empty())?;
+    match filter_file::resolve(path, set) {
+        Ok(buf) => Some(buf),
+        Err(e) => {
+            diagnostics::file_error("Error while reading filter", path, &e);
+            diagnostics::exit(Exit::Filter);
+        }
+    }
+}
+
+/// Parse the filter file contents `text`, if any, and apply the top-level
+/// options that adjust filters: `--keep-decoys-only`, `--psm-qvalues`, and
+/// the default intensity floors of a declared `chemistry`
+pub fn filter<'a>(
+    matches: &ArgMatches,
+    text: Option<&'a str>,
+    chemistry: Option<labels::Chemistry>,
+) -> Filter<'a> {
+    let filter = match text {
+        Some(text) => match serde_json::from_str(text) {
+            Ok(f) => f,
+            Err(e) => {
+                diagnostics::error(format!("Error while parsing filter.json {:?}", e));
+                diagnostics::exit(Exit::Filter);
+            }
+        },
+        None => Filter::default(),
+    };
+    let filter = if matches.is_present("keep-decoys-only") {
+        filter.keep_decoys_only()
+    } else {
+        filter
+    };
+    let filter = match matches.value_of("psm-qvalues") {
+        Some(path) => match remote::read_to_string(path).and_then(|s| qvalues::read(&s)) {
+            Ok((qvalues, repeated)) => {
+                if repeated > 0 {
+                    diagnostics::warning(
+                        Some(path),
+                        format!(
+                            "{} scans appear more than once, and are given their largest q-value",
+                            repeated
+                        ),
+                    );
+                }
+                filter.with_psm_qvalues(qvalues)
+            }
+            Err(e) => {
+                diagnostics::file_error("Error while reading q-values", path, &e);
+                diagnostics::exit(Exit::of(&e));
+            }
+        },
+        None if filter.uses_psm_qvalues() => {
+            diagnostics::error(
+                "MaxPsmQValue filters require PSM q-values, given with --psm-qvalues",
+            );
+            diagnostics::exit(Exit::Filter);
+        }
+        None => filter,
+    };
+    match chemistry {
+        Some(chemistry) => filter
+            .with_default_peptide_filter(PeptideFilter::TotalIntensity(
+                chemistry.min_total_intensity(),
+            ))
+            .with_default_peptide_filter(PeptideFilter::MeanIntensity(
+                chemistry.min_mean_intensity(),
+            )),
+        None => filter,
+    }
+}
@@ -0,0 +1,74 @@
+//! `annotate`: join columns from an annotation file onto a CSV output
+use crate::diagnostics::{self, Exit};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::path::PathBuf;
+
+/// Options of the `annotate` subcommand
+pub struct Options<'a> {
+    input: &'a str,
+    /// Tab-delimited annotation file
+    with: &'a str,
+    /// Column to join on
+    on: &'a str,
+    output: PathBuf,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("annotate")
+        .about("Left-join columns from a tab-delimited annotation file onto a CSV output")
+        .arg(
+            Arg::with_name("with")
+                .help("Tab-delimited annotation file with a header row")
+                .long("with")
+                .short("w")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("on")
+                .help("Column to join on, default is accession")
+                .long("on")
+                .value_name("COLUMN")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Output file, default is <INPUT>.annotated.csv")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("CSV file produced by census2csv")
+                .required(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        let input = matches.value_of("INPUT").unwrap();
+        Options {
+            input,
+            with: matches.value_of("with").unwrap(),
+            on: matches.value_of("on").unwrap_or("accession"),
+            output: match matches.value_of("output") {
+                Some(path) => PathBuf::from(path),
+                None => PathBuf::from(input).with_extension("annotated.csv"),
+            },
+        }
+    }
+}
+
+/// Write the annotated table
+pub fn run(opts: &Options) {
+    let res = fs::File::create(&opts.output)
+        .and_then(|out| crate::annotate::annotate(opts.input, opts.with, opts.on, out));
+    if let Err(e) = res {
+        diagnostics::file_error("Error while annotating", opts.input, &e);
+        diagnostics::exit(Exit::of(&e));
+    }
+}
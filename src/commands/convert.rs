@@ -0,0 +1,1225 @@
+//! The default command: convert each input, or the fractions or plexes of
+//! one experiment, to output files
+use super::publish;
+use crate::diagnostics::{self, Exit};
+use crate::output::{self, Aggregate, Counts, Format, Layout};
+use crate::{
+    append, cli, coverage, idmap, manifest, meta, plot, remote, resume, saint, terms, uniprot,
+};
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use census2csv::number::FloatFormat;
+use census2csv::{abpp, duplicates, fractions, labels, qc};
+use census_proteomics::*;
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Add the arguments of the conversion to `app`
+pub fn args(app: App<'static, 'static>) -> App<'static, 'static> {
+    app
+        .group(
+            ArgGroup::with_name("combine")
+                .args(&["peptide", "protein", "flat"])
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("peptide")
+                .help("Output peptide-level data. Several layouts may be requested at once, and are written to <output>.<layout>.<ext>")
+                .long("peptide")
+                .short("e")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("protein")
+                .help("Output protein-level data (default)")
+                .long("protein")
+                .short("r")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("flat")
+                .help("Output completely flat")
+                .long("flat")
+                .short("F")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .help("JSON file containing filters to apply")
+                .short("f")
+                .long("filter")
+                .value_name("FILE")
+                .env("CENSUS2CSV_FILTER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("psm-qvalues")
+                .help("Tab-delimited PSM rescoring results, such as Percolator or crux output, giving the q-value of each PSM by scan for MaxPsmQValue filters")
+                .long("psm-qvalues")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter-set")
+                .help("Use the filter set called NAME, from the \"sets\" of the filter file. Filters and sets may build on one another with \"extends\", naming another filter file or set")
+                .long("filter-set")
+                .value_name("NAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("average")
+                .help("Average results by number of reported spectral matches, default is sum")
+                .short("a")
+                .long("avg"),
+        )
+        .arg(
+            Arg::with_name("correlation-weighting")
+                .help("Weight each PSM in protein rollups by the correlation of its channel profile with the protein's other PSMs, down-weighting PSMs with reporter ion interference instead of removing them")
+                .long("correlation-weighting"),
+        )
+        .arg(
+            Arg::with_name("fractions")
+                .help("Treat input files as fractions of one experiment, pooling the PSMs of every file")
+                .long("combine-fractions")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .help("Number of threads used to filter proteins and build rows, default is the number of available cores")
+                .long("threads")
+                .short("j")
+                .value_name("N")
+                .env("CENSUS2CSV_THREADS")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-memory")
+                .help("Approximate limit on memory used for rows built at once, such as 512M or 4G")
+                .long("max-memory")
+                .value_name("SIZE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .help("CSV manifest listing input files with their plex, fraction, bridge channel, and channel conditions")
+                .long("manifest")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("conditions")
+                .help("Experimental condition of each channel, as channel=condition pairs such as 1=ctrl;2=ctrl;3=drug;4=drug, or a tab-delimited file of channels and conditions. Channel columns are named after their condition")
+                .long("conditions")
+                .value_name("CONDITIONS")
+                .conflicts_with("manifest")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aggregate-conditions")
+                .help("Write one column per condition, with the mean or median of its channels, rather than one column per channel. Conditions are given by --conditions or --manifest")
+                .long("aggregate-conditions")
+                .value_name("STAT")
+                .possible_values(&["mean", "median"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("time-points")
+                .help("Time point of each channel, as channel=time pairs such as 1=0;2=0;3=30;4=60, or a tab-delimited file of channels and times. Channel columns are ordered chronologically, followed by channels without a time point")
+                .long("time-points")
+                .value_name("TIMES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("time-course")
+                .help("Append the change in intensity of each later time point from the first, as delta_ columns, and the least squares slope of intensity against time")
+                .long("time-course")
+                .requires("time-points")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Output file for combined fractions, which may be an s3:// or gs:// URI")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("append")
+                .help("Append the rows of each input to one CSV or TSV table, after a run column naming the input by its file stem, creating it if needed. Inputs whose run is already in the table are skipped, so newly acquired runs can be added by nightly conversions, and the columns of each run must match the table")
+                .long("append")
+                .value_name("FILE")
+                .conflicts_with_all(&["fractions", "manifest", "split-by"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("outdir")
+                .help("Directory to write outputs to, which may be an s3:// or gs:// URI, rather than next to each input")
+                .long("outdir")
+                .value_name("DIR")
+                .env("CENSUS2CSV_OUTDIR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("input-format")
+                .help("Input format: census (default), maxquant (evidence.txt), fragpipe (psm.tsv), pd (Proteome Discoverer PSM export), dtaselect (DTASelect-filter.txt), or mztab")
+                .long("input-format")
+                .value_name("FORMAT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Output format: csv (default), tsv, census to write a filtered census_out file, perseus for a Perseus-ready matrix, saint for SAINTexpress inter/prey/bait files, gct for Morpheus/ssGSEA, sql for a text script to load into SQLite with sqlite3, sqlite for a SQLite database, arrow for an Arrow IPC (Feather) file, parquet for a Parquet file, or nested-json for one JSON document of proteins, their peptides, and their PSMs")
+                .long("format")
+                .value_name("FORMAT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-schema")
+                .help("Column layout of output tables: v1 (default) is the original layout, and v2 adds the scan number of each PSM to flat rows. Recorded in --header-comments and --meta")
+                .long("output-schema")
+                .value_name("VERSION")
+                .possible_values(&["v1", "v2"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("split-by")
+                .help("Write one CSV per channel, or per condition from --manifest, named <output>.<name>.csv")
+                .long("split-by")
+                .value_name("SPLIT")
+                .possible_values(&["channel", "condition"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("baits")
+                .help("Tab-delimited file of channel, bait, and T/C type for --format saint, default is channel conditions from --manifest")
+                .long("baits")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("saint-control")
+                .help("Comma-separated condition names of control purifications for --format saint without --baits, compared ignoring case and replicate numbers. Default is control, ctrl, ctl, IgG, mock, empty, negative, and neg")
+                .long("saint-control")
+                .value_name("NAMES")
+                .takes_value(true)
+                .use_delimiter(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("saint-quantity")
+                .help("Interaction quantity of --format saint: spc (default) for the number of PSMs with signal in each channel, for SAINTexpress-spc, or int for reporter ion intensity, for SAINTexpress-int")
+                .long("saint-quantity")
+                .value_name("QUANTITY")
+                .possible_values(&["spc", "int"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("meta")
+                .help("Write <output>.meta.json describing each column")
+                .long("meta")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("header-comments")
+                .help("Write #-prefixed provenance lines at the top of each CSV")
+                .long("header-comments")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .help("Write CSV and TSV outputs in chunks of ROWS rows, each synced to disk and recorded in <output>.progress, so a killed job leaves a valid partial table that --resume continues from the last checkpoint")
+                .long("checkpoint")
+                .value_name("ROWS")
+                .conflicts_with_all(&["append", "split-by"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("qc-report")
+                .help("Write quality control results for each output to <output>.qc.json")
+                .long("qc-report")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("totals-row")
+                .help("Append a final row of per-channel sums and the row count")
+                .long("totals-row")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("label-names")
+                .help("Use TMT reporter ion labels (126, 127N, ...) as channel headers: auto, tmt6, tmt10, tmt11, tmt16, tmt18")
+                .long("label-names")
+                .value_name("PLEX")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chemistry")
+                .help("Labeling chemistry: tmt10, tmt16, or tmtpro. Adds PSM intensity floors suited to the chemistry, unless the filter already has TotalIntensity or MeanIntensity rules, and warns if a file's channel count does not match")
+                .long("chemistry")
+                .value_name("CHEMISTRY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dedupe")
+                .help("Remove protein blocks identical to an earlier block, with the same accession and PSMs, so that the duplicated rows of files concatenated from repeated runs are written once. Applied before --duplicates")
+                .long("dedupe")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("duplicates")
+                .help("How to handle accessions that appear in multiple protein blocks")
+                .long("duplicates")
+                .value_name("POLICY")
+                .possible_values(&["error", "merge", "keep"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("key")
+                .help("Identity of protein blocks when finding duplicates: accession (default), accession_noiso to strip isoform suffixes such as -2, gene for the GN= field of the description, or description-hash for identical descriptions. Blocks sharing a key are merged into the row of the first, unless --duplicates is given")
+                .long("key")
+                .value_name("KEY")
+                .possible_values(&["accession", "accession_noiso", "gene", "description-hash"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-decoys-only")
+                .help("Invert ExcludeReverse, writing only decoy proteins, to characterize the noise distribution of reporter signals")
+                .long("keep-decoys-only")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("row-stats")
+                .help("Append row_min, row_max, row_median, and row_total columns computed across channels")
+                .long("row-stats")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("plot")
+                .help("Plot the distribution of intensities in each channel, and of peptides per protein, after filtering. terminal draws ASCII histograms on stdout")
+                .long("plot")
+                .value_name("PLOT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("plot-dir")
+                .help("Write figures of each output after filtering to DIR: a boxplot of channel intensities (<output>.boxplot.svg), a heatmap of missing values (<output>.missing.svg), and the channels on the first two principal components (<output>.pca.svg), with the extension of --plot-format")
+                .long("plot-dir")
+                .value_name("DIR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("plot-format")
+                .help("Format of the figures written with --plot-dir, svg (default) or png")
+                .long("plot-format")
+                .value_name("FORMAT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("preview")
+                .help("Print the first N rows of the output to stdout as an aligned table, without writing any files")
+                .long("preview")
+                .value_name("N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sample")
+                .help("Process a random subset of protein blocks, such as 0.05 for 5%, for quickly iterating on settings. The subset is the same on every run with the same --seed")
+                .long("sample")
+                .value_name("FRACTION")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .help("Seed choosing the protein blocks kept by --sample, default is 0")
+                .long("seed")
+                .value_name("N")
+                .requires("sample")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("abpp-ratios")
+                .help("Append competition ratios of control to treated channels for ABPP experiments, given as control:treated pairs such as 1:4,2:5,3:6, or a tab-delimited file of pairs, along with their median")
+                .long("abpp-ratios")
+                .value_name("PAIRS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("paired-ratios")
+                .help("Append the log2 ratio of each pair of channels in a paired design, such as tumor and normal samples from each patient, followed by their mean and a paired t-test against zero. Pairs are given as name=numerator:denominator such as p1=1:2,p2=3:4, where names are optional, or a tab-delimited file of names and channels")
+                .long("paired-ratios")
+                .value_name("PAIRS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ratio-cap")
+                .help("Maximum value of --abpp-ratios competition ratios, default is 20")
+                .long("ratio-cap")
+                .value_name("N")
+                .requires("abpp-ratios")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("group-proteins")
+                .help("Remove proteins whose peptide sequences are all explained by another protein, adding inference_score and subsumed_accessions columns to protein rows")
+                .long("group-proteins")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("impute")
+                .help("Impute missing (zero) channel values in output rows with the smallest non-zero PSM intensity of the channel (min), or half of it (half-min). Adds imputed_count and imputed_channels columns")
+                .long("impute")
+                .value_name("METHOD")
+                .possible_values(&["min", "half-min"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("transform")
+                .help("Variance-stabilizing transform applied to channel values after aggregation: asinh (or vsn) for asinh(x / cofactor), or glog for log2((x + sqrt(x^2 + cofactor^2)) / 2)")
+                .long("transform")
+                .value_name("TRANSFORM")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cofactor")
+                .help("Cofactor of --transform, near which values change from linear to logarithmic scaling, default is 1")
+                .long("cofactor")
+                .value_name("C")
+                .requires("transform")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-raw")
+                .help("Write --transform values in <channel>_<transform> columns alongside the raw channel values, rather than in place of them")
+                .long("keep-raw")
+                .requires("transform")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("batch-correct")
+                .help("Remove residual plex effects across the plexes of --manifest, after any bridge normalization, with a ComBat-like empirical Bayes adjustment of log2 protein intensities")
+                .long("batch-correct")
+                .requires("manifest")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("subtract-noise")
+                .help("Reporter ion noise floor subtracted from PSM values before aggregation, clamping at zero. Either a single value for every channel, per-channel values such as 1=120,2=95, or a tab-delimited file of channels and values")
+                .long("subtract-noise")
+                .value_name("NOISE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .help("Per-channel correction factors applied to PSM values before aggregation, such as 1=1.0,2=0.95, or a tab-delimited file of channels and factors")
+                .long("scale")
+                .value_name("FACTORS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("precision")
+                .help("Number of digits written after the decimal point of floating point values, such as medians and percentiles. By default, the shortest representation of each value is written")
+                .long("precision")
+                .value_name("N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scientific")
+                .help("Write floating point values in exponent notation, such as 1.25e3")
+                .long("scientific")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("rank")
+                .help("Append the abundance rank and percentile of each protein, by intensity summed across channels")
+                .long("rank")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("list-peptides")
+                .help("Append a semicolon-separated list of the peptide sequences contributing to each protein row")
+                .long("list-peptides")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("cleavage-class")
+                .help("Add a cleavage_class column to peptide and flat outputs, classifying each peptide as fully, semi- (N- or C-ragged), or non-tryptic")
+                .long("cleavage-class")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("skip-unparseable-sequences")
+                .help("Remove PSMs whose sequences cannot be parsed, with a warning, rather than keeping them. Crosslinked and concatenated sequences joined by --, ~, or | are parsed as several peptides")
+                .long("skip-unparseable-sequences")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("ptm-groups")
+                .help("Group peptide rows by sequence and full modification string, without flanking residues, and add modifications and modification_composition columns to peptide and flat outputs, for quantifying modified forms such as histone marks")
+                .long("ptm-groups")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("contaminant-prefix")
+                .help("Accession prefix identifying contaminant proteins, such as contaminant_ or Cont_. May be given more than once. Contaminants are flagged in an is_contaminant column")
+                .long("contaminant-prefix")
+                .value_name("PREFIX")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exclude-contaminants")
+                .help("Remove proteins with a --contaminant-prefix, rather than flagging them")
+                .long("exclude-contaminants")
+                .requires("contaminant-prefix")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("recount")
+                .help("Source of the spectral_count and sequence_count of protein rows: filtered (default) recounts the PSMs passing filters, census keeps the values reported by census before filtering, and both writes recounted values followed by census_spectral_count and census_sequence_count columns")
+                .long("recount")
+                .value_name("SOURCE")
+                .possible_values(&["filtered", "census", "both"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("single-hit")
+                .help("How to handle proteins identified by a single distinct peptide sequence after filtering: keep them (default), flag them in an is_single_hit column, or drop them")
+                .long("single-hit")
+                .value_name("POLICY")
+                .possible_values(&["keep", "flag", "drop"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-decoy-rate")
+                .help("Warn if the fraction of decoy proteins after filtering exceeds RATE, default is 0.01")
+                .long("max-decoy-rate")
+                .value_name("RATE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fail-fast")
+                .help("Stop at the first input that cannot be converted, rather than continuing with the remaining inputs. The exit status is nonzero if any input failed either way")
+                .long("fail-fast")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .help("Record the progress of a batch run in a state file, and skip inputs that an interrupted run with the same settings already converted, as recorded there. Outputs written with --checkpoint are continued from their last checkpoint")
+                .long("resume")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("state")
+                .help("State file recording the progress of a batch run, removed once every input is converted. Progress is only recorded with --resume or --state, and the default with --resume is census2csv.state in the working directory")
+                .long("state")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("errors-json")
+                .help("Write messages to stderr as JSON lines, with level, file, and message fields, rather than as text. The summary of a batch is a single record with level summary")
+                .long("errors-json")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no-color")
+                .help("Print warnings and errors without ANSI colors, which are otherwise used when stderr is a terminal and NO_COLOR is not set")
+                .long("no-color")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("stdout")
+                .help("Write the output table to stdout rather than to a file, for a single input or combined fractions in one layout. Warnings, errors, and summaries always go to stderr")
+                .long("stdout")
+                .conflicts_with_all(&["append", "checkpoint", "manifest", "outdir", "preview", "split-by"])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .help("Stop at the first malformed line of an input, rather than skipping malformed lines, and exit with a nonzero status if any quality control check fails")
+                .long("strict")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("uniprot")
+                .help("Add gene name, protein name, and subcellular location from UniProt")
+                .long("fetch-uniprot")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("uniprot-cache")
+                .help("Cache file for UniProt annotations, default is ~/.cache/census2csv/uniprot.tsv")
+                .long("uniprot-cache")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("term-annotations")
+                .help("Tab-delimited FILE of accessions and annotated terms, such as GO terms or CORUM complexes, for --select-term")
+                .long("term-annotations")
+                .value_name("FILE")
+                .requires("select-term")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("select-term")
+                .help("Write only proteins with an annotated term containing TERM, ignoring case, adding their matching terms in a term column")
+                .long("select-term")
+                .value_name("TERM")
+                .requires("term-annotations")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("map-ids")
+                .help("Rewrite accessions in output tables using a tab-delimited mapping FILE, with a source and target ID on each line, or a UniProt idmapping.dat file with --id-namespace. Unmapped accessions are written unchanged")
+                .long("map-ids")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("id-namespace")
+                .help("Namespace to map accessions to from a UniProt idmapping.dat file, such as RefSeq, Ensembl_PRO, or GeneID")
+                .long("id-namespace")
+                .value_name("NAME")
+                .requires("map-ids")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fasta")
+                .help("Protein sequences in FASTA format, for --coverage-map and the positions of peptides in --nterm-mode")
+                .long("fasta")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coverage-map")
+                .help("Write the residues of each protein covered by peptides passing filters to <output>.coverage.json, with a coverage string and intervals, or to a BED-like <output>.coverage.bed with one line per peptide")
+                .long("coverage-map")
+                .value_name("FORMAT")
+                .possible_values(&["json", "bed"])
+                .requires("fasta")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nterm-mode")
+                .help("Keep only protein N-terminal and neo-N-terminal peptides, for TAILS-style N-terminomics, and add nterm_type, cleavage_site, and nterm_position columns to peptide and flat outputs. Positions require --fasta")
+                .long("nterm-mode")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("nterm-label")
+                .help("Require an N-terminal label in the modifications of each peptide in --nterm-mode")
+                .long("nterm-label")
+                .value_name("LABEL")
+                .possible_values(&["tmt", "acetyl", "any"])
+                .requires("nterm-mode")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("list of input files to convert, which may be s3:// or gs:// URIs")
+                .multiple(true),
+        )
+}
+
+/// Options of the conversion, beyond the content of its outputs
+pub struct Options<'a> {
+    inputs: Vec<&'a str>,
+    input_format: InputFormat,
+    /// Stop at the first malformed line, and fail on quality control
+    strict: bool,
+    /// Manifest of the plexes to process, in place of inputs
+    manifest: Option<&'a str>,
+    /// Directory, or remote prefix, to write outputs to
+    outdir: Option<&'a str>,
+    /// Combine the inputs as fractions of one experiment
+    fractions: bool,
+    /// Output of the combined fractions
+    output: Option<&'a str>,
+    /// Stop at the first input that fails
+    fail_fast: bool,
+    /// Skip the inputs recorded as done by an interrupted run
+    resume: bool,
+    /// State file recording the inputs converted
+    state: Option<&'a str>,
+}
+
+/// Options controlling the content of outputs, with `threads` threads
+pub fn output(
+    matches: &ArgMatches,
+    chemistry: Option<labels::Chemistry>,
+    threads: usize,
+) -> output::Options {
+    let mut layouts = [
+        ("protein", Layout::Protein),
+        ("peptide", Layout::Peptide),
+        ("flat", Layout::Flat),
+    ]
+    .iter()
+    .filter(|(arg, _)| matches.is_present(arg))
+    .map(|(_, layout)| *layout)
+    .collect::<Vec<Layout>>();
+    if layouts.is_empty() {
+        layouts.push(Layout::Protein);
+    }
+    let figures = cli::parse(matches, "plot-format").unwrap_or(plot::Figures::Svg);
+    let seed = cli::parse(matches, "seed").unwrap_or(0);
+    let sample = cli::parse_valid(
+        matches,
+        "sample",
+        "a fraction between 0 and 1",
+        |fraction: &f64| *fraction > 0.0 && *fraction <= 1.0,
+    );
+    let cap = cli::parse_valid(matches, "ratio-cap", "a positive number", |cap: &f64| {
+        *cap > 0.0
+    });
+    let abpp = cli::parse::<abpp::Pairs>(matches, "abpp-ratios").map(|pairs| match cap {
+        Some(cap) => abpp::Pairs { cap, ..pairs },
+        None => pairs,
+    });
+    let select_term = match matches.value_of("select-term") {
+        Some(query) => read_file(
+            matches,
+            "term-annotations",
+            "Error while reading term annotations",
+            |s| terms::Selection::read(s, query),
+        ),
+        None => None,
+    };
+    let idmap = read_file(matches, "map-ids", "Error while reading ID mapping", |s| {
+        idmap::IdMap::read(s, matches.value_of("id-namespace"))
+    });
+    let fasta = read_file(
+        matches,
+        "fasta",
+        "Error while reading FASTA",
+        coverage::Fasta::read,
+    );
+
+    let mut opts = output::Options {
+        layout: layouts[0],
+        layouts,
+        qc_report: matches.is_present("qc-report"),
+        format: cli::parse(matches, "format").unwrap_or(Format::Csv),
+        average: matches.is_present("average"),
+        correlation_weighting: matches.is_present("correlation-weighting"),
+        uniprot: if matches.is_present("uniprot") {
+            let cache = matches
+                .value_of("uniprot-cache")
+                .map(PathBuf::from)
+                .unwrap_or_else(uniprot::default_cache);
+            Some(uniprot::UniProt::open(cache))
+        } else {
+            None
+        },
+        select_term,
+        idmap,
+        fasta,
+        append: None,
+        coverage: cli::parse(matches, "coverage-map"),
+        nterm_mode: matches.is_present("nterm-mode"),
+        nterm_label: cli::parse(matches, "nterm-label"),
+        row_stats: matches.is_present("row-stats"),
+        float_format: FloatFormat {
+            precision: cli::parse_valid(matches, "precision", "a non-negative integer", |_| true),
+            scientific: matches.is_present("scientific"),
+        },
+        rank: matches.is_present("rank"),
+        list_peptides: matches.is_present("list-peptides"),
+        cleavage_class: matches.is_present("cleavage-class"),
+        skip_unparseable: matches.is_present("skip-unparseable-sequences"),
+        dedupe: matches.is_present("dedupe"),
+        ptm_groups: matches.is_present("ptm-groups"),
+        contaminant_prefixes: matches
+            .values_of("contaminant-prefix")
+            .map(|v| v.map(String::from).collect())
+            .unwrap_or_default(),
+        exclude_contaminants: matches.is_present("exclude-contaminants"),
+        counts: cli::parse(matches, "recount").unwrap_or(Counts::Filtered),
+        census_counts: Vec::new(),
+        single_hits: cli::parse(matches, "single-hit").unwrap_or(qc::SingleHits::Keep),
+        // Every protein is expected to be a decoy
+        max_decoy_rate: match matches.is_present("keep-decoys-only") {
+            true => 1.0,
+            false => cli::parse(matches, "max-decoy-rate").unwrap_or(qc::MAX_DECOY_RATE),
+        },
+        duplicates: match cli::parse(matches, "duplicates") {
+            Some(policy) => Some(policy),
+            // Choosing a key asks for rows sharing it to be combined
+            None if matches.is_present("key") => Some(duplicates::Policy::Merge),
+            None => None,
+        },
+        key: cli::parse(matches, "key").unwrap_or(duplicates::Key::Accession),
+        labels: cli::parse(matches, "label-names"),
+        chemistry,
+        meta: matches.is_present("meta"),
+        header_comments: matches.is_present("header-comments"),
+        schema: cli::parse(matches, "output-schema").unwrap_or(meta::Schema::V1),
+        checkpoint: cli::parse_valid(matches, "checkpoint", "a positive integer", |rows| {
+            *rows > 0
+        }),
+        resume: matches.is_present("resume"),
+        stdout: matches.is_present("stdout"),
+        totals_row: matches.is_present("totals-row"),
+        conditions: cli::conditions(matches),
+        time_points: cli::time_points(matches),
+        time_course: matches.is_present("time-course"),
+        aggregate: cli::parse::<Aggregate>(matches, "aggregate-conditions"),
+        normalization: "none".to_string(),
+        transform: cli::parse(matches, "transform"),
+        cofactor: cli::parse_valid(matches, "cofactor", "a positive number", |c: &f64| {
+            *c > 0.0 && c.is_finite()
+        })
+        .unwrap_or(1.0),
+        keep_raw: matches.is_present("keep-raw"),
+        preview: cli::parse(matches, "preview"),
+        plot: cli::parse(matches, "plot"),
+        plot_dir: matches
+            .value_of("plot-dir")
+            .map(|dir| (PathBuf::from(dir), figures)),
+        sample: sample.map(|fraction| (fraction, seed)),
+        abpp,
+        paired: cli::parse(matches, "paired-ratios"),
+        group_proteins: matches.is_present("group-proteins"),
+        groups: HashMap::new(),
+        impute: cli::parse(matches, "impute"),
+        impute_floors: Vec::new(),
+        batch_correct: matches.is_present("batch-correct"),
+        noise: cli::parse(matches, "subtract-noise"),
+        scale: cli::parse(matches, "scale"),
+        baits: match matches.value_of("baits").map(saint::read_baits) {
+            Some(Ok(baits)) => Some(baits),
+            Some(Err(e)) => {
+                diagnostics::error(format!("Error while reading bait file: {}", e));
+                diagnostics::exit(Exit::of(&e));
+            }
+            None => None,
+        },
+        saint_controls: matches
+            .values_of("saint-control")
+            .map(|names| names.map(String::from).collect())
+            .unwrap_or_default(),
+        saint_quantity: cli::parse(matches, "saint-quantity").unwrap_or(saint::Quantity::Spectral),
+        split_by: cli::parse(matches, "split-by"),
+        threads,
+        max_memory: matches.value_of("max-memory").map(|size| {
+            cli::parse_size(size)
+                .unwrap_or_else(|e| cli::usage(format!("Invalid value for --max-memory: {}", e)))
+        }),
+    };
+    if opts.split_by.is_some() && opts.format != Format::Csv {
+        cli::usage("--split-by can only be used with CSV output");
+    }
+    if opts.stdout && (opts.layouts.len() > 1 || opts.format == Format::Saint) {
+        cli::usage("--stdout can only be used with one layout, in a format other than saint");
+    }
+    if let Some(path) = matches.value_of("append") {
+        if !matches!(opts.format, Format::Csv | Format::Tsv) || opts.layouts.len() > 1 {
+            cli::usage("--append can only be used with CSV or TSV output in one layout");
+        }
+        match append::Target::open(path, opts.format == Format::Tsv) {
+            Ok(target) => opts.append = Some(target),
+            Err(e) => {
+                diagnostics::file_error("Error while reading table", path, &e);
+                diagnostics::exit(Exit::of(&e));
+            }
+        }
+    }
+    if opts.aggregate.is_some() && opts.conditions.is_empty() && !matches.is_present("manifest") {
+        cli::usage(
+            "--aggregate-conditions requires channel conditions, from --conditions or --manifest",
+        );
+    }
+    opts
+}
+
+/// Read the file, which may be remote, given by `arg` with `read`, exiting
+/// if it cannot be read
+fn read_file<T, F>(matches: &ArgMatches, arg: &str, context: &str, read: F) -> Option<T>
+where
+    F: FnOnce(&str) -> std::io::Result<T>,
+{
+    let path = matches.value_of(arg)?;
+    match remote::read_to_string(path).and_then(|s| read(&s)) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            diagnostics::file_error(context, path, &e);
+            diagnostics::exit(Exit::of(&e));
+        }
+    }
+}
+
+impl<'a> Options<'a> {
+    /// Options given by `matches`
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        let manifest = matches.value_of("manifest");
+        let inputs = match matches.values_of("INPUT") {
+            Some(inputs) => inputs.collect(),
+            None if manifest.is_some() => Vec::new(),
+            None => cli::usage(format!(
+                "No input files given\n\n{}\n\nFor more information try --help",
+                matches.usage()
+            )),
+        };
+        Options {
+            inputs,
+            input_format: cli::input_format(matches),
+            strict: matches.is_present("strict"),
+            manifest,
+            outdir: matches.value_of("outdir").filter(|dir| !dir.is_empty()),
+            fractions: matches.is_present("fractions"),
+            output: matches.value_of("output"),
+            fail_fast: matches.is_present("fail-fast"),
+            resume: matches.is_present("resume"),
+            state: matches.value_of("state"),
+        }
+    }
+}
+
+/// Convert the inputs, or the plexes of the manifest, exiting with the
+/// status of any failure
+pub fn run(opts: &Options, filter: &Filter, mut output: output::Options) {
+    if let Some(path) = opts.manifest {
+        if let Err(e) = run_manifest(path, opts.input_format, filter, &mut output, opts.strict) {
+            diagnostics::file_error("Error while processing manifest", path, &e);
+            diagnostics::exit(Exit::of(&e));
+        }
+        return;
+    }
+
+    let inputs = &opts.inputs;
+    let mut staging = remote::Staging::default();
+    let local = match staging.fetch(inputs) {
+        Ok(local) => local,
+        Err(e) => {
+            diagnostics::error(format!("Error while downloading inputs: {}", e));
+            drop(staging);
+            diagnostics::exit(Exit::of(&e));
+        }
+    };
+
+    if output.stdout && inputs.len() > 1 && !opts.fractions {
+        diagnostics::error(
+            "--stdout can only be used with a single input, or with --combine-fractions",
+        );
+        drop(staging);
+        diagnostics::exit(Exit::Usage);
+    }
+    if opts.fractions {
+        // Name of the output as given, or as derived from a remote input
+        let name = match opts.output {
+            Some(path) => path.to_string(),
+            None => {
+                let mut path = PathBuf::from(inputs[0]);
+                path.set_extension(format!("combined.{}", output.format.extension()));
+                path.display().to_string()
+            }
+        };
+        let outpath = match opts.output.map(|path| staging.output(path)) {
+            Some(Ok(path)) => path,
+            Some(Err(e)) => {
+                diagnostics::error(format!("Invalid value for --output: {}", e));
+                drop(staging);
+                diagnostics::exit(Exit::Usage);
+            }
+            None => {
+                let mut path = PathBuf::from(&local[0]);
+                path.set_extension(format!("combined.{}", output.format.extension()));
+                match relocate(path, opts.outdir, &mut staging) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        diagnostics::error(format!("Invalid value for --outdir: {}", e));
+                        drop(staging);
+                        diagnostics::exit(Exit::Usage);
+                    }
+                }
+            }
+        };
+        let res = read_fractions(&local, opts.input_format, opts.strict).and_then(|data| {
+            output::convert(data, &inputs.join(";"), &outpath, filter, &mut output)
+        });
+        let failed = match res {
+            Ok(report) if !qc_passed(&name, &report) && opts.strict => Some(Exit::Qc),
+            Ok(_) => None,
+            // The reader of stdout has stopped, as with `| head`
+            Err(e) if output.stdout && e.kind() == std::io::ErrorKind::BrokenPipe => None,
+            Err(e) => {
+                diagnostics::error(format!("Error while combining fractions: {}", e));
+                Some(Exit::of(&e))
+            }
+        };
+        publish(&staging);
+        if let Some(status) = failed {
+            drop(staging);
+            diagnostics::exit(status);
+        }
+        return;
+    }
+
+    // Progress is recorded only when asked for, and unless nothing is written
+    let mut state = if (opts.resume || opts.state.is_some()) && output.preview.is_none() {
+        let settings = format!(
+            "filter {}, format {}, layouts {}, schema {}",
+            meta::filter_hash(filter),
+            output.format.name(),
+            output
+                .layouts
+                .iter()
+                .map(|l| l.name())
+                .collect::<Vec<_>>()
+                .join(","),
+            output.schema.name()
+        );
+        let path = opts.state.unwrap_or(resume::DEFAULT_PATH);
+        match resume::State::open(path, &settings, opts.resume) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                diagnostics::file_error("Error while opening state file", path, &e);
+                drop(staging);
+                diagnostics::exit(Exit::of(&e));
+            }
+        }
+    } else {
+        None
+    };
+    let mut failed_qc = false;
+    let mut converted = 0;
+    let mut resumed = 0;
+    let mut failed = Vec::new();
+    // Exit status of the first failure
+    let mut failure = None;
+    for (f, path) in inputs.iter().zip(&local) {
+        if state.as_ref().is_some_and(|s| s.is_done(f)) {
+            resumed += 1;
+            continue;
+        }
+        if let Some(target) = &output.append {
+            if target.contains(&append::run_name(f)) {
+                diagnostics::warning(
+                    Some(f),
+                    format!(
+                        "run {} is already in {}, skipping",
+                        append::run_name(f),
+                        target.path().display()
+                    ),
+                );
+                resumed += 1;
+                continue;
+            }
+        }
+        let outpath = relocate(output_path(path, output.format), opts.outdir, &mut staging);
+        let res = outpath.and_then(|outpath| {
+            read_input(path, opts.input_format, opts.strict)
+                .and_then(|data| {
+                    output::convert(data, f, &outpath, &filter.for_input(f), &mut output)
+                })
+                .map(|report| (report, outpath))
+        });
+        match res {
+            Ok((report, outpath)) => {
+                converted += 1;
+                failed_qc |= !qc_passed(f, &report);
+                if let Some(state) = state.as_mut() {
+                    if let Err(e) = state.complete(f, first_output(&outpath, &output)) {
+                        diagnostics::file_error("Error while writing state file", f, e);
+                    }
+                }
+            }
+            Err(e) if output.stdout && e.kind() == std::io::ErrorKind::BrokenPipe => break,
+            Err(e) => {
+                diagnostics::file_error("Error during processing of file", f, &e);
+                failure = failure.or(Some(Exit::of(&e)));
+                failed.push(*f);
+                if opts.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+    publish(&staging);
+    if inputs.len() > 1 {
+        diagnostics::batch_summary(inputs.len(), converted, resumed, &failed);
+    }
+    if let (Some(state), true) = (state, failed.is_empty()) {
+        if let Err(e) = state.remove() {
+            diagnostics::error(format!("Error while removing state file: {}", e));
+        }
+    }
+    let status = match failure {
+        Some(_) if converted + resumed > 0 => Some(Exit::PartialBatch),
+        Some(status) => Some(status),
+        None if failed_qc && opts.strict => Some(Exit::Qc),
+        None => None,
+    };
+    if let Some(status) = status {
+        drop(staging);
+        diagnostics::exit(status);
+    }
+}
+
+/// Return the path that the output for `path` should be written to
+fn output_path<P: AsRef<Path>>(path: P, format: Format) -> PathBuf {
+    let mut outpath = PathBuf::from(path.as_ref());
+    if !outpath.set_extension(format.extension()) {
+        panic!("Cannot set file extension for {}", outpath.display());
+    }
+    outpath
+}
+
+/// Move `outpath`, which is next to its input, into `outdir` if one is
+/// given, which may be an s3:// or gs:// URI
+fn relocate(
+    outpath: PathBuf,
+    outdir: Option<&str>,
+    staging: &mut remote::Staging,
+) -> std::io::Result<PathBuf> {
+    let dir = match outdir {
+        Some(dir) => dir,
+        None => return Ok(outpath),
+    };
+    let name = outpath
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if remote::is_remote(dir) {
+        staging.output(&format!("{}/{}", dir.trim_end_matches('/'), name))
+    } else {
+        fs::create_dir_all(dir)?;
+        Ok(Path::new(dir).join(name))
+    }
+}
+
+/// First file written by `convert` for `outpath`, which is distinguished by
+/// layout when several layouts are requested
+fn first_output(outpath: &Path, opts: &output::Options) -> PathBuf {
+    match opts.layouts.first() {
+        Some(layout) if opts.format.is_table() && opts.layouts.len() > 1 => PathBuf::from(format!(
+            "{}.{}.{}",
+            output::output_stem(outpath, opts.format),
+            layout.name(),
+            opts.format.extension()
+        )),
+        _ => outpath.to_path_buf(),
+    }
+}
+
+/// Print any quality control warnings for `name`, returning true if all
+/// checks passed
+fn qc_passed(name: &str, report: &qc::Report) -> bool {
+    for warning in &report.warnings {
+        diagnostics::warning(Some(name), warning);
+    }
+    report.passed()
+}
+
+/// Read `path`. Unless `strict` is true, malformed lines are skipped with a
+/// warning, and recorded in `<input>.rejects.txt`.
+fn read_input<P: AsRef<Path>>(
+    path: P,
+    input_format: input::InputFormat,
+    strict: bool,
+) -> std::io::Result<Dataset> {
+    let path = path.as_ref();
+    let (data, rejects) = input::read_lenient(path, input_format, strict)?;
+    if !rejects.is_empty() {
+        let rejects_path = path.with_extension("rejects.txt");
+        let mut file = std::io::BufWriter::new(fs::File::create(&rejects_path)?);
+        for reject in &rejects {
+            writeln!(file, "{}\t{}\t{}", reject.line, reject.reason, reject.text)?;
+        }
+        file.flush()?;
+        diagnostics::warning(
+            Some(&path.display().to_string()),
+            format!(
+                "skipped {} malformed lines, recorded in {}. Use --strict to stop at the first malformed line",
+                rejects.len(),
+                rejects_path.display()
+            ),
+        );
+    }
+    Ok(data)
+}
+
+/// Read `files` one at a time and combine them as fractions of one
+/// experiment
+fn read_fractions<P: AsRef<Path>>(
+    files: &[P],
+    input_format: input::InputFormat,
+    strict: bool,
+) -> std::io::Result<Dataset> {
+    // Errors name the file, since there are several
+    let read = |f: &P| {
+        read_input(f, input_format, strict)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", f.as_ref().display(), e)))
+    };
+    fractions::combine_each(files, read)
+}
+
+/// Process every plex described by the manifest at `path`, writing one
+/// output per plex to `<manifest>.<plex>.<ext>`
+fn run_manifest<'a>(
+    path: &str,
+    input_format: input::InputFormat,
+    filters: &Filter<'a>,
+    opts: &mut output::Options,
+    strict: bool,
+) -> std::io::Result<()> {
+    let plexes = manifest::read(path)?;
+    let mut datasets = plexes
+        .iter()
+        .map(|plex| read_fractions(&plex.files, input_format, strict))
+        .collect::<std::io::Result<Vec<Dataset>>>()?;
+    let bridges = plexes.iter().map(|p| p.bridge).collect::<Vec<_>>();
+    let factors = manifest::bridge_normalize(&mut datasets, &bridges)?;
+    let corrected = if opts.batch_correct {
+        Some(manifest::batch_correct(&mut datasets)?)
+    } else {
+        None
+    };
+
+    let mut failed_qc = false;
+    for ((plex, data), factor) in plexes.iter().zip(datasets).zip(factors) {
+        let outpath = PathBuf::from(path).with_extension(format!(
+            "{}.{}",
+            plex.name,
+            opts.format.extension()
+        ));
+        let input = plex
+            .files
+            .iter()
+            .map(|f| f.display().to_string())
+            .collect::<Vec<String>>()
+            .join(";");
+        opts.conditions = plex.channel_conditions(data.channels);
+        opts.normalization = match (factor, plex.bridge) {
+            (Some(factor), Some(bridge)) => {
+                format!("bridge channel {}, scaled by {:.4}", bridge, factor)
+            }
+            _ => "none".to_string(),
+        };
+        if let Some(n) = corrected {
+            let combat = format!(
+                "empirical Bayes batch correction of {} proteins across {} plexes",
+                n,
+                plexes.len()
+            );
+            opts.normalization = match opts.normalization.as_str() {
+                "none" => combat,
+                bridge => format!("{}; {}", bridge, combat),
+            };
+        }
+        let report = output::convert(data, &input, &outpath, &filters.for_input(&plex.name), opts)?;
+        failed_qc |= !qc_passed(&outpath.display().to_string(), &report);
+    }
+    if failed_qc && strict {
+        diagnostics::exit(Exit::Qc);
+    }
+    Ok(())
+}
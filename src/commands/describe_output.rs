@@ -0,0 +1,90 @@
+//! `describe-output`: write a JSON schema of every output
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::meta;
+use crate::output::{self, Format, Layout};
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+
+/// Options of the `describe-output` subcommand
+pub struct Options<'a> {
+    /// Number of channels to describe, if given directly
+    channels: Option<u8>,
+    /// Input to take the number of channels from, otherwise
+    input: Option<&'a str>,
+    input_format: InputFormat,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("describe-output")
+        .about("Write a JSON schema of every output format and layout to stdout, with the name, type, units, and description of each column, given the other options in effect")
+        .group(
+            ArgGroup::with_name("plex")
+                .args(&["channels", "INPUT"])
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("channels")
+                .help("Number of channels to describe")
+                .long("channels")
+                .value_name("N")
+                .takes_value(true),
+        )
+        .arg(cli::input_format_arg())
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to take the number of channels from"),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            channels: cli::parse_valid(matches, "channels", "a positive integer", |n| *n > 0),
+            input: matches.value_of("INPUT"),
+            input_format: cli::input_format(matches),
+        }
+    }
+}
+
+/// Write the schema of the outputs of `output`, for any layout, to stdout
+pub fn run(opts: &Options, filter: &Filter, output: &mut output::Options) {
+    let channels = match (opts.channels, opts.input) {
+        (Some(n), _) => n,
+        (None, Some(input)) => match input::read(input, opts.input_format) {
+            Ok(data) => data.channels,
+            Err(e) => {
+                diagnostics::file_error("Error during processing of file", input, &e);
+                diagnostics::exit(Exit::of(&e));
+            }
+        },
+        (None, None) => unreachable!(),
+    };
+    let mut tables = Vec::new();
+    let mut others = Vec::new();
+    for format in Format::ALL.iter().copied() {
+        match format.summary() {
+            Some(summary) => others.push((format.name(), format.extension(), summary)),
+            None => {
+                for layout in Layout::ALL.iter().copied() {
+                    output.layout = layout;
+                    let columns = output.columns(channels);
+                    tables.push((format.name(), format.extension(), layout.name(), columns));
+                }
+            }
+        }
+    }
+    if let Err(e) = meta::write_schema(
+        std::io::stdout().lock(),
+        channels,
+        filter,
+        &output.normalization(),
+        output.schema,
+        &tables,
+        &others,
+    ) {
+        diagnostics::error(format!("Error while writing schema: {}", e));
+        diagnostics::exit(Exit::of(&e));
+    }
+}
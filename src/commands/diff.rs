@@ -0,0 +1,85 @@
+//! `diff`: compare two runs
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use census2csv::filter::Filter;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+/// Options of the `diff` subcommand
+pub struct Options<'a> {
+    a: &'a str,
+    b: &'a str,
+    /// Compare peptides, rather than proteins
+    peptide: bool,
+    /// Minimum fold change in total intensity to report
+    fold: f64,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("diff")
+        .about("Report proteins or peptides gained, lost, or shifted between two runs")
+        .arg(
+            Arg::with_name("peptide")
+                .help("Compare peptide-level data")
+                .long("peptide")
+                .short("e")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .help("JSON file containing filters to apply to census inputs")
+                .short("f")
+                .long("filter")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fold")
+                .help("Minimum fold change in total intensity to report, greater than 0, default is 2. A fold change below 1 is taken as its reciprocal")
+                .long("fold")
+                .value_name("FOLD")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("A")
+                .help("census file or CSV output of the first run")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("B")
+                .help("census file or CSV output of the second run")
+                .required(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            a: matches.value_of("A").unwrap(),
+            b: matches.value_of("B").unwrap(),
+            peptide: matches.is_present("peptide"),
+            fold: cli::parse_valid(matches, "fold", "a number greater than 0", |fold: &f64| {
+                fold.is_finite() && *fold > 0.0
+            })
+            .unwrap_or(2.0),
+        }
+    }
+}
+
+/// Write the differences to stdout
+pub fn run(opts: &Options, filter: &Filter) {
+    let stdout = std::io::stdout();
+    if let Err(e) = crate::diff::diff(
+        opts.a,
+        opts.b,
+        filter,
+        opts.peptide,
+        opts.fold,
+        stdout.lock(),
+    ) {
+        diagnostics::error(format!(
+            "Error while comparing {} and {}: {}",
+            opts.a, opts.b, e
+        ));
+        diagnostics::exit(Exit::of(&e));
+    }
+}
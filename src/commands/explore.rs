@@ -0,0 +1,68 @@
+//! `explore`: browse the proteins of a file interactively
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::explore::Explorer;
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::io::IsTerminal;
+
+/// Options of the `explore` subcommand
+pub struct Options<'a> {
+    input: &'a str,
+    input_format: InputFormat,
+    line_mode: bool,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("explore")
+        .about("Browse the proteins of a file interactively, expanding their peptides and switching the rules of a top-level --filter off and on. From a terminal this is a full-screen interface driven by the arrow keys; otherwise commands are read from stdin, one per line")
+        .arg(cli::input_format_arg())
+        .arg(
+            Arg::with_name("line-mode")
+                .long("line-mode")
+                .help("Read commands from stdin one per line, even from a terminal"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to explore")
+                .required(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            input: matches.value_of("INPUT").unwrap(),
+            input_format: cli::input_format(matches),
+            line_mode: matches.is_present("line-mode"),
+        }
+    }
+}
+
+/// Can the full-screen interface be used?
+#[cfg(unix)]
+fn full_screen(opts: &Options) -> bool {
+    !opts.line_mode
+        && std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal()
+        && std::env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+/// Browse the input until the user quits
+pub fn run(opts: &Options, filter: Filter, threads: usize) {
+    let res = input::read(opts.input, opts.input_format).and_then(|data| {
+        let explorer = Explorer::new(data, filter, threads);
+        #[cfg(unix)]
+        if full_screen(opts) {
+            return crate::explore::Screen::new(explorer).run();
+        }
+        let stdin = std::io::stdin();
+        let mut explorer = explorer;
+        explorer.run(stdin.lock(), std::io::stdout().lock(), stdin.is_terminal())
+    });
+    if let Err(e) = res {
+        diagnostics::file_error("Error during processing of file", opts.input, &e);
+        diagnostics::exit(Exit::of(&e));
+    }
+}
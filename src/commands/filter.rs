@@ -0,0 +1,101 @@
+//! `filter`: write the PSMs passing filters, without aggregation
+use super::publish;
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::{census, psms, remote};
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use census2csv::parallel;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::path::PathBuf;
+
+/// Options of the `filter` subcommand
+pub struct Options<'a> {
+    inputs: Vec<&'a str>,
+    input_format: InputFormat,
+    /// Write one row per PSM, rather than a census_out file
+    tsv: bool,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("filter")
+        .about("Apply filters and write the remaining PSMs without aggregation")
+        .arg(
+            Arg::with_name("filter")
+                .help("JSON file containing filters to apply")
+                .short("f")
+                .long("filter")
+                .value_name("FILE")
+                .env("CENSUS2CSV_FILTER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Output format: census (default) for a filtered census_out file, or tsv for one row per PSM")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["census", "tsv"])
+                .takes_value(true),
+        )
+        .arg(cli::input_format_arg())
+        .arg(
+            Arg::with_name("INPUT")
+                .help("list of input files to filter")
+                .required(true)
+                .multiple(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            inputs: matches.values_of("INPUT").unwrap().collect(),
+            input_format: cli::input_format(matches),
+            tsv: matches.value_of("format") == Some("tsv"),
+        }
+    }
+}
+
+/// Write each input to `<INPUT>.filtered.txt`, or `<INPUT>.psms.tsv`
+pub fn run(opts: &Options, filter: &Filter, threads: usize) {
+    let mut staging = remote::Staging::default();
+    let local = match staging.fetch(&opts.inputs) {
+        Ok(local) => local,
+        Err(e) => {
+            diagnostics::error(format!("Error while downloading inputs: {}", e));
+            drop(staging);
+            diagnostics::exit(Exit::of(&e));
+        }
+    };
+    let (mut failure, mut converted) = (None, 0);
+    for (f, path) in opts.inputs.iter().zip(&local) {
+        let mut outpath = PathBuf::from(path);
+        outpath.set_extension(if opts.tsv { "psms.tsv" } else { "filtered.txt" });
+        let res = input::read(path, opts.input_format).and_then(|data| {
+            let data = parallel::filter(data, filter, threads);
+            let file = fs::File::create(&outpath)?;
+            if opts.tsv {
+                psms::write_tsv(&data, file)
+            } else {
+                census::write_census(&data, file)
+            }
+        });
+        match res {
+            Ok(()) => converted += 1,
+            Err(e) => {
+                diagnostics::file_error("Error during processing of file", f, &e);
+                failure.get_or_insert(Exit::of(&e));
+            }
+        }
+    }
+    publish(&staging);
+    if let Some(status) = failure {
+        drop(staging);
+        diagnostics::exit(if converted > 0 {
+            Exit::PartialBatch
+        } else {
+            status
+        });
+    }
+}
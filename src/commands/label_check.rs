@@ -0,0 +1,65 @@
+//! `label-check`: flag channels that look mislabeled
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::manifest;
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use census2csv::parallel;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+/// Options of the `label-check` subcommand
+pub struct Options<'a> {
+    input: &'a str,
+    input_format: InputFormat,
+    /// Planned condition of each channel, as pairs or a file
+    conditions: &'a str,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("label-check")
+        .about("Flag channels that correlate better with another condition than their own, which suggests mislabeled samples. Writes a CSV report to stdout, and exits with status 6 if any channel is suspect")
+        .arg(
+            Arg::with_name("conditions")
+                .help("Planned condition of each channel, as channel=condition pairs such as 1=ctrl;2=ctrl;3=drug;4=drug, or a tab-delimited file of channels and conditions")
+                .long("conditions")
+                .short("c")
+                .value_name("CONDITIONS")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(cli::input_format_arg())
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to check, after applying any top-level --filter")
+                .required(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            input: matches.value_of("INPUT").unwrap(),
+            input_format: cli::input_format(matches),
+            conditions: matches.value_of("conditions").unwrap(),
+        }
+    }
+}
+
+/// Write the report to stdout, exiting with `Exit::Qc` if any channel is
+/// suspect
+pub fn run(opts: &Options, filter: &Filter, threads: usize) {
+    let res = manifest::read_conditions(opts.conditions).and_then(|conditions| {
+        let data = parallel::filter(input::read(opts.input, opts.input_format)?, filter, threads);
+        let checks = crate::label_check::check(&data, &conditions)?;
+        crate::label_check::write_report(&checks, std::io::stdout().lock())?;
+        Ok(checks.iter().any(crate::label_check::Check::suspect))
+    });
+    match res {
+        Ok(true) => diagnostics::exit(Exit::Qc),
+        Ok(false) => {}
+        Err(e) => {
+            diagnostics::file_error("Error during processing of file", opts.input, &e);
+            diagnostics::exit(Exit::of(&e));
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Subcommands, each with its arguments, its options, and an entry point
+//! that reports errors and exits with the matching status
+pub mod annotate;
+pub mod convert;
+pub mod describe_output;
+pub mod diff;
+pub mod explore;
+pub mod filter;
+pub mod label_check;
+pub mod serve;
+pub mod shared_peptides;
+pub mod silac;
+pub mod sweep;
+
+use crate::{diagnostics, remote};
+use clap::App;
+
+/// Every subcommand, in the order they are listed by `--help`
+pub fn all() -> Vec<App<'static, 'static>> {
+    vec![
+        annotate::command(),
+        silac::command(),
+        label_check::command(),
+        shared_peptides::command(),
+        sweep::command(),
+        explore::command(),
+        filter::command(),
+        describe_output::command(),
+        diff::command(),
+        serve::command(),
+    ]
+}
+
+/// Upload outputs written for remote inputs and outputs
+pub fn publish(staging: &remote::Staging) {
+    if let Err(e) = staging.publish() {
+        diagnostics::error(format!("Error while uploading outputs: {}", e));
+    }
+}
@@ -0,0 +1,112 @@
+//! `serve`: convert files posted over HTTP
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::serve::Limits;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::time::Duration;
+
+/// Options of the `serve` subcommand
+pub struct Options<'a> {
+    host: &'a str,
+    port: u16,
+    limits: Limits,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("serve")
+        .about("Serve conversions over HTTP: POST a census file (and optional filter) to /convert")
+        .arg(
+            Arg::with_name("port")
+                .help("Port to listen on, default is 8080")
+                .long("port")
+                .short("p")
+                .value_name("PORT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("host")
+                .help("Address to listen on, default is 127.0.0.1")
+                .long("host")
+                .value_name("HOST")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-body")
+                .help("Largest request body accepted, such as 64M (the default) or 1G")
+                .long("max-body")
+                .value_name("SIZE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .help("Connections handled at once, default is 16. Further connections are refused with 503 until one finishes")
+                .long("max-connections")
+                .value_name("N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .help("Seconds a read or write of a connection may stall before it is closed, default is 30")
+                .long("timeout")
+                .value_name("SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("deadline")
+                .help("Seconds a connection may take, from being accepted to the end of its response, default is 300")
+                .long("deadline")
+                .value_name("SECONDS")
+                .takes_value(true),
+        )
+}
+
+fn seconds(matches: &ArgMatches, name: &str) -> Option<Duration> {
+    cli::parse_valid(
+        matches,
+        name,
+        "a number of seconds greater than 0, and at most a day",
+        |s: &f64| *s > 0.0 && *s <= 86400.0,
+    )
+    .map(Duration::from_secs_f64)
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        let default = Limits::default();
+        let limits = Limits {
+            max_body: matches
+                .value_of("max-body")
+                .map(|size| {
+                    cli::parse_size(size).unwrap_or_else(|e| {
+                        cli::usage(format!("Invalid value for --max-body: {}", e))
+                    })
+                })
+                .unwrap_or(default.max_body),
+            max_connections: cli::parse_valid(
+                matches,
+                "max-connections",
+                "a whole number greater than 0",
+                |n: &usize| *n > 0,
+            )
+            .unwrap_or(default.max_connections),
+            timeout: seconds(matches, "timeout").unwrap_or(default.timeout),
+            deadline: seconds(matches, "deadline").unwrap_or(default.deadline),
+        };
+        Options {
+            host: matches.value_of("host").unwrap_or("127.0.0.1"),
+            port: cli::parse(matches, "port").unwrap_or(8080),
+            limits,
+        }
+    }
+}
+
+/// Serve conversions until the server fails
+pub fn run(opts: &Options) {
+    if let Err(e) = crate::serve::serve(opts.host, opts.port, opts.limits) {
+        diagnostics::error(format!(
+            "Error while serving on {}:{}: {}",
+            opts.host, opts.port, e
+        ));
+        diagnostics::exit(Exit::of(&e));
+    }
+}
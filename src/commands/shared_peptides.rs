@@ -0,0 +1,45 @@
+//! `shared-peptides`: report peptides assigned to several proteins
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use census2csv::parallel;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+/// Options of the `shared-peptides` subcommand
+pub struct Options<'a> {
+    input: &'a str,
+    input_format: InputFormat,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("shared-peptides")
+        .about("Report peptide sequences assigned to more than one protein, with their PSMs, intensity, and intensity split evenly between the proteins, as CSV written to stdout. A summary of the ambiguous signal is printed to stderr")
+        .arg(cli::input_format_arg())
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to report on, after applying any top-level --filter")
+                .required(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            input: matches.value_of("INPUT").unwrap(),
+            input_format: cli::input_format(matches),
+        }
+    }
+}
+
+/// Write the report to stdout
+pub fn run(opts: &Options, filter: &Filter, threads: usize) {
+    let res = input::read(opts.input, opts.input_format).and_then(|data| {
+        let data = parallel::filter(data, filter, threads);
+        crate::shared::write_report(&data, std::io::stdout().lock())
+    });
+    if let Err(e) = res {
+        diagnostics::file_error("Error during processing of file", opts.input, &e);
+        diagnostics::exit(Exit::of(&e));
+    }
+}
@@ -0,0 +1,83 @@
+//! `silac`: convert SILAC census files
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::writer;
+use census2csv::number::FloatFormat;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::path::PathBuf;
+
+/// Options of the `silac` subcommand
+pub struct Options<'a> {
+    inputs: Vec<&'a str>,
+    /// Write one row per light/heavy pair, rather than one per protein
+    peptide: bool,
+    /// Exclude pairs with a lower regression factor
+    min_regression: f64,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("silac")
+        .about("Convert SILAC census files, with light/heavy peptide pairs, to CSV")
+        .arg(
+            Arg::with_name("peptide")
+                .help("Write one row per light/heavy pair, rather than one row per protein")
+                .long("peptide")
+                .short("e")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("min-regression")
+                .help("Exclude pairs with a regression factor below R, default is 0")
+                .long("min-regression")
+                .value_name("R")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("SILAC census files, each written to <INPUT>.silac.csv")
+                .required(true)
+                .multiple(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            inputs: matches.values_of("INPUT").unwrap().collect(),
+            peptide: matches.is_present("peptide"),
+            min_regression: cli::parse(matches, "min-regression").unwrap_or(0.0),
+        }
+    }
+}
+
+/// Convert each input to `<INPUT>.silac.csv`
+pub fn run(opts: &Options) {
+    let (mut failure, mut converted) = (None, 0);
+    for &input in &opts.inputs {
+        let res = crate::silac::read(input).and_then(|mut proteins| {
+            crate::silac::filter(&mut proteins, opts.min_regression);
+            let path = PathBuf::from(input).with_extension("silac.csv");
+            let mut out = writer::Delimited::csv(fs::File::create(path)?);
+            if opts.peptide {
+                crate::silac::write_pairs(&proteins, &mut out, FloatFormat::default())
+            } else {
+                crate::silac::write_proteins(&proteins, &mut out, FloatFormat::default())
+            }
+        });
+        match res {
+            Ok(()) => converted += 1,
+            Err(e) => {
+                diagnostics::file_error("Error during processing of file", input, &e);
+                failure.get_or_insert(Exit::of(&e));
+            }
+        }
+    }
+    if let Some(status) = failure {
+        diagnostics::exit(if converted > 0 {
+            Exit::PartialBatch
+        } else {
+            status
+        });
+    }
+}
@@ -0,0 +1,72 @@
+//! `sweep`: report the data retained at each threshold of a filter
+use crate::cli;
+use crate::diagnostics::{self, Exit};
+use crate::sweep::Range;
+use census2csv::filter::Filter;
+use census2csv::input::{self, InputFormat};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+/// Options of the `sweep` subcommand
+pub struct Options<'a> {
+    input: &'a str,
+    input_format: InputFormat,
+    /// Name of the filter to sweep
+    filter: &'a str,
+    range: Range,
+}
+
+pub fn command() -> App<'static, 'static> {
+    SubCommand::with_name("sweep")
+        .about("Report the proteins, peptides, and PSMs retained at each threshold of a filter, as CSV written to stdout. Rules from a top-level --filter are applied at every threshold")
+        .arg(
+            Arg::with_name("filter")
+                .help("Filter to sweep: TotalIntensity, MeanIntensity, MaxChannelIntensity, or Purity for peptides, or SpectralCounts, SequenceCounts, ProteinTotalIntensity, or TopNPeptidesByIntensity for proteins")
+                .long("filter")
+                .value_name("FILTER")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("range")
+                .help("Thresholds to test, as start:stop:step, such as 1000:20000:1000")
+                .long("range")
+                .value_name("RANGE")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(cli::input_format_arg())
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to sweep")
+                .required(true),
+        )
+}
+
+impl<'a> Options<'a> {
+    pub fn new(matches: &'a ArgMatches) -> Options<'a> {
+        Options {
+            input: matches.value_of("INPUT").unwrap(),
+            input_format: cli::input_format(matches),
+            filter: matches.value_of("filter").unwrap(),
+            range: cli::parse(matches, "range").unwrap(),
+        }
+    }
+}
+
+/// Write the report to stdout, applying `filter` at every threshold
+pub fn run(opts: &Options, filter: &Filter, threads: usize) {
+    let res = input::read(opts.input, opts.input_format).and_then(|data| {
+        crate::sweep::sweep(
+            &data,
+            filter,
+            opts.filter,
+            opts.range,
+            threads,
+            std::io::stdout().lock(),
+        )
+    });
+    if let Err(e) = res {
+        diagnostics::file_error("Error during processing of file", opts.input, &e);
+        diagnostics::exit(Exit::of(&e));
+    }
+}
@@ -0,0 +1,225 @@
+//! In-memory conversion, for builds without filesystem access and for
+//! serving requests
+//!
+//! `convert` operates on the bytes of a census file, and is exported to
+//! JavaScript by wasm32 builds. Since no bindings generator is used, the
+//! exports exchange strings through linear memory: the caller allocates
+//! input buffers with `census_alloc`, calls `census_convert`, and then reads
+//! the result (CSV text, or an error message) from `census_result_ptr` and
+//! `census_result_len`. wasm/census2csv.js wraps this protocol.
+use crate::filter::Filter;
+use crate::frame::Frame;
+use crate::input;
+use crate::rollup;
+use census_proteomics::Dataset;
+
+/// Table produced by `convert`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Table {
+    /// One row per protein, summing PSMs
+    Proteins,
+    /// One row per protein, averaging PSMs
+    ProteinsAverage,
+    /// One row per PSM
+    Psms,
+}
+
+impl Table {
+    /// Build this table from parsed data
+    pub fn frame(self, data: &Dataset) -> Frame {
+        match self {
+            Table::Proteins => Frame::proteins(data, &rollup::Sum),
+            Table::ProteinsAverage => Frame::proteins(data, &rollup::Mean),
+            Table::Psms => Frame::psms(data),
+        }
+    }
+}
+
+impl std::str::FromStr for Table {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "protein" => Ok(Table::Proteins),
+            "protein-average" => Ok(Table::ProteinsAverage),
+            "psm" => Ok(Table::Psms),
+            _ => Err(format!("unknown table {}", s)),
+        }
+    }
+}
+
+/// Parse census file contents, apply the JSON filter `filter` if given, and
+/// return the requested table
+pub fn frame(input: &[u8], filter: Option<&str>, table: Table) -> Result<Frame, String> {
+    let text = std::str::from_utf8(input).map_err(|_| "input is not valid UTF-8".to_string())?;
+    let mut data = input::parse_census(text).map_err(|e| format!("invalid census file: {}", e))?;
+    if let Some(json) = filter {
+        let filter =
+            serde_json::from_str::<Filter>(json).map_err(|e| format!("invalid filter: {}", e))?;
+        data = filter.filter_dataset(data);
+    }
+    Ok(table.frame(&data))
+}
+
+/// As `frame`, returning the table as CSV text
+pub fn convert(input: &[u8], filter: Option<&str>, table: Table) -> Result<String, String> {
+    frame(input, filter, table).map(|f| f.to_csv())
+}
+
+/// As `convert`, with arguments as passed through linear memory: the filter
+/// as UTF-8 bytes, empty for none, and the table as 0 for proteins, 1 for
+/// averaged proteins, and 2 for PSMs
+#[cfg(any(target_arch = "wasm32", test))]
+fn convert_raw(input: &[u8], filter: &[u8], table: u32) -> Result<String, String> {
+    let filter = match filter {
+        [] => None,
+        f => Some(std::str::from_utf8(f).map_err(|_| "filter is not valid UTF-8".to_string())?),
+    };
+    let table = match table {
+        0 => Table::Proteins,
+        1 => Table::ProteinsAverage,
+        2 => Table::Psms,
+        n => return Err(format!("unknown table {}", n)),
+    };
+    convert(input, filter, table)
+}
+
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static RESULT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Allocate `len` bytes for the caller to fill
+    #[no_mangle]
+    pub extern "C" fn census_alloc(len: usize) -> *mut u8 {
+        let mut buf = Vec::<u8>::with_capacity(len);
+        let ptr = buf.as_mut_ptr();
+        std::mem::forget(buf);
+        ptr
+    }
+
+    /// Release a buffer returned by `census_alloc`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must come from a single call to `census_alloc`.
+    #[no_mangle]
+    pub unsafe extern "C" fn census_dealloc(ptr: *mut u8, len: usize) {
+        drop(Vec::from_raw_parts(ptr, 0, len));
+    }
+
+    /// Convert `input_len` bytes of census data at `input`, with the
+    /// optional filter JSON at `filter` (null if `filter_len` is zero).
+    /// `table` is 0 for proteins, 1 for averaged proteins, and 2 for PSMs.
+    /// Returns 0 on success, or 1 if the result is an error message.
+    ///
+    /// # Safety
+    ///
+    /// `input` and `filter` must point to buffers of the given lengths.
+    #[no_mangle]
+    pub unsafe extern "C" fn census_convert(
+        input: *const u8,
+        input_len: usize,
+        filter: *const u8,
+        filter_len: usize,
+        table: u32,
+    ) -> u32 {
+        let input = std::slice::from_raw_parts(input, input_len);
+        let filter = match filter_len {
+            0 => &[],
+            n => std::slice::from_raw_parts(filter, n),
+        };
+        let (status, result) = match convert_raw(input, filter, table) {
+            Ok(csv) => (0, csv),
+            Err(e) => (1, e),
+        };
+        RESULT.with(|r| *r.borrow_mut() = result.into_bytes());
+        status
+    }
+
+    /// Location of the result of the last conversion
+    #[no_mangle]
+    pub extern "C" fn census_result_ptr() -> *const u8 {
+        RESULT.with(|r| r.borrow().as_ptr())
+    }
+
+    /// Length in bytes of the result of the last conversion
+    #[no_mangle]
+    pub extern "C" fn census_result_len() -> usize {
+        RESULT.with(|r| r.borrow().len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t2\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t300\t0.5
+S\tU\tK.PEPTIDER.L\t200\t0.5\t100\t0.5";
+
+    #[test]
+    fn convert_proteins() {
+        let csv = convert(FILE.as_bytes(), None, Table::Proteins).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("accession"));
+        assert!(lines.next().unwrap().ends_with("300,400"));
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        for text in [
+            FILE.replace("S\tU\tK.PEPTIDER", "Sx\tU\tK.PEPTIDER"),
+            FILE.replace("\t200\t", "\tlots\t"),
+            FILE.replace("S\tU", "S\tUU"),
+        ] {
+            let e = convert(text.as_bytes(), None, Table::Psms).err().unwrap();
+            assert!(e.starts_with("invalid census file"), "{}", e);
+        }
+        assert!(convert(&[0xff, 0xfe], None, Table::Psms).is_err());
+    }
+
+    #[test]
+    fn linear_memory_arguments() {
+        let filter = r#"{"peptide_filters": [], "protein_filters": [{"SpectralCounts": 3}]}"#;
+        assert_eq!(
+            convert_raw(FILE.as_bytes(), &[], 0),
+            convert(FILE.as_bytes(), None, Table::Proteins)
+        );
+        assert_eq!(
+            convert_raw(FILE.as_bytes(), filter.as_bytes(), 1),
+            convert(FILE.as_bytes(), Some(filter), Table::ProteinsAverage)
+        );
+        assert_eq!(
+            convert_raw(FILE.as_bytes(), filter.as_bytes(), 0)
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+        assert_eq!(
+            convert_raw(FILE.as_bytes(), &[], 2)
+                .unwrap()
+                .lines()
+                .count(),
+            3
+        );
+        assert_eq!(
+            convert_raw(FILE.as_bytes(), &[0xff], 0),
+            Err("filter is not valid UTF-8".to_string())
+        );
+        assert_eq!(
+            convert_raw(FILE.as_bytes(), &[], 3),
+            Err("unknown table 3".to_string())
+        );
+        assert!(convert_raw(FILE.as_bytes(), b"{", 0)
+            .unwrap_err()
+            .starts_with("invalid filter"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Per-channel corrections applied to PSM values before aggregation
+//!
+//! Corrections are given as a list of `channel=value` pairs, such as
+//! `1=1.0,2=0.95`, or as a tab-delimited file with one channel and value per
+//! line. A noise floor may also be a single value, used for every channel. Channels are 1-indexed, and channels that are not listed are left
+//! unchanged.
+use census_proteomics::Dataset;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Parse `channel=value` pairs separated by commas
+fn parse_pairs(s: &str) -> Result<Vec<(usize, f64)>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (channel, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected channel=value, found {}", pair))?;
+            parse_pair(channel, value)
+        })
+        .collect()
+}
+
+fn parse_pair(channel: &str, value: &str) -> Result<(usize, f64), String> {
+    let channel = match channel.trim().parse::<usize>() {
+        Ok(c) if c > 0 => c,
+        _ => return Err(format!("invalid channel {}", channel.trim())),
+    };
+    match value.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok((channel, v)),
+        _ => Err(format!(
+            "invalid value {} for channel {}",
+            value.trim(),
+            channel
+        )),
+    }
+}
+
+/// Read a tab-delimited file of channels and values. Blank lines, lines
+/// beginning with `#`, and a header line are skipped.
+fn read_pairs<P: AsRef<Path>>(path: P) -> Result<Vec<(usize, f64)>, String> {
+    let path = path.as_ref();
+    let file = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut pairs = Vec::new();
+    for (idx, line) in file.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (channel, value) = (fields.next().unwrap_or(""), fields.next().unwrap_or(""));
+        match parse_pair(channel, value) {
+            Ok(pair) => pairs.push(pair),
+            Err(_) if pairs.is_empty() && channel.trim().parse::<usize>().is_err() => {}
+            Err(e) => return Err(format!("{} line {}: {}", path.display(), idx + 1, e)),
+        }
+    }
+    Ok(pairs)
+}
+
+/// Parse `s` as `channel=value` pairs, or as the path of a tab-delimited
+/// file of channels and values if it does not contain `=`
+fn parse_channel_values(s: &str) -> Result<Vec<(usize, f64)>, String> {
+    if s.contains('=') {
+        parse_pairs(s)
+    } else {
+        read_pairs(s)
+    }
+}
+
+/// Check that every channel of `pairs` exists in a dataset with `channels`
+/// channels
+fn check_channels(pairs: &[(usize, f64)], channels: u8) -> std::io::Result<()> {
+    match pairs.iter().find(|(c, _)| *c > channels as usize) {
+        Some((c, _)) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("channel {} is out of range for {} channels", c, channels),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Describe `pairs` as `channel=value` pairs, for header comments
+fn describe(pairs: &[(usize, f64)]) -> String {
+    pairs
+        .iter()
+        .map(|(c, v)| format!("{}={}", c, v))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Per-channel correction factors, such as those measured from a
+/// calibration mix, by which PSM values are multiplied
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scale {
+    factors: Vec<(usize, f64)>,
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let factors = parse_channel_values(s)?;
+        match factors.iter().find(|(_, f)| *f < 0.0) {
+            Some((c, _)) => Err(format!("negative scale factor for channel {}", c)),
+            None => Ok(Scale { factors }),
+        }
+    }
+}
+
+impl Scale {
+    /// Multiply the values of every PSM in `data` by the factor of their
+    /// channel, rounding to the nearest integer
+    pub fn apply(&self, data: &mut Dataset) -> std::io::Result<()> {
+        check_channels(&self.factors, data.channels)?;
+        for pep in data
+            .proteins
+            .iter_mut()
+            .flat_map(|prot| prot.peptides.iter_mut())
+        {
+            for &(channel, factor) in &self.factors {
+                if let Some(val) = pep.values.get_mut(channel - 1) {
+                    *val = (*val as f64 * factor).round() as u32;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        format!("channels scaled by {}", describe(&self.factors))
+    }
+}
+
+/// Estimated reporter ion noise floor, subtracted from PSM values
+#[derive(Clone, Debug, PartialEq)]
+pub enum Noise {
+    /// The same noise floor for every channel
+    All(f64),
+    /// Noise floor of each listed channel
+    Channels(Vec<(usize, f64)>),
+}
+
+impl FromStr for Noise {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let noise = match s.trim().parse::<f64>() {
+            Ok(v) if v.is_finite() => Noise::All(v),
+            Ok(_) => return Err(format!("invalid value {}", s.trim())),
+            Err(_) => Noise::Channels(parse_channel_values(s)?),
+        };
+        let negative = match &noise {
+            Noise::All(v) => *v < 0.0,
+            Noise::Channels(values) => values.iter().any(|(_, v)| *v < 0.0),
+        };
+        if negative {
+            Err("noise floor must not be negative".into())
+        } else {
+            Ok(noise)
+        }
+    }
+}
+
+impl Noise {
+    /// Subtract the noise floor of each channel from the values of every PSM
+    /// in `data`, clamping at zero
+    pub fn apply(&self, data: &mut Dataset) -> std::io::Result<()> {
+        let floors = match self {
+            Noise::All(v) => (1..=data.channels as usize).map(|c| (c, *v)).collect(),
+            Noise::Channels(values) => {
+                check_channels(values, data.channels)?;
+                values.clone()
+            }
+        };
+        for pep in data
+            .proteins
+            .iter_mut()
+            .flat_map(|prot| prot.peptides.iter_mut())
+        {
+            for &(channel, floor) in &floors {
+                if let Some(val) = pep.values.get_mut(channel - 1) {
+                    *val = (*val as f64 - floor).max(0.0).round() as u32;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Noise::All(v) => format!("noise floor of {} subtracted", v),
+            Noise::Channels(values) => format!("noise floor of {} subtracted", describe(values)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+    use census_proteomics::{Peptide, Protein};
+
+    fn dataset(values: &[u32]) -> Dataset {
+        Dataset {
+            proteins: vec![Protein {
+                peptides: vec![Peptide {
+                    values: values.to_vec(),
+                    ..Peptide::default()
+                }],
+                ..Protein::default()
+            }],
+            channels: values.len() as u8,
+        }
+    }
+
+    fn values(data: &Dataset) -> &[u32] {
+        &data.proteins[0].peptides[0].values
+    }
+
+    #[test]
+    fn scale_channels() {
+        let scale = "1=0.5, 3=1.26".parse::<Scale>().unwrap();
+        let mut data = dataset(&[101, 200, 100]);
+        scale.apply(&mut data).unwrap();
+        assert_eq!(values(&data), &[51, 200, 126]);
+        assert_eq!(scale.describe(), "channels scaled by 1=0.5,3=1.26");
+
+        let mut data = dataset(&[1, 1]);
+        let e = scale.apply(&mut data).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        assert!("1=-0.5".parse::<Scale>().is_err());
+        assert!("0=1".parse::<Scale>().is_err());
+        assert!("1=inf".parse::<Scale>().is_err());
+        assert!("1=1,2".parse::<Scale>().is_err());
+    }
+
+    #[test]
+    fn read_channel_values() {
+        let dir = fixture::dir(
+            "scale",
+            &[(
+                "scale.tsv",
+                "channel\tfactor\n# calibration\n1\t0.9\n\n2\t1.1\n",
+            )],
+        );
+        let path = dir.join("scale.tsv");
+        let scale = path.to_str().unwrap().parse::<Scale>().unwrap();
+        assert_eq!(scale.describe(), "channels scaled by 1=0.9,2=1.1");
+
+        std::fs::write(&path, "1\t0.9\n2\tx\n").unwrap();
+        let e = path.to_str().unwrap().parse::<Scale>().err().unwrap();
+        assert!(
+            e.ends_with("line 2: invalid value x for channel 2"),
+            "{}",
+            e
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn subtract_noise() {
+        let mut data = dataset(&[100, 5, 0]);
+        "10".parse::<Noise>().unwrap().apply(&mut data).unwrap();
+        assert_eq!(values(&data), &[90, 0, 0]);
+
+        let noise = "2=1.5".parse::<Noise>().unwrap();
+        assert_eq!(noise, Noise::Channels(vec![(2, 1.5)]));
+        let mut data = dataset(&[100, 5, 0]);
+        noise.apply(&mut data).unwrap();
+        assert_eq!(values(&data), &[100, 4, 0]);
+        assert_eq!(noise.describe(), "noise floor of 2=1.5 subtracted");
+
+        assert!("-1".parse::<Noise>().is_err());
+        assert!("NaN".parse::<Noise>().is_err());
+        assert!("2=-1".parse::<Noise>().is_err());
+    }
+}
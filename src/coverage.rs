@@ -0,0 +1,222 @@
+//! Protein sequence coverage maps
+//!
+//! Peptides passing filters are located in protein sequences read from a
+//! FASTA file, and the covered residues of each protein are written either
+//! as JSON, with a coverage string in which covered residues are upper case
+//! and the rest lower case, along with merged 1-indexed intervals, or as a
+//! BED-like file with one 0-indexed, half-open interval per peptide.
+use census2csv::sequence;
+use census_proteomics::Dataset;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::io::prelude::*;
+use std::str::FromStr;
+
+/// Format of a coverage map
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Bed,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "bed" => Ok(Format::Bed),
+            _ => Err(format!("unknown coverage format {}", s)),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "coverage.json",
+            Format::Bed => "coverage.bed",
+        }
+    }
+}
+
+/// Protein sequences, by the identifier of each FASTA entry and by its
+/// UniProt accession
+#[derive(Clone, Debug, Default)]
+pub struct Fasta {
+    sequences: HashMap<String, String>,
+}
+
+impl Fasta {
+    pub fn read(text: &str) -> std::io::Result<Fasta> {
+        let mut sequences = HashMap::new();
+        let mut entries: Vec<(Vec<String>, String)> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(header) = line.strip_prefix('>') {
+                let id = header.split_whitespace().next().unwrap_or_default();
+                let mut ids = vec![id.to_string()];
+                if let Some(acc) = id.split('|').nth(1) {
+                    ids.push(acc.to_string());
+                }
+                entries.push((ids, String::new()));
+            } else if let Some((_, seq)) = entries.last_mut() {
+                seq.push_str(&line.to_ascii_uppercase());
+            } else if !line.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "sequence before the first FASTA header",
+                ));
+            }
+        }
+        for (ids, seq) in entries {
+            for id in ids {
+                sequences.entry(id).or_insert_with(|| seq.clone());
+            }
+        }
+        Ok(Fasta { sequences })
+    }
+
+    /// Sequence of the protein with census locus `accession`
+    pub fn get(&self, accession: &str) -> Option<&str> {
+        self.sequences
+            .get(accession)
+            .or_else(|| self.sequences.get(accession.split('|').nth(1)?))
+            .map(String::as_str)
+    }
+}
+
+/// A peptide located in a protein sequence
+struct Hit {
+    /// 0-indexed start, and end, exclusive
+    start: usize,
+    end: usize,
+    residues: String,
+    psms: usize,
+}
+
+/// The peptides of a protein, located in its sequence
+struct Located<'d, 'f> {
+    accession: &'d str,
+    sequence: &'f str,
+    hits: Vec<Hit>,
+}
+
+/// Every occurrence of each peptide of each protein in its sequence, for
+/// proteins found in `fasta`. Also returns the number of proteins without a
+/// sequence.
+fn locate<'d, 'f>(data: &'d Dataset, fasta: &'f Fasta) -> (Vec<Located<'d, 'f>>, usize) {
+    let mut missing = 0;
+    let mut proteins = Vec::new();
+    for prot in &data.proteins {
+        let seq = match fasta.get(&prot.accession) {
+            Some(seq) => seq,
+            None => {
+                missing += 1;
+                continue;
+            }
+        };
+        let mut psms: BTreeMap<String, usize> = BTreeMap::new();
+        for pep in &prot.peptides {
+            for link in sequence::links(&pep.sequence) {
+                *psms.entry(link.residues()).or_insert(0) += 1;
+            }
+        }
+        let mut hits = Vec::new();
+        for (residues, psms) in psms {
+            if residues.is_empty() {
+                continue;
+            }
+            for (start, _) in seq.match_indices(&residues) {
+                hits.push(Hit {
+                    start,
+                    end: start + residues.len(),
+                    residues: residues.clone(),
+                    psms,
+                });
+            }
+        }
+        hits.sort_by_key(|h| (h.start, h.end));
+        proteins.push(Located {
+            accession: &prot.accession,
+            sequence: seq,
+            hits,
+        });
+    }
+    (proteins, missing)
+}
+
+/// Merge overlapping and adjacent hits into 1-indexed, inclusive intervals
+fn intervals(hits: &[Hit]) -> Vec<(usize, usize)> {
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for hit in hits {
+        match merged.last_mut() {
+            Some(last) if hit.start <= last.1 => last.1 = last.1.max(hit.end),
+            _ => merged.push((hit.start, hit.end)),
+        }
+    }
+    merged.into_iter().map(|(s, e)| (s + 1, e)).collect()
+}
+
+/// Write the coverage map of `data` in `format`, returning the number of
+/// proteins without a sequence in `fasta`
+pub fn write<W: Write>(
+    data: &Dataset,
+    fasta: &Fasta,
+    format: Format,
+    mut out: W,
+) -> std::io::Result<usize> {
+    let (proteins, missing) = locate(data, fasta);
+    match format {
+        Format::Json => {
+            let mut map = serde_json::Map::new();
+            for Located {
+                accession,
+                sequence: seq,
+                hits,
+            } in &proteins
+            {
+                let mut covered = vec![false; seq.len()];
+                for hit in hits {
+                    covered[hit.start..hit.end]
+                        .iter_mut()
+                        .for_each(|c| *c = true);
+                }
+                let string = seq
+                    .chars()
+                    .zip(&covered)
+                    .map(|(c, &cov)| if cov { c } else { c.to_ascii_lowercase() })
+                    .collect::<String>();
+                let n = covered.iter().filter(|&&c| c).count();
+                map.insert(
+                    accession.to_string(),
+                    json!({
+                        "length": seq.len(),
+                        "covered_residues": n,
+                        "coverage": if seq.is_empty() { 0.0 } else { n as f64 / seq.len() as f64 },
+                        "sequence": string,
+                        "intervals": intervals(hits),
+                    }),
+                );
+            }
+            serde_json::to_writer_pretty(&mut out, &map)?;
+            writeln!(out)?;
+        }
+        Format::Bed => {
+            for Located {
+                accession, hits, ..
+            } in &proteins
+            {
+                for hit in hits {
+                    writeln!(
+                        out,
+                        "{}\t{}\t{}\t{}\t{}",
+                        accession, hit.start, hit.end, hit.residues, hit.psms
+                    )?;
+                }
+            }
+        }
+    }
+    out.flush()?;
+    Ok(missing)
+}
@@ -0,0 +1,184 @@
+//! Warnings and errors reported to the user
+//!
+//! Messages are printed as text by default. With `--errors-json`, each is
+//! instead written to stderr as one JSON object per line, with a `level` of
+//! `info`, `warning` or `error`, the `file` concerned if there is one, and
+//! the `message`, so that workflow managers can report failures precisely.
+//! The summary of a batch is a single record with a `level` of `summary`.
+//! Messages always go to stderr, leaving stdout for data.
+use serde_json::json;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Exit status of a failed run, so that workflow managers can branch on the
+/// kind of failure
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Invalid command line arguments
+    Usage = 1,
+    /// An input or other file could not be parsed
+    Parse = 2,
+    /// The filter could not be read or parsed
+    Filter = 3,
+    /// A file could not be read or written
+    Io = 4,
+    /// Some inputs of a batch were converted, and others failed
+    PartialBatch = 5,
+    /// Outputs were written, but failed quality control checks with
+    /// `--strict`
+    Qc = 6,
+}
+
+impl Exit {
+    /// Exit status for an error while reading or writing a file, which is a
+    /// parse error if the file was read but its contents are invalid
+    pub fn of(error: &std::io::Error) -> Exit {
+        use std::io::ErrorKind;
+        match error.kind() {
+            ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof => {
+                Exit::Parse
+            }
+            _ => Exit::Io,
+        }
+    }
+}
+
+/// Exit the process with `status`
+pub fn exit(status: Exit) -> ! {
+    std::process::exit(status as i32)
+}
+
+/// Emit all later messages as JSON lines on stderr
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+/// Highlight warnings and errors printed as text with ANSI colors
+pub fn set_color(color: bool) {
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+/// `text` in the ANSI color `code`, if colors are enabled
+fn paint(code: &str, text: &str) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn emit(level: &str, file: Option<&str>, message: &str) {
+    let line = json!({
+        "level": level,
+        "file": file,
+        "message": message,
+    });
+    eprintln!("{}", line);
+}
+
+/// Report a warning, about `file` if given
+pub fn warning<M: Display>(file: Option<&str>, message: M) {
+    if JSON.load(Ordering::Relaxed) {
+        emit("warning", file, &message.to_string());
+    } else {
+        match file {
+            Some(file) => eprintln!("{} {}: {}", paint("33", "WARNING:"), file, message),
+            None => eprintln!("{} {}", paint("33", "WARNING:"), message),
+        }
+    }
+}
+
+/// Report progress or a summary of the results
+pub fn info<M: Display>(message: M) {
+    if JSON.load(Ordering::Relaxed) {
+        emit("info", None, &message.to_string());
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// Summarize a batch of `total` inputs, of which `converted` were converted
+/// by this run, `resumed` were converted earlier, and `failed` failed. The
+/// remaining inputs were skipped after the first failure.
+pub fn batch_summary(total: usize, converted: usize, resumed: usize, failed: &[&str]) {
+    let skipped = total - resumed - converted - failed.len();
+    if JSON.load(Ordering::Relaxed) {
+        let line = json!({
+            "level": "summary",
+            "total": total,
+            "converted": converted,
+            "already_converted": resumed,
+            "skipped": skipped,
+            "failed": failed,
+        });
+        eprintln!("{}", line);
+        return;
+    }
+    eprintln!(
+        "Converted {} of {} files, {} failed{}{}",
+        converted,
+        total,
+        failed.len(),
+        if resumed > 0 {
+            format!(", {} already converted", resumed)
+        } else {
+            String::new()
+        },
+        if skipped > 0 {
+            format!(", {} skipped after the first failure", skipped)
+        } else {
+            String::new()
+        }
+    );
+    for f in failed {
+        eprintln!("  failed: {}", f);
+    }
+}
+
+/// Report an error that does not concern a single file
+pub fn error<M: Display>(message: M) {
+    if JSON.load(Ordering::Relaxed) {
+        emit("error", None, &message.to_string());
+    } else {
+        eprintln!("{}", paint("31", &message.to_string()));
+    }
+}
+
+/// Report an error while processing `file`, printed as text after `context`
+pub fn file_error<E: Display>(context: &str, file: &str, error: E) {
+    if JSON.load(Ordering::Relaxed) {
+        emit("error", Some(file), &error.to_string());
+    } else {
+        eprintln!("{} {}: {}", paint("31", context), file, error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn exit_status_of_io_errors() {
+        for kind in [
+            ErrorKind::InvalidData,
+            ErrorKind::InvalidInput,
+            ErrorKind::UnexpectedEof,
+        ] {
+            assert_eq!(Exit::of(&Error::new(kind, "bad")), Exit::Parse);
+        }
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::PermissionDenied,
+            ErrorKind::Other,
+        ] {
+            assert_eq!(Exit::of(&Error::new(kind, "bad")), Exit::Io);
+        }
+        assert_eq!(Exit::Usage as i32, 1);
+        assert_eq!(Exit::PartialBatch as i32, 5);
+        assert_eq!(Exit::Qc as i32, 6);
+    }
+}
@@ -0,0 +1,290 @@
+//! Compare two runs of the same sample, reporting proteins or peptides that
+//! were gained, lost, or shifted in intensity between them
+//!
+//! Either side of the comparison may be a census file or a CSV file that was
+//! previously produced by census2csv
+use crate::diagnostics;
+use census2csv::filter::Filter;
+use census2csv::input;
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Identifier for a single row in the comparison: accession, and sequence
+/// when comparing at the peptide level.
+type Key = (String, String);
+
+/// Summed channel intensities for each protein or peptide in a run
+struct Run {
+    entries: HashMap<Key, Vec<f64>>,
+}
+
+impl Run {
+    fn total(&self, key: &Key) -> f64 {
+        self.entries[key].iter().sum()
+    }
+}
+
+fn load_census<'a, P: AsRef<Path>>(
+    path: P,
+    filters: &Filter<'a>,
+    peptide: bool,
+) -> std::io::Result<Run> {
+    let file = fs::read_to_string(path)?;
+    let data = input::parse_census(&file)?;
+    let data = filters.filter_dataset(data);
+
+    let mut entries: HashMap<Key, Vec<f64>> = HashMap::new();
+    for prot in &data.proteins {
+        for pep in &prot.peptides {
+            let seq = if peptide {
+                pep.sequence.clone()
+            } else {
+                String::new()
+            };
+            let entry = entries
+                .entry((prot.accession.clone(), seq))
+                .or_insert_with(|| vec![0.0; data.channels as usize]);
+            for (idx, val) in pep.values.iter().enumerate() {
+                entry[idx] += *val as f64;
+            }
+        }
+    }
+    Ok(Run { entries })
+}
+
+/// Read a CSV output of census2csv. Lines are split on every comma, without
+/// any handling of quotes, which relies on `writer::Delimited` replacing
+/// commas within fields with semicolons rather than quoting them. Channel
+/// values that are not finite numbers are ignored.
+fn load_csv<P: AsRef<Path>>(path: P, peptide: bool) -> std::io::Result<Run> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+
+    let file = fs::read_to_string(path)?;
+    let mut lines = file.lines().filter(|l| !l.starts_with('#'));
+    let header = lines
+        .next()
+        .ok_or_else(|| invalid("empty CSV file"))?
+        .split(',')
+        .collect::<Vec<&str>>();
+
+    let accession = header
+        .iter()
+        .position(|h| *h == "accession")
+        .ok_or_else(|| invalid("missing accession column"))?;
+    let sequence = header.iter().position(|h| *h == "sequence");
+    if peptide && sequence.is_none() {
+        return Err(invalid(
+            "missing sequence column for peptide-level comparison",
+        ));
+    }
+    let channels = header
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| crate::labels::is_channel_column(h))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<usize>>();
+
+    let mut entries: HashMap<Key, Vec<f64>> = HashMap::new();
+    for line in lines {
+        let row = line.split(',').collect::<Vec<&str>>();
+        // Skip the footer written by --totals-row
+        if row.get(accession) == Some(&"TOTAL") {
+            continue;
+        }
+        let seq = match sequence {
+            Some(idx) if peptide => row.get(idx).copied().unwrap_or("").to_string(),
+            _ => String::new(),
+        };
+        let acc = row.get(accession).copied().unwrap_or("").to_string();
+        let entry = entries
+            .entry((acc, seq))
+            .or_insert_with(|| vec![0.0; channels.len()]);
+        for (i, idx) in channels.iter().enumerate() {
+            entry[i] += row
+                .get(*idx)
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|v| v.is_finite())
+                .unwrap_or(0.0);
+        }
+    }
+    Ok(Run { entries })
+}
+
+fn load<'a, P: AsRef<Path>>(path: P, filters: &Filter<'a>, peptide: bool) -> std::io::Result<Run> {
+    let is_csv = path
+        .as_ref()
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("csv"))
+        .unwrap_or(false);
+    if is_csv {
+        load_csv(path, peptide)
+    } else {
+        load_census(path, filters, peptide)
+    }
+}
+
+/// Compare runs `a` and `b`, writing a CSV report of every protein (or
+/// peptide) that is present in only one run, or whose total intensity
+/// differs by at least `fold` between runs. `fold` must be a finite number
+/// greater than 0, and a fold change below 1 is taken as its reciprocal.
+///
+/// Proteins present in both runs with a total of zero in exactly one are
+/// reported as shifted. The log2 fold change of those, and of every gained
+/// or lost protein, is not finite and is left empty.
+pub fn diff<'a, P: AsRef<Path>, W: Write>(
+    a: P,
+    b: P,
+    filters: &Filter<'a>,
+    peptide: bool,
+    fold: f64,
+    mut out: W,
+) -> std::io::Result<()> {
+    if !(fold.is_finite() && fold > 0.0) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("fold change must be a number greater than 0, not {}", fold),
+        ));
+    }
+    let a = load(a, filters, peptide)?;
+    let b = load(b, filters, peptide)?;
+
+    let mut keys = a
+        .entries
+        .keys()
+        .chain(b.entries.keys())
+        .collect::<Vec<&Key>>();
+    keys.sort();
+    keys.dedup();
+
+    if peptide {
+        writeln!(
+            out,
+            "status,accession,sequence,total_a,total_b,log2_fold_change"
+        )?;
+    } else {
+        writeln!(out, "status,accession,total_a,total_b,log2_fold_change")?;
+    }
+
+    let threshold = fold.log2().abs();
+    let (mut gained, mut lost, mut shifted, mut shared) = (0, 0, 0, 0);
+    for key in keys {
+        let (status, ta, tb) = match (a.entries.contains_key(key), b.entries.contains_key(key)) {
+            (true, false) => {
+                lost += 1;
+                ("lost", a.total(key), 0.0)
+            }
+            (false, true) => {
+                gained += 1;
+                ("gained", 0.0, b.total(key))
+            }
+            _ => {
+                shared += 1;
+                let (ta, tb) = (a.total(key), b.total(key));
+                if ta == tb || (tb / ta).log2().abs() < threshold {
+                    continue;
+                }
+                shifted += 1;
+                ("shifted", ta, tb)
+            }
+        };
+
+        let lfc = (tb / ta).log2();
+        let lfc = if lfc.is_finite() {
+            format!("{:.3}", lfc)
+        } else {
+            String::new()
+        };
+        if peptide {
+            writeln!(out, "{},{},{},{},{},{}", status, key.0, key.1, ta, tb, lfc)?;
+        } else {
+            writeln!(out, "{},{},{},{},{}", status, key.0, ta, tb, lfc)?;
+        }
+    }
+
+    diagnostics::info(format!(
+        "{} shared, {} gained, {} lost, {} shifted by at least {}-fold",
+        shared, gained, lost, shifted, fold
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const A: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tPROT1\t1\t1\t30.3%\t335\t82944\tShared
+S\tU\tK.PEPTIDEK.R\t100\t0.5\t100\t0.5
+P\tPROT2\t1\t1\t10.0%\t100\t10000\tShifted
+S\tU\tR.SHIFTEDK.A\t100\t0.5\t100\t0.5
+P\tPROT3\t1\t1\t10.0%\t100\t10000\tLost
+S\tU\tR.LOSTK.A\t50\t0.5\t50\t0.5";
+
+    const B: &str = "\
+accession,description,spectral_count,sequence_count,channel_1,channel_2
+PROT1,Shared,1,1,110,100
+PROT2,Shifted,1,1,500,300
+PROT4,Gained,1,1,NaN,7
+TOTAL,,,,inf,407";
+
+    fn run(test: &str, fold: f64, files: &[(&str, &str)]) -> std::io::Result<String> {
+        let dir = fixture::dir(test, files);
+        let mut out = Vec::new();
+        diff(
+            dir.join(files[0].0),
+            dir.join(files[1].0),
+            &Filter::default(),
+            false,
+            fold,
+            &mut out,
+        )?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn census_against_csv() {
+        let out = run("diff", 2.0, &[("a.txt", A), ("b.csv", B)]).unwrap();
+        assert_eq!(
+            out,
+            "status,accession,total_a,total_b,log2_fold_change
+shifted,PROT2,200,800,2.000
+lost,PROT3,100,0,
+gained,PROT4,0,7,
+"
+        );
+    }
+
+    #[test]
+    fn fold_below_one_is_reciprocal() {
+        let files = [("a.txt", A), ("b.csv", B)];
+        assert_eq!(
+            run("diff-reciprocal", 0.5, &files).unwrap(),
+            run("diff-reciprocal", 2.0, &files).unwrap()
+        );
+    }
+
+    #[test]
+    fn zero_total_is_flagged() {
+        let b = "accession,channel_1\nPROT1,0\nPROT2,0\nPROT3,0";
+        let a = "accession,channel_1\nPROT1,0\nPROT2,5\nPROT3,0";
+        let out = run("diff-zero", 1.5, &[("a.csv", a), ("b.csv", b)]).unwrap();
+        assert_eq!(
+            out,
+            "status,accession,total_a,total_b,log2_fold_change\nshifted,PROT2,5,0,\n"
+        );
+    }
+
+    #[test]
+    fn fold_must_be_positive() {
+        for fold in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            let err = run("diff-fold", fold, &[("a.txt", A), ("b.csv", B)])
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Detection and handling of accessions that appear in multiple protein
+//! blocks of the same census file
+use census_proteomics::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// How to handle proteins with duplicated accessions
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Policy {
+    /// Refuse to process the file
+    Error,
+    /// Combine duplicated protein blocks, pooling their peptides
+    Merge,
+    /// Emit each protein block separately
+    Keep,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Policy::Error),
+            "merge" => Ok(Policy::Merge),
+            "keep" => Ok(Policy::Keep),
+            _ => Err(format!("unknown duplicate policy {}", s)),
+        }
+    }
+}
+
+/// Identity of a protein block, deciding which blocks are duplicates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Key {
+    /// The accession, as written
+    Accession,
+    /// The accession without an isoform suffix such as `-2`, so that
+    /// isoforms of a protein are combined
+    AccessionNoIso,
+    /// The gene name from a `GN=` field of the description, or the
+    /// accession if there is none
+    Gene,
+    /// A hash of the description, so that blocks with identical
+    /// descriptions are combined
+    DescriptionHash,
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accession" => Ok(Key::Accession),
+            "accession_noiso" => Ok(Key::AccessionNoIso),
+            "gene" => Ok(Key::Gene),
+            "description-hash" => Ok(Key::DescriptionHash),
+            _ => Err(format!("unknown protein key {}", s)),
+        }
+    }
+}
+
+/// Strip an isoform suffix from a bare accession
+fn strip_isoform(acc: &str) -> &str {
+    match acc.rsplit_once('-') {
+        Some((base, iso)) if !iso.is_empty() && iso.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => acc,
+    }
+}
+
+impl Key {
+    /// What blocks sharing this key have in common, for messages
+    fn describe(self) -> &'static str {
+        match self {
+            Key::Accession => "accessions",
+            Key::AccessionNoIso => "accessions without isoform suffixes",
+            Key::Gene => "gene names",
+            Key::DescriptionHash => "descriptions",
+        }
+    }
+
+    /// Identity of `prot` under this key
+    pub fn of(self, prot: &Protein) -> String {
+        match self {
+            Key::Accession => prot.accession.clone(),
+            // Isoforms may be suffixed within a UniProt locus such as
+            // sp|P12345-2|ALBU_HUMAN
+            Key::AccessionNoIso => prot
+                .accession
+                .split('|')
+                .map(strip_isoform)
+                .collect::<Vec<&str>>()
+                .join("|"),
+            Key::Gene => prot
+                .description
+                .split_whitespace()
+                .find_map(|field| field.strip_prefix("GN="))
+                .map(|gene| format!("GN={}", gene))
+                .unwrap_or_else(|| prot.accession.clone()),
+            Key::DescriptionHash => {
+                // 64-bit FNV-1a, which does not vary between builds
+                let hash = prot
+                    .description
+                    .bytes()
+                    .fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+                        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+                    });
+                format!("{:016x}", hash)
+            }
+        }
+    }
+}
+
+/// Return accessions that appear in more than one protein block, in the
+/// order they are first encountered
+pub fn find(data: &Dataset) -> Vec<String> {
+    find_by(data, Key::Accession)
+}
+
+/// Return the keys shared by more than one protein block, in the order they
+/// are first encountered, as the accession of the first such block
+pub fn find_by(data: &Dataset, key: Key) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = Vec::new();
+    for prot in &data.proteins {
+        let count = counts.entry(key.of(prot)).or_insert(0);
+        *count += 1;
+        if *count == 2 {
+            order.push(key.of(prot));
+        }
+    }
+    // Report keys as the accession of their first block, which is the
+    // accession of the combined row
+    let mut first = HashMap::new();
+    for prot in &data.proteins {
+        first.entry(key.of(prot)).or_insert(prot.accession.as_str());
+    }
+    order.into_iter().map(|k| first[&k].to_string()).collect()
+}
+
+/// Combine protein blocks that share an accession. Peptides are pooled, so
+/// that their evidence is summed during rollup, and spectral counts are
+/// summed. The first block's description and metadata are retained.
+pub fn merge(data: Dataset) -> Dataset {
+    merge_by(data, Key::Accession)
+}
+
+/// Combine protein blocks that share a `key`, as `merge` does for blocks
+/// sharing an accession. The first block's accession is retained.
+pub fn merge_by(data: Dataset, key: Key) -> Dataset {
+    let mut proteins: Vec<Protein> = Vec::with_capacity(data.proteins.len());
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for prot in data.proteins {
+        let k = key.of(&prot);
+        match index.get(&k) {
+            Some(&idx) => {
+                let merged = &mut proteins[idx];
+                merged.spectral_count += prot.spectral_count;
+                merged.peptides.extend(prot.peptides);
+            }
+            None => {
+                index.insert(k, proteins.len());
+                proteins.push(prot);
+            }
+        }
+    }
+
+    for prot in &mut proteins {
+        let mut seqs = prot
+            .peptides
+            .iter()
+            .map(|p| p.sequence.as_str())
+            .collect::<Vec<&str>>();
+        seqs.sort_unstable();
+        seqs.dedup();
+        prot.sequence_count = seqs.len() as u16;
+    }
+
+    Dataset {
+        proteins,
+        channels: data.channels,
+    }
+}
+
+/// Remove protein blocks identical to an earlier block, with the same
+/// accession, description, and PSMs, as when a census file was concatenated
+/// with itself. Returns the remaining dataset and the number of blocks
+/// removed.
+pub fn dedupe(data: Dataset) -> (Dataset, usize) {
+    let mut kept: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut proteins: Vec<Protein> = Vec::with_capacity(data.proteins.len());
+    let mut removed = 0;
+    for prot in data.proteins {
+        let earlier = kept.entry(prot.accession.clone()).or_default();
+        if earlier.iter().any(|&idx| proteins[idx] == prot) {
+            removed += 1;
+            continue;
+        }
+        earlier.push(proteins.len());
+        proteins.push(prot);
+    }
+    (
+        Dataset {
+            proteins,
+            channels: data.channels,
+        },
+        removed,
+    )
+}
+
+/// Apply a duplicate `policy` to `data`, where blocks sharing a `key` are
+/// duplicates. If no policy was chosen, duplicates are kept and a warning is
+/// added to `warnings`.
+pub fn apply(
+    data: Dataset,
+    policy: Option<Policy>,
+    key: Key,
+    warnings: &mut Vec<String>,
+) -> std::io::Result<Dataset> {
+    let dups = find_by(&data, key);
+    if dups.is_empty() {
+        return Ok(data);
+    }
+
+    let list = dups.iter().take(5).cloned().collect::<Vec<_>>().join(", ");
+    let more = if dups.len() > 5 { ", ..." } else { "" };
+    match policy {
+        Some(Policy::Error) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} {} appear in multiple protein blocks: {}{}",
+                dups.len(),
+                key.describe(),
+                list,
+                more
+            ),
+        )),
+        Some(Policy::Merge) => Ok(merge_by(data, key)),
+        Some(Policy::Keep) => Ok(data),
+        None => {
+            warnings.push(format!(
+                "{} {} appear in multiple protein blocks and will be output as duplicate rows ({}{}). Use --duplicates to choose how these are handled",
+                dups.len(),
+                key.describe(),
+                list,
+                more
+            ));
+            Ok(data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int
+P\tsp|P1|A_HUMAN\t1\t1\t10.0%\t100\t10000\tAlpha GN=ALPHA
+S\tU\tK.PEPTIDEK.R\t100\t0.5
+P\tP2\t1\t1\t10.0%\t100\t10000\tBeta
+S\tU\tK.BETAK.R\t5\t0.5
+P\tsp|P1|A_HUMAN\t2\t2\t10.0%\t100\t10000\tAlpha GN=ALPHA
+S\tU\tK.PEPTIDEK.R\t200\t0.5
+S\tU\tK.OTHERK.R\t300\t0.5
+P\tsp|P1-2|A_HUMAN\t1\t1\t10.0%\t100\t10000\tAlpha isoform GN=ALPHA
+S\tU\tK.ISOFORMK.R\t400\t0.5";
+
+    fn dataset() -> Dataset {
+        input::parse_census(FILE).unwrap()
+    }
+
+    fn accessions(data: &Dataset) -> Vec<&str> {
+        data.proteins.iter().map(|p| p.accession.as_str()).collect()
+    }
+
+    #[test]
+    fn find_duplicates() {
+        assert_eq!(find(&dataset()), ["sp|P1|A_HUMAN"]);
+        assert_eq!(find_by(&dataset(), Key::AccessionNoIso), ["sp|P1|A_HUMAN"]);
+        assert_eq!(find_by(&dataset(), Key::DescriptionHash), ["sp|P1|A_HUMAN"]);
+        assert!(find(&merge(dataset())).is_empty());
+    }
+
+    #[test]
+    fn merge_pools_peptides() {
+        let data = merge(dataset());
+        assert_eq!(
+            accessions(&data),
+            ["sp|P1|A_HUMAN", "P2", "sp|P1-2|A_HUMAN"]
+        );
+        let merged = &data.proteins[0];
+        assert_eq!(merged.spectral_count, 3);
+        assert_eq!(merged.sequence_count, 2);
+        assert_eq!(merged.peptides.len(), 3);
+
+        let data = merge_by(dataset(), Key::Gene);
+        assert_eq!(accessions(&data), ["sp|P1|A_HUMAN", "P2"]);
+        assert_eq!(data.proteins[0].sequence_count, 3);
+    }
+
+    #[test]
+    fn isoform_suffixes() {
+        assert_eq!(strip_isoform("P12345-2"), "P12345");
+        assert_eq!(strip_isoform("P12345"), "P12345");
+        assert_eq!(strip_isoform("HLA-A"), "HLA-A");
+        assert_eq!(strip_isoform("P12345-"), "P12345-");
+    }
+
+    #[test]
+    fn policies() {
+        let mut warnings = Vec::new();
+        let err = apply(
+            dataset(),
+            Some(Policy::Error),
+            Key::Accession,
+            &mut warnings,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "1 accessions appear in multiple protein blocks: sp|P1|A_HUMAN"
+        );
+
+        let kept = apply(dataset(), Some(Policy::Keep), Key::Accession, &mut warnings).unwrap();
+        assert_eq!(kept.proteins.len(), 4);
+        let merged = apply(
+            dataset(),
+            Some(Policy::Merge),
+            Key::Accession,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(merged.proteins.len(), 3);
+        assert!(warnings.is_empty());
+
+        let unset = apply(dataset(), None, Key::Accession, &mut warnings).unwrap();
+        assert_eq!(unset.proteins.len(), 4);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("--duplicates"));
+    }
+
+    #[test]
+    fn dedupe_identical_blocks() {
+        let doubled = format!(
+            "{}\n{}",
+            FILE,
+            FILE.lines().skip(1).collect::<Vec<_>>().join("\n")
+        );
+        let (data, removed) = dedupe(input::parse_census(&doubled).unwrap());
+        assert_eq!(removed, 4);
+        assert_eq!(data.proteins.len(), 4);
+    }
+}
@@ -0,0 +1,731 @@
+//! Interactive exploration of a census file in the terminal
+//!
+//! Proteins are listed with their channel intensities drawn as sparkline
+//! bars, and can be expanded to show their peptides. The rules of the
+//! filter given with `--filter` can be switched off and on, and the listing
+//! is updated immediately.
+//!
+//! From a terminal, `Screen` takes over the whole screen, and is driven by
+//! the arrow keys. Otherwise, as when commands are piped in, `Explorer::run`
+//! reads one command per line and prints each result below the last.
+use crate::terminal::{self, Key};
+use census2csv::filter::Filter;
+use census2csv::parallel;
+use census2csv::rollup::{self, Sum};
+use census_proteomics::{Dataset, Peptide, Protein};
+use std::collections::HashSet;
+use std::io::prelude::*;
+
+/// Proteins listed per page
+const PAGE: usize = 20;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+const HELP: &str = "\
+commands:
+  list [PAGE]        list proteins, a page at a time
+  next, prev         move to the next or previous page
+  show N|ACCESSION   expand a protein to show its peptides
+  find TEXT          list proteins whose accession or description contains TEXT
+  filters            list filter rules, and whether each is enabled
+  toggle N           switch filter rule N off or on
+  help               show this message
+  quit               exit";
+
+/// Sparkline of `values`, scaled to the largest value. Channels without
+/// any intensity are left blank.
+fn sparkline(values: &[f64]) -> String {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|&v| {
+            if v <= 0.0 || max <= 0.0 {
+                ' '
+            } else {
+                let level = (v / max * BARS.len() as f64).ceil() as usize;
+                BARS[level.clamp(1, BARS.len()) - 1]
+            }
+        })
+        .collect()
+}
+
+pub struct Explorer<'a> {
+    data: Dataset,
+    filter: Filter<'a>,
+    enabled: Vec<bool>,
+    threads: usize,
+    /// Proteins passing the enabled filter rules
+    view: Vec<Protein>,
+    page: usize,
+}
+
+impl<'a> Explorer<'a> {
+    pub fn new(data: Dataset, filter: Filter<'a>, threads: usize) -> Explorer<'a> {
+        let enabled = vec![true; filter.rules().len()];
+        let mut explorer = Explorer {
+            data,
+            filter,
+            enabled,
+            threads,
+            view: Vec::new(),
+            page: 0,
+        };
+        explorer.refilter();
+        explorer
+    }
+
+    fn refilter(&mut self) {
+        let copy = Dataset {
+            proteins: self.data.proteins.clone(),
+            channels: self.data.channels,
+        };
+        let filter = self.filter.select(&self.enabled);
+        self.view = parallel::filter(copy, &filter, self.threads).proteins;
+        self.page = self.page.min(self.pages().saturating_sub(1));
+    }
+
+    fn pages(&self) -> usize {
+        self.view.len().div_ceil(PAGE)
+    }
+
+    fn summary<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "{} of {} proteins pass {} of {} filter rules",
+            self.view.len(),
+            self.data.proteins.len(),
+            self.enabled.iter().filter(|&&e| e).count(),
+            self.enabled.len()
+        )
+    }
+
+    /// Listing of the protein at `idx` in the view
+    fn row(&self, idx: usize) -> String {
+        let prot = &self.view[idx];
+        format!(
+            "{:>5}  {:<16} {:>4} {:>4}  {}  {}",
+            idx + 1,
+            prot.accession,
+            prot.spectral_count,
+            prot.sequence_count,
+            sparkline(&rollup::protein(prot, &Sum)),
+            prot.description
+        )
+    }
+
+    fn line<W: Write>(&self, idx: usize, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "{}", self.row(idx))
+    }
+
+    fn list<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        if self.view.is_empty() {
+            return writeln!(out, "no proteins pass the enabled filter rules");
+        }
+        writeln!(
+            out,
+            "{:>5}  {:<16} {:>4} {:>4}  channels",
+            "#", "accession", "spec", "seq"
+        )?;
+        let start = self.page * PAGE;
+        for idx in start..(start + PAGE).min(self.view.len()) {
+            self.line(idx, out)?;
+        }
+        writeln!(out, "page {} of {}", self.page + 1, self.pages())
+    }
+
+    fn show<W: Write>(&self, key: &str, out: &mut W) -> std::io::Result<()> {
+        let found = match key.parse::<usize>() {
+            Ok(n) if n > 0 => self.view.get(n - 1),
+            _ => self.view.iter().find(|p| p.accession == key),
+        };
+        let prot = match found {
+            Some(prot) => prot,
+            None => return writeln!(out, "no protein {} passes the enabled filter rules", key),
+        };
+        writeln!(out, "{}  {}", prot.accession, prot.description)?;
+        writeln!(
+            out,
+            "spectral count {}, sequence count {}",
+            prot.spectral_count, prot.sequence_count
+        )?;
+        let totals = rollup::protein(prot, &Sum);
+        writeln!(out, "  {}  {}", sparkline(&totals), join(&totals))?;
+        for pep in &prot.peptides {
+            writeln!(out, "  {}", peptide(pep))?;
+        }
+        Ok(())
+    }
+
+    fn find<W: Write>(&self, text: &str, out: &mut W) -> std::io::Result<()> {
+        let text = text.to_lowercase();
+        let mut any = false;
+        for (idx, prot) in self.view.iter().enumerate() {
+            if matches(prot, &text) {
+                self.line(idx, out)?;
+                any = true;
+            }
+        }
+        if !any {
+            writeln!(out, "no matching proteins")?;
+        }
+        Ok(())
+    }
+
+    fn filters<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let rules = self.filter.rules();
+        if rules.is_empty() {
+            return writeln!(out, "no filter rules, use --filter to load a filter file");
+        }
+        for (idx, (rule, on)) in rules.iter().zip(&self.enabled).enumerate() {
+            writeln!(
+                out,
+                "{:>3} [{}] {}",
+                idx + 1,
+                if *on { 'x' } else { ' ' },
+                rule
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Run commands read from `input`, writing a `> ` prompt before each if
+    /// `prompt` is true, as when reading from a terminal
+    pub fn run<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        mut out: W,
+        prompt: bool,
+    ) -> std::io::Result<()> {
+        self.summary(&mut out)?;
+        writeln!(out, "type help for a list of commands")?;
+        if prompt {
+            write!(out, "> ")?;
+        }
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let (cmd, arg) = match line.trim().split_once(char::is_whitespace) {
+                Some((cmd, arg)) => (cmd, arg.trim()),
+                None => (line.trim(), ""),
+            };
+            match cmd {
+                "" => {}
+                "list" | "l" => {
+                    if let Ok(n) = arg.parse::<usize>() {
+                        self.page = n.clamp(1, self.pages().max(1)) - 1;
+                    }
+                    self.list(&mut out)?;
+                }
+                "next" | "n" => {
+                    self.page = (self.page + 1).min(self.pages().saturating_sub(1));
+                    self.list(&mut out)?;
+                }
+                "prev" | "p" => {
+                    self.page = self.page.saturating_sub(1);
+                    self.list(&mut out)?;
+                }
+                "show" | "s" => self.show(arg, &mut out)?,
+                "find" | "f" => self.find(arg, &mut out)?,
+                "filters" => self.filters(&mut out)?,
+                "toggle" | "t" => match arg.parse::<usize>() {
+                    Ok(n) if n > 0 && n <= self.enabled.len() => {
+                        self.enabled[n - 1] = !self.enabled[n - 1];
+                        self.refilter();
+                        self.filters(&mut out)?;
+                        self.summary(&mut out)?;
+                    }
+                    _ => writeln!(out, "expected a rule number, see filters")?,
+                },
+                "help" | "h" | "?" => writeln!(out, "{}", HELP)?,
+                "quit" | "q" | "exit" => return Ok(()),
+                _ => writeln!(out, "unknown command {}, type help for a list", cmd)?,
+            }
+            if prompt {
+                write!(out, "> ")?;
+            }
+            out.flush()?;
+        }
+        if prompt {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Does the accession or description of `prot` contain `text`, which is
+/// lowercase?
+fn matches(prot: &Protein, text: &str) -> bool {
+    prot.accession.to_lowercase().contains(text) || prot.description.to_lowercase().contains(text)
+}
+
+/// Listing of a peptide, below its protein
+fn peptide(pep: &Peptide) -> String {
+    let values = pep.values.iter().map(|&v| v as f64).collect::<Vec<f64>>();
+    format!(
+        "{}  {}{}  {}",
+        sparkline(&values),
+        pep.sequence,
+        if pep.unique { "" } else { " (shared)" },
+        join(&values)
+    )
+}
+
+fn join(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+const KEYS: &str = "\
+keys:
+  up, down, k, j         select the previous or next row
+  page up, page down     move a screen at a time
+  home, end, g, G        select the first or last row
+  enter, right, space    expand or collapse the selected protein
+  left, h                collapse the selected protein
+  /                      find proteins whose accession or description contains text
+  f, tab                 switch filter rules off and on
+  esc                    clear the search, or quit
+  ?                      show or hide this message
+  q                      quit";
+
+/// What keys act on
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Proteins,
+    /// Typing the text to find
+    Search,
+    Filters,
+}
+
+/// A row of the protein list: the index of a protein in the view, and the
+/// index of one of its peptides for the rows of an expanded protein
+type Entry = (usize, Option<usize>);
+
+/// Full-screen interface to an `Explorer`
+pub struct Screen<'a> {
+    explorer: Explorer<'a>,
+    mode: Mode,
+    /// Selected row of the protein list, and the first row shown
+    cursor: usize,
+    top: usize,
+    /// Accessions of the expanded proteins
+    expanded: HashSet<String>,
+    /// Text that listed proteins must contain
+    query: String,
+    /// Selected filter rule
+    rule: usize,
+    help: bool,
+}
+
+impl<'a> Screen<'a> {
+    pub fn new(explorer: Explorer<'a>) -> Screen<'a> {
+        Screen {
+            explorer,
+            mode: Mode::Proteins,
+            cursor: 0,
+            top: 0,
+            expanded: HashSet::new(),
+            query: String::new(),
+            rule: 0,
+            help: false,
+        }
+    }
+
+    /// Rows of the protein list, with the peptides of expanded proteins
+    fn entries(&self) -> Vec<Entry> {
+        let query = self.query.to_lowercase();
+        let mut entries = Vec::new();
+        for (idx, prot) in self.explorer.view.iter().enumerate() {
+            if !matches(prot, &query) {
+                continue;
+            }
+            entries.push((idx, None));
+            if self.expanded.contains(&prot.accession) {
+                entries.extend((0..prot.peptides.len()).map(|pep| (idx, Some(pep))));
+            }
+        }
+        entries
+    }
+
+    /// Accession of the protein of the selected row
+    fn selected(&self) -> Option<String> {
+        let entries = self.entries();
+        let (idx, _) = entries.get(self.cursor.min(entries.len().saturating_sub(1)))?;
+        Some(self.explorer.view[*idx].accession.clone())
+    }
+
+    /// Select the row of the protein `accession`, if it is listed
+    fn select(&mut self, accession: Option<String>) {
+        let view = &self.explorer.view;
+        if let Some(pos) = self.entries().iter().position(|(idx, pep)| {
+            pep.is_none() && Some(&view[*idx].accession) == accession.as_ref()
+        }) {
+            self.cursor = pos;
+        }
+    }
+
+    /// Handle a key press, where a page is `page` rows. Returns false once
+    /// the user has quit.
+    pub fn key(&mut self, key: Key, page: usize) -> bool {
+        if key == Key::Interrupt {
+            return false;
+        }
+        match self.mode {
+            Mode::Search => {
+                match key {
+                    Key::Char(c) => self.query.push(c),
+                    Key::Backspace => {
+                        self.query.pop();
+                    }
+                    Key::Enter | Key::Tab | Key::Down => self.mode = Mode::Proteins,
+                    Key::Esc => {
+                        self.query.clear();
+                        self.mode = Mode::Proteins;
+                    }
+                    _ => {}
+                }
+                self.cursor = 0;
+            }
+            Mode::Filters => {
+                let rules = self.explorer.enabled.len();
+                match key {
+                    Key::Up | Key::Char('k') => self.rule = self.rule.saturating_sub(1),
+                    Key::Down | Key::Char('j') => {
+                        self.rule = (self.rule + 1).min(rules.saturating_sub(1))
+                    }
+                    Key::Enter | Key::Char(' ') | Key::Char('t') if self.rule < rules => {
+                        let selected = self.selected();
+                        self.explorer.enabled[self.rule] = !self.explorer.enabled[self.rule];
+                        self.explorer.refilter();
+                        self.cursor = 0;
+                        self.select(selected);
+                    }
+                    Key::Esc | Key::Tab | Key::Char('f') | Key::Char('q') => {
+                        self.mode = Mode::Proteins
+                    }
+                    _ => {}
+                }
+            }
+            Mode::Proteins => {
+                let entries = self.entries();
+                let last = entries.len().saturating_sub(1);
+                match key {
+                    Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+                    Key::Down | Key::Char('j') => self.cursor = (self.cursor + 1).min(last),
+                    Key::PageUp => self.cursor = self.cursor.saturating_sub(page),
+                    Key::PageDown => self.cursor = (self.cursor + page).min(last),
+                    Key::Home | Key::Char('g') => self.cursor = 0,
+                    Key::End | Key::Char('G') => self.cursor = last,
+                    Key::Enter | Key::Right | Key::Char('l') | Key::Char(' ') => {
+                        if let Some(accession) = self.selected() {
+                            if !self.expanded.remove(&accession) {
+                                self.expanded.insert(accession.clone());
+                            }
+                            self.select(Some(accession));
+                        }
+                    }
+                    Key::Left | Key::Char('h') => {
+                        if let Some(accession) = self.selected() {
+                            self.expanded.remove(&accession);
+                            self.select(Some(accession));
+                        }
+                    }
+                    Key::Char('/') => self.mode = Mode::Search,
+                    Key::Char('f') | Key::Tab => self.mode = Mode::Filters,
+                    Key::Char('?') => self.help = !self.help,
+                    Key::Esc if !self.query.is_empty() => {
+                        let selected = self.selected();
+                        self.query.clear();
+                        self.select(selected);
+                    }
+                    Key::Esc | Key::Char('q') => return false,
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+
+    /// Lines of a screen `height` lines high, with the selected row
+    /// highlighted. Lines are cut to the width of the screen when drawn.
+    pub fn render(&mut self, height: usize) -> Vec<(bool, String)> {
+        let explorer = &self.explorer;
+        let body = height.saturating_sub(3).max(1);
+        let mut summary = Vec::new();
+        explorer.summary(&mut summary).unwrap_or_default();
+        let mut summary = String::from_utf8_lossy(&summary).trim_end().to_string();
+        if !self.query.is_empty() {
+            summary.push_str(&format!(", listing those matching \"{}\"", self.query));
+        }
+        let mut lines = vec![(false, summary)];
+
+        if self.help {
+            lines.extend(KEYS.lines().map(|l| (false, l.to_string())));
+        } else if self.mode == Mode::Filters {
+            lines.push((
+                false,
+                "filter rules: space switches a rule off or on, esc returns".into(),
+            ));
+            let rules = explorer.filter.rules();
+            if rules.is_empty() {
+                lines.push((
+                    false,
+                    "no filter rules, use --filter to load a filter file".into(),
+                ));
+            }
+            let start = self.rule.saturating_sub(body - 1);
+            for (idx, (rule, on)) in rules
+                .iter()
+                .zip(&explorer.enabled)
+                .enumerate()
+                .skip(start)
+                .take(body)
+            {
+                let line = format!("{:>3} [{}] {}", idx + 1, if *on { 'x' } else { ' ' }, rule);
+                lines.push((idx == self.rule, line));
+            }
+        } else {
+            lines.push((
+                false,
+                format!(
+                    "{:>5}  {:<16} {:>4} {:>4}  channels",
+                    "#", "accession", "spec", "seq"
+                ),
+            ));
+            let entries = self.entries();
+            self.cursor = self.cursor.min(entries.len().saturating_sub(1));
+            // Scroll just far enough to show the selected row
+            if self.cursor < self.top {
+                self.top = self.cursor;
+            } else if self.cursor >= self.top + body {
+                self.top = self.cursor + 1 - body;
+            }
+            self.top = self.top.min(entries.len().saturating_sub(body));
+            if entries.is_empty() {
+                lines.push((
+                    false,
+                    match self.query.is_empty() {
+                        true => "no proteins pass the enabled filter rules".into(),
+                        false => "no matching proteins".into(),
+                    },
+                ));
+            }
+            for (pos, (idx, pep)) in entries.iter().enumerate().skip(self.top).take(body) {
+                let prot = &explorer.view[*idx];
+                let line = match pep {
+                    Some(pep) => format!("{:>7}  {}", "", peptide(&prot.peptides[*pep])),
+                    None => explorer.row(*idx),
+                };
+                lines.push((pos == self.cursor, line));
+            }
+        }
+
+        lines.resize(height.saturating_sub(1).max(1), (false, String::new()));
+        lines.truncate(height.saturating_sub(1).max(1));
+        let status = match self.mode {
+            Mode::Search => format!("find: {}_", self.query),
+            _ => "arrows move  enter expands  / finds  f filters  ? keys  q quits".into(),
+        };
+        lines.push((true, status));
+        lines
+    }
+
+    /// Draw the screen and handle key presses until the user quits
+    #[cfg(unix)]
+    pub fn run(mut self) -> std::io::Result<()> {
+        let _raw = terminal::Raw::enable()?;
+        let stdin = std::io::stdin();
+        let mut input = stdin.lock();
+        let mut out = std::io::stdout().lock();
+        let mut buf = [0u8; 256];
+        let mut drawn = None;
+        let mut dirty = true;
+        loop {
+            let (width, height) = terminal::size().unwrap_or((80, 24));
+            if dirty || drawn != Some((width, height)) {
+                terminal::draw(&mut out, &self.render(height), width)?;
+                drawn = Some((width, height));
+                dirty = false;
+            }
+            let n = match input.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            for key in terminal::keys(&buf[..n]) {
+                if !self.key(key, height.saturating_sub(3).max(1)) {
+                    return Ok(());
+                }
+                dirty = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use census2csv::filter::ProteinFilter;
+    use census2csv::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t3\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t200\t0.5
+S\t\tK.SHAREDK.L\t1000\t0.5\t2000\t0.5
+S\tU\tR.SEQUENCER.A\t300\t0.5\t400\t0.5
+P\tQ67890\t1\t1\t10.0%\t100\t10000\tKeratin
+S\tU\tK.KERATINK.R\t5\t0.5\t0\t0.5";
+
+    fn screen() -> Screen<'static> {
+        let data = input::parse_census(FILE).unwrap();
+        let filter = Filter::default().add_protein_filter(ProteinFilter::SpectralCounts(3));
+        Screen::new(Explorer::new(data, filter, 1))
+    }
+
+    /// Rows of the rendered list, marking the selected one with `>`
+    fn rows(screen: &mut Screen, height: usize) -> Vec<String> {
+        screen
+            .render(height)
+            .into_iter()
+            .skip(2)
+            .take(height - 3)
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(selected, line)| {
+                format!("{}{}", if selected { ">" } else { " " }, line.trim_end())
+            })
+            .collect()
+    }
+
+    fn press(screen: &mut Screen, keys: &[Key]) -> bool {
+        keys.iter().all(|&key| screen.key(key, 2))
+    }
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[0.0, 50.0, 100.0, 1.0]), " ▄█▁");
+        assert_eq!(sparkline(&[0.0, 0.0]), "  ");
+    }
+
+    #[test]
+    fn expand_proteins() {
+        let mut screen = screen();
+        let lines = screen.render(8);
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0].1, "1 of 2 proteins pass 1 of 1 filter rules");
+        assert!(lines[7].0, "the status line is highlighted");
+        assert_eq!(
+            rows(&mut screen, 8),
+            [">    1  P12345              3    3  ▅█  Serum albumin"]
+        );
+
+        assert!(press(&mut screen, &[Key::Enter, Key::Down]));
+        assert_eq!(
+            rows(&mut screen, 8),
+            [
+                "     1  P12345              3    3  ▅█  Serum albumin",
+                ">         ▄█  K.PEPTIDEK.L  100 200",
+                "          ▄█  K.SHAREDK.L (shared)  1000 2000",
+                "          ▆█  R.SEQUENCER.A  300 400",
+            ]
+        );
+        // Only as many rows as fit are shown, scrolled to the selection
+        assert!(press(&mut screen, &[Key::End]));
+        assert_eq!(
+            rows(&mut screen, 5),
+            [
+                "          ▄█  K.SHAREDK.L (shared)  1000 2000",
+                ">         ▆█  R.SEQUENCER.A  300 400"
+            ]
+        );
+        // Collapsing from a peptide selects its protein
+        assert!(press(&mut screen, &[Key::Left]));
+        assert_eq!(
+            rows(&mut screen, 8),
+            [">    1  P12345              3    3  ▅█  Serum albumin"]
+        );
+        assert!(!press(&mut screen, &[Key::Char('q')]));
+        assert!(!press(&mut self::screen(), &[Key::Interrupt]));
+    }
+
+    #[test]
+    fn toggle_filters_and_find() {
+        let mut screen = screen();
+        assert!(press(&mut screen, &[Key::Char('f')]));
+        let lines = screen.render(8);
+        assert_eq!(lines[2], (true, "  1 [x] SpectralCounts(3)".to_string()));
+        assert!(press(&mut screen, &[Key::Char(' '), Key::Esc, Key::Down]));
+        assert_eq!(
+            screen.render(8)[0].1,
+            "2 of 2 proteins pass 0 of 1 filter rules"
+        );
+        assert_eq!(
+            rows(&mut screen, 8),
+            [
+                "     1  P12345              3    3  ▅█  Serum albumin",
+                ">    2  Q67890              1    1  █   Keratin",
+            ]
+        );
+
+        // Searching narrows the list as each character is typed
+        assert!(press(
+            &mut screen,
+            &[Key::Char('/'), Key::Char('S'), Key::Char('e')]
+        ));
+        assert_eq!(screen.render(8)[7].1, "find: Se_");
+        assert_eq!(
+            rows(&mut screen, 8),
+            [">    1  P12345              3    3  ▅█  Serum albumin"]
+        );
+        assert!(press(&mut screen, &[Key::Char('x')]));
+        assert_eq!(rows(&mut screen, 8), [" no matching proteins"]);
+        assert!(press(
+            &mut screen,
+            &[
+                Key::Backspace,
+                Key::Backspace,
+                Key::Backspace,
+                Key::Char('k'),
+                Key::Enter
+            ]
+        ));
+        assert_eq!(
+            rows(&mut screen, 8),
+            [">    2  Q67890              1    1  █   Keratin"]
+        );
+        // Esc clears the search before it quits
+        assert!(press(&mut screen, &[Key::Esc]));
+        assert_eq!(rows(&mut screen, 8).len(), 2);
+        assert!(!press(&mut screen, &[Key::Esc]));
+    }
+
+    #[test]
+    fn line_commands() {
+        let data = input::parse_census(FILE).unwrap();
+        let filter = Filter::default().add_protein_filter(ProteinFilter::SpectralCounts(3));
+        let mut out = Vec::new();
+        Explorer::new(data, filter, 1)
+            .run(
+                &b"list\ntoggle 1\nshow Q67890\nfind albumin\nbogus\nquit\nlist\n"[..],
+                &mut out,
+                false,
+            )
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("1 of 2 proteins pass 1 of 1 filter rules\n"));
+        assert!(
+            out.contains("  1 [ ] SpectralCounts(3)\n2 of 2 proteins pass 0 of 1 filter rules\n")
+        );
+        assert!(out.contains("Q67890  Keratin\nspectral count 1, sequence count 1\n"));
+        assert!(out.contains("  █   K.KERATINK.R  5 0\n"));
+        assert!(out.contains("unknown command bogus"));
+        assert_eq!(
+            out.matches("page 1 of 1").count(),
+            1,
+            "commands after quit are ignored"
+        );
+    }
+}
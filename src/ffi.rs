@@ -0,0 +1,363 @@
+//! C ABI for linking census2csv into other applications
+//!
+//! `census_read` parses and filters a file, returning a table of flat arrays
+//! that remain valid until the table is passed to `census_free`. On failure,
+//! a null pointer is returned and `census_last_error` describes the error.
+//! `census_read_arrow` instead exports a typed record batch through the
+//! Arrow C data interface, for consumers such as pyarrow and polars.
+//! See include/census2csv.h for the C declarations.
+use crate::arrow::{ArrowArray, ArrowSchema, RecordBatch};
+use crate::convert::Table;
+use crate::filter::Filter;
+use crate::input::{self, InputFormat};
+use crate::rollup;
+use census_proteomics::Dataset;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Parsed and filtered data. PSM arrays have `psm_count` entries, and the
+/// `psm_values` matrix is stored row-major with `channels` values per PSM.
+/// Protein values are summed across PSMs.
+#[repr(C)]
+pub struct CensusTable {
+    pub channels: usize,
+    pub psm_count: usize,
+    pub psm_accessions: *const *const c_char,
+    pub psm_sequences: *const *const c_char,
+    pub psm_values: *const u32,
+    pub protein_count: usize,
+    pub protein_accessions: *const *const c_char,
+    pub protein_values: *const f64,
+}
+
+/// Storage backing the pointers of a `CensusTable`, which must be the first
+/// field so that the two can be converted
+#[repr(C)]
+struct Owned {
+    table: CensusTable,
+    strings: Vec<CString>,
+    psm_accessions: Vec<*const c_char>,
+    psm_sequences: Vec<*const c_char>,
+    psm_values: Vec<u32>,
+    protein_accessions: Vec<*const c_char>,
+    protein_values: Vec<f64>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error<S: Into<String>>(msg: S) {
+    let msg = CString::new(msg.into().replace('\0', " ")).unwrap_or_default();
+    // Keep going if the thread is exiting and its error has been destroyed
+    let _ = LAST_ERROR.try_with(|e| *e.borrow_mut() = msg);
+}
+
+/// Run `f`, returning a panic as an error rather than unwinding into the
+/// caller, which is undefined behavior across the C ABI
+fn guard<T, F: FnOnce() -> Result<T, String>>(f: F) -> Result<T, String> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(payload) => {
+            let msg = match payload.downcast_ref::<String>() {
+                Some(s) => s.as_str(),
+                None => payload.downcast_ref::<&str>().copied().unwrap_or("unknown"),
+            };
+            Err(format!("census2csv panicked: {}", msg))
+        }
+    }
+}
+
+/// Read an optional C string argument
+unsafe fn arg<'a>(s: *const c_char) -> Result<Option<&'a str>, String> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(Some)
+        .map_err(|_| "argument is not valid UTF-8".to_string())
+}
+
+fn c_string(strings: &mut Vec<CString>, s: &str) -> *const c_char {
+    let s = CString::new(s.replace('\0', " ")).unwrap_or_default();
+    // The heap allocation of a CString does not move when it is pushed
+    let ptr = s.as_ptr();
+    strings.push(s);
+    ptr
+}
+
+fn read_filtered(
+    path: &str,
+    format: Option<&str>,
+    filter: Option<&str>,
+) -> Result<Dataset, String> {
+    let format = match format {
+        Some(f) => f.parse::<InputFormat>()?,
+        None => InputFormat::Census,
+    };
+    let mut data = input::read(path, format).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(json) = filter {
+        let filter =
+            serde_json::from_str::<Filter>(json).map_err(|e| format!("invalid filter: {}", e))?;
+        data = filter.filter_dataset(data);
+    }
+    Ok(data)
+}
+
+fn read(path: &str, format: Option<&str>, filter: Option<&str>) -> Result<Box<Owned>, String> {
+    let data = read_filtered(path, format, filter)?;
+
+    let mut strings = Vec::new();
+    let (mut psm_accessions, mut psm_sequences, mut psm_values) =
+        (Vec::new(), Vec::new(), Vec::new());
+    let (mut protein_accessions, mut protein_values) = (Vec::new(), Vec::new());
+    for prot in &data.proteins {
+        let acc = c_string(&mut strings, &prot.accession);
+        protein_accessions.push(acc);
+        protein_values.extend(rollup::protein(prot, &rollup::Sum));
+        for pep in &prot.peptides {
+            psm_accessions.push(acc);
+            psm_sequences.push(c_string(&mut strings, &pep.sequence));
+            psm_values.extend_from_slice(&pep.values);
+        }
+    }
+
+    let mut owned = Box::new(Owned {
+        table: CensusTable {
+            channels: data.channels as usize,
+            psm_count: psm_sequences.len(),
+            psm_accessions: ptr::null(),
+            psm_sequences: ptr::null(),
+            psm_values: ptr::null(),
+            protein_count: protein_accessions.len(),
+            protein_accessions: ptr::null(),
+            protein_values: ptr::null(),
+        },
+        strings,
+        psm_accessions,
+        psm_sequences,
+        psm_values,
+        protein_accessions,
+        protein_values,
+    });
+    owned.table.psm_accessions = owned.psm_accessions.as_ptr();
+    owned.table.psm_sequences = owned.psm_sequences.as_ptr();
+    owned.table.psm_values = owned.psm_values.as_ptr();
+    owned.table.protein_accessions = owned.protein_accessions.as_ptr();
+    owned.table.protein_values = owned.protein_values.as_ptr();
+    Ok(owned)
+}
+
+/// Read `path`, in `input_format` (or census, if null), and apply the JSON
+/// filter `filter_json` (if not null)
+///
+/// # Safety
+///
+/// Each argument must be null or a valid NUL-terminated string, and `path`
+/// must not be null.
+#[no_mangle]
+pub unsafe extern "C" fn census_read(
+    path: *const c_char,
+    input_format: *const c_char,
+    filter_json: *const c_char,
+) -> *mut CensusTable {
+    let res = guard(|| {
+        let path = arg(path)?.ok_or_else(|| "path is null".to_string())?;
+        read(path, arg(input_format)?, arg(filter_json)?)
+    });
+    match res {
+        Ok(owned) => Box::into_raw(owned) as *mut CensusTable,
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Read and filter `path` as for `census_read`, and export `table`
+/// (protein, protein-average, or psm; protein if null) as an Arrow record
+/// batch into `array` and `schema`. Returns 0 on success, and -1 on failure.
+///
+/// # Safety
+///
+/// String arguments must be as for `census_read`. `array` and `schema` must
+/// be valid for writes, and on success are owned by the caller, who must
+/// call their release callbacks.
+#[no_mangle]
+pub unsafe extern "C" fn census_read_arrow(
+    path: *const c_char,
+    input_format: *const c_char,
+    filter_json: *const c_char,
+    table: *const c_char,
+    array: *mut ArrowArray,
+    schema: *mut ArrowSchema,
+) -> c_int {
+    let res = guard(|| {
+        let path = arg(path)?.ok_or_else(|| "path is null".to_string())?;
+        let table = arg(table)?.unwrap_or("protein").parse::<Table>()?;
+        let data = read_filtered(path, arg(input_format)?, arg(filter_json)?)?;
+        Ok(RecordBatch::from(&table.frame(&data)).export())
+    });
+    match res {
+        Ok((a, s)) => {
+            ptr::write(array, a);
+            ptr::write(schema, s);
+            0
+        }
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Description of the last error on this thread. The string is valid until
+/// the next call to `census_read` or `census_read_arrow` that fails, and is
+/// null if no error can be reported.
+#[no_mangle]
+pub extern "C" fn census_last_error() -> *const c_char {
+    guard(|| {
+        LAST_ERROR
+            .try_with(|e| e.borrow().as_ptr())
+            .map_err(|e| e.to_string())
+    })
+    .unwrap_or(ptr::null())
+}
+
+/// Release a table returned by `census_read`
+///
+/// # Safety
+///
+/// `table` must be null, or a pointer returned by `census_read` that has not
+/// already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn census_free(table: *mut CensusTable) {
+    if !table.is_null() {
+        if let Err(e) = guard(|| {
+            drop(Box::from_raw(table as *mut Owned));
+            Ok(())
+        }) {
+            set_error(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+    use std::mem::MaybeUninit;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t2\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t300\t0.5
+S\tU\tK.PEPTIDER.L\t200\t0.5\t100\t0.5
+P\tQ67890\t1\t1\t5.0%\t10\t1000\tKeratin
+S\tU\tK.KERATINK.R\t7\t0.5\t8\t0.5";
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(census_last_error()) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn read_table() {
+        let dir = fixture::dir("ffi-read", &[("census.txt", FILE)]);
+        let path = CString::new(dir.join("census.txt").to_str().unwrap()).unwrap();
+        let filter =
+            CString::new(r#"{"peptide_filters": [], "protein_filters": [{"SpectralCounts": 2}]}"#)
+                .unwrap();
+        unsafe {
+            let table = census_read(path.as_ptr(), ptr::null(), filter.as_ptr());
+            assert!(!table.is_null());
+            let t = &*table;
+            assert_eq!((t.channels, t.psm_count, t.protein_count), (2, 2, 1));
+            let values = std::slice::from_raw_parts(t.psm_values, 4);
+            assert_eq!(values, [100, 300, 200, 100]);
+            let sequence = CStr::from_ptr(*t.psm_sequences.add(1));
+            assert_eq!(sequence.to_str(), Ok("K.PEPTIDER.L"));
+            let accession = CStr::from_ptr(*t.protein_accessions);
+            assert_eq!(accession.to_str(), Ok("P12345"));
+            let sums = std::slice::from_raw_parts(t.protein_values, 2);
+            assert_eq!(sums, [300.0, 400.0]);
+            census_free(table);
+            census_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn read_arrow() {
+        let dir = fixture::dir("ffi-arrow", &[("census.txt", FILE)]);
+        let path = CString::new(dir.join("census.txt").to_str().unwrap()).unwrap();
+        let psm = CString::new("psm").unwrap();
+        let mut array = MaybeUninit::<ArrowArray>::uninit();
+        let mut schema = MaybeUninit::<ArrowSchema>::uninit();
+        unsafe {
+            let ret = census_read_arrow(
+                path.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                psm.as_ptr(),
+                array.as_mut_ptr(),
+                schema.as_mut_ptr(),
+            );
+            assert_eq!(ret, 0);
+            let (mut array, mut schema) = (array.assume_init(), schema.assume_init());
+            assert_eq!(CStr::from_ptr(schema.format).to_str(), Ok("+s"));
+            assert_eq!(array.length, 3);
+            assert_eq!(array.n_children, schema.n_children);
+            let names = std::slice::from_raw_parts(schema.children, schema.n_children as usize)
+                .iter()
+                .map(|&c| CStr::from_ptr((*c).name).to_str().unwrap())
+                .collect::<Vec<_>>();
+            let sequence = names.iter().position(|n| *n == "sequence").unwrap();
+            let column = &**array.children.add(sequence);
+            let buffers = std::slice::from_raw_parts(column.buffers, 3);
+            let offsets = std::slice::from_raw_parts(buffers[1] as *const i32, 4);
+            let data = std::slice::from_raw_parts(buffers[2] as *const u8, offsets[3] as usize);
+            assert_eq!(&data[..offsets[1] as usize], b"K.PEPTIDEK.L");
+            (array.release.unwrap())(&mut array);
+            (schema.release.unwrap())(&mut schema);
+            assert!(array.release.is_none() && schema.release.is_none());
+
+            let table = CString::new("peptide").unwrap();
+            let ret = census_read_arrow(
+                path.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                table.as_ptr(),
+                &mut array,
+                &mut schema,
+            );
+            assert_eq!(ret, -1);
+            assert!(last_error().contains("peptide"));
+        }
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let missing = CString::new("/nonexistent/census.txt").unwrap();
+        unsafe {
+            assert!(census_read(ptr::null(), ptr::null(), ptr::null()).is_null());
+            assert_eq!(last_error(), "path is null");
+            assert!(census_read(missing.as_ptr(), ptr::null(), ptr::null()).is_null());
+            assert!(last_error().starts_with("/nonexistent/census.txt: "));
+        }
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let res = guard::<(), _>(|| panic!("bad {}", "input"));
+        assert_eq!(res, Err("census2csv panicked: bad input".to_string()));
+        let res = guard::<(), _>(|| std::panic::panic_any(1));
+        assert_eq!(res, Err("census2csv panicked: unknown".to_string()));
+        assert_eq!(guard(|| Ok(1)), Ok(1));
+    }
+}
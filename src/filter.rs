@@ -0,0 +1,778 @@
+//! Composable protein and peptide filters
+//!
+//! `Filter` reads the same filter.json files as `census_proteomics::Filter`,
+//! and applies its rules in the same way, while adding rules that the
+//! census_proteomics filter does not support. Its rule enums cannot be
+//! extended from outside that crate, and its rules are applied in a fixed
+//! order by `filter_protein`, so the filter is reimplemented here.
+use crate::presets::Preset;
+use crate::rollup;
+use crate::sequence;
+use census_proteomics::{util, Dataset, Peptide, Protein};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::mem::discriminant;
+use std::path::Path;
+
+/// Protein-level filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProteinFilter<'a> {
+    /// Include only proteins that have spectral counts >= N
+    SpectralCounts(u16),
+    /// Include only proteins that have sequence counts >= N
+    SequenceCounts(u16),
+    /// Include only proteins that do not have "Reverse" in their
+    /// UniProt accession
+    ExcludeReverse,
+    /// Include only decoy proteins, which have "Reverse" in their UniProt
+    /// accession
+    OnlyReverse,
+    /// Include only proteins that are not in a curated list of common
+    /// contaminants, such as `"affinity_purification"`
+    ExcludePreset(Preset),
+    /// Include only proteins whose intensity, summed across all channels of
+    /// the PSMs passing peptide filters, is >= N
+    TotalIntensity(u64),
+    /// Retain only the N PSMs with the highest total intensity, so that
+    /// rollups aggregate the best quantified peptides. Proteins are not
+    /// removed, and sequence and spectral count filters are evaluated
+    /// beforehand.
+    TopNPeptidesByIntensity(usize),
+    /// Include only proteins with at least `n` distinct peptide sequences
+    /// that pass `filter`, among the PSMs passing peptide filters. Unlike a
+    /// peptide filter, PSMs failing `filter` are not removed.
+    MinPeptidesPassing {
+        #[serde(borrow)]
+        filter: PeptideFilter<'a>,
+        n: usize,
+    },
+}
+
+/// Peptide-level filter
+///
+/// Filter individual peptides within a protein based on sequence matches,
+/// total intensities, coefficient of variance between channels, or intensity
+/// values on specified channels.
+///
+/// Peptide can also be filtered based on whether they have 2 tryptic ends,
+/// or if they are unique.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PeptideFilter<'a> {
+    /// Include only peptides that have a sequence matching the pattern
+    SequenceMatch(&'a str),
+    /// Include only peptides that do NOT have a sequence matching the pattern
+    SequenceExclude(&'a str),
+    /// Include only peptides that have a total ion itensity >= N
+    TotalIntensity(u32),
+
+    /// Include only peptides where the total intensity in a set of channels
+    /// >= N
+    TotalIntensityChannels(Vec<usize>, u32),
+
+    /// ChannelCV(channels, N)
+    ///
+    /// Include only peptides where the coeff. of variance is < N between
+    /// the specified channels
+    ChannelCV(Vec<usize>, f64),
+
+    /// ChannelIntensity(channel, cutoff)
+    ///
+    /// Include only peptides that have an ion intensity >= N
+    /// in the specified channel
+    ChannelIntensity(usize, u32),
+
+    /// Include only peptides where the mean ion intensity across channels is
+    /// >= N, which unlike `TotalIntensity` does not depend on plex size
+    MeanIntensity(f64),
+
+    /// Include only peptides where no channel has an ion intensity > N,
+    /// excluding PSMs with detector-saturated reporter ions
+    MaxChannelIntensity(u32),
+
+    /// TMT purity
+    Purity(f32),
+
+    /// Include only tryptic peptides
+    Tryptic,
+    /// Include only unique peptides
+    Unique,
+    /// Include only peptides whose sequence is listed under at most N
+    /// proteins of the dataset, as census_out files list a shared peptide
+    /// under each protein it matches. 1 keeps only unique peptides, while
+    /// larger values keep peptides shared within small protein families.
+    MaxProteinMatches(usize),
+    /// Include only PSMs with a q-value <= N, from rescoring results such
+    /// as those of Percolator given to `with_psm_qvalues`. PSMs without a
+    /// q-value are excluded.
+    MaxPsmQValue(f64),
+    /// ModMassRange(min, max)
+    ///
+    /// Include only peptides carrying a modification with a mass shift from
+    /// min to max Da, inclusive, such as glycan masses of several hundred to
+    /// a few thousand Da for glycopeptides
+    ModMassRange(f64, f64),
+}
+
+/// Value of the 1-indexed `channel` of `peptide`, if it exists
+fn channel_value(peptide: &Peptide, channel: usize) -> Option<u32> {
+    peptide.values.get(channel.wrapping_sub(1)).copied()
+}
+
+/// Set the spectral and sequence counts of `protein` from its peptides
+pub fn recount(protein: &mut Protein) {
+    protein.spectral_count = protein.peptides.len() as u16;
+    protein.sequence_count = protein
+        .peptides
+        .iter()
+        .map(|pep| &pep.sequence)
+        .collect::<HashSet<_>>()
+        .len() as u16;
+}
+
+/// Remove all but the `n` peptides of `protein` with the highest total
+/// intensity, keeping the earlier of any ties, and preserving order
+fn top_peptides(protein: &mut Protein, n: usize) {
+    if protein.peptides.len() <= n {
+        return;
+    }
+    let mut ranked = protein
+        .peptides
+        .iter()
+        .enumerate()
+        .map(|(idx, pep)| (pep.values.iter().map(|&v| v as u64).sum::<u64>(), idx))
+        .collect::<Vec<_>>();
+    ranked.sort_by_key(|&(total, idx)| (std::cmp::Reverse(total), idx));
+    let mut keep = vec![false; ranked.len()];
+    for &(_, idx) in &ranked[..n] {
+        keep[idx] = true;
+    }
+    let mut keep = keep.into_iter();
+    protein.peptides.retain(|_| keep.next().unwrap_or(false));
+}
+
+/// Rules applied to a single input of a batch in place of the base rules
+/// of the same kind, such as a higher intensity floor for a noisy plex
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterOverride<'a> {
+    /// File name or path of the input, or the plex name for manifests
+    file: &'a str,
+    #[serde(borrow, default)]
+    peptide_filters: Vec<PeptideFilter<'a>>,
+    #[serde(borrow, default)]
+    protein_filters: Vec<ProteinFilter<'a>>,
+}
+
+impl FilterOverride<'_> {
+    fn matches(&self, input: &str) -> bool {
+        input == self.file
+            || Path::new(input)
+                .file_name()
+                .is_some_and(|name| name == self.file)
+    }
+}
+
+/// Replace the rules in `base` of the same kind as any in `rules`, and add
+/// the rest
+fn merge<T: Clone>(base: &mut Vec<T>, rules: &[T]) {
+    base.retain(|b| !rules.iter().any(|r| discriminant(r) == discriminant(b)));
+    base.extend(rules.iter().cloned());
+}
+
+/// Provides filtering functionality on datasets and proteins
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Filter<'a> {
+    #[serde(borrow)]
+    peptide_filters: Vec<PeptideFilter<'a>>,
+    #[serde(borrow)]
+    protein_filters: Vec<ProteinFilter<'a>>,
+    #[serde(borrow, default, skip_serializing_if = "Vec::is_empty")]
+    overrides: Vec<FilterOverride<'a>>,
+    /// Number of proteins listing each peptide sequence, for
+    /// `MaxProteinMatches`
+    #[serde(skip)]
+    protein_matches: HashMap<String, usize>,
+    /// q-value of each PSM by scan number, for `MaxPsmQValue`
+    #[serde(skip)]
+    psm_qvalues: HashMap<usize, f64>,
+}
+
+impl<'a> Filter<'a> {
+    /// Add a new `ProteinFilter` to the `Filter` object.
+    ///
+    /// This follows the Builder pattern
+    pub fn add_protein_filter(mut self, filter: ProteinFilter<'a>) -> Self {
+        self.protein_filters.push(filter);
+        self
+    }
+
+    /// Add a new `PeptideFilter` to the `Filter` object.
+    ///
+    /// This follows the Builder pattern
+    pub fn add_peptide_filter(mut self, filter: PeptideFilter<'a>) -> Self {
+        self.peptide_filters.push(filter);
+        self
+    }
+
+    /// Add `filter` unless there is already a `PeptideFilter` of the same
+    /// kind, so that defaults do not override rules given by the user.
+    ///
+    /// This follows the Builder pattern
+    pub fn with_default_peptide_filter(self, filter: PeptideFilter<'a>) -> Self {
+        if self
+            .peptide_filters
+            .iter()
+            .any(|f| discriminant(f) == discriminant(&filter))
+        {
+            self
+        } else {
+            self.add_peptide_filter(filter)
+        }
+    }
+
+    /// Invert `ExcludeReverse`, so that only decoy proteins pass, for
+    /// characterizing the distribution of reporter signal under the null
+    pub fn keep_decoys_only(mut self) -> Self {
+        self.protein_filters.retain(|f| {
+            !matches!(
+                f,
+                ProteinFilter::ExcludeReverse | ProteinFilter::OnlyReverse
+            )
+        });
+        self.protein_filters.push(ProteinFilter::OnlyReverse);
+        self
+    }
+
+    /// Return the rules for `input`, with the rules of any overrides naming
+    /// it merged over the base rules: each override rule replaces the base
+    /// rules of the same kind, and other base rules are kept
+    pub fn for_input(&self, input: &str) -> Filter<'a> {
+        let mut filter = Filter {
+            peptide_filters: self.peptide_filters.clone(),
+            protein_filters: self.protein_filters.clone(),
+            overrides: Vec::new(),
+            protein_matches: self.protein_matches.clone(),
+            psm_qvalues: self.psm_qvalues.clone(),
+        };
+        for o in self.overrides.iter().filter(|o| o.matches(input)) {
+            merge(&mut filter.peptide_filters, &o.peptide_filters);
+            merge(&mut filter.protein_filters, &o.protein_filters);
+        }
+        filter
+    }
+
+    /// Description of each rule, peptide filters first
+    pub fn rules(&self) -> Vec<String> {
+        self.peptide_filters
+            .iter()
+            .map(|f| format!("{:?}", f))
+            .chain(self.protein_filters.iter().map(|f| format!("{:?}", f)))
+            .collect()
+    }
+
+    /// Return a new `Filter` with only the rules at the positions of
+    /// `rules` for which `enabled` is true
+    pub fn select(&self, enabled: &[bool]) -> Filter<'a> {
+        let on = |idx: usize| enabled.get(idx).copied().unwrap_or(true);
+        let n = self.peptide_filters.len();
+        Filter {
+            peptide_filters: (self.peptide_filters.iter().enumerate())
+                .filter(|(idx, _)| on(*idx))
+                .map(|(_, f)| f.clone())
+                .collect(),
+            protein_filters: (self.protein_filters.iter().enumerate())
+                .filter(|(idx, _)| on(n + idx))
+                .map(|(_, f)| f.clone())
+                .collect(),
+            overrides: self.overrides.clone(),
+            protein_matches: self.protein_matches.clone(),
+            psm_qvalues: self.psm_qvalues.clone(),
+        }
+    }
+
+    /// Count the proteins of `data` listing each peptide sequence, so that
+    /// `MaxProteinMatches` can be evaluated on them. Nothing is counted if
+    /// no rule needs it.
+    ///
+    /// This follows the Builder pattern
+    pub fn with_protein_matches(mut self, data: &Dataset) -> Self {
+        self.protein_matches.clear();
+        if !self.uses(|f| matches!(f, PeptideFilter::MaxProteinMatches(_))) {
+            return self;
+        }
+        let mut proteins: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for prot in &data.proteins {
+            for pep in &prot.peptides {
+                proteins
+                    .entry(&pep.sequence)
+                    .or_default()
+                    .insert(&prot.accession);
+            }
+        }
+        self.protein_matches = proteins
+            .into_iter()
+            .map(|(seq, accessions)| (seq.to_string(), accessions.len()))
+            .collect();
+        self
+    }
+
+    /// Use `qvalues`, the q-value of each PSM by scan number, to evaluate
+    /// `MaxPsmQValue`
+    ///
+    /// This follows the Builder pattern
+    pub fn with_psm_qvalues(mut self, qvalues: HashMap<usize, f64>) -> Self {
+        self.psm_qvalues = qvalues;
+        self
+    }
+
+    /// Does any rule filter on PSM q-values?
+    pub fn uses_psm_qvalues(&self) -> bool {
+        self.uses(|f| matches!(f, PeptideFilter::MaxPsmQValue(_)))
+    }
+
+    /// Does any peptide rule, including those within protein rules, satisfy
+    /// `pred`?
+    fn uses<F: Fn(&PeptideFilter) -> bool>(&self, pred: F) -> bool {
+        self.peptide_filters.iter().any(&pred)
+            || self.protein_filters.iter().any(|f| match f {
+                ProteinFilter::MinPeptidesPassing { filter, .. } => pred(filter),
+                _ => false,
+            })
+    }
+
+    pub fn tryptic_regex() -> Regex {
+        Regex::new(r#"(R|K|-)\..*((R|K)\..|.-)"#).unwrap()
+    }
+
+    /// Does `peptide` pass `filter`? Peptides whose sequence has not been
+    /// counted pass `MaxProteinMatches`, and PSMs without a q-value fail
+    /// `MaxPsmQValue`
+    fn passes(&self, filter: &PeptideFilter, peptide: &Peptide, tryptic_regex: &Regex) -> bool {
+        match filter {
+            PeptideFilter::SequenceExclude(pat) => !peptide.sequence.contains(pat),
+            PeptideFilter::SequenceMatch(pat) => peptide.sequence.contains(pat),
+            PeptideFilter::TotalIntensity(n) => peptide.values.iter().sum::<u32>() >= *n,
+            PeptideFilter::Tryptic => {
+                tryptic_regex.is_match(&sequence::unmodified(&peptide.sequence))
+            }
+            PeptideFilter::Unique => peptide.unique,
+            PeptideFilter::MeanIntensity(n) => {
+                let sum = peptide.values.iter().map(|&v| v as f64).sum::<f64>();
+                sum >= *n * peptide.values.len() as f64
+            }
+            PeptideFilter::MaxChannelIntensity(n) => peptide.values.iter().all(|v| v <= n),
+            PeptideFilter::Purity(cutoff) => peptide.purity >= *cutoff,
+            PeptideFilter::ChannelCV(channels, cutoff) => {
+                let v = channels
+                    .iter()
+                    .filter_map(|&chan| channel_value(peptide, chan))
+                    .collect::<Vec<u32>>();
+                util::cv(&v) < *cutoff
+            }
+            // Ignore incorrect channel values
+            PeptideFilter::ChannelIntensity(channel, cutoff) => {
+                channel_value(peptide, *channel).map_or(true, |v| v >= *cutoff)
+            }
+            PeptideFilter::TotalIntensityChannels(chan, cutoff) => {
+                let sum = chan
+                    .iter()
+                    .filter_map(|&c| channel_value(peptide, c))
+                    .sum::<u32>();
+                sum >= *cutoff
+            }
+            PeptideFilter::MaxProteinMatches(n) => self
+                .protein_matches
+                .get(&peptide.sequence)
+                .map_or(true, |m| m <= n),
+            PeptideFilter::MaxPsmQValue(cutoff) => self
+                .psm_qvalues
+                .get(&peptide.scan)
+                .is_some_and(|q| q <= cutoff),
+            PeptideFilter::ModMassRange(min, max) => {
+                sequence::parse(&peptide.sequence).has_mod_mass(*min, *max)
+            }
+        }
+    }
+
+    /// Return a new `Dataset` that only contains filtered `Protein`s
+    pub fn filter_dataset(&self, dataset: Dataset) -> Dataset {
+        let reg = Self::tryptic_regex();
+        let filter = self.clone().with_protein_matches(&dataset);
+        Dataset {
+            channels: dataset.channels,
+            proteins: dataset
+                .proteins
+                .into_iter()
+                .filter_map(|prot| filter.filter_protein(prot, &reg))
+                .collect(),
+        }
+    }
+
+    /// Filter a `Protein`, returning `Some` if it passes any `ProteinFilter`s
+    /// that need to be applied or `None` if the protein fails a given
+    /// `ProteinFilter`.
+    ///
+    /// The peptides associated with the returned `Protein` object are those
+    /// that passed any given `PeptideFilter`s. `MaxProteinMatches` passes
+    /// every peptide unless `with_protein_matches` has counted the dataset.
+    pub fn filter_protein(&self, mut protein: Protein, tryptic_regex: &Regex) -> Option<Protein> {
+        // First run through any protein level filters
+        for filter in &self.protein_filters {
+            match filter {
+                ProteinFilter::SequenceCounts(n) => {
+                    if protein.sequence_count < *n {
+                        return None;
+                    }
+                }
+                ProteinFilter::SpectralCounts(n) => {
+                    if protein.spectral_count < *n {
+                        return None;
+                    }
+                }
+                ProteinFilter::ExcludeReverse => {
+                    if protein.accession.contains("Reverse") {
+                        return None;
+                    }
+                }
+                ProteinFilter::ExcludePreset(preset) => {
+                    if preset.matches(&protein) {
+                        return None;
+                    }
+                }
+                ProteinFilter::OnlyReverse => {
+                    if !protein.accession.contains("Reverse") {
+                        return None;
+                    }
+                }
+                ProteinFilter::TotalIntensity(_)
+                | ProteinFilter::TopNPeptidesByIntensity(_)
+                | ProteinFilter::MinPeptidesPassing { .. } => {}
+            }
+        }
+
+        protein.peptides.retain(|peptide| {
+            self.peptide_filters
+                .iter()
+                .all(|filter| self.passes(filter, peptide, tryptic_regex))
+        });
+
+        // We must have at least a single peptide...
+        if protein.peptides.is_empty() {
+            return None;
+        }
+
+        recount(&mut protein);
+        let (spec, seq) = (protein.spectral_count, protein.sequence_count);
+
+        // Second pass through protein filters, in case we no longer have
+        // enough filtered peptides
+        for filter in &self.protein_filters {
+            match filter {
+                ProteinFilter::SequenceCounts(n) if seq < *n => return None,
+                ProteinFilter::SpectralCounts(n) if spec < *n => return None,
+                ProteinFilter::TopNPeptidesByIntensity(n) => {
+                    top_peptides(&mut protein, *n);
+                    recount(&mut protein);
+                }
+                ProteinFilter::MinPeptidesPassing { filter, n } => {
+                    let passing = protein
+                        .peptides
+                        .iter()
+                        .filter(|pep| self.passes(filter, pep, tryptic_regex))
+                        .map(|pep| &pep.sequence)
+                        .collect::<HashSet<_>>();
+                    if passing.len() < *n {
+                        return None;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Intensity is evaluated on the peptides that are rolled up
+        for filter in &self.protein_filters {
+            if let ProteinFilter::TotalIntensity(n) = filter {
+                let total = rollup::protein(&protein, &rollup::Sum)
+                    .into_iter()
+                    .sum::<f64>();
+                if total < *n as f64 {
+                    return None;
+                }
+            }
+        }
+
+        Some(protein)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn psm(sequence: &str, values: &[u32]) -> Peptide {
+        Peptide {
+            sequence: sequence.to_string(),
+            values: values.to_vec(),
+            unique: true,
+            purity: 1.0,
+            scan: 0,
+        }
+    }
+
+    fn protein(accession: &str, peptides: Vec<Peptide>) -> Protein {
+        let mut prot = Protein {
+            accession: accession.to_string(),
+            peptides,
+            channels: 2,
+            ..Protein::default()
+        };
+        recount(&mut prot);
+        prot
+    }
+
+    fn apply(filter: &Filter, prot: Protein) -> Option<Protein> {
+        filter.filter_protein(prot, &Filter::tryptic_regex())
+    }
+
+    #[test]
+    fn peptide_filters() {
+        let filter = Filter::default();
+        let regex = Filter::tryptic_regex();
+        let pep = psm("K.PEPTIDEK.L", &[100, 300]);
+        let passes = |f: PeptideFilter| filter.passes(&f, &pep, &regex);
+        assert!(passes(PeptideFilter::TotalIntensity(400)));
+        assert!(!passes(PeptideFilter::TotalIntensity(401)));
+        assert!(passes(PeptideFilter::ChannelIntensity(2, 300)));
+        assert!(!passes(PeptideFilter::ChannelIntensity(1, 101)));
+        // Channels the PSM does not have are ignored
+        assert!(passes(PeptideFilter::ChannelIntensity(3, 1)));
+        assert!(passes(PeptideFilter::TotalIntensityChannels(
+            vec![2, 3],
+            300
+        )));
+        assert!(passes(PeptideFilter::Tryptic));
+        assert!(passes(PeptideFilter::SequenceMatch("TIDE")));
+        assert!(!passes(PeptideFilter::SequenceExclude("TIDE")));
+        assert!(passes(PeptideFilter::Unique));
+        assert!(passes(PeptideFilter::Purity(1.0)));
+
+        let semi = psm("A.PEPTIDEK.L", &[1, 1]);
+        assert!(!filter.passes(&PeptideFilter::Tryptic, &semi, &regex));
+        let nterm = psm("-.PEPTIDEK.L", &[1, 1]);
+        assert!(filter.passes(&PeptideFilter::Tryptic, &nterm, &regex));
+    }
+
+    #[test]
+    fn protein_counts_follow_peptide_filters() {
+        let prot = || {
+            protein(
+                "P1",
+                vec![
+                    psm("K.AAAK.L", &[10, 10]),
+                    psm("K.AAAK.L", &[500, 500]),
+                    psm("K.CCCK.L", &[500, 500]),
+                ],
+            )
+        };
+        let filter = Filter::default().add_peptide_filter(PeptideFilter::TotalIntensity(100));
+        let kept = apply(&filter, prot()).unwrap();
+        assert_eq!((kept.spectral_count, kept.sequence_count), (2, 2));
+
+        // Count rules are checked again on the PSMs that remain
+        let filter = filter.add_protein_filter(ProteinFilter::SpectralCounts(3));
+        assert!(apply(&filter, prot()).is_none());
+
+        let filter = Filter::default().add_peptide_filter(PeptideFilter::TotalIntensity(10_000));
+        assert!(apply(&filter, prot()).is_none());
+    }
+
+    #[test]
+    fn total_intensity_of_remaining_psms() {
+        let prot = || {
+            protein(
+                "P1",
+                vec![psm("K.AAAK.L", &[50, 50]), psm("K.CCCK.L", &[5, 5])],
+            )
+        };
+        let filter = Filter::default().add_protein_filter(ProteinFilter::TotalIntensity(110));
+        assert!(apply(&filter, prot()).is_some());
+        let filter = filter.add_peptide_filter(PeptideFilter::TotalIntensity(20));
+        assert!(apply(&filter, prot()).is_none());
+    }
+
+    #[test]
+    fn max_channel_intensity() {
+        let filter = Filter::default();
+        let regex = Filter::tryptic_regex();
+        let pep = psm("K.PEPTIDEK.L", &[100, 300]);
+        assert!(filter.passes(&PeptideFilter::MaxChannelIntensity(300), &pep, &regex));
+        assert!(!filter.passes(&PeptideFilter::MaxChannelIntensity(299), &pep, &regex));
+    }
+
+    #[test]
+    fn mean_intensity() {
+        let filter = Filter::default();
+        let regex = Filter::tryptic_regex();
+        let pep = psm("K.PEPTIDEK.L", &[100, 301]);
+        assert!(filter.passes(&PeptideFilter::MeanIntensity(200.5), &pep, &regex));
+        assert!(!filter.passes(&PeptideFilter::MeanIntensity(200.6), &pep, &regex));
+    }
+
+    #[test]
+    fn top_peptides_by_intensity() {
+        let prot = protein(
+            "P1",
+            vec![
+                psm("K.AAAK.L", &[1, 1]),
+                psm("K.CCCK.L", &[5, 5]),
+                psm("K.DDDK.L", &[2, 2]),
+                psm("K.EEEK.L", &[5, 5]),
+            ],
+        );
+        let filter =
+            Filter::default().add_protein_filter(ProteinFilter::TopNPeptidesByIntensity(2));
+        let kept = apply(&filter, prot).unwrap();
+        let sequences = kept
+            .peptides
+            .iter()
+            .map(|p| p.sequence.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, vec!["K.CCCK.L", "K.EEEK.L"]);
+        assert_eq!(kept.spectral_count, 2);
+    }
+
+    #[test]
+    fn decoys() {
+        let filter = Filter::default().add_protein_filter(ProteinFilter::ExcludeReverse);
+        let decoy = || protein("Reverse_P1", vec![psm("K.AAAK.L", &[1, 1])]);
+        assert!(apply(&filter, decoy()).is_none());
+        let filter = filter.keep_decoys_only();
+        assert!(apply(&filter, decoy()).is_some());
+        assert!(apply(&filter, protein("P1", vec![psm("K.AAAK.L", &[1, 1])])).is_none());
+    }
+
+    #[test]
+    fn select_rules() {
+        let filter = Filter::default()
+            .add_peptide_filter(PeptideFilter::TotalIntensity(100))
+            .add_peptide_filter(PeptideFilter::Tryptic)
+            .add_protein_filter(ProteinFilter::SpectralCounts(2));
+        let selected = filter.select(&[false, true, false]);
+        assert_eq!(selected.rules(), vec!["Tryptic"]);
+        // Rules beyond the end of `enabled` are kept
+        assert_eq!(filter.select(&[false]).rules().len(), 2);
+    }
+
+    #[test]
+    fn min_peptides_passing() {
+        let prot = || {
+            protein(
+                "P1",
+                vec![
+                    psm("K.AAAK.L", &[1, 1]),
+                    psm("K.CCCK.L", &[100, 100]),
+                    psm("K.CCCK.L", &[100, 100]),
+                ],
+            )
+        };
+        let rule = |n| ProteinFilter::MinPeptidesPassing {
+            filter: PeptideFilter::TotalIntensity(100),
+            n,
+        };
+        let kept = apply(&Filter::default().add_protein_filter(rule(1)), prot()).unwrap();
+        // PSMs failing the rule are kept
+        assert_eq!(kept.peptides.len(), 3);
+        assert!(apply(&Filter::default().add_protein_filter(rule(2)), prot()).is_none());
+    }
+
+    #[test]
+    fn overrides_replace_rules_of_the_same_kind() {
+        let json = r#"{
+            "peptide_filters": [{"TotalIntensity": 100}, "Tryptic"],
+            "protein_filters": [{"SpectralCounts": 2}],
+            "overrides": [{"file": "noisy.txt", "peptide_filters": [{"TotalIntensity": 500}]}]
+        }"#;
+        let filter = serde_json::from_str::<Filter>(json).unwrap();
+        let base = filter.for_input("other.txt");
+        assert_eq!(
+            base.rules(),
+            vec!["TotalIntensity(100)", "Tryptic", "SpectralCounts(2)"]
+        );
+        let noisy = filter.for_input("/data/noisy.txt");
+        assert_eq!(
+            noisy.rules(),
+            vec!["Tryptic", "TotalIntensity(500)", "SpectralCounts(2)"]
+        );
+    }
+
+    #[test]
+    fn shared_peptides() {
+        let mut shared = psm("K.SHAREDK.L", &[1, 1]);
+        shared.scan = 2;
+        let mut own = psm("K.OWNK.L", &[1, 1]);
+        own.scan = 1;
+        let data = Dataset {
+            proteins: vec![
+                protein("P1", vec![own, shared.clone()]),
+                protein("P2", vec![shared]),
+            ],
+            channels: 2,
+        };
+        let filter = Filter::default().add_peptide_filter(PeptideFilter::MaxProteinMatches(1));
+        let kept = filter.filter_dataset(data);
+        assert_eq!(kept.proteins.len(), 1);
+        assert_eq!(kept.proteins[0].peptides[0].scan, 1);
+    }
+
+    #[test]
+    fn psm_qvalues() {
+        let mut qvalues = HashMap::new();
+        qvalues.insert(1, 0.001);
+        qvalues.insert(2, 0.05);
+        let filter = Filter::default()
+            .add_peptide_filter(PeptideFilter::MaxPsmQValue(0.01))
+            .with_psm_qvalues(qvalues);
+        assert!(filter.uses_psm_qvalues());
+        assert!(!Filter::default().uses_psm_qvalues());
+        let scans = [1, 2, 3];
+        let prot = protein(
+            "P1",
+            scans
+                .iter()
+                .map(|&scan| Peptide {
+                    scan,
+                    ..psm("K.AAAK.L", &[1, 1])
+                })
+                .collect(),
+        );
+        // PSMs above the cutoff, or without a q-value, fail
+        let kept = apply(&filter, prot).unwrap();
+        assert_eq!(kept.peptides.len(), 1);
+        assert_eq!(kept.peptides[0].scan, 1);
+    }
+
+    #[test]
+    fn default_rules() {
+        let defaults = Filter::default()
+            .add_peptide_filter(PeptideFilter::Purity(0.5))
+            .with_default_peptide_filter(PeptideFilter::Purity(0.9))
+            .with_default_peptide_filter(PeptideFilter::Unique);
+        assert_eq!(defaults.rules(), vec!["Purity(0.5)", "Unique"]);
+    }
+
+    #[test]
+    fn modification_masses() {
+        let filter = Filter::default();
+        let regex = Filter::tryptic_regex();
+        let glyco = psm("K.PEPN(2350.87)TIDEK.L", &[1, 1]);
+        let oxidized = psm("K.PEPM(15.9949)TIDEK.L", &[1, 1]);
+        let range = PeptideFilter::ModMassRange(200.0, 5000.0);
+        assert!(filter.passes(&range, &glyco, &regex));
+        assert!(!filter.passes(&range, &oxidized, &regex));
+        assert!(!filter.passes(&range, &psm("K.PEPTIDEK.L", &[1, 1]), &regex));
+        // Modification masses are not mistaken for cleavage sites
+        assert!(filter.passes(&PeptideFilter::Tryptic, &glyco, &regex));
+        let cterm = psm("K.PEPTIDEK(229.1629).L", &[1, 1]);
+        assert!(filter.passes(&PeptideFilter::Tryptic, &cterm, &regex));
+    }
+}
@@ -0,0 +1,156 @@
+//! Filter files that extend other filters, or hold several named sets
+//!
+//! A filter may name another filter it builds on with `"extends"`, either
+//! the path of another filter file, relative to the file naming it, or the
+//! name of a set in the same file. Named sets are kept under `"sets"`, and
+//! one is chosen with `--filter-set`:
+//!
+//! ```json
+//! {
+//!   "sets": {
+//!     "default": { "extends": "lab_filters.json", "protein_filters": ["ExcludeReverse"] },
+//!     "strict": { "extends": "default", "peptide_filters": [{ "TotalIntensity": 20000 }] }
+//!   }
+//! }
+//! ```
+//!
+//! Rules of an extending filter replace the rules of the same kind in the
+//! filter it extends, and other rules are kept, so that `strict` above
+//! raises the intensity floor of `default` without restating the rest.
+//! Overrides are combined.
+use crate::remote;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Deepest chain of filters extending one another, beyond which the chain
+/// is assumed to be circular
+const MAX_DEPTH: usize = 16;
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Name of the variant of a serialized rule, such as `Unique` or
+/// `TotalIntensity`
+fn kind(rule: &Value) -> Option<&str> {
+    match rule {
+        Value::String(s) => Some(s),
+        Value::Object(map) if map.len() == 1 => map.keys().next().map(String::as_str),
+        _ => None,
+    }
+}
+
+/// Merge the rules of `child` over those of `parent`
+fn merge(mut parent: Map<String, Value>, child: Map<String, Value>) -> Map<String, Value> {
+    for (key, value) in child {
+        match (key.as_str(), parent.get_mut(&key), value) {
+            (
+                "peptide_filters" | "protein_filters",
+                Some(Value::Array(base)),
+                Value::Array(rules),
+            ) => {
+                base.retain(|b| {
+                    !rules
+                        .iter()
+                        .any(|r| kind(r).is_some() && kind(r) == kind(b))
+                });
+                base.extend(rules);
+            }
+            ("overrides", Some(Value::Array(base)), Value::Array(rules)) => base.extend(rules),
+            (_, _, value) => {
+                parent.insert(key, value);
+            }
+        }
+    }
+    parent
+}
+
+fn read(path: &str) -> std::io::Result<Map<String, Value>> {
+    let text = remote::read_to_string(path)?;
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(_) => Err(invalid("filter is not a JSON object")),
+        Err(e) => Err(invalid(e.to_string())),
+    }
+}
+
+/// Resolve `filter`, found in the file at `path` whose top level is `doc`,
+/// and everything it extends
+fn resolve_filter(
+    path: &str,
+    doc: &Map<String, Value>,
+    mut filter: Map<String, Value>,
+    depth: usize,
+) -> std::io::Result<Map<String, Value>> {
+    if depth > MAX_DEPTH {
+        return Err(invalid(format!(
+            "filters extend more than {} others, and may extend one another in a cycle",
+            MAX_DEPTH
+        )));
+    }
+    filter.remove("sets");
+    let parent = match filter.remove("extends") {
+        None => return Ok(filter),
+        Some(Value::String(name)) => match set(doc, &name) {
+            Some(set) => resolve_filter(path, doc, set?, depth + 1)?,
+            None => {
+                let file = match Path::new(path).parent() {
+                    Some(dir) => dir.join(&name).display().to_string(),
+                    None => name,
+                };
+                let parent =
+                    read(&file).map_err(|e| invalid(format!("extended filter {}: {}", file, e)))?;
+                resolve_filter(&file, &parent, parent.clone(), depth + 1)?
+            }
+        },
+        Some(_) => return Err(invalid("extends must be a string")),
+    };
+    Ok(merge(parent, filter))
+}
+
+/// The set called `name` in `doc`, if there is one
+fn set(doc: &Map<String, Value>, name: &str) -> Option<std::io::Result<Map<String, Value>>> {
+    match doc.get("sets")?.get(name)? {
+        Value::Object(map) => Some(Ok(map.clone())),
+        _ => Some(Err(invalid(format!(
+            "filter set {} is not a JSON object",
+            name
+        )))),
+    }
+}
+
+/// Read the filter file at `path`, or its set called `name`, resolving
+/// anything it extends, and return the resulting filter as JSON
+pub fn resolve(path: &str, name: Option<&str>) -> std::io::Result<String> {
+    let doc = read(path)?;
+    let filter = match name {
+        Some(name) => match set(&doc, name) {
+            Some(set) => set?,
+            None => {
+                let names = match doc.get("sets") {
+                    Some(Value::Object(sets)) => {
+                        sets.keys().cloned().collect::<Vec<_>>().join(", ")
+                    }
+                    _ => "none".to_string(),
+                };
+                return Err(invalid(format!(
+                    "no filter set {}, available sets: {}",
+                    name, names
+                )));
+            }
+        },
+        None if !doc.contains_key("peptide_filters") && doc.contains_key("sets") => {
+            return Err(invalid(
+                "the file only contains named filter sets; choose one with --filter-set",
+            ))
+        }
+        None => doc.clone(),
+    };
+    let mut filter = resolve_filter(path, &doc, filter, 0)?;
+    for key in &["peptide_filters", "protein_filters"] {
+        filter
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Array(Vec::new()));
+    }
+    serde_json::to_string(&filter).map_err(std::io::Error::other)
+}
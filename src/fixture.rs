@@ -0,0 +1,15 @@
+//! Input files for tests
+use std::fs;
+use std::path::PathBuf;
+
+/// Write `files` into a fresh directory named for `test`, returning the path
+/// of the directory
+pub fn dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("census2csv-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in files {
+        fs::write(dir.join(name), contents).unwrap();
+    }
+    dir
+}
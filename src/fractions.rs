@@ -0,0 +1,189 @@
+//! Combine fractionated runs of the same sample into a single `Dataset`
+//!
+//! Each input file is treated as one fraction of the same TMT experiment.
+//! Proteins are matched on accession across fractions, and the PSMs of every
+//! fraction are pooled under the combined protein. Each PSM is a spectrum of
+//! its own, so PSMs are never merged, even when a fraction holds several of
+//! the same sequence, and spectral counts count every spectrum. Channel
+//! intensities are summed across fractions when PSMs are rolled up into
+//! protein rows, or into peptide rows keyed on (accession, sequence). The
+//! census parser does not retain precursor charge, so PSMs of the same
+//! sequence at different charge states are combined in peptide rows as well.
+//!
+//! `combine_each` reads one fraction at a time, so that combining hundreds
+//! of files holds only the combined dataset and a single input in memory,
+//! rather than every input at once.
+use census_proteomics::*;
+use std::collections::{HashMap, HashSet};
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Fractions combined so far
+#[derive(Default)]
+struct Combined {
+    channels: Option<u8>,
+    proteins: Vec<Protein>,
+    /// Position of each accession in `proteins`
+    index: HashMap<String, usize>,
+}
+
+impl Combined {
+    /// Add the proteins of the fraction `data`, and their PSMs
+    fn add(&mut self, data: Dataset) -> std::io::Result<()> {
+        if *self.channels.get_or_insert(data.channels) != data.channels {
+            return Err(invalid("fractions do not have the same number of channels"));
+        }
+        for prot in data.proteins {
+            match self.index.get(&prot.accession) {
+                Some(&idx) => {
+                    let combined = &mut self.proteins[idx];
+                    combined.spectral_count += prot.spectral_count;
+                    combined.peptides.extend(prot.peptides);
+                }
+                None => {
+                    self.index
+                        .insert(prot.accession.clone(), self.proteins.len());
+                    self.proteins.push(prot);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Dataset {
+        for prot in &mut self.proteins {
+            let sequences = prot
+                .peptides
+                .iter()
+                .map(|pep| pep.sequence.as_str())
+                .collect::<HashSet<&str>>();
+            prot.sequence_count = sequences.len() as u16;
+        }
+        Dataset {
+            proteins: self.proteins,
+            channels: self.channels.unwrap_or(0),
+        }
+    }
+}
+
+/// Combine the fractions in `datasets`, in order, into a single `Dataset`.
+///
+/// Proteins appear in the order they are first encountered, followed within
+/// each protein by the PSMs of each fraction in turn. Spectral counts are
+/// summed across fractions, while sequence counts are recomputed from the
+/// pooled PSMs.
+pub fn combine(datasets: Vec<Dataset>) -> std::io::Result<Dataset> {
+    let mut combined = Combined::default();
+    for data in datasets {
+        combined.add(data)?;
+    }
+    Ok(combined.finish())
+}
+
+/// Combine the fractions produced by calling `read` on each of `inputs`, in
+/// order, with the same result as `combine`. Only one input is read at a
+/// time, and its PSMs are moved into the combined dataset before the next
+/// is read.
+pub fn combine_each<T, F>(inputs: &[T], mut read: F) -> std::io::Result<Dataset>
+where
+    F: FnMut(&T) -> std::io::Result<Dataset>,
+{
+    let mut combined = Combined::default();
+    for input in inputs {
+        combined.add(read(input)?)?;
+    }
+    Ok(combined.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter::Filter;
+    use crate::{input, parallel};
+
+    const FRACTION: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tPROT1\t3\t2\t30.3%\t335\t82944\tFirst protein
+S\tU\tK.PEPTIDEK.R\t100\t0.5\t200\t0.5
+S\tU\tK.PEPTIDEK.R\t300\t0.5\t400\t0.5
+S\tU\tR.SEQUENCER.A\t500\t0.5\t600\t0.5";
+
+    const OTHER: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tPROT2\t1\t1\t10.0%\t100\t10000\tSecond protein
+S\tU\tR.OTHERK.A\t7\t0.5\t8\t0.5
+P\tPROT1\t1\t1\t30.3%\t335\t82944\tFirst protein
+S\tU\tR.SEQUENCER.A\t1\t0.5\t2\t0.5";
+
+    fn fraction(text: &str) -> Dataset {
+        input::parse_census(text).unwrap()
+    }
+
+    #[test]
+    fn psms_of_one_fraction_are_kept_apart() {
+        let data = combine(vec![fraction(FRACTION), fraction(FRACTION)]).unwrap();
+        let data = parallel::filter(data, &Filter::default(), 1);
+        assert_eq!(data.proteins.len(), 1);
+        let prot = &data.proteins[0];
+        assert_eq!((prot.spectral_count, prot.sequence_count), (6, 2));
+        let repeated = prot
+            .peptides
+            .iter()
+            .filter(|pep| pep.sequence == "K.PEPTIDEK.R")
+            .count();
+        assert_eq!(repeated, 4);
+        let total = prot.peptides.iter().map(|pep| pep.values[0]).sum::<u32>();
+        assert_eq!(total, 1800);
+    }
+
+    #[test]
+    fn proteins_are_matched_on_accession() {
+        let data = combine(vec![fraction(FRACTION), fraction(OTHER)]).unwrap();
+        let accessions = data
+            .proteins
+            .iter()
+            .map(|prot| prot.accession.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(accessions, ["PROT1", "PROT2"]);
+        let prot = &data.proteins[0];
+        assert_eq!(prot.spectral_count, 4);
+        assert_eq!(prot.sequence_count, 2);
+        assert_eq!(prot.peptides.len(), 4);
+        assert_eq!(prot.peptides[3].values, vec![1, 2]);
+    }
+
+    #[test]
+    fn combine_each_matches_combine() {
+        let texts = [FRACTION, OTHER, FRACTION];
+        let each = combine_each(&texts, |text| Ok(fraction(text))).unwrap();
+        let all = combine(texts.iter().map(|text| fraction(text)).collect()).unwrap();
+        assert_eq!(each.channels, all.channels);
+        for (a, b) in each.proteins.iter().zip(&all.proteins) {
+            assert_eq!(a.accession, b.accession);
+            assert_eq!(a.spectral_count, b.spectral_count);
+            assert_eq!(a.sequence_count, b.sequence_count);
+            let values = |p: &Protein| {
+                p.peptides
+                    .iter()
+                    .map(|pep| pep.values.clone())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(values(a), values(b));
+        }
+    }
+
+    #[test]
+    fn channels_must_match() {
+        let wide = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int\tm/z_128.1_int\tnorm_m/z_128.1_int
+P\tPROT1\t1\t1\t30.3%\t335\t82944\tFirst protein
+S\tU\tK.PEPTIDEK.R\t100\t0.5\t200\t0.5\t300\t0.5";
+        assert_eq!(fraction(wide).channels, 3);
+        let err = combine(vec![fraction(FRACTION), fraction(wide)])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,278 @@
+//! Column-oriented tables of proteins and PSMs
+//!
+//! Frames hold the same values as the CSV outputs, with one vector per
+//! column, and map directly onto pandas and R data frames and onto C arrays.
+use crate::labels::channel_names;
+use crate::number;
+use crate::rollup::{self, Rollup};
+use census_proteomics::Dataset;
+
+/// Values of a single column
+#[derive(Clone, Debug, PartialEq)]
+pub enum Values {
+    Text(Vec<String>),
+    Integer(Vec<i64>),
+    Number(Vec<f64>),
+    Boolean(Vec<bool>),
+}
+
+impl Values {
+    pub fn len(&self) -> usize {
+        match self {
+            Values::Text(v) => v.len(),
+            Values::Integer(v) => v.len(),
+            Values::Number(v) => v.len(),
+            Values::Boolean(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Value of row `idx`, formatted for a CSV field
+    fn field(&self, idx: usize) -> String {
+        match self {
+            Values::Text(v) => v[idx].replace(',', ";"),
+            Values::Integer(v) => number::int(v[idx]),
+            Values::Number(v) => number::float(v[idx]),
+            Values::Boolean(v) => v[idx].to_string(),
+        }
+    }
+}
+
+/// Named columns of equal length
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub columns: Vec<(String, Values)>,
+}
+
+impl Frame {
+    /// Number of rows
+    pub fn rows(&self) -> usize {
+        self.columns.first().map(|(_, v)| v.len()).unwrap_or(0)
+    }
+
+    /// Values of the column named `name`
+    pub fn column(&self, name: &str) -> Option<&Values> {
+        self.columns.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// One row per protein, with channel values combined by `rollup`
+    pub fn proteins(data: &Dataset, rollup: &dyn Rollup) -> Frame {
+        let prots = &data.proteins;
+        let mut columns = vec![
+            (
+                "accession".to_string(),
+                Values::Text(prots.iter().map(|p| p.accession.clone()).collect()),
+            ),
+            (
+                "description".to_string(),
+                Values::Text(prots.iter().map(|p| p.description.clone()).collect()),
+            ),
+            (
+                "spectral_count".to_string(),
+                Values::Integer(prots.iter().map(|p| p.spectral_count as i64).collect()),
+            ),
+            (
+                "sequence_count".to_string(),
+                Values::Integer(prots.iter().map(|p| p.sequence_count as i64).collect()),
+            ),
+        ];
+
+        let values = prots
+            .iter()
+            .map(|p| rollup::protein(p, rollup))
+            .collect::<Vec<Vec<f64>>>();
+        for (idx, name) in channel_names(None, data.channels).into_iter().enumerate() {
+            columns.push((
+                name,
+                Values::Number(values.iter().map(|v| v[idx]).collect()),
+            ));
+        }
+        Frame { columns }
+    }
+
+    /// One row per PSM
+    pub fn psms(data: &Dataset) -> Frame {
+        let rows = data
+            .proteins
+            .iter()
+            .flat_map(|prot| prot.peptides.iter().map(move |pep| (prot, pep)))
+            .collect::<Vec<_>>();
+        let mut columns = vec![
+            (
+                "accession".to_string(),
+                Values::Text(rows.iter().map(|(p, _)| p.accession.clone()).collect()),
+            ),
+            (
+                "description".to_string(),
+                Values::Text(rows.iter().map(|(p, _)| p.description.clone()).collect()),
+            ),
+            (
+                "sequence".to_string(),
+                Values::Text(rows.iter().map(|(_, s)| s.sequence.clone()).collect()),
+            ),
+            (
+                "unique".to_string(),
+                Values::Boolean(rows.iter().map(|(_, s)| s.unique).collect()),
+            ),
+            (
+                "purity".to_string(),
+                Values::Number(rows.iter().map(|(_, s)| s.purity as f64).collect()),
+            ),
+            (
+                "scan".to_string(),
+                Values::Integer(rows.iter().map(|(_, s)| s.scan as i64).collect()),
+            ),
+        ];
+        for (idx, name) in channel_names(None, data.channels).into_iter().enumerate() {
+            columns.push((
+                name,
+                Values::Integer(rows.iter().map(|(_, s)| s.values[idx] as i64).collect()),
+            ));
+        }
+        Frame { columns }
+    }
+
+    /// Render as CSV text. As with the command line outputs, commas within
+    /// fields are replaced with semicolons.
+    pub fn to_csv(&self) -> String {
+        let mut out = self
+            .columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>()
+            .join(",");
+        out.push('\n');
+        for row in 0..self.rows() {
+            let fields = self
+                .columns
+                .iter()
+                .map(|(_, values)| values.field(row))
+                .collect::<Vec<String>>();
+            out.push_str(&fields.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render as a JSON array with one object per row
+    pub fn to_json(&self) -> serde_json::Value {
+        let rows = (0..self.rows())
+            .map(|row| {
+                let obj = self
+                    .columns
+                    .iter()
+                    .map(|(name, values)| {
+                        let v = match values {
+                            Values::Text(v) => serde_json::json!(v[row]),
+                            Values::Integer(v) => serde_json::json!(v[row]),
+                            Values::Number(v) => serde_json::json!(v[row]),
+                            Values::Boolean(v) => serde_json::json!(v[row]),
+                        };
+                        (name.clone(), v)
+                    })
+                    .collect::<serde_json::Map<String, serde_json::Value>>();
+                serde_json::Value::Object(obj)
+            })
+            .collect();
+        serde_json::Value::Array(rows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t2\t2\t30.3%\t335\t82944\tSerum albumin, human
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t300\t0.5
+S\t\tK.PEPTIDER.L\t200\t0.5\t100\t0.5
+P\tQ67890\t1\t1\t5.0%\t10\t1000\tKeratin
+S\tU\tK.KERATINK.R\t7\t0.5\t8\t0.5";
+
+    #[test]
+    fn protein_columns() {
+        let data = input::parse_census(FILE).unwrap();
+        let frame = Frame::proteins(&data, &rollup::Sum);
+        let names = frame
+            .columns
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "accession",
+                "description",
+                "spectral_count",
+                "sequence_count",
+                "channel_1",
+                "channel_2"
+            ]
+        );
+        assert_eq!(frame.rows(), 2);
+        assert_eq!(
+            frame.column("spectral_count"),
+            Some(&Values::Integer(vec![2, 1]))
+        );
+        assert_eq!(
+            frame.column("channel_2"),
+            Some(&Values::Number(vec![400.0, 8.0]))
+        );
+        assert_eq!(frame.column("gene"), None);
+
+        let mean = Frame::proteins(&data, &rollup::Mean);
+        assert_eq!(
+            mean.column("channel_1"),
+            Some(&Values::Number(vec![150.0, 7.0]))
+        );
+    }
+
+    #[test]
+    fn psm_columns() {
+        let data = input::parse_census(FILE).unwrap();
+        let frame = Frame::psms(&data);
+        assert_eq!(frame.rows(), 3);
+        assert!(frame.columns.iter().all(|(_, v)| v.len() == 3));
+        assert_eq!(
+            frame.column("accession"),
+            Some(&Values::Text(vec![
+                "P12345".into(),
+                "P12345".into(),
+                "Q67890".into()
+            ]))
+        );
+        assert_eq!(
+            frame.column("unique"),
+            Some(&Values::Boolean(vec![true, false, true]))
+        );
+        assert_eq!(
+            frame.column("channel_1"),
+            Some(&Values::Integer(vec![100, 200, 7]))
+        );
+    }
+
+    #[test]
+    fn csv_and_json() {
+        let data = input::parse_census(FILE).unwrap();
+        let frame = Frame::proteins(&data, &rollup::Sum);
+        assert_eq!(
+            frame.to_csv(),
+            "\
+accession,description,spectral_count,sequence_count,channel_1,channel_2
+P12345,Serum albumin; human,2,2,300,400
+Q67890,Keratin,1,1,7,8
+"
+        );
+        let json = frame.to_json();
+        assert_eq!(json[0]["description"], "Serum albumin, human");
+        assert_eq!(json[1]["spectral_count"], 1);
+        assert_eq!(json[1]["channel_2"], 8.0);
+        assert!(Frame::default().to_json().as_array().unwrap().is_empty());
+        assert_eq!(Frame::default().rows(), 0);
+    }
+}
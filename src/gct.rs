@@ -0,0 +1,204 @@
+//! Write output tables in the Broad GCT 1.3 format, as read by Morpheus and
+//! ssGSEA
+//!
+//! Each row is a protein (or peptide), identified by its accession, with the
+//! remaining non-channel columns as row metadata. Each channel is a sample,
+//! with its reporter ion label, and its condition if known, as column
+//! metadata. Totals rows are not written, since they are not samples.
+use crate::meta::Column;
+use crate::writer::{RecordWriter, Row, Table};
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+/// GCT placeholder for missing metadata
+const NA: &str = "na";
+
+/// GCT writer. The dimensions of the matrix are written first, so records
+/// are buffered until the table is finished.
+pub struct Writer<W: Write> {
+    out: W,
+    table: Table,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Writer<W> {
+        Writer {
+            out,
+            table: Table::default(),
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for Writer<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.table.header(columns)
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        if row.get(0) != Some("TOTAL") {
+            self.table.record(row)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        write(&self.table, &mut self.out)?;
+        self.out.flush()
+    }
+}
+
+/// Write `table` as a GCT 1.3 file. Row identifiers are accessions, with the
+/// peptide sequence appended for peptide layouts, and a numeric suffix added
+/// to any repeated identifier.
+fn write<W: Write>(table: &Table, mut out: W) -> std::io::Result<()> {
+    let columns = &table.columns;
+    let sequence = columns.iter().position(|c| c.name == "sequence");
+    let samples = (0..columns.len())
+        .filter(|&i| columns[i].channel.is_some())
+        .collect::<Vec<usize>>();
+    let meta = (1..columns.len())
+        .filter(|&i| columns[i].channel.is_none())
+        .collect::<Vec<usize>>();
+
+    let mut col_meta: Vec<(&str, Vec<String>)> = vec![(
+        "label",
+        samples
+            .iter()
+            .map(|&i| {
+                columns[i]
+                    .channel
+                    .as_ref()
+                    .map(|c| c.1.clone())
+                    .unwrap_or_default()
+            })
+            .collect(),
+    )];
+    if columns.iter().any(|c| c.condition.is_some()) {
+        col_meta.push((
+            "condition",
+            samples
+                .iter()
+                .map(|&i| columns[i].condition.clone().unwrap_or_else(|| NA.into()))
+                .collect(),
+        ));
+    }
+
+    writeln!(out, "#1.3")?;
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{}",
+        table.rows.len(),
+        samples.len(),
+        meta.len(),
+        col_meta.len()
+    )?;
+
+    let names = |idx: &[usize]| {
+        idx.iter()
+            .map(|&i| columns[i].name.as_str())
+            .collect::<Vec<&str>>()
+            .join("\t")
+    };
+    writeln!(out, "id\t{}\t{}", names(&meta), names(&samples))?;
+    for (name, values) in &col_meta {
+        writeln!(
+            out,
+            "{}\t{}{}",
+            name,
+            meta.iter().map(|_| format!("{}\t", NA)).collect::<String>(),
+            values.join("\t")
+        )?;
+    }
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for row in table.rows.iter() {
+        let get = |i: usize| row.get(i).unwrap_or("");
+        let mut id = match sequence {
+            Some(s) => format!("{}:{}", get(0), get(s)),
+            None => get(0).to_string(),
+        };
+        let count = seen.entry(id.clone()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            id = format!("{}_{}", id, count);
+        }
+
+        let values = |idx: &[usize], blank: &str| {
+            idx.iter()
+                .map(|&i| match get(i) {
+                    "" => blank.to_string(),
+                    v => v.replace('\t', " "),
+                })
+                .collect::<Vec<String>>()
+                .join("\t")
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            id,
+            values(&meta, NA),
+            values(&samples, "")
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::writer::Rows;
+
+    fn write_rows(columns: &[Column], records: &[&[&str]]) -> String {
+        let mut writer = Writer::new(Vec::new());
+        writer.header(columns).unwrap();
+        let mut rows = Rows::default();
+        for record in records {
+            rows.record(record.iter());
+        }
+        for row in rows.iter() {
+            writer.record(row).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(writer.out).unwrap()
+    }
+
+    #[test]
+    fn protein_matrix() {
+        let columns = [
+            Column::new("accession", "string", ""),
+            Column::new("description", "string", ""),
+            Column::new("126", "number", "").channel(1, "126"),
+            Column::new("127", "number", "").channel(2, "127"),
+        ];
+        let out = write_rows(
+            &columns,
+            &[
+                &["P1", "Alpha", "100", "200"],
+                &["P2", "", "3", ""],
+                &["TOTAL", "2 rows", "103", "200"],
+            ],
+        );
+        assert_eq!(
+            out,
+            "#1.3\n2\t2\t1\t1\nid\tdescription\t126\t127\nlabel\tna\t126\t127\nP1\tAlpha\t100\t200\nP2\tna\t3\t\n"
+        );
+    }
+
+    #[test]
+    fn peptide_ids_and_conditions() {
+        let mut columns = vec![
+            Column::new("accession", "string", ""),
+            Column::new("sequence", "string", ""),
+            Column::new("channel_1", "number", "").channel(1, "channel_1"),
+        ];
+        columns[2].condition = Some("treated".into());
+        let out = write_rows(
+            &columns,
+            &[&["P1", "K.PEPK.R", "1"], &["P1", "K.PEPK.R", "2"]],
+        );
+        assert_eq!(
+            out,
+            "#1.3\n2\t1\t1\t2\nid\tsequence\tchannel_1\nlabel\tna\tchannel_1\ncondition\tna\ttreated\nP1:K.PEPK.R\tK.PEPK.R\t1\nP1:K.PEPK.R_2\tK.PEPK.R\t2\n"
+        );
+    }
+}
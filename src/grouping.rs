@@ -0,0 +1,114 @@
+//! Parsimonious protein grouping
+//!
+//! A protein whose peptide sequences are all explained by another protein is
+//! subsumed by it: the subsuming protein is retained as the representative of
+//! the group, and the subsumed protein is removed from the dataset. Proteins
+//! with identical sequences are indistinguishable, and the first is kept.
+//!
+//! Each retained protein is given an inference score, so that groups resting
+//! on shared evidence alone remain visible. The score is the number of
+//! sequences matching no other retained protein, plus the fraction of its
+//! PSMs with those sequences: a protein with 2 unique sequences scores
+//! between 2 and 3, and a protein with only shared sequences scores 0.
+use census_proteomics::Dataset;
+use std::collections::{HashMap, HashSet};
+
+/// Inference results for a retained protein
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Group {
+    pub score: f64,
+    /// Accessions of the proteins subsumed by this one
+    pub subsumed: Vec<String>,
+}
+
+/// Remove subsumed proteins from `data`, returning the remaining proteins in
+/// their original order, and the group of each protein keyed by accession
+pub fn group(data: Dataset) -> (Dataset, HashMap<String, Group>) {
+    let (data, groups, _) = group_indexed(data);
+    (data, groups)
+}
+
+/// As `group`, also returning the index in `data` of each remaining protein
+pub fn group_indexed(data: Dataset) -> (Dataset, HashMap<String, Group>, Vec<usize>) {
+    let sequences = data
+        .proteins
+        .iter()
+        .map(|prot| {
+            prot.peptides
+                .iter()
+                .map(|pep| pep.sequence.as_str())
+                .collect::<HashSet<&str>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Visit proteins from the most to the least sequences, so that a
+    // subsuming protein is always retained before those it subsumes
+    let mut order = (0..sequences.len()).collect::<Vec<usize>>();
+    order.sort_by_key(|&idx| std::cmp::Reverse(sequences[idx].len()));
+
+    let mut retained: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut subsumed_by = vec![None; sequences.len()];
+    for idx in order {
+        let seqs = &sequences[idx];
+        let parent = seqs.iter().next().and_then(|seq| {
+            retained.get(seq).and_then(|candidates| {
+                candidates
+                    .iter()
+                    .copied()
+                    .find(|&c| seqs.is_subset(&sequences[c]))
+            })
+        });
+        match parent {
+            Some(p) => subsumed_by[idx] = Some(p),
+            None => {
+                for &seq in seqs {
+                    retained.entry(seq).or_default().push(idx);
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Group> = vec![Group::default(); sequences.len()];
+    for (idx, parent) in subsumed_by.iter().enumerate() {
+        if let Some(p) = parent {
+            groups[*p]
+                .subsumed
+                .push(data.proteins[idx].accession.clone());
+        }
+    }
+    for (idx, prot) in data.proteins.iter().enumerate() {
+        if subsumed_by[idx].is_some() {
+            continue;
+        }
+        let unique = sequences[idx]
+            .iter()
+            .filter(|seq| retained[*seq].len() == 1)
+            .collect::<HashSet<_>>();
+        let psms = prot
+            .peptides
+            .iter()
+            .filter(|pep| unique.contains(&pep.sequence.as_str()))
+            .count();
+        groups[idx].score = unique.len() as f64 + psms as f64 / prot.peptides.len().max(1) as f64;
+    }
+
+    let mut by_accession = HashMap::new();
+    let mut proteins = Vec::with_capacity(data.proteins.len());
+    let mut indices = Vec::with_capacity(data.proteins.len());
+    let items = data.proteins.into_iter().zip(groups).zip(subsumed_by);
+    for (idx, ((prot, group), parent)) in items.enumerate() {
+        if parent.is_none() {
+            by_accession.entry(prot.accession.clone()).or_insert(group);
+            proteins.push(prot);
+            indices.push(idx);
+        }
+    }
+    (
+        Dataset {
+            proteins,
+            channels: data.channels,
+        },
+        by_accession,
+        indices,
+    )
+}
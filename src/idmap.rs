@@ -0,0 +1,76 @@
+//! Rewrite accessions in output tables to another namespace
+//!
+//! A mapping file is tab-delimited, either with a source and target ID on
+//! each line, or in the three column `idmapping.dat` format distributed by
+//! UniProt, with a UniProt accession, a namespace such as `RefSeq`,
+//! `Ensembl_PRO`, or `GeneID`, and an ID in that namespace. Accessions with
+//! several targets are written as a semicolon-separated list, and those
+//! without any are written unchanged.
+use crate::uniprot;
+use std::collections::HashMap;
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+pub struct IdMap {
+    targets: HashMap<String, String>,
+}
+
+impl IdMap {
+    /// Read a mapping file. Three column files require the `namespace` to
+    /// map to, and lines for other namespaces are ignored.
+    pub fn read(text: &str, namespace: Option<&str>) -> std::io::Result<IdMap> {
+        let mut targets: HashMap<String, Vec<&str>> = HashMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split('\t').map(str::trim).collect::<Vec<&str>>();
+            let (from, to) = match (fields.as_slice(), namespace) {
+                ([from, to], _) => (*from, *to),
+                ([from, ns, to, ..], Some(namespace)) => {
+                    if *ns != namespace {
+                        continue;
+                    }
+                    (*from, *to)
+                }
+                ([_, _, _, ..], None) => {
+                    return Err(invalid(
+                        "mappings with a namespace column require --id-namespace",
+                    ))
+                }
+                _ => return Err(invalid(format!("line {} has no target ID", idx + 1))),
+            };
+            if from.is_empty() || to.is_empty() {
+                continue;
+            }
+            let ids = targets.entry(from.to_string()).or_default();
+            if !ids.contains(&to) {
+                ids.push(to);
+            }
+        }
+        if targets.is_empty() {
+            return Err(invalid(match namespace {
+                Some(ns) => format!("no mappings to namespace {}", ns),
+                None => "no mappings".to_string(),
+            }));
+        }
+        Ok(IdMap {
+            targets: targets
+                .into_iter()
+                .map(|(from, to)| (from, to.join(";")))
+                .collect(),
+        })
+    }
+
+    /// Target of `accession`, looked up as written and then as the UniProt
+    /// accession of a locus such as `sp|P12345|ALBU_HUMAN`
+    pub fn map<'a>(&'a self, accession: &'a str) -> &'a str {
+        self.targets
+            .get(accession)
+            .or_else(|| uniprot::accession(accession).and_then(|acc| self.targets.get(acc)))
+            .map(String::as_str)
+            .unwrap_or(accession)
+    }
+}
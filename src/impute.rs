@@ -0,0 +1,64 @@
+//! Imputation of missing channel values
+//!
+//! Reporter ions that are not observed are recorded with an intensity of
+//! zero. Imputation replaces these values with an estimate of the detection
+//! limit of their channel: the smallest non-zero intensity of any PSM in
+//! that channel, or half of it.
+use census_proteomics::Dataset;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Impute {
+    /// Smallest non-zero PSM intensity of the channel
+    Min,
+    /// Half of the smallest non-zero PSM intensity of the channel
+    HalfMin,
+}
+
+impl FromStr for Impute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "min" => Ok(Impute::Min),
+            "half-min" => Ok(Impute::HalfMin),
+            _ => Err(format!("unknown imputation method {}", s)),
+        }
+    }
+}
+
+impl Impute {
+    /// Value imputed for each channel of `data`. Channels without any
+    /// non-zero intensity are imputed as zero.
+    pub fn floors(self, data: &Dataset) -> Vec<u32> {
+        let mut floors = vec![u32::MAX; data.channels as usize];
+        for pep in data.proteins.iter().flat_map(|prot| prot.peptides.iter()) {
+            for (floor, &v) in floors.iter_mut().zip(&pep.values) {
+                if v > 0 && v < *floor {
+                    *floor = v;
+                }
+            }
+        }
+        floors
+            .into_iter()
+            .map(|f| match (f, self) {
+                (u32::MAX, _) => 0,
+                (f, Impute::Min) => f,
+                (f, Impute::HalfMin) => (f / 2).max(1),
+            })
+            .collect()
+    }
+}
+
+/// Replace the zero values of `values` with the corresponding `floors`,
+/// returning the 0-indexed channels that were imputed
+pub fn impute(values: &mut [u32], floors: &[u32]) -> Vec<usize> {
+    let mut imputed = Vec::new();
+    for (idx, (v, &floor)) in values.iter_mut().zip(floors).enumerate() {
+        if *v == 0 && floor > 0 {
+            *v = floor;
+            imputed.push(idx);
+        }
+    }
+    imputed
+}
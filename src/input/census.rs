@@ -0,0 +1,256 @@
+//! census_out reader, checking each line before it is parsed
+//!
+//! The census_proteomics parser stops at the first line it cannot parse, so
+//! a single truncated or corrupted PSM line fails the whole file. Each line
+//! is checked here in the same way the parser reads it. In strict mode the
+//! first malformed line is an error; otherwise malformed lines are removed
+//! and returned, and the remaining lines are parsed. A malformed P line
+//! removes the PSMs of its protein along with it.
+use super::*;
+
+/// A malformed line, removed from the input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reject {
+    /// 1-indexed line number
+    pub line: usize,
+    pub reason: String,
+    pub text: String,
+}
+
+/// Check a P line, which the parser reads as the accession, spectral
+/// count, sequence count, sequence coverage, molecular weight, and a
+/// description in the last field
+fn check_protein(line: &str) -> Result<(), String> {
+    let fields = line.split('\t').collect::<Vec<&str>>();
+    if fields[0] != "P" {
+        return Err(format!("unrecognized line type {:?}", fields[0]));
+    }
+    if fields.len() < 7 {
+        return Err(format!(
+            "protein line has {} fields, expected at least 7",
+            fields.len()
+        ));
+    }
+    if fields[2].parse::<u16>().is_err() {
+        return Err(format!("invalid spectral count {:?}", fields[2]));
+    }
+    if fields[3].parse::<u16>().is_err() {
+        return Err(format!("invalid sequence count {:?}", fields[3]));
+    }
+    if fields[4].trim_end_matches('%').parse::<f32>().is_err() {
+        return Err(format!("invalid sequence coverage {:?}", fields[4]));
+    }
+    if fields[5].parse::<u32>().is_err() {
+        return Err(format!("invalid molecular weight {:?}", fields[5]));
+    }
+    Ok(())
+}
+
+/// Check an S line, which the parser reads as the uniqueness marker, the
+/// sequence, and a raw and normalized intensity for each channel
+fn check_peptide(line: &str, channels: u8) -> Result<(), String> {
+    let fields = line.split('\t').collect::<Vec<&str>>();
+    if fields[0] != "S" {
+        return Err(format!("unrecognized line type {:?}", fields[0]));
+    }
+    let expected = 3 + 2 * channels as usize;
+    if fields.len() < expected {
+        return Err(format!(
+            "PSM line has {} fields, expected at least {} for {} channels",
+            fields.len(),
+            expected,
+            channels
+        ));
+    }
+    if fields[1].len() > 1 {
+        return Err(format!("invalid uniqueness marker {:?}", fields[1]));
+    }
+    for c in 0..channels as usize {
+        let raw = fields[3 + 2 * c];
+        if raw.parse::<u32>().is_err() {
+            return Err(format!("invalid intensity {:?} for channel {}", raw, c + 1));
+        }
+    }
+    Ok(())
+}
+
+/// Characters of a malformed line shown in diagnostics
+const SNIPPET: usize = 120;
+
+impl Reject {
+    /// Start of the line, with tabs shown as `\t` so that empty and missing
+    /// fields can be seen
+    pub fn snippet(&self) -> String {
+        let mut snippet = self
+            .text
+            .chars()
+            .take(SNIPPET)
+            .collect::<String>()
+            .replace('\t', "\\t");
+        if self.text.chars().count() > SNIPPET {
+            snippet.push_str("...");
+        }
+        snippet
+    }
+}
+
+/// Version of Census that wrote the file, from a `H  Census version` line
+fn version(text: &str) -> Option<&str> {
+    text.lines()
+        .take_while(|l| l.starts_with('H'))
+        .find_map(|l| l.split('\t').nth(1)?.strip_prefix("Census version "))
+        .map(str::trim)
+}
+
+/// Channels declared by the intensity columns of the SLINE header
+fn declared_channels(text: &str) -> Option<usize> {
+    text.lines()
+        .take_while(|l| l.starts_with('H'))
+        .find(|l| l.contains("m/z"))
+        .map(|l| l.matches("m/z_").count() / 2)
+}
+
+/// What is known about the file, to help explain why a line is malformed
+fn hint(text: &str) -> String {
+    let written = match version(text) {
+        Some(v) => format!("was written by Census version {}", v),
+        None => "has no Census version header".to_string(),
+    };
+    match declared_channels(text) {
+        Some(0) | None => format!(
+            "the file {}, and declares no reporter ion intensity columns. It may not be an isobaric labeling census file; SILAC files can be read with the silac subcommand",
+            written
+        ),
+        Some(n) => format!(
+            "the file {}, and declares {} channels in the SLINE header",
+            written, n
+        ),
+    }
+}
+
+/// Check every line of `text`, returning the lines to parse and the
+/// malformed lines
+fn check(text: &str) -> (Vec<&str>, Vec<Reject>) {
+    let mut kept = Vec::new();
+    let mut rejects = Vec::new();
+    let mut channels = 0;
+    // Is there a protein for S lines to belong to, and was it kept?
+    let mut protein = None;
+    for (idx, line) in text.lines().enumerate() {
+        let result = match line.chars().next() {
+            Some('H') => {
+                // As for the parser, a header line ends the current protein
+                protein = None;
+                if line.contains("m/z") {
+                    channels = (line.matches("m/z_").count() / 2) as u8;
+                }
+                Ok(())
+            }
+            Some('P') => {
+                let result = check_protein(line);
+                protein = Some(result.is_ok());
+                result
+            }
+            Some('S') => match protein {
+                Some(true) => check_peptide(line, channels),
+                Some(false) => Err("PSM of a malformed protein line".into()),
+                None => Err("PSM line before any protein line".into()),
+            },
+            Some(c) => Err(format!("unrecognized line type {:?}", c)),
+            None => Err("empty line".into()),
+        };
+        match result {
+            Ok(()) => kept.push(line),
+            Err(reason) => rejects.push(Reject {
+                line: idx + 1,
+                reason,
+                text: line.to_string(),
+            }),
+        }
+    }
+    (kept, rejects)
+}
+
+/// Parse the census_out file `text`. Malformed lines are an error if
+/// `strict` is true, and are otherwise removed and returned. Errors give the
+/// line number, the start of the line, and a hint about the file.
+pub fn parse(text: &str, strict: bool) -> std::io::Result<(Dataset, Vec<Reject>)> {
+    let (kept, rejects) = check(text);
+    if let (true, Some(reject)) = (strict, rejects.first()) {
+        return Err(invalid(format!(
+            "line {}: {}\n  {}\n  hint: {}",
+            reject.line,
+            reject.reason,
+            reject.snippet(),
+            hint(text)
+        )));
+    }
+    let data = if rejects.is_empty() {
+        census_proteomics::read_census(text)
+    } else {
+        census_proteomics::read_census(&kept.join("\n"))
+    };
+    data.map(|data| (data, rejects))
+        .map_err(|e| invalid(format!("{}\n  hint: {}", e, hint(text))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FILE: &str = "\
+H\tCensus version 2.51
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t2\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t100\t0.5
+S\tU\tK.PEPTIDER.L\t200\t0.5\t200\t0.5
+P\tQ67890\t1\t1\t10.0%\t100\t10000\tKinase
+S\t\tR.SEQR.A\t300\t0.5\t300\t0.5";
+
+    #[test]
+    fn parse_valid() {
+        let (data, rejects) = parse(FILE, true).unwrap();
+        assert!(rejects.is_empty());
+        assert_eq!(data.channels, 2);
+        assert_eq!(data.proteins.len(), 2);
+        assert_eq!(data.proteins[0].peptides[1].values, vec![200, 200]);
+    }
+
+    #[test]
+    fn line_type_is_the_whole_first_field() {
+        let psm = FILE.replace("S\tU\tK.PEPTIDER", "Sx\tU\tK.PEPTIDER");
+        let (data, rejects) = parse(&psm, false).unwrap();
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].line, 5);
+        assert_eq!(data.proteins[0].peptides.len(), 1);
+        assert!(parse(&psm, true).is_err());
+
+        let protein = FILE.replace("P\tQ67890", "PX\tQ67890");
+        let (data, rejects) = parse(&protein, false).unwrap();
+        // The protein line and its PSM
+        assert_eq!(rejects.len(), 2);
+        assert_eq!(data.proteins.len(), 1);
+        assert!(parse(&protein, true).is_err());
+    }
+
+    #[test]
+    fn malformed_psms_are_skipped_unless_strict() {
+        let text = FILE.replace("\t200\t0.5\t200", "\t200\t0.5\tx");
+        let (data, rejects) = parse(&text, false).unwrap();
+        assert_eq!(rejects.len(), 1);
+        assert!(rejects[0].reason.contains("channel 2"));
+        assert_eq!(data.proteins[0].peptides.len(), 1);
+
+        let e = parse(&text, true).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("line 5"));
+    }
+
+    #[test]
+    fn psm_before_protein() {
+        let text = FILE.replace("P\tP12345\t2\t2\t30.3%\t335\t82944\tSerum albumin\n", "");
+        let (data, rejects) = parse(&text, false).unwrap();
+        assert_eq!(rejects.len(), 2);
+        assert_eq!(data.proteins.len(), 1);
+    }
+}
@@ -0,0 +1,169 @@
+//! DTASelect-filter.txt reader
+//!
+//! DTASelect files contain protein and peptide evidence but no reporter ion
+//! intensities, so the resulting dataset has zero channels. Protein filters
+//! and spectral/sequence counts work as they do for census files.
+//!
+//! Consecutive protein lines that are followed by a single set of peptide
+//! lines are indistinguishable proteins; each protein in such a group is
+//! given a copy of the group's peptides.
+use super::*;
+
+/// Scan number from a file name of the form file.scan.scan.charge
+fn scan(file: &str) -> usize {
+    file.rsplit('.')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Peptide lines begin with a uniqueness marker (* or empty), or a
+/// redundancy count
+fn is_peptide_line(first: &str) -> bool {
+    first.is_empty() || first == "*" || first.chars().all(|c| c.is_ascii_digit())
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Dataset> {
+    let file = fs::read_to_string(path)?;
+    let mut lines = file.lines();
+
+    // Skip the preamble, up to and including the protein and peptide headers
+    let mut protein_header = Vec::new();
+    for line in lines.by_ref() {
+        if line.starts_with("Locus\t") {
+            protein_header = line.split('\t').collect::<Vec<&str>>();
+            break;
+        }
+    }
+    let peptide_header = lines
+        .next()
+        .ok_or_else(|| invalid("DTASelect file is missing the peptide header"))?
+        .split('\t')
+        .collect::<Vec<&str>>();
+    if protein_header.is_empty() || peptide_header.first() != Some(&"Unique") {
+        return Err(invalid("DTASelect file is missing the protein header"));
+    }
+
+    let col = |header: &[&str], name: &str| header.iter().position(|h| *h == name);
+    let spec = col(&protein_header, "Spectrum Count");
+    let seqs = col(&protein_header, "Sequence Count");
+    let coverage = col(&protein_header, "Sequence Coverage");
+    let mw = col(&protein_header, "MolWt");
+    let desc = col(&protein_header, "Descriptive Name");
+    let sequence = col(&peptide_header, "Sequence")
+        .ok_or_else(|| invalid("DTASelect file is missing the Sequence column"))?;
+    let filename = col(&peptide_header, "FileName");
+
+    let get = |fields: &[&str], idx: Option<usize>| -> String {
+        idx.and_then(|i| fields.get(i))
+            .map(|s| s.to_string())
+            .unwrap_or_default()
+    };
+
+    let mut proteins: Vec<Protein> = Vec::new();
+    // Index of the first protein in the current indistinguishable group
+    let mut group = 0;
+    let mut in_peptides = false;
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        if fields.get(1) == Some(&"Proteins") {
+            // Start of the summary table
+            break;
+        }
+
+        if is_peptide_line(fields[0]) {
+            in_peptides = true;
+            let pep = Peptide {
+                sequence: get(&fields, Some(sequence)),
+                values: Vec::new(),
+                unique: fields[0] == "*",
+                purity: 1.0,
+                scan: scan(&get(&fields, filename)),
+            };
+            for prot in &mut proteins[group..] {
+                prot.peptides.push(pep.clone());
+            }
+        } else {
+            if in_peptides || proteins.is_empty() {
+                group = proteins.len();
+                in_peptides = false;
+            }
+            proteins.push(Protein {
+                accession: fields[0].to_string(),
+                description: get(&fields, desc),
+                spectral_count: get(&fields, spec).parse().unwrap_or(0),
+                sequence_count: get(&fields, seqs).parse().unwrap_or(0),
+                sequence_coverage: get(&fields, coverage)
+                    .trim_end_matches('%')
+                    .parse()
+                    .unwrap_or(0.0),
+                molecular_weight: get(&fields, mw).parse().unwrap_or(0),
+                peptides: Vec::new(),
+                channels: 0,
+            });
+        }
+    }
+
+    Ok(Dataset {
+        proteins,
+        channels: 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const FILE: &str = "\
+DTASelect v2.1.12
+Locus\tSequence Count\tSpectrum Count\tSequence Coverage\tLength\tMolWt\tpI\tValidation Status\tDescriptive Name
+Unique\tFileName\tXCorr\tDeltCN\tConf%\tM+H+\tCalcM+H+\tTotalIntensity\tSpR\tSpScore\tIonProportion\tRedundancy\tSequence
+P1\t2\t3\t25.5%\t100\t11000\t7.0\tU\tKinase
+*\trun.100.100.2\t3.0\t0.3\t100\t1000\t1000\t1000\t1\t1\t50\t1\tK.PEPTIDEK.L
+\trun.101.101.2\t2.0\t0.2\t100\t1000\t1000\t1000\t1\t1\t50\t2\tR.SEQR.A
+P2\t1\t1\t10%\t100\t9000\t7.0\tU\tIsoform 1
+P3\t1\t1\t10%\t100\t9000\t7.0\tU\tIsoform 2
+2\trun.102.102.2\t2.0\t0.2\t100\t1000\t1000\t1000\t1\t1\t50\t1\tK.SHARED.R
+
+\tProteins\tPeptide IDs\tSpectra
+Unfiltered\t3\t3\t3
+";
+
+    #[test]
+    fn read_proteins() {
+        let dir = fixture::dir("dtaselect", &[("DTASelect-filter.txt", FILE)]);
+        let data = read(dir.join("DTASelect-filter.txt")).unwrap();
+        assert_eq!(data.channels, 0);
+        assert_eq!(data.proteins.len(), 3);
+
+        let prot = &data.proteins[0];
+        assert_eq!(prot.accession, "P1");
+        assert_eq!(prot.description, "Kinase");
+        assert_eq!((prot.spectral_count, prot.sequence_count), (3, 2));
+        assert_eq!(prot.sequence_coverage, 25.5);
+        assert_eq!(prot.molecular_weight, 11000);
+        assert_eq!(prot.peptides.len(), 2);
+        assert!(prot.peptides[0].unique && !prot.peptides[1].unique);
+        assert_eq!(prot.peptides[1].scan, 101);
+        assert!(prot.peptides[0].values.is_empty());
+
+        // Indistinguishable proteins each get a copy of the group's peptides
+        for prot in &data.proteins[1..] {
+            assert_eq!(prot.peptides.len(), 1);
+            assert_eq!(prot.peptides[0].sequence, "K.SHARED.R");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn require_headers() {
+        let dir = fixture::dir(
+            "dtaselect-missing",
+            &[("DTASelect-filter.txt", "DTASelect v2.1.12\nP1\t1\n")],
+        );
+        let e = read(dir.join("DTASelect-filter.txt")).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! FragPipe / Philosopher psm.tsv reader
+//!
+//! Reporter intensity columns are identified by their TMT label names (126,
+//! 127N, ...). If the channels have been renamed with sample names by an
+//! annotation file, every column following Purity is treated as a channel.
+//! Peptide sequences are written in census style with flanking residues, so
+//! that the `Tryptic` filter applies.
+use super::*;
+use crate::labels::is_channel_column;
+
+fn accession(id: &str) -> String {
+    match id.strip_prefix("rev_") {
+        Some(id) => format!("Reverse_{}", id),
+        None => id.to_string(),
+    }
+}
+
+/// Scan number from a spectrum identifier of the form file.scan.scan.charge
+fn scan(spectrum: &str) -> usize {
+    spectrum
+        .rsplit('.')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Dataset> {
+    let table = Table::read(&path)?;
+
+    let mut channels = table
+        .header
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| is_channel_column(h))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<usize>>();
+    let purity = table.column("Purity");
+    if channels.is_empty() {
+        if let Some(p) = purity {
+            channels = (p + 1..table.header.len()).collect();
+        }
+    }
+    if channels.is_empty() {
+        return Err(invalid("psm.tsv has no reporter intensity columns"));
+    }
+
+    let peptide = table.require("Peptide")?;
+    let modified = table.column("Modified Peptide");
+    let prev = table.column("Prev AA");
+    let next = table.column("Next AA");
+    let protein = table
+        .column("Protein ID")
+        .map(Ok)
+        .unwrap_or_else(|| table.require("Protein"))?;
+    let description = table.column("Protein Description");
+    let unique = table.column("Is Unique");
+    let spectrum = table.column("Spectrum");
+
+    let mut info = HashMap::new();
+    let mut psms = Vec::with_capacity(table.rows.len());
+    for row in &table.rows {
+        let acc = accession(field(row, protein));
+        if acc.is_empty() {
+            continue;
+        }
+        if let Some(i) = description {
+            info.entry(acc.clone()).or_insert_with(|| ProteinInfo {
+                description: field(row, i).to_string(),
+                ..ProteinInfo::default()
+            });
+        }
+
+        let seq = modified
+            .map(|i| field(row, i))
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| field(row, peptide));
+        let flank = |col: Option<usize>| match col.map(|i| field(row, i)) {
+            Some(aa) if !aa.is_empty() => aa.to_string(),
+            _ => "-".to_string(),
+        };
+
+        let values = channels
+            .iter()
+            .map(|&i| field(row, i).parse::<f64>().unwrap_or(0.0).max(0.0).round() as u32)
+            .collect();
+
+        psms.push((
+            acc,
+            Peptide {
+                sequence: format!("{}.{}.{}", flank(prev), seq, flank(next)),
+                values,
+                unique: unique
+                    .map(|i| field(row, i).eq_ignore_ascii_case("true"))
+                    .unwrap_or(true),
+                purity: purity
+                    .and_then(|i| field(row, i).parse().ok())
+                    .unwrap_or(1.0),
+                scan: spectrum.map(|i| scan(field(row, i))).unwrap_or(0),
+            },
+        ));
+    }
+
+    Ok(assemble(psms, &info, channels.len() as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const PSMS: &str = "\
+Spectrum\tPeptide\tModified Peptide\tPrev AA\tNext AA\tProtein ID\tProtein Description\tIs Unique\tPurity\t126\t127N
+run.00100.00100.2\tPEPTIDEK\tPEPTIDEK[42]\tK\tL\tP1\tKinase\ttrue\t0.8\t10.4\t20.6
+run.00101.00101.2\tSEQR\t\t\t\trev_P2\tDecoy\tfalse\t\t-1\t5
+run.00102.00102.2\tAAAR\t\tR\tA\tP1\tOther\tTRUE\t0.9\t1\t2
+\tNONE\t\t\t\t\t\t\t\t1\t1
+";
+
+    #[test]
+    fn read_psms() {
+        let dir = fixture::dir("fragpipe", &[("psm.tsv", PSMS)]);
+        let data = read(dir.join("psm.tsv")).unwrap();
+        assert_eq!(data.channels, 2);
+        assert_eq!(data.proteins.len(), 2);
+
+        let prot = &data.proteins[0];
+        assert_eq!(prot.accession, "P1");
+        // The first description of each protein is kept
+        assert_eq!(prot.description, "Kinase");
+        assert_eq!(prot.spectral_count, 2);
+        let pep = &prot.peptides[0];
+        assert_eq!(pep.sequence, "K.PEPTIDEK[42].L");
+        assert_eq!(pep.values, vec![10, 21]);
+        assert!(pep.unique);
+        assert_eq!((pep.purity, pep.scan), (0.8, 100));
+        assert!(prot.peptides[1].unique);
+
+        let decoy = &data.proteins[1];
+        assert_eq!(decoy.accession, "Reverse_P2");
+        assert_eq!(decoy.peptides[0].sequence, "-.SEQR.-");
+        assert_eq!(decoy.peptides[0].values, vec![0, 5]);
+        assert!(!decoy.peptides[0].unique);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renamed_channels_follow_purity() {
+        let text =
+            "Peptide\tProtein\tPurity\tsample_a\tsample_b\tsample_c\nPEPTIDEK\tP1\t1\t1\t2\t3\n";
+        let dir = fixture::dir("fragpipe-renamed", &[("psm.tsv", text)]);
+        let data = read(dir.join("psm.tsv")).unwrap();
+        assert_eq!(data.channels, 3);
+        assert_eq!(data.proteins[0].peptides[0].values, vec![1, 2, 3]);
+
+        fs::write(dir.join("psm.tsv"), "Peptide\tProtein\nPEPTIDEK\tP1\n").unwrap();
+        assert!(read(dir.join("psm.tsv")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
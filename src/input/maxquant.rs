@@ -0,0 +1,186 @@
+//! MaxQuant evidence.txt reader
+//!
+//! Each row of evidence.txt becomes a PSM, assigned to its leading razor
+//! protein. Corrected reporter intensities are used when present. If a
+//! proteinGroups.txt file is found alongside evidence.txt, it is used to fill
+//! in protein descriptions, sequence coverage, and molecular weight.
+//!
+//! MaxQuant marks decoys with a REV__ prefix; these are renamed with the
+//! census Reverse_ prefix so that `ExcludeReverse` behaves as expected.
+//! MaxQuant does not report flanking residues in evidence.txt, so sequences
+//! are written without them and the `Tryptic` filter will not match.
+use super::*;
+
+/// Reporter intensity columns with the given prefix, ordered by channel
+/// number
+fn reporter_columns(table: &Table, prefix: &str) -> Vec<usize> {
+    let mut cols = table
+        .header
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, h)| {
+            h.strip_prefix(prefix)
+                .and_then(|n| n.parse::<usize>().ok())
+                .map(|n| (n, idx))
+        })
+        .collect::<Vec<(usize, usize)>>();
+    cols.sort_unstable();
+    cols.into_iter().map(|(_, idx)| idx).collect()
+}
+
+fn accession(id: &str) -> String {
+    match id.strip_prefix("REV__") {
+        Some(id) => format!("Reverse_{}", id),
+        None => id.to_string(),
+    }
+}
+
+fn read_protein_groups<P: AsRef<Path>>(path: P) -> std::io::Result<HashMap<String, ProteinInfo>> {
+    let table = Table::read(path)?;
+    let ids = table.require("Protein IDs")?;
+    let names = table
+        .column("Protein names")
+        .or_else(|| table.column("Fasta headers"));
+    let coverage = table.column("Sequence coverage [%]");
+    let mw = table.column("Mol. weight [kDa]");
+
+    let mut info = HashMap::new();
+    for row in &table.rows {
+        let meta = ProteinInfo {
+            description: names.map(|i| field(row, i).to_string()).unwrap_or_default(),
+            sequence_coverage: coverage
+                .and_then(|i| field(row, i).parse().ok())
+                .unwrap_or(0.0),
+            molecular_weight: mw
+                .and_then(|i| field(row, i).parse::<f64>().ok())
+                .map(|kda| (kda * 1000.0) as u32)
+                .unwrap_or(0),
+        };
+        for id in field(row, ids).split(';') {
+            info.insert(accession(id), meta.clone());
+        }
+    }
+    Ok(info)
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Dataset> {
+    let table = Table::read(&path)?;
+
+    let mut channels = reporter_columns(&table, "Reporter intensity corrected ");
+    if channels.is_empty() {
+        channels = reporter_columns(&table, "Reporter intensity ");
+    }
+    if channels.is_empty() {
+        return Err(invalid("evidence.txt has no reporter intensity columns"));
+    }
+
+    let sequence = table
+        .column("Modified sequence")
+        .map(Ok)
+        .unwrap_or_else(|| table.require("Sequence"))?;
+    let proteins = table.require("Proteins")?;
+    let razor = table.column("Leading razor protein");
+    let reverse = table.column("Reverse");
+    let pif = table.column("PIF");
+    let scan = table.column("MS/MS scan number");
+
+    let mut psms = Vec::with_capacity(table.rows.len());
+    for row in &table.rows {
+        let all = field(row, proteins);
+        let leading = razor
+            .map(|i| field(row, i))
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| all.split(';').next().unwrap_or(""));
+        if leading.is_empty() {
+            continue;
+        }
+        let mut acc = accession(leading);
+        if reverse.map(|i| field(row, i) == "+").unwrap_or(false) && !acc.contains("Reverse") {
+            acc = format!("Reverse_{}", acc);
+        }
+
+        let values = channels
+            .iter()
+            .map(|&i| field(row, i).parse::<f64>().unwrap_or(0.0).max(0.0).round() as u32)
+            .collect();
+
+        psms.push((
+            acc,
+            Peptide {
+                sequence: field(row, sequence).trim_matches('_').to_string(),
+                values,
+                unique: !all.contains(';'),
+                purity: pif.and_then(|i| field(row, i).parse().ok()).unwrap_or(1.0),
+                scan: scan.and_then(|i| field(row, i).parse().ok()).unwrap_or(0),
+            },
+        ));
+    }
+
+    let groups = path.as_ref().with_file_name("proteinGroups.txt");
+    let info = if groups.exists() {
+        read_protein_groups(groups)?
+    } else {
+        HashMap::new()
+    };
+
+    Ok(assemble(psms, &info, channels.len() as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const EVIDENCE: &str = "\
+Sequence\tModified sequence\tProteins\tLeading razor protein\tReverse\tPIF\tMS/MS scan number\tReporter intensity 2\tReporter intensity 1\tReporter intensity corrected 1\tReporter intensity corrected 2
+PEPTIDEK\t_PEPTIDEK_\tP1;P2\tP2\t\t0.9\t100\t20\t10\t11.4\t21.6
+SEQR\t_SEQR_\tREV__P3\t\t+\t\t101\t5\t5\t-3\t7
+NONE\t_NONE_\t\t\t\t\t102\t1\t1\t1\t1
+";
+
+    const GROUPS: &str = "\
+Protein IDs\tProtein names\tSequence coverage [%]\tMol. weight [kDa]
+P1;P2\tKinase\t12.5\t50.25
+";
+
+    #[test]
+    fn read_evidence() {
+        let dir = fixture::dir("maxquant", &[("evidence.txt", EVIDENCE)]);
+        let data = read(dir.join("evidence.txt")).unwrap();
+        assert_eq!(data.channels, 2);
+        assert_eq!(data.proteins.len(), 2);
+
+        let prot = &data.proteins[0];
+        assert_eq!(prot.accession, "P2");
+        let pep = &prot.peptides[0];
+        // Corrected intensities are preferred, in channel order
+        assert_eq!(pep.values, vec![11, 22]);
+        assert_eq!(pep.sequence, "PEPTIDEK");
+        assert!(!pep.unique);
+        assert_eq!((pep.purity, pep.scan), (0.9, 100));
+        assert!(prot.description.is_empty());
+
+        let decoy = &data.proteins[1];
+        assert_eq!(decoy.accession, "Reverse_P3");
+        assert_eq!(decoy.peptides[0].values, vec![0, 7]);
+        assert_eq!(decoy.peptides[0].purity, 1.0);
+
+        fs::write(dir.join("proteinGroups.txt"), GROUPS).unwrap();
+        let data = read(dir.join("evidence.txt")).unwrap();
+        assert_eq!(data.proteins[0].description, "Kinase");
+        assert_eq!(data.proteins[0].sequence_coverage, 12.5);
+        assert_eq!(data.proteins[0].molecular_weight, 50250);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn require_reporter_intensities() {
+        let dir = fixture::dir(
+            "maxquant-missing",
+            &[("evidence.txt", "Sequence\tProteins\nPEPTIDEK\tP1\n")],
+        );
+        let e = read(dir.join("evidence.txt")).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Readers for census files and for the outputs of other search engines
+//!
+//! Each reader maps its input onto the census data model, so that the same
+//! filters, rollups, and output layouts apply regardless of which tool was
+//! used upstream.
+use census_proteomics::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub use census::Reject;
+
+mod census;
+mod dtaselect;
+mod fragpipe;
+mod maxquant;
+mod mztab;
+mod pd;
+
+/// Supported input file formats
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputFormat {
+    Census,
+    /// MaxQuant evidence.txt, with optional proteinGroups.txt
+    MaxQuant,
+    /// FragPipe / Philosopher psm.tsv
+    FragPipe,
+    /// Proteome Discoverer tab-delimited PSM export
+    ProteomeDiscoverer,
+    /// DTASelect-filter.txt, containing spectral counts only
+    DtaSelect,
+    /// mzTab 1.0, using PSM or PEP abundance columns
+    MzTab,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "census" => Ok(InputFormat::Census),
+            "maxquant" => Ok(InputFormat::MaxQuant),
+            "fragpipe" => Ok(InputFormat::FragPipe),
+            "pd" => Ok(InputFormat::ProteomeDiscoverer),
+            "dtaselect" => Ok(InputFormat::DtaSelect),
+            "mztab" => Ok(InputFormat::MzTab),
+            _ => Err(format!("unknown input format {}", s)),
+        }
+    }
+}
+
+/// Read and parse an input file from disk. Any malformed line is an error.
+pub fn read<P: AsRef<Path>>(path: P, format: InputFormat) -> std::io::Result<Dataset> {
+    read_lenient(path, format, true).map(|(data, _)| data)
+}
+
+/// Parse the contents of a census file, checking every line first so that
+/// malformed input is an error rather than a panic
+pub fn parse_census(text: &str) -> std::io::Result<Dataset> {
+    census::parse(text, true).map(|(data, _)| data)
+}
+
+/// Read and parse an input file from disk. Malformed lines of census files
+/// are an error if `strict` is true, and are otherwise skipped and returned.
+pub fn read_lenient<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    strict: bool,
+) -> std::io::Result<(Dataset, Vec<Reject>)> {
+    let data = match format {
+        InputFormat::Census => {
+            let file = fs::read_to_string(path)?;
+            return census::parse(&file, strict);
+        }
+        InputFormat::MaxQuant => maxquant::read(path),
+        InputFormat::FragPipe => fragpipe::read(path),
+        InputFormat::ProteomeDiscoverer => pd::read(path),
+        InputFormat::DtaSelect => dtaselect::read(path),
+        InputFormat::MzTab => mztab::read(path),
+    }?;
+    Ok((data, Vec::new()))
+}
+
+pub(crate) fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Tab-delimited table with a header row. Surrounding double quotes, which
+/// some exporters add to every field, are removed.
+pub(crate) struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Table> {
+        let file = fs::read_to_string(path)?;
+        let mut lines = file.lines().filter(|l| !l.trim().is_empty());
+        let header = lines
+            .next()
+            .ok_or_else(|| invalid("empty input file"))?
+            .split('\t')
+            .map(|s| s.trim().trim_matches('"').to_string())
+            .collect::<Vec<String>>();
+        let rows = lines
+            .map(|l| {
+                l.split('\t')
+                    .map(|s| s.trim_matches('"').to_string())
+                    .collect()
+            })
+            .collect();
+        Ok(Table { header, rows })
+    }
+
+    /// Index of the column named `name`
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h == name)
+    }
+
+    /// Index of the column named `name`, or an error naming the missing
+    /// column
+    pub fn require(&self, name: &str) -> std::io::Result<usize> {
+        self.column(name)
+            .ok_or_else(|| invalid(format!("input is missing column {}", name)))
+    }
+}
+
+/// Field `idx` of `row`, or an empty string
+pub(crate) fn field(row: &[String], idx: usize) -> &str {
+    row.get(idx).map(|s| s.as_str()).unwrap_or("")
+}
+
+/// Protein-level metadata that is not derived from PSMs
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ProteinInfo {
+    pub description: String,
+    pub sequence_coverage: f32,
+    pub molecular_weight: u32,
+}
+
+/// Group `(accession, Peptide)` PSMs into proteins, in the order each
+/// accession is first encountered. Spectral and sequence counts are computed
+/// from the PSMs.
+pub(crate) fn assemble(
+    psms: Vec<(String, Peptide)>,
+    info: &HashMap<String, ProteinInfo>,
+    channels: u8,
+) -> Dataset {
+    let mut proteins: Vec<Protein> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (accession, pep) in psms {
+        let idx = *index.entry(accession.clone()).or_insert_with(|| {
+            let meta = info.get(&accession).cloned().unwrap_or_default();
+            proteins.push(Protein {
+                accession,
+                description: meta.description,
+                sequence_coverage: meta.sequence_coverage,
+                molecular_weight: meta.molecular_weight,
+                channels,
+                ..Protein::default()
+            });
+            proteins.len() - 1
+        });
+        proteins[idx].peptides.push(pep);
+    }
+
+    for prot in &mut proteins {
+        let mut seqs = prot
+            .peptides
+            .iter()
+            .map(|p| p.sequence.as_str())
+            .collect::<Vec<&str>>();
+        seqs.sort_unstable();
+        seqs.dedup();
+        prot.spectral_count = prot.peptides.len() as u16;
+        prot.sequence_count = seqs.len() as u16;
+    }
+
+    Dataset { proteins, channels }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn input_formats() {
+        assert_eq!("pd".parse(), Ok(InputFormat::ProteomeDiscoverer));
+        assert_eq!("mztab".parse(), Ok(InputFormat::MzTab));
+        assert!("census_out".parse::<InputFormat>().is_err());
+    }
+
+    #[test]
+    fn read_table() {
+        let dir = fixture::dir(
+            "table",
+            &[
+                ("quoted.tsv", "\"A\"\t B \n\n\"1\"\t2\n3\n"),
+                ("empty.tsv", "\n  \n"),
+            ],
+        );
+        let table = Table::read(dir.join("quoted.tsv")).unwrap();
+        assert_eq!(table.header, vec!["A", "B"]);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(field(&table.rows[0], 0), "1");
+        assert_eq!(field(&table.rows[1], 1), "");
+        assert_eq!(table.require("B").unwrap(), 1);
+        let e = table.require("C").err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        assert!(Table::read(dir.join("empty.tsv")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn assemble_in_order_of_appearance() {
+        let psm = |sequence: &str| Peptide {
+            sequence: sequence.to_string(),
+            values: vec![1, 2],
+            ..Peptide::default()
+        };
+        let psms = vec![
+            ("B".to_string(), psm("K.AAA.R")),
+            ("A".to_string(), psm("K.CCC.R")),
+            ("B".to_string(), psm("K.AAA.R")),
+            ("B".to_string(), psm("K.DDD.R")),
+        ];
+        let mut info = HashMap::new();
+        info.insert(
+            "A".to_string(),
+            ProteinInfo {
+                description: "Protein A".to_string(),
+                ..ProteinInfo::default()
+            },
+        );
+        let data = assemble(psms, &info, 2);
+        assert_eq!(data.channels, 2);
+        let accessions = data
+            .proteins
+            .iter()
+            .map(|p| p.accession.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(accessions, vec!["B", "A"]);
+        assert_eq!(
+            (
+                data.proteins[0].spectral_count,
+                data.proteins[0].sequence_count
+            ),
+            (3, 2)
+        );
+        assert_eq!(data.proteins[1].description, "Protein A");
+        assert!(data.proteins[0].description.is_empty());
+    }
+}
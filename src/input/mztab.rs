@@ -0,0 +1,219 @@
+//! mzTab 1.0 reader
+//!
+//! Reporter intensities are read from the `abundance_assay[n]` columns, with
+//! one assay per channel. PSM rows are used when the PSM section reports
+//! abundances, and PEP rows otherwise. Protein descriptions and coverage are
+//! taken from the PRT section.
+//!
+//! Rows flagged by the PSI-MS decoy peptide column are given the census
+//! Reverse_ prefix, so that `ExcludeReverse` behaves as expected.
+use super::*;
+
+/// Optional column used by OpenMS and others to flag decoy rows
+const DECOY: &str = "opt_global_cv_MS:1002217_decoy_peptide";
+
+/// Split the tab-delimited sections of an mzTab file, keyed on the line
+/// prefix of their rows (PRT, PEP, PSM)
+fn sections(file: &str) -> HashMap<&str, Table> {
+    let mut tables: HashMap<&str, Table> = HashMap::new();
+    for line in file.lines() {
+        let mut fields = line.split('\t');
+        let prefix = fields.next().unwrap_or("");
+        let fields = fields
+            .map(|s| s.trim().to_string())
+            .collect::<Vec<String>>();
+        let key = match prefix {
+            "PRH" | "PRT" => "PRT",
+            "PEH" | "PEP" => "PEP",
+            "PSH" | "PSM" => "PSM",
+            _ => continue,
+        };
+        let table = tables.entry(key).or_insert(Table {
+            header: Vec::new(),
+            rows: Vec::new(),
+        });
+        if prefix.ends_with('H') {
+            table.header = fields;
+        } else {
+            table.rows.push(fields);
+        }
+    }
+    tables
+}
+
+/// Abundance columns ordered by assay number
+fn abundance_columns(table: &Table) -> Vec<usize> {
+    let mut cols = table
+        .header
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, h)| {
+            let start = h.find("abundance_assay[")? + "abundance_assay[".len();
+            let end = start + h[start..].find(']')?;
+            h[start..end].parse::<usize>().ok().map(|n| (n, idx))
+        })
+        .collect::<Vec<(usize, usize)>>();
+    cols.sort_unstable();
+    cols.into_iter().map(|(_, idx)| idx).collect()
+}
+
+/// Scan number from a spectra_ref such as ms_run[1]:scan=1234
+fn scan(spectra_ref: &str) -> usize {
+    spectra_ref
+        .split(|c: char| c == '=' || c.is_whitespace())
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+fn proteins(table: &Table) -> HashMap<String, ProteinInfo> {
+    let (acc, desc, cov) = (
+        table.column("accession"),
+        table.column("description"),
+        table.column("protein_coverage"),
+    );
+    let mut info = HashMap::new();
+    if let Some(acc) = acc {
+        for row in &table.rows {
+            let value = |col: Option<usize>| {
+                col.map(|i| field(row, i))
+                    .filter(|s| *s != "null")
+                    .unwrap_or("")
+            };
+            info.insert(
+                field(row, acc).to_string(),
+                ProteinInfo {
+                    description: value(desc).to_string(),
+                    // protein_coverage is reported as a fraction
+                    sequence_coverage: value(cov).parse::<f32>().unwrap_or(0.0) * 100.0,
+                    molecular_weight: 0,
+                },
+            );
+        }
+    }
+    info
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Dataset> {
+    let file = fs::read_to_string(path)?;
+    let tables = sections(&file);
+
+    let table = match (tables.get("PSM"), tables.get("PEP")) {
+        (Some(psm), _) if !abundance_columns(psm).is_empty() => psm,
+        (_, Some(pep)) if !abundance_columns(pep).is_empty() => pep,
+        _ => return Err(invalid("mzTab file has no PSM or PEP abundance columns")),
+    };
+    let channels = abundance_columns(table);
+
+    let sequence = table.require("sequence")?;
+    let accession = table.require("accession")?;
+    let unique = table.column("unique");
+    let pre = table.column("pre");
+    let post = table.column("post");
+    let spectra = table.column("spectra_ref");
+    let decoy = table.column(DECOY);
+
+    let mut psms = Vec::with_capacity(table.rows.len());
+    for row in &table.rows {
+        let mut acc = field(row, accession).to_string();
+        if acc.is_empty() || acc == "null" {
+            continue;
+        }
+        if decoy.map(|i| field(row, i) == "1").unwrap_or(false) && !acc.contains("Reverse") {
+            acc = format!("Reverse_{}", acc);
+        }
+
+        let seq = match (pre.map(|i| field(row, i)), post.map(|i| field(row, i))) {
+            (Some(a), Some(b)) if a != "null" && b != "null" => {
+                format!("{}.{}.{}", a, field(row, sequence), b)
+            }
+            _ => field(row, sequence).to_string(),
+        };
+        let values = channels
+            .iter()
+            .map(|&i| field(row, i).parse::<f64>().unwrap_or(0.0).max(0.0).round() as u32)
+            .collect();
+
+        psms.push((
+            acc,
+            Peptide {
+                sequence: seq,
+                values,
+                unique: unique.map(|i| field(row, i) == "1").unwrap_or(true),
+                purity: 1.0,
+                scan: spectra.map(|i| scan(field(row, i))).unwrap_or(0),
+            },
+        ));
+    }
+
+    let info = tables.get("PRT").map(proteins).unwrap_or_default();
+    Ok(assemble(psms, &info, channels.len() as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const FILE: &str = "\
+MTD\tmzTab-version\t1.0.0
+PRH\taccession\tdescription\tprotein_coverage
+PRT\tP1\tKinase\t0.25
+PRT\tP2\tnull\tnull
+PEH\tsequence\taccession\tpeptide_abundance_assay[1]
+PEP\tIGNORED\tP1\t1
+PSH\tsequence\taccession\tunique\tpre\tpost\tspectra_ref\topt_global_cv_MS:1002217_decoy_peptide\tpeptide_abundance_assay[2]\tpeptide_abundance_assay[1]
+PSM\tPEPTIDEK\tP1\t1\tK\tL\tms_run[1]:scan=100\t0\t20.4\t10.6
+PSM\tSEQR\tP2\t0\tnull\tnull\tms_run[1]:scan=101\t1\tnull\t5
+PSM\tNONE\tnull\t1\tK\tL\tms_run[1]:scan=102\t0\t1\t1
+";
+
+    #[test]
+    fn read_psms() {
+        let dir = fixture::dir("mztab", &[("run.mzTab", FILE)]);
+        let data = read(dir.join("run.mzTab")).unwrap();
+        assert_eq!(data.channels, 2);
+        assert_eq!(data.proteins.len(), 2);
+
+        let prot = &data.proteins[0];
+        assert_eq!(prot.accession, "P1");
+        assert_eq!(prot.description, "Kinase");
+        assert_eq!(prot.sequence_coverage, 25.0);
+        let pep = &prot.peptides[0];
+        assert_eq!(pep.sequence, "K.PEPTIDEK.L");
+        // Abundances are ordered by assay number
+        assert_eq!(pep.values, vec![11, 20]);
+        assert!(pep.unique);
+        assert_eq!(pep.scan, 100);
+
+        let decoy = &data.proteins[1];
+        assert_eq!(decoy.accession, "Reverse_P2");
+        assert!(decoy.description.is_empty());
+        assert_eq!(decoy.peptides[0].sequence, "SEQR");
+        assert_eq!(decoy.peptides[0].values, vec![5, 0]);
+        assert!(!decoy.peptides[0].unique);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn peptide_rows_without_psm_abundances() {
+        let text = "\
+PEH\tsequence\taccession\tpeptide_abundance_assay[1]
+PEP\tPEPTIDEK\tP1\t7
+PSH\tsequence\taccession
+PSM\tPEPTIDEK\tP1
+";
+        let dir = fixture::dir("mztab-pep", &[("run.mzTab", text)]);
+        let data = read(dir.join("run.mzTab")).unwrap();
+        assert_eq!(data.channels, 1);
+        assert_eq!(data.proteins[0].peptides[0].values, vec![7]);
+
+        fs::write(
+            dir.join("run.mzTab"),
+            "PSH\tsequence\taccession\nPSM\tSEQR\tP1\n",
+        )
+        .unwrap();
+        assert!(read(dir.join("run.mzTab")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Proteome Discoverer tab-delimited PSM export reader
+//!
+//! Reporter abundances are read from the "Abundance" columns in the order
+//! they appear. PSMs are assigned to the first master protein accession, and
+//! are considered unique if they map to a single protein. Purity is derived
+//! from the isolation interference.
+use super::*;
+
+/// Convert an annotated sequence, such as [K].PEPTIDEK.[L], into census
+/// style with flanking residues
+fn sequence(annotated: &str) -> String {
+    annotated
+        .split('.')
+        .map(|s| s.trim_matches(|c| c == '[' || c == ']'))
+        .map(|s| if s.is_empty() { "-" } else { s })
+        .collect::<Vec<&str>>()
+        .join(".")
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Dataset> {
+    let table = Table::read(&path)?;
+
+    let channels = table
+        .header
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| h.starts_with("Abundance"))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<usize>>();
+    if channels.is_empty() {
+        return Err(invalid("PSM export has no Abundance columns"));
+    }
+
+    let seq = table
+        .column("Annotated Sequence")
+        .map(Ok)
+        .unwrap_or_else(|| table.require("Sequence"))?;
+    let master = table
+        .column("Master Protein Accessions")
+        .map(Ok)
+        .unwrap_or_else(|| table.require("Protein Accessions"))?;
+    let proteins = table.column("# Proteins");
+    let interference = table.column("Isolation Interference [%]");
+    let scan = table.column("First Scan");
+
+    let mut psms = Vec::with_capacity(table.rows.len());
+    for row in &table.rows {
+        let accessions = field(row, master);
+        let acc = accessions.split(';').next().unwrap_or("").trim();
+        if acc.is_empty() {
+            continue;
+        }
+
+        let unique = match proteins.and_then(|i| field(row, i).parse::<u32>().ok()) {
+            Some(n) => n == 1,
+            None => !accessions.contains(';'),
+        };
+        let values = channels
+            .iter()
+            .map(|&i| field(row, i).parse::<f64>().unwrap_or(0.0).max(0.0).round() as u32)
+            .collect();
+
+        psms.push((
+            acc.to_string(),
+            Peptide {
+                sequence: sequence(field(row, seq)),
+                values,
+                unique,
+                purity: interference
+                    .and_then(|i| field(row, i).parse::<f32>().ok())
+                    .map(|pct| 1.0 - pct / 100.0)
+                    .unwrap_or(1.0),
+                scan: scan.and_then(|i| field(row, i).parse().ok()).unwrap_or(0),
+            },
+        ));
+    }
+
+    Ok(assemble(psms, &HashMap::new(), channels.len() as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    const PSMS: &str = "\
+\"Annotated Sequence\"\t\"Master Protein Accessions\"\t\"# Proteins\"\t\"Isolation Interference [%]\"\t\"First Scan\"\t\"Abundance 126\"\t\"Abundance 127N\"
+\"[K].PEPTIDEK.[L]\"\t\"P1; P2\"\t\"1\"\t\"25\"\t\"100\"\t\"10.5\"\t\"\"
+\"[-].SEQR.[A]\"\t\"P2\"\t\"2\"\t\"\"\t\"101\"\t\"3\"\t\"4\"
+\"[R].NONE.[A]\"\t\"\"\t\"1\"\t\"\"\t\"102\"\t\"1\"\t\"1\"
+";
+
+    #[test]
+    fn read_psms() {
+        let dir = fixture::dir("pd", &[("psms.txt", PSMS)]);
+        let data = read(dir.join("psms.txt")).unwrap();
+        assert_eq!(data.channels, 2);
+        assert_eq!(data.proteins.len(), 2);
+
+        let pep = &data.proteins[0].peptides[0];
+        assert_eq!(data.proteins[0].accession, "P1");
+        assert_eq!(pep.sequence, "K.PEPTIDEK.L");
+        assert_eq!(pep.values, vec![11, 0]);
+        // Uniqueness follows # Proteins when it is given
+        assert!(pep.unique);
+        assert_eq!((pep.purity, pep.scan), (0.75, 100));
+
+        let pep = &data.proteins[1].peptides[0];
+        assert_eq!(pep.sequence, "-.SEQR.A");
+        assert!(!pep.unique);
+        assert_eq!(pep.purity, 1.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn require_abundances() {
+        let dir = fixture::dir(
+            "pd-missing",
+            &[("psms.txt", "Sequence\tProtein Accessions\nSEQR\tP1\n")],
+        );
+        let e = read(dir.join("psms.txt")).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
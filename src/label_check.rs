@@ -0,0 +1,138 @@
+//! Detect channels that may have been assigned to the wrong condition
+//!
+//! Replicates of the same condition should correlate more strongly with one
+//! another than with other conditions. Each protein's log2 intensities are
+//! centered across channels, so that correlations reflect differences between
+//! samples rather than protein abundance, and each channel's mean correlation
+//! with the other channels of its planned condition is compared with its
+//! mean correlation with each other condition. A channel that correlates
+//! better with another condition than with its own is reported as a possible
+//! swap.
+use census2csv::rollup::{self, Sum};
+use census_proteomics::Dataset;
+use std::io::prelude::*;
+
+/// Result of checking a single channel
+pub struct Check {
+    /// 1-indexed channel
+    pub channel: usize,
+    pub condition: String,
+    /// Mean correlation with the other channels of the same condition, if
+    /// there are any
+    pub within: Option<f64>,
+    /// Other condition with the highest mean correlation, and that
+    /// correlation
+    pub nearest: Option<(String, f64)>,
+}
+
+impl Check {
+    /// Does the channel correlate better with another condition than with
+    /// its own?
+    pub fn suspect(&self) -> bool {
+        match (self.within, &self.nearest) {
+            (Some(within), Some((_, other))) => *other > within,
+            _ => false,
+        }
+    }
+}
+
+fn pearson(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let (ma, mb) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut cov, mut va, mut vb) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - ma) * (y - mb);
+        va += (x - ma).powi(2);
+        vb += (y - mb).powi(2);
+    }
+    if va == 0.0 || vb == 0.0 {
+        0.0
+    } else {
+        cov / (va * vb).sqrt()
+    }
+}
+
+/// Check every channel of `data` with a condition in `conditions`, which
+/// contains 1-indexed channels and their planned conditions
+pub fn check(data: &Dataset, conditions: &[(usize, String)]) -> std::io::Result<Vec<Check>> {
+    if let Some((c, _)) = conditions
+        .iter()
+        .find(|(c, _)| *c == 0 || *c > data.channels as usize)
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "channel {} is out of range for {} channels",
+                c, data.channels
+            ),
+        ));
+    }
+
+    // Centered log2 intensity profile of each channel, across proteins
+    let mut profiles = vec![Vec::new(); data.channels as usize];
+    for prot in data.proteins.iter().filter(|p| !p.peptides.is_empty()) {
+        let logs = rollup::protein(prot, &Sum)
+            .into_iter()
+            .map(|v| (v + 1.0).log2())
+            .collect::<Vec<f64>>();
+        let mean = logs.iter().sum::<f64>() / logs.len() as f64;
+        for (profile, v) in profiles.iter_mut().zip(logs) {
+            profile.push(v - mean);
+        }
+    }
+
+    let mut checks = Vec::new();
+    for (channel, condition) in conditions {
+        let mut groups: Vec<(&str, Vec<f64>)> = Vec::new();
+        for (other, cond) in conditions.iter().filter(|(o, _)| o != channel) {
+            let r = pearson(&profiles[channel - 1], &profiles[other - 1]);
+            match groups.iter_mut().find(|(c, _)| c == cond) {
+                Some((_, rs)) => rs.push(r),
+                None => groups.push((cond, vec![r])),
+            }
+        }
+        let mean = |rs: &[f64]| rs.iter().sum::<f64>() / rs.len() as f64;
+        let within = groups
+            .iter()
+            .find(|(c, _)| c == condition)
+            .map(|(_, rs)| mean(rs));
+        let nearest = groups
+            .iter()
+            .filter(|(c, _)| c != condition)
+            .map(|(c, rs)| (c.to_string(), mean(rs)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        checks.push(Check {
+            channel: *channel,
+            condition: condition.clone(),
+            within,
+            nearest,
+        });
+    }
+    Ok(checks)
+}
+
+/// Write one CSV row per channel check
+pub fn write_report<W: Write>(checks: &[Check], mut out: W) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "channel,condition,within_correlation,nearest_condition,nearest_correlation,status"
+    )?;
+    let fmt = |v: Option<f64>| v.map(|v| format!("{:.4}", v)).unwrap_or_default();
+    for check in checks {
+        let (nearest, r) = match &check.nearest {
+            Some((c, r)) => (c.as_str(), Some(*r)),
+            None => ("", None),
+        };
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            check.channel,
+            check.condition,
+            fmt(check.within),
+            nearest,
+            fmt(r),
+            if check.suspect() { "suspect" } else { "ok" }
+        )?;
+    }
+    Ok(())
+}
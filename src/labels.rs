@@ -0,0 +1,289 @@
+//! TMT plex detection and reporter ion label names
+use std::fmt;
+use std::str::FromStr;
+
+const TMT2: &[&str] = &["126", "127"];
+const TMT6: &[&str] = &["126", "127", "128", "129", "130", "131"];
+const TMT10: &[&str] = &[
+    "126", "127N", "127C", "128N", "128C", "129N", "129C", "130N", "130C", "131",
+];
+const TMT11: &[&str] = &[
+    "126", "127N", "127C", "128N", "128C", "129N", "129C", "130N", "130C", "131N", "131C",
+];
+const TMT16: &[&str] = &[
+    "126", "127N", "127C", "128N", "128C", "129N", "129C", "130N", "130C", "131N", "131C", "132N",
+    "132C", "133N", "133C", "134N",
+];
+const TMT18: &[&str] = &[
+    "126", "127N", "127C", "128N", "128C", "129N", "129C", "130N", "130C", "131N", "131C", "132N",
+    "132C", "133N", "133C", "134N", "134C", "135N",
+];
+
+/// Reporter ion m/z for a label. Labels without an N/C suffix (TMT6) refer
+/// to the isotopologue used in the 6-plex reagents
+fn mass(label: &str) -> f64 {
+    match label {
+        "126" => 126.127726,
+        "127N" | "127" => 127.124761,
+        "127C" => 127.131081,
+        "128N" => 128.128116,
+        "128C" | "128" => 128.134436,
+        "129N" | "129" => 129.131471,
+        "129C" => 129.137790,
+        "130N" => 130.134825,
+        "130C" | "130" => 130.141145,
+        "131N" | "131" => 131.138180,
+        "131C" => 131.144500,
+        "132N" => 132.141535,
+        "132C" => 132.147855,
+        "133N" => 133.144890,
+        "133C" => 133.151210,
+        "134N" => 134.148245,
+        "134C" => 134.154565,
+        "135N" => 135.151600,
+        _ => 0.0,
+    }
+}
+
+/// TMT reagent plex
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Plex {
+    Tmt2,
+    Tmt6,
+    Tmt10,
+    Tmt11,
+    Tmt16,
+    Tmt18,
+}
+
+impl Plex {
+    /// Detect the plex from the number of channels in a dataset
+    pub fn detect(channels: u8) -> Option<Plex> {
+        match channels {
+            2 => Some(Plex::Tmt2),
+            6 => Some(Plex::Tmt6),
+            10 => Some(Plex::Tmt10),
+            11 => Some(Plex::Tmt11),
+            16 => Some(Plex::Tmt16),
+            18 => Some(Plex::Tmt18),
+            _ => None,
+        }
+    }
+
+    /// Reporter ion label names, in channel order
+    pub fn labels(self) -> &'static [&'static str] {
+        match self {
+            Plex::Tmt2 => TMT2,
+            Plex::Tmt6 => TMT6,
+            Plex::Tmt10 => TMT10,
+            Plex::Tmt11 => TMT11,
+            Plex::Tmt16 => TMT16,
+            Plex::Tmt18 => TMT18,
+        }
+    }
+
+    pub fn channels(self) -> u8 {
+        self.labels().len() as u8
+    }
+
+    /// Reporter ion m/z values, in channel order
+    pub fn masses(self) -> Vec<f64> {
+        self.labels().iter().map(|l| mass(l)).collect()
+    }
+}
+
+impl fmt::Display for Plex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tmt{}", self.channels())
+    }
+}
+
+/// Labeling chemistry of an experiment, which sets intensity floors suited
+/// to how its reporter ion signal is divided between channels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Chemistry {
+    /// TMT 10-plex or 11-plex
+    Tmt10,
+    /// TMTpro 16-plex
+    Tmt16,
+    /// TMTpro 16-plex or 18-plex
+    TmtPro,
+}
+
+impl FromStr for Chemistry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tmt10" => Ok(Chemistry::Tmt10),
+            "tmt16" => Ok(Chemistry::Tmt16),
+            "tmtpro" => Ok(Chemistry::TmtPro),
+            _ => Err(format!("unknown chemistry {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Chemistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chemistry::Tmt10 => write!(f, "tmt10"),
+            Chemistry::Tmt16 => write!(f, "tmt16"),
+            Chemistry::TmtPro => write!(f, "tmtpro"),
+        }
+    }
+}
+
+impl Chemistry {
+    /// Plexes made with this chemistry
+    pub fn plexes(self) -> &'static [Plex] {
+        match self {
+            Chemistry::Tmt10 => &[Plex::Tmt10, Plex::Tmt11],
+            Chemistry::Tmt16 => &[Plex::Tmt16],
+            Chemistry::TmtPro => &[Plex::Tmt16, Plex::Tmt18],
+        }
+    }
+
+    /// Does a file with `channels` channels match this chemistry?
+    pub fn accepts(self, channels: u8) -> bool {
+        self.plexes().iter().any(|p| p.channels() == channels)
+    }
+
+    /// Minimum summed reporter ion intensity of a PSM. TMTpro divides the
+    /// signal of a PSM between more channels, so the floor is lower per
+    /// channel than for TMT 10-plex.
+    pub fn min_total_intensity(self) -> u32 {
+        match self {
+            Chemistry::Tmt10 => 10_000,
+            Chemistry::Tmt16 | Chemistry::TmtPro => 12_800,
+        }
+    }
+
+    /// Minimum mean reporter ion intensity of a PSM across channels
+    pub fn min_mean_intensity(self) -> f64 {
+        match self {
+            Chemistry::Tmt10 => 500.0,
+            Chemistry::Tmt16 | Chemistry::TmtPro => 400.0,
+        }
+    }
+}
+
+/// Which label names to use for channel column headers
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LabelNames {
+    /// Choose the plex based on the number of channels in each file
+    Auto,
+    Plex(Plex),
+}
+
+impl FromStr for LabelNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(LabelNames::Auto),
+            "tmt2" => Ok(LabelNames::Plex(Plex::Tmt2)),
+            "tmt6" => Ok(LabelNames::Plex(Plex::Tmt6)),
+            "tmt10" => Ok(LabelNames::Plex(Plex::Tmt10)),
+            "tmt11" => Ok(LabelNames::Plex(Plex::Tmt11)),
+            "tmt16" | "tmtpro" => Ok(LabelNames::Plex(Plex::Tmt16)),
+            "tmt18" => Ok(LabelNames::Plex(Plex::Tmt18)),
+            _ => Err(format!("unknown label set {}", s)),
+        }
+    }
+}
+
+impl LabelNames {
+    /// Resolve to a plex for a dataset with `channels` channels, returning an
+    /// error if the plex does not match the data
+    pub fn resolve(self, channels: u8) -> Result<Plex, String> {
+        match self {
+            LabelNames::Auto => Plex::detect(channels)
+                .ok_or_else(|| format!("cannot detect TMT plex for {} channels", channels)),
+            LabelNames::Plex(plex) if plex.channels() == channels => Ok(plex),
+            LabelNames::Plex(plex) => Err(format!(
+                "{} has {} channels, but the file has {}",
+                plex,
+                plex.channels(),
+                channels
+            )),
+        }
+    }
+}
+
+/// Column header names for each channel, using TMT labels if `plex` is
+/// given, or generic channel_N names otherwise
+pub fn channel_names(plex: Option<Plex>, channels: u8) -> Vec<String> {
+    match plex {
+        Some(plex) => plex.labels().iter().map(|s| s.to_string()).collect(),
+        None => (1..=channels).map(|i| format!("channel_{}", i)).collect(),
+    }
+}
+
+/// Is this CSV column header the name of a channel?
+pub fn is_channel_column(header: &str) -> bool {
+    header.starts_with("channel_") || TMT18.contains(&header) || TMT6.contains(&header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_plexes() {
+        for plex in [
+            Plex::Tmt2,
+            Plex::Tmt6,
+            Plex::Tmt10,
+            Plex::Tmt11,
+            Plex::Tmt16,
+            Plex::Tmt18,
+        ] {
+            assert_eq!(Plex::detect(plex.channels()), Some(plex));
+            let masses = plex.masses();
+            assert!(masses.iter().all(|m| *m > 126.0));
+            assert!(masses.windows(2).all(|w| w[0] < w[1]));
+        }
+        assert_eq!(Plex::detect(8), None);
+        assert_eq!(Plex::Tmt16.to_string(), "tmt16");
+    }
+
+    #[test]
+    fn resolve_label_names() {
+        assert_eq!(LabelNames::Auto.resolve(10), Ok(Plex::Tmt10));
+        assert_eq!(
+            LabelNames::Auto.resolve(8),
+            Err("cannot detect TMT plex for 8 channels".to_string())
+        );
+        let pro = "TMTpro".parse::<LabelNames>().unwrap();
+        assert_eq!(pro.resolve(16), Ok(Plex::Tmt16));
+        assert_eq!(
+            pro.resolve(18),
+            Err("tmt16 has 16 channels, but the file has 18".to_string())
+        );
+        assert!("tmt12".parse::<LabelNames>().is_err());
+    }
+
+    #[test]
+    fn column_names() {
+        assert_eq!(
+            channel_names(Some(Plex::Tmt6), 6),
+            ["126", "127", "128", "129", "130", "131"]
+        );
+        assert_eq!(channel_names(None, 2), ["channel_1", "channel_2"]);
+        for name in ["channel_3", "127N", "135N", "129"] {
+            assert!(is_channel_column(name));
+        }
+        for name in ["accession", "sequence", "127X"] {
+            assert!(!is_channel_column(name));
+        }
+    }
+
+    #[test]
+    fn chemistries() {
+        let pro = "tmtpro".parse::<Chemistry>().unwrap();
+        assert!(pro.accepts(16) && pro.accepts(18) && !pro.accepts(10));
+        assert!(Chemistry::Tmt10.accepts(11));
+        assert_eq!(pro.to_string(), "tmtpro");
+        assert!("itraq".parse::<Chemistry>().is_err());
+    }
+}
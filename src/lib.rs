@@ -0,0 +1,34 @@
+//! census2csv library
+//!
+//! Components of census2csv that are useful to downstream users: input
+//! readers, filters, fraction and duplicate merging, quality control checks, protein
+//! rollup strategies, parallel filtering, column-oriented tables for
+//! language bindings, Arrow record batches, a C ABI, and in-memory
+//! conversion for WebAssembly builds and the HTTP server.
+pub mod abpp;
+pub mod arrow;
+pub mod convert;
+pub mod correction;
+pub mod duplicates;
+pub mod ffi;
+pub mod filter;
+pub mod fractions;
+pub mod frame;
+pub mod grouping;
+pub mod impute;
+pub mod input;
+pub mod labels;
+pub mod number;
+pub mod paired;
+pub mod parallel;
+pub mod presets;
+pub mod qc;
+pub mod rollup;
+pub mod sample;
+pub mod sequence;
+pub mod transform;
+
+#[cfg(test)]
+mod fixture;
+
+pub use census_proteomics;
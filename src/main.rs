@@ -21,10 +21,24 @@
 //!     {
 //!       "SequenceCounts": 2
 //!     }
+//!   ],
+//!   "overrides": [
+//!     {
+//!       "file": "noisy_plex.txt",
+//!       "peptide_filters": [
+//!         {
+//!           "TotalIntensity": 20000
+//!         }
+//!       ]
+//!     }
 //!   ]
 //! }
 //! ```
 //!
+//! Rules of an override replace the rules of the same kind for the input
+//! with that file name (or the plex of that name in a manifest), so that
+//! `noisy_plex.txt` above is filtered with a higher intensity floor.
+//!
 //! MIT License
 //! Copyright (c) 2019 Michael Lazear
 //!
@@ -46,176 +60,55 @@
 //! OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //! SOFTWARE.
 
-use census_proteomics::*;
-use clap::{App, Arg, ArgGroup};
-use serde_json;
-use std::collections::HashMap;
+mod annotate;
+mod append;
+mod census;
+mod checkpoint;
+mod cli;
+mod commands;
+mod coverage;
+mod diagnostics;
+mod diff;
+mod explore;
+mod filter_file;
+#[cfg(test)]
+mod fixture;
+mod gct;
+mod idmap;
+mod label_check;
+mod manifest;
+mod meta;
+mod nested;
+mod nterm;
+mod output;
+mod parquet;
+mod perseus;
+mod plot;
+mod psms;
+mod qvalues;
+mod raster;
+mod remote;
+mod resume;
+mod saint;
+mod serve;
+mod shared;
+mod silac;
+mod split;
+mod sqlite;
+mod sweep;
+mod terminal;
+mod terms;
+mod uniprot;
+mod writer;
+
+use census2csv::filter::{Filter, PeptideFilter, ProteinFilter};
+use census2csv::labels;
+use clap::{App, AppSettings};
 use std::fs;
 use std::io::prelude::*;
-use std::path::{Path, PathBuf};
-
-fn combine_protein<'a, P: AsRef<Path>>(
-    path: P,
-    filters: &Filter<'a>,
-    average: bool,
-) -> std::io::Result<()> {
-    let mut outpath = PathBuf::from(path.as_ref());
-    if !outpath.set_extension("csv") {
-        panic!("Cannot set file extension for {}", outpath.display());
-    }
-
-    let file = fs::read_to_string(path)?;
-    let data = census_proteomics::read_census(&file)
-        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
-    let data = data.filter(filters);
-
-    let mut file = fs::File::create(outpath)?;
-    writeln!(
-        file,
-        "accession,description,spectral_count,sequence_count,{}",
-        (1..=data.channels)
-            .map(|i| format!("channel_{}", i))
-            .collect::<Vec<String>>()
-            .join(",")
-    )?;
-
-    for prot in &data.proteins {
-        let adj = prot
-            .total()
-            .into_iter()
-            .map(|v| {
-                format!(
-                    "{}",
-                    if average {
-                        v / prot.peptides.len() as u32
-                    } else {
-                        v
-                    }
-                )
-            })
-            .collect::<Vec<String>>()
-            .join(",");
-
-        writeln!(
-            file,
-            "{},{},{},{},{}",
-            prot.accession,
-            prot.description.replace(",", ";"),
-            prot.spectral_count,
-            prot.sequence_count,
-            adj
-        )?;
-    }
-
-    Ok(())
-}
-
-fn flat_peptide<'a, P: AsRef<Path>>(
-    path: P,
-    filters: &Filter<'a>,
-    average: bool,
-) -> std::io::Result<()> {
-    let mut outpath = PathBuf::from(path.as_ref());
-    if !outpath.set_extension("csv") {
-        panic!("Cannot set file extension for {}", outpath.display());
-    }
-
-    let file = fs::read_to_string(path)?;
-    let data = census_proteomics::read_census(&file).expect("Error parsing census file!");
-    let data = data.filter(filters);
-
-    let mut file = fs::File::create(outpath)?;
-    writeln!(
-        file,
-        "accession,description,sequence,{}",
-        (1..=data.channels)
-            .map(|i| format!("channel_{}", i))
-            .collect::<Vec<String>>()
-            .join(",")
-    )?;
-
-    for prot in &data.proteins {
-        for peptide in &prot.peptides {
-            let adj = (*peptide.values)
-                .into_iter()
-                .map(|v| format!("{}", v))
-                .collect::<Vec<String>>()
-                .join(",");
-            writeln!(
-                file,
-                "{},{},{},{}",
-                prot.accession,
-                prot.description.replace(",", ";"),
-                peptide.sequence,
-                adj
-            )?;
-        }
-    }
-
-    Ok(())
-}
-
-fn combine_peptide<'a, P: AsRef<Path>>(
-    path: P,
-    filters: &Filter<'a>,
-    average: bool,
-) -> std::io::Result<()> {
-    let mut outpath = PathBuf::from(path.as_ref());
-    if !outpath.set_extension("csv") {
-        panic!("Cannot set file extension for {}", outpath.display());
-    }
-
-    let file = fs::read_to_string(path)?;
-    let data = census_proteomics::read_census(&file).expect("Error parsing census file!");
-    let data = data.filter(filters);
-
-    let mut file = fs::File::create(outpath)?;
-    writeln!(
-        file,
-        "accession,description,spectral_count,sequence,{}",
-        (1..=data.channels)
-            .map(|i| format!("channel_{}", i))
-            .collect::<Vec<String>>()
-            .join(",")
-    )?;
-
-    for prot in &data.proteins {
-        let mut map: HashMap<&str, Vec<u32>> = HashMap::new();
-        let mut cnt: HashMap<&str, u32> = HashMap::new();
-        for peptide in &prot.peptides {
-            let entry = map
-                .entry(&peptide.sequence)
-                .or_insert((0..data.channels).map(|_| 0).collect::<Vec<u32>>());
-            for (idx, val) in peptide.values.iter().enumerate() {
-                entry[idx] += *val;
-            }
-            *cnt.entry(&peptide.sequence).or_insert(0) += 1;
-        }
-
-        for (sequence, summed_values) in map {
-            let spec = cnt[sequence];
-            let adj = summed_values
-                .into_iter()
-                .map(|v| format!("{}", if average { v / spec } else { v }))
-                .collect::<Vec<String>>()
-                .join(",");
-
-            writeln!(
-                file,
-                "{},{},{},{},{}",
-                prot.accession,
-                prot.description.replace(",", ";"),
-                spec,
-                // prot.sequence_count,
-                sequence,
-                adj
-            )?;
-        }
-    }
-
-    Ok(())
-}
+use std::io::IsTerminal;
 
+#[allow(dead_code)]
 fn generate_example() {
     let filter = Filter::default()
         .add_peptide_filter(PeptideFilter::ChannelIntensity(1, 1000))
@@ -226,95 +119,81 @@ fn generate_example() {
         .add_protein_filter(ProteinFilter::ExcludeReverse)
         .add_protein_filter(ProteinFilter::SequenceCounts(2));
     let s = serde_json::to_string_pretty(&filter).expect("Serialization error");
-    let mut f = fs::File::create("filter.json")
+    fs::File::create("filter.json")
         .expect("Could not open filter.json for writing")
         .write_all(s.as_bytes())
         .expect("Error writing to filter.json");
 }
 
 fn main() {
-    let matches = App::new("census2csv")
+    let app = App::new("census2csv")
         .version("0.1")
         .author("Michael R. Lazear <lazear@scripps.edu>")
         .about("Parse, filter, and convert census out files to csv")
-        .group(
-            ArgGroup::with_name("combine")
-                .required(true)
-                .args(&["peptide", "protein", "flat"]),
-        )
-        .arg(
-            Arg::with_name("peptide")
-                .help("Output peptide-level data")
-                .long("peptide")
-                .short("e")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name("protein")
-                .help("Output protein-level data")
-                .long("protein")
-                .short("r")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name("flat")
-                .help("Output completely flat")
-                .long("flat")
-                .short("F")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name("filter")
-                .help("JSON file containing filters to apply")
-                .short("f")
-                .long("filter")
-                .value_name("FILE")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("average")
-                .help("Average results by number of reported spectral matches, default is sum")
-                .short("a")
-                .long("avg"),
-        )
-        .arg(
-            Arg::with_name("INPUT")
-                .help("list of input files to convert")
-                .multiple(true),
-        )
+        .after_help("ENVIRONMENT:\n    CENSUS2CSV_FILTER     Filter file, in place of --filter\n    CENSUS2CSV_OUTDIR     Output directory, in place of --outdir\n    CENSUS2CSV_THREADS    Number of threads, in place of --threads\n\nOptions given on the command line take precedence over the environment.\n\nEXIT STATUS:\n    0    Success\n    1    Invalid command line arguments\n    2    An input or other file could not be parsed\n    3    The filter could not be read or parsed\n    4    A file could not be read or written\n    5    Some inputs of a batch failed, and others were converted\n    6    Quality control checks failed, with --strict")
+        .setting(AppSettings::SubcommandsNegateReqs);
+    let matches = commands::convert::args(app)
+        .subcommands(commands::all())
         .get_matches();
-
-    // declare up here to get around borrowck and lifetimes
-    let filterbuf;
-
-    let filter = match matches.value_of("filter") {
-        Some(path) => {
-            filterbuf = fs::read_to_string(path).expect(&format!("Cannot read {}", path));
-            match serde_json::from_str(&filterbuf) {
-                Ok(f) => f,
-                Err(e) => {
-                    println!("Error while parsing filter.json {:?}", e);
-                    std::process::abort();
-                }
-            }
+    diagnostics::set_json(matches.is_present("errors-json"));
+    diagnostics::set_color(
+        !matches.is_present("no-color")
+            && std::env::var_os("NO_COLOR").is_none()
+            && std::io::stderr().is_terminal(),
+    );
+
+    match matches.subcommand() {
+        ("serve", Some(sub)) => return commands::serve::run(&commands::serve::Options::new(sub)),
+        ("annotate", Some(sub)) => {
+            return commands::annotate::run(&commands::annotate::Options::new(sub))
         }
-        None => Filter::default(),
-    };
+        ("silac", Some(sub)) => return commands::silac::run(&commands::silac::Options::new(sub)),
+        _ => {}
+    }
 
-    for f in matches
-        .value_of("INPUT")
-        .expect("No input files!")
-        .split_whitespace()
-    {
-        let res = if matches.is_present("peptide") {
-            combine_peptide(f, &filter, matches.is_present("average"))
-        } else if matches.is_present("flat") {
-            flat_peptide(f, &filter, matches.is_present("average"))
-        } else {
-            combine_protein(f, &filter, matches.is_present("average"))
-        };
-        if let Err(e) = res {
-            println!("Error during processing of file {}: {}", f, e);
+    let filter_path = match matches.subcommand() {
+        ("diff", Some(sub)) | ("filter", Some(sub)) => sub.value_of("filter"),
+        _ => matches.value_of("filter"),
+    };
+    let filterbuf = cli::read_filter(filter_path, matches.value_of("filter-set"));
+    let chemistry = cli::parse::<labels::Chemistry>(&matches, "chemistry");
+    let filter = cli::filter(&matches, filterbuf.as_deref(), chemistry);
+    let threads = cli::threads(&matches);
+
+    match matches.subcommand() {
+        ("sweep", Some(sub)) => {
+            return commands::sweep::run(&commands::sweep::Options::new(sub), &filter, threads)
+        }
+        ("explore", Some(sub)) => {
+            return commands::explore::run(&commands::explore::Options::new(sub), filter, threads)
+        }
+        ("label-check", Some(sub)) => {
+            return commands::label_check::run(
+                &commands::label_check::Options::new(sub),
+                &filter,
+                threads,
+            )
         }
+        ("shared-peptides", Some(sub)) => {
+            return commands::shared_peptides::run(
+                &commands::shared_peptides::Options::new(sub),
+                &filter,
+                threads,
+            )
+        }
+        ("filter", Some(sub)) => {
+            return commands::filter::run(&commands::filter::Options::new(sub), &filter, threads)
+        }
+        ("diff", Some(sub)) => {
+            return commands::diff::run(&commands::diff::Options::new(sub), &filter)
+        }
+        _ => {}
+    }
+
+    let mut output = commands::convert::output(&matches, chemistry, threads);
+    if let ("describe-output", Some(sub)) = matches.subcommand() {
+        let opts = commands::describe_output::Options::new(sub);
+        return commands::describe_output::run(&opts, &filter, &mut output);
     }
+    commands::convert::run(&commands::convert::Options::new(&matches), &filter, output);
 }
@@ -0,0 +1,606 @@
+//! Study manifests describing the input files of a multi-plex experiment
+//!
+//! A manifest is a CSV file with one row per input file, and the columns
+//!
+//! - `file`: path to the input, relative to the manifest
+//! - `plex`: name of the TMT plex the file belongs to
+//! - `fraction`: fraction number within the plex (optional)
+//! - `bridge`: 1-indexed channel containing the bridge/reference sample
+//!   (optional)
+//! - `conditions`: semicolon separated `channel=condition` pairs (optional)
+//!
+//! Files belonging to the same plex are combined as fractions, in fraction
+//! order. Channel columns are named after their condition, and if a bridge
+//! channel is given, each plex is scaled so that its bridge channel total
+//! matches the mean bridge total across plexes. Remaining plex effects can
+//! be removed with an empirical Bayes batch correction.
+use census_proteomics::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// All of the files and annotations for a single plex
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plex {
+    pub name: String,
+    /// Input files, ordered by fraction
+    pub files: Vec<PathBuf>,
+    /// 1-indexed bridge channel
+    pub bridge: Option<usize>,
+    /// 1-indexed channel numbers and their condition names
+    pub conditions: Vec<(usize, String)>,
+}
+
+impl Plex {
+    /// Condition name of each channel, for a plex with `channels` channels
+    pub fn channel_conditions(&self, channels: u8) -> Vec<Option<String>> {
+        (1..=channels as usize)
+            .map(|ch| {
+                self.conditions
+                    .iter()
+                    .find(|(c, _)| *c == ch)
+                    .map(|(_, name)| name.clone())
+                    .or_else(|| match self.bridge {
+                        Some(b) if b == ch => Some("bridge".to_string()),
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+}
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+fn parse_conditions(s: &str) -> std::io::Result<Vec<(usize, String)>> {
+    s.split(';')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let mut it = pair.splitn(2, '=');
+            let channel = it.next().unwrap_or("").trim();
+            let name = it.next().unwrap_or("").trim();
+            match channel.parse::<usize>() {
+                Ok(ch) if ch > 0 && !name.is_empty() => Ok((ch, name.to_string())),
+                _ => Err(invalid(format!("invalid condition mapping {}", pair))),
+            }
+        })
+        .collect()
+}
+
+/// Parse channel conditions given either as semicolon separated
+/// `channel=condition` pairs, as in the `conditions` column of a manifest, or
+/// as the path of a tab-delimited file with a channel and condition on each
+/// line, optionally preceded by a header line
+pub fn read_conditions(s: &str) -> std::io::Result<Vec<(usize, String)>> {
+    if s.contains('=') {
+        return parse_conditions(s);
+    }
+    let file = fs::read_to_string(s)?;
+    let mut conditions = Vec::new();
+    for line in file.lines().filter(|l| !l.trim().is_empty()) {
+        let mut fields = line.split('\t');
+        let channel = fields.next().unwrap_or("").trim();
+        let name = fields.next().unwrap_or("").trim();
+        match channel.parse::<usize>() {
+            Ok(ch) if ch > 0 && !name.is_empty() => conditions.push((ch, name.to_string())),
+            Err(_) if conditions.is_empty() => {}
+            _ => return Err(invalid(format!("invalid condition line {}", line))),
+        }
+    }
+    Ok(conditions)
+}
+
+/// Read a manifest, returning each plex in the order it first appears
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Plex>> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let file = fs::read_to_string(path)?;
+    let mut lines = file
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+
+    let header = lines
+        .next()
+        .ok_or_else(|| invalid("empty manifest"))?
+        .split(',')
+        .map(|s| s.trim().to_lowercase())
+        .collect::<Vec<String>>();
+    let col = |name: &str| header.iter().position(|h| h == name);
+    let file_col = col("file").ok_or_else(|| invalid("manifest is missing column file"))?;
+    let plex_col = col("plex").ok_or_else(|| invalid("manifest is missing column plex"))?;
+    let (fraction_col, bridge_col, conditions_col) =
+        (col("fraction"), col("bridge"), col("conditions"));
+
+    let mut plexes: Vec<(Plex, Vec<u32>)> = Vec::new();
+    for line in lines {
+        let row = line.split(',').map(str::trim).collect::<Vec<&str>>();
+        let get = |idx: Option<usize>| idx.and_then(|i| row.get(i)).copied().unwrap_or("");
+
+        let name = get(Some(plex_col));
+        let fraction = match get(fraction_col) {
+            "" => 0,
+            s => s
+                .parse::<u32>()
+                .map_err(|_| invalid(format!("invalid fraction {}", s)))?,
+        };
+        let bridge = match get(bridge_col) {
+            "" => None,
+            s => Some(
+                s.parse::<usize>()
+                    .map_err(|_| invalid(format!("invalid bridge channel {}", s)))?,
+            ),
+        };
+        let conditions = parse_conditions(get(conditions_col))?;
+
+        let idx = match plexes.iter().position(|(p, _)| p.name == name) {
+            Some(idx) => idx,
+            None => {
+                plexes.push((
+                    Plex {
+                        name: name.to_string(),
+                        ..Plex::default()
+                    },
+                    Vec::new(),
+                ));
+                plexes.len() - 1
+            }
+        };
+        let (plex, fractions) = &mut plexes[idx];
+        plex.files.push(dir.join(get(Some(file_col))));
+        fractions.push(fraction);
+        if bridge.is_some() {
+            if plex.bridge.is_some() && plex.bridge != bridge {
+                return Err(invalid(format!(
+                    "plex {} has conflicting bridge channels",
+                    name
+                )));
+            }
+            plex.bridge = bridge;
+        }
+        for (ch, cond) in conditions {
+            if !plex.conditions.iter().any(|(c, _)| *c == ch) {
+                plex.conditions.push((ch, cond));
+            }
+        }
+    }
+
+    Ok(plexes
+        .into_iter()
+        .map(|(mut plex, fractions)| {
+            let mut files = fractions
+                .into_iter()
+                .zip(plex.files)
+                .collect::<Vec<(u32, PathBuf)>>();
+            files.sort_by_key(|(fraction, _)| *fraction);
+            plex.files = files.into_iter().map(|(_, file)| file).collect();
+            plex
+        })
+        .collect())
+}
+
+/// Scale each dataset with a bridge channel so that the total intensity of
+/// its bridge channel matches the mean bridge total across those datasets.
+/// Datasets without a bridge channel are left unchanged. Scaling factors are
+/// computed from all PSMs, prior to filtering, and returned in order.
+pub fn bridge_normalize(
+    datasets: &mut [Dataset],
+    bridges: &[Option<usize>],
+) -> std::io::Result<Vec<Option<f64>>> {
+    let totals = datasets
+        .iter()
+        .zip(bridges)
+        .map(|(data, bridge)| match *bridge {
+            None => Ok(None),
+            Some(b) if b == 0 || b > data.channels as usize => Err(invalid(format!(
+                "bridge channel {} is out of range for {} channels",
+                b, data.channels
+            ))),
+            Some(b) => Ok(Some(
+                data.proteins
+                    .iter()
+                    .flat_map(|prot| prot.peptides.iter())
+                    .map(|pep| pep.values[b - 1] as f64)
+                    .sum::<f64>(),
+            )),
+        })
+        .collect::<std::io::Result<Vec<Option<f64>>>>()?;
+    let bridged = totals.iter().flatten().copied().collect::<Vec<f64>>();
+    if bridged.contains(&0.0) {
+        return Err(invalid("bridge channel has zero total intensity"));
+    }
+    let mean = bridged.iter().sum::<f64>() / bridged.len().max(1) as f64;
+
+    let factors = totals
+        .into_iter()
+        .map(|total| total.map(|t| mean / t))
+        .collect::<Vec<Option<f64>>>();
+    for (data, factor) in datasets.iter_mut().zip(&factors) {
+        if let Some(factor) = factor {
+            for pep in data
+                .proteins
+                .iter_mut()
+                .flat_map(|prot| prot.peptides.iter_mut())
+            {
+                for val in &mut pep.values {
+                    *val = (*val as f64 * factor).round() as u32;
+                }
+            }
+        }
+    }
+    Ok(factors)
+}
+
+/// Empirical Bayes estimates of the location and scale of one batch's effect
+/// on a protein, from standardized values `z`, shrunk towards the priors of
+/// the batch as in ComBat
+fn shrink(z: &[f64], gamma: (f64, f64), delta: (f64, f64)) -> (f64, f64) {
+    let n = z.len() as f64;
+    let (gamma_bar, tau2) = gamma;
+    let (a, b) = delta;
+    let gamma_hat = z.iter().sum::<f64>() / n;
+    let mut g = gamma_hat;
+    let mut d = variance(z).max(f64::EPSILON);
+    for _ in 0..100 {
+        let g_new = (n * tau2 * gamma_hat + d * gamma_bar) / (n * tau2 + d);
+        let sum2 = z.iter().map(|v| (v - g_new).powi(2)).sum::<f64>();
+        let d_new = (b + sum2 / 2.0) / (n / 2.0 + a - 1.0);
+        let converged =
+            (g_new - g).abs() <= 1e-4 * g.abs().max(1e-8) && (d_new - d).abs() <= 1e-4 * d;
+        g = g_new;
+        d = d_new;
+        if converged {
+            break;
+        }
+    }
+    (g, d)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample variance of `values`, which has at least 2 elements
+fn variance(values: &[f64]) -> f64 {
+    let m = mean(values);
+    values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+/// Remove residual plex effects from `datasets`, each of which is one plex,
+/// with a ComBat-like empirical Bayes adjustment of the location and scale
+/// of log2 protein intensities, using plex as the batch variable. Proteins
+/// are matched across plexes by accession, and those found in only one plex
+/// are left unchanged. Each PSM is scaled by the adjustment of its protein
+/// and channel, so that protein rollups reflect the corrected values.
+/// Adjustments are computed from all PSMs, prior to filtering, and the
+/// number of proteins adjusted is returned.
+pub fn batch_correct(datasets: &mut [Dataset]) -> std::io::Result<usize> {
+    use std::collections::HashMap;
+
+    if datasets.len() < 2 {
+        return Err(invalid("batch correction requires at least 2 plexes"));
+    }
+    if datasets.iter().any(|data| data.channels < 2) {
+        return Err(invalid(
+            "batch correction requires at least 2 channels per plex",
+        ));
+    }
+
+    // Summed intensity of each protein in each plex, as log2(x + 1)
+    let mut proteins: HashMap<&str, Vec<Option<Vec<f64>>>> = HashMap::new();
+    for (batch, data) in datasets.iter().enumerate() {
+        for prot in &data.proteins {
+            let sums = proteins
+                .entry(prot.accession.as_str())
+                .or_insert_with(|| vec![None; datasets.len()])[batch]
+                .get_or_insert_with(|| vec![0.0; data.channels as usize]);
+            for pep in &prot.peptides {
+                for (sum, &v) in sums.iter_mut().zip(&pep.values) {
+                    *sum += v as f64;
+                }
+            }
+        }
+    }
+
+    // Standardize each protein found in at least 2 plexes by its grand mean
+    // and pooled within-plex variance
+    struct Standardized<'a> {
+        accession: &'a str,
+        mean: f64,
+        sd: f64,
+        z: Vec<Option<Vec<f64>>>,
+    }
+    let mut standardized = Vec::new();
+    for (accession, batches) in proteins {
+        if batches.iter().flatten().count() < 2 {
+            continue;
+        }
+        let logs = batches
+            .into_iter()
+            .map(|b| b.map(|sums| sums.iter().map(|s| (s + 1.0).log2()).collect::<Vec<f64>>()))
+            .collect::<Vec<_>>();
+        let all = logs
+            .iter()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect::<Vec<f64>>();
+        let grand = mean(&all);
+        let pooled = logs
+            .iter()
+            .flatten()
+            .map(|y| {
+                let m = mean(y);
+                y.iter().map(|v| (v - m).powi(2)).sum::<f64>()
+            })
+            .sum::<f64>()
+            / all.len() as f64;
+        if pooled <= 0.0 {
+            continue;
+        }
+        let sd = pooled.sqrt();
+        let z = logs
+            .into_iter()
+            .map(|y| y.map(|y| y.into_iter().map(|v| (v - grand) / sd).collect()))
+            .collect();
+        standardized.push(Standardized {
+            accession,
+            mean: grand,
+            sd,
+            z,
+        });
+    }
+
+    // Priors for the location and scale of each plex's effect, estimated
+    // across proteins by the method of moments
+    let priors = (0..datasets.len())
+        .map(|batch| {
+            let (gammas, deltas): (Vec<f64>, Vec<f64>) = standardized
+                .iter()
+                .filter_map(|s| s.z[batch].as_ref())
+                .map(|z| (mean(z), variance(z)))
+                .unzip();
+            if gammas.len() < 2 {
+                return None;
+            }
+            let (m, s2) = (mean(&deltas), variance(&deltas));
+            let tau2 = variance(&gammas);
+            if s2 <= 0.0 || tau2 <= 0.0 {
+                return None;
+            }
+            let a = (2.0 * s2 + m * m) / s2;
+            let b = (m * s2 + m * m * m) / s2;
+            Some(((mean(&gammas), tau2), (a, b)))
+        })
+        .collect::<Vec<_>>();
+
+    // Scaling factor of each channel, for each plex and protein
+    let mut factors: Vec<HashMap<String, Vec<f64>>> = vec![HashMap::new(); datasets.len()];
+    for s in &standardized {
+        for (batch, z) in s.z.iter().enumerate() {
+            let z = match z {
+                Some(z) => z,
+                None => continue,
+            };
+            let (gamma, delta) = match priors[batch] {
+                Some((gamma, delta)) => shrink(z, gamma, delta),
+                // Too few proteins to estimate priors, so use the estimates
+                // from this protein alone
+                None => (mean(z), variance(z)),
+            };
+            let scale = if delta.is_finite() && delta > 0.0 {
+                delta.sqrt()
+            } else {
+                1.0
+            };
+            let f = z
+                .iter()
+                .map(|&zv| {
+                    let y = zv * s.sd + s.mean;
+                    let adjusted = (zv - gamma) / scale * s.sd + s.mean;
+                    let (before, after) = (y.exp2() - 1.0, adjusted.exp2() - 1.0);
+                    if before > 0.0 {
+                        after.max(0.0) / before
+                    } else {
+                        1.0
+                    }
+                })
+                .collect();
+            factors[batch].insert(s.accession.to_string(), f);
+        }
+    }
+
+    let adjusted = standardized.len();
+    for (data, factors) in datasets.iter_mut().zip(&factors) {
+        for prot in &mut data.proteins {
+            let f = match factors.get(&prot.accession) {
+                Some(f) => f,
+                None => continue,
+            };
+            for pep in &mut prot.peptides {
+                for (val, factor) in pep.values.iter_mut().zip(f) {
+                    *val = (*val as f64 * factor).round() as u32;
+                }
+            }
+        }
+    }
+    Ok(adjusted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    fn plex(proteins: &[(&str, &[u32])]) -> Dataset {
+        let channels = proteins[0].1.len() as u8;
+        Dataset {
+            proteins: proteins
+                .iter()
+                .map(|(accession, values)| Protein {
+                    accession: accession.to_string(),
+                    channels,
+                    peptides: vec![Peptide {
+                        values: values.to_vec(),
+                        ..Peptide::default()
+                    }],
+                    ..Protein::default()
+                })
+                .collect(),
+            channels,
+        }
+    }
+
+    fn values<'a>(data: &'a Dataset, accession: &str) -> &'a [u32] {
+        &data
+            .proteins
+            .iter()
+            .find(|p| p.accession == accession)
+            .unwrap()
+            .peptides[0]
+            .values
+    }
+
+    #[test]
+    fn read_manifest() {
+        let dir = fixture::dir(
+            "manifest",
+            &[(
+                "manifest.csv",
+                "# study\nFile,Plex,Fraction,Bridge,Conditions\n\
+                 b2.txt,B,2,,\na.txt,A,,1,2=ctrl;3=drug\nb1.txt,B,1,4,1=ctrl\n",
+            )],
+        );
+        let path = dir.join("manifest.csv");
+        let plexes = read(&path).unwrap();
+        assert_eq!(plexes.len(), 2);
+        assert_eq!(plexes[0].name, "B");
+        assert_eq!(
+            plexes[0].files,
+            vec![dir.join("b1.txt"), dir.join("b2.txt")]
+        );
+        assert_eq!(plexes[0].bridge, Some(4));
+        assert_eq!(
+            plexes[1].channel_conditions(4),
+            vec![
+                Some("bridge".to_string()),
+                Some("ctrl".to_string()),
+                Some("drug".to_string()),
+                None
+            ]
+        );
+
+        fs::write(&path, "file,plex,bridge\na.txt,A,1\nb.txt,A,2\n").unwrap();
+        assert!(read(&path).is_err());
+        fs::write(&path, "file,fraction\na.txt,1\n").unwrap();
+        assert!(read(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn normalize_to_bridge() {
+        let mut datasets = vec![
+            plex(&[("P1", &[100, 10]), ("P2", &[100, 20])]),
+            plex(&[("P1", &[400, 10])]),
+            plex(&[("P1", &[7, 7])]),
+        ];
+        let factors = bridge_normalize(&mut datasets, &[Some(1), Some(1), None]).unwrap();
+        // Bridge totals of 200 and 400, with a mean of 300
+        assert_eq!(factors, vec![Some(1.5), Some(0.75), None]);
+        assert_eq!(values(&datasets[0], "P1"), &[150, 15]);
+        assert_eq!(values(&datasets[0], "P2"), &[150, 30]);
+        assert_eq!(values(&datasets[1], "P1"), &[300, 8]);
+        assert_eq!(values(&datasets[2], "P1"), &[7, 7]);
+
+        assert!(bridge_normalize(&mut datasets, &[Some(3), None, None]).is_err());
+        let mut zero = vec![plex(&[("P1", &[0, 10])])];
+        assert!(bridge_normalize(&mut zero, &[Some(1)]).is_err());
+    }
+
+    #[test]
+    fn moments() {
+        assert_eq!(mean(&[1.0, 2.0, 6.0]), 3.0);
+        assert_eq!(variance(&[1.0, 2.0, 6.0]), 7.0);
+        // With uninformative priors, the estimates are those of the protein
+        let (g, d) = shrink(&[1.0, 2.0, 6.0], (0.0, 1e12), (1.0 + 1e-12, 1e-12));
+        assert!((g - 3.0).abs() < 1e-6);
+        assert!((d - 14.0 / 3.0).abs() < 1e-6);
+        // A tight prior on the location pulls it towards the prior mean
+        let (g, _) = shrink(&[1.0, 2.0, 6.0], (0.0, 1e-6), (3.0, 2.0));
+        assert!(g.abs() < 1e-3);
+    }
+
+    #[test]
+    fn remove_plex_effects() {
+        let proteins = [
+            ("P1", [1000, 2000, 1500, 1200]),
+            ("P2", [300, 500, 450, 350]),
+            ("P3", [8000, 6000, 7000, 9000]),
+            ("P4", [50, 80, 60, 70]),
+        ];
+        let a = proteins
+            .iter()
+            .map(|(acc, v)| (*acc, v.to_vec()))
+            .collect::<Vec<_>>();
+        // The second plex has 4 times the intensity of the first, with a
+        // protein that is only found in that plex
+        let mut b = proteins
+            .iter()
+            .map(|(acc, v)| (*acc, v.iter().map(|x| x * 4).collect::<Vec<u32>>()))
+            .collect::<Vec<_>>();
+        b[1].1.reverse();
+        b.push(("P5", vec![10, 20, 30, 40]));
+        let as_plex = |rows: &[(&str, Vec<u32>)]| {
+            plex(
+                &rows
+                    .iter()
+                    .map(|(acc, v)| (*acc, v.as_slice()))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut datasets = vec![as_plex(&a), as_plex(&b)];
+
+        let log_ratio = |datasets: &[Dataset], accession: &str| {
+            let total = |data: &Dataset| values(data, accession).iter().sum::<u32>() as f64;
+            (total(&datasets[1]) / total(&datasets[0])).log2().abs()
+        };
+        let before = proteins
+            .iter()
+            .map(|(acc, _)| log_ratio(&datasets, acc))
+            .collect::<Vec<f64>>();
+
+        assert_eq!(batch_correct(&mut datasets).unwrap(), 4);
+        for ((acc, _), before) in proteins.iter().zip(before) {
+            let after = log_ratio(&datasets, acc);
+            assert!(after < before / 2.0, "{}: {} -> {}", acc, before, after);
+        }
+        assert_eq!(values(&datasets[1], "P5"), &[10, 20, 30, 40]);
+
+        assert!(batch_correct(&mut datasets[..1]).is_err());
+        let mut single = vec![plex(&[("P1", &[1])]), plex(&[("P1", &[2])])];
+        assert!(batch_correct(&mut single).is_err());
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(
+            read_conditions("1=ctrl; 2 = drug;").unwrap(),
+            vec![(1, "ctrl".to_string()), (2, "drug".to_string())]
+        );
+        assert!(read_conditions("0=ctrl").is_err());
+        assert!(read_conditions("1=").is_err());
+
+        let dir = fixture::dir(
+            "conditions",
+            &[("conditions.tsv", "channel\tcondition\n1\tctrl\n\n2\tdrug\n")],
+        );
+        let path = dir.join("conditions.tsv");
+        let file = path.to_str().unwrap();
+        assert_eq!(
+            read_conditions(file).unwrap(),
+            vec![(1, "ctrl".to_string()), (2, "drug".to_string())]
+        );
+        fs::write(&path, "1\tctrl\nx\tdrug\n").unwrap();
+        assert!(read_conditions(file).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Column descriptions and provenance for output tables, optionally written
+//! as a JSON sidecar file or as comment lines at the top of each output
+use census2csv::filter::Filter;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Version of the column layout of output tables. Columns written by
+/// default are only added in a new version, which is chosen with
+/// `--output-schema`, so that existing pipelines keep the layout they were
+/// written against.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Schema {
+    /// The original layout
+    V1,
+    /// Adds the scan number of each PSM to flat rows
+    V2,
+}
+
+impl Schema {
+    pub fn name(self) -> &'static str {
+        match self {
+            Schema::V1 => "v1",
+            Schema::V2 => "v2",
+        }
+    }
+}
+
+impl FromStr for Schema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "v1" | "1" => Ok(Schema::V1),
+            "v2" | "2" => Ok(Schema::V2),
+            _ => Err(format!("unknown output schema {}", s)),
+        }
+    }
+}
+
+/// Description of a single output column
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    /// Data type: string, integer, number, or boolean
+    pub kind: &'static str,
+    pub units: Option<&'static str>,
+    /// 1-indexed channel number and reporter ion label, for channel columns
+    pub channel: Option<(usize, String)>,
+    /// Experimental condition of the channel, if known
+    pub condition: Option<String>,
+    pub description: String,
+}
+
+impl Column {
+    pub fn new<S: Into<String>>(name: S, kind: &'static str, description: &str) -> Column {
+        Column {
+            name: name.into(),
+            kind,
+            units: None,
+            channel: None,
+            condition: None,
+            description: description.into(),
+        }
+    }
+
+    pub fn units(mut self, units: &'static str) -> Column {
+        self.units = Some(units);
+        self
+    }
+
+    pub fn channel<S: Into<String>>(mut self, channel: usize, label: S) -> Column {
+        self.channel = Some((channel, label.into()));
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut v = json!({
+            "name": self.name,
+            "type": self.kind,
+            "units": self.units,
+            "description": self.description,
+        });
+        if let Some((channel, label)) = &self.channel {
+            v["channel"] = json!(channel);
+            v["label"] = json!(label);
+            v["condition"] = json!(self.condition);
+        }
+        v
+    }
+}
+
+/// Write a `<output>.meta.json` file describing each column of `output`
+pub fn write_sidecar<P: AsRef<Path>>(
+    output: P,
+    columns: &[Column],
+    schema: Schema,
+) -> std::io::Result<()> {
+    let output = output.as_ref();
+    let mut path = output.as_os_str().to_owned();
+    path.push(".meta.json");
+
+    let doc = json!({
+        "file": output.file_name().map(|f| f.to_string_lossy()),
+        "generator": format!("census2csv {}", env!("CARGO_PKG_VERSION")),
+        "schema": schema.name(),
+        "columns": columns.iter().map(Column::to_json).collect::<Vec<Value>>(),
+    });
+    let s = serde_json::to_string_pretty(&doc).map_err(std::io::Error::other)?;
+    fs::File::create(path)?.write_all(s.as_bytes())
+}
+
+/// Describe every output of a run: each table format and layout with its
+/// columns, and each other format with a summary of its contents, along
+/// with the settings that determine the columns
+pub fn write_schema<W: Write>(
+    mut out: W,
+    channels: u8,
+    filter: &Filter,
+    normalization: &str,
+    schema: Schema,
+    tables: &[(&str, &str, &str, Vec<Column>)],
+    others: &[(&str, &str, &str)],
+) -> std::io::Result<()> {
+    let outputs = tables
+        .iter()
+        .map(|(format, extension, layout, columns)| {
+            json!({
+                "format": format,
+                "extension": extension,
+                "layout": layout,
+                "columns": columns.iter().map(Column::to_json).collect::<Vec<Value>>(),
+            })
+        })
+        .chain(others.iter().map(|(format, extension, description)| {
+            json!({
+                "format": format,
+                "extension": extension,
+                "description": description,
+            })
+        }))
+        .collect::<Vec<Value>>();
+    let doc = json!({
+        "generator": format!("census2csv {}", env!("CARGO_PKG_VERSION")),
+        "schema": schema.name(),
+        "channels": channels,
+        "filter": filter_hash(filter),
+        "normalization": normalization,
+        "outputs": outputs,
+    });
+    let s = serde_json::to_string_pretty(&doc).map_err(std::io::Error::other)?;
+    writeln!(out, "{}", s)
+}
+
+/// Stable hash of the serialized filter, so that outputs produced with the
+/// same filter settings can be identified
+pub fn filter_hash(filter: &Filter) -> String {
+    let s = serde_json::to_string(filter).unwrap_or_default();
+    // 64-bit FNV-1a, which does not vary between builds or platforms
+    let hash = s.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Write `#`-prefixed provenance lines recording the tool version, output
+/// schema, input file, filters, and normalization used to produce an output
+pub fn write_comments<W: Write>(
+    mut out: W,
+    input: &str,
+    filter: &Filter,
+    normalization: &str,
+    schema: Schema,
+) -> std::io::Result<()> {
+    writeln!(out, "# census2csv {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, "# schema: {}", schema.name())?;
+    writeln!(out, "# input: {}", input)?;
+    writeln!(out, "# filter: {}", filter_hash(filter))?;
+    writeln!(out, "# normalization: {}", normalization)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+    use census2csv::filter::PeptideFilter;
+
+    #[test]
+    fn filter_hash_identifies_settings() {
+        let unique = || Filter::default().add_peptide_filter(PeptideFilter::Unique);
+        let hash = filter_hash(&unique());
+        assert_eq!(hash.len(), 16);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(hash, filter_hash(&unique()));
+        assert_ne!(hash, filter_hash(&Filter::default()));
+        assert_ne!(
+            hash,
+            filter_hash(&unique().add_peptide_filter(PeptideFilter::Tryptic))
+        );
+    }
+
+    #[test]
+    fn sidecar_describes_columns() {
+        let dir = fixture::dir("meta", &[]);
+        let columns = vec![
+            Column::new("accession", "string", "Protein accession"),
+            Column::new("126", "number", "Reporter ion intensity")
+                .units("counts")
+                .channel(1, "126"),
+        ];
+        write_sidecar(dir.join("out.csv"), &columns, Schema::V2).unwrap();
+        let doc: Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("out.csv.meta.json")).unwrap())
+                .unwrap();
+        assert_eq!(doc["file"], "out.csv");
+        assert_eq!(doc["schema"], "v2");
+        assert_eq!(
+            doc["columns"][0],
+            json!({
+                "name": "accession",
+                "type": "string",
+                "units": null,
+                "description": "Protein accession",
+            })
+        );
+        assert_eq!(doc["columns"][1]["units"], "counts");
+        assert_eq!(doc["columns"][1]["channel"], 1);
+        assert_eq!(doc["columns"][1]["label"], "126");
+        assert_eq!(doc["columns"][1]["condition"], Value::Null);
+    }
+
+    #[test]
+    fn schema_names() {
+        assert_eq!("2".parse::<Schema>(), Ok(Schema::V2));
+        assert_eq!("V1".parse::<Schema>().map(Schema::name), Ok("v1"));
+        assert!("v3".parse::<Schema>().is_err());
+        assert!(Schema::V1 < Schema::V2);
+    }
+}
@@ -0,0 +1,87 @@
+//! Write datasets as a single JSON document preserving the hierarchy of
+//! proteins, peptides, and PSMs
+//!
+//! Each protein carries its rolled up channel values, each peptide the
+//! values of its PSMs combined as in the peptide layout, and each PSM its
+//! raw reporter ion intensities, so that consumers do not need to join
+//! separate protein and peptide tables.
+use census_proteomics::{Dataset, Protein};
+use serde_json::json;
+use std::io::prelude::*;
+
+/// Peptides of `prot`, in order of first appearance, with their PSMs
+fn peptides(prot: &Protein, average: bool) -> Vec<serde_json::Value> {
+    let mut sequences: Vec<&str> = Vec::new();
+    for pep in &prot.peptides {
+        if !sequences.contains(&pep.sequence.as_str()) {
+            sequences.push(&pep.sequence);
+        }
+    }
+    sequences
+        .into_iter()
+        .map(|sequence| {
+            let psms = prot
+                .peptides
+                .iter()
+                .filter(|p| p.sequence == sequence)
+                .collect::<Vec<_>>();
+            let mut values = vec![0u32; psms[0].values.len()];
+            for psm in &psms {
+                for (total, v) in values.iter_mut().zip(&psm.values) {
+                    *total += v;
+                }
+            }
+            if average {
+                values.iter_mut().for_each(|v| *v /= psms.len() as u32);
+            }
+            json!({
+                "sequence": sequence,
+                "unique": psms[0].unique,
+                "spectral_count": psms.len(),
+                "values": values,
+                "psms": psms
+                    .iter()
+                    .map(|psm| json!({
+                        "scan": psm.scan,
+                        // Through the shortest f32 representation, so that
+                        // the purity is not written as 0.7200000286102295
+                        "purity": psm.purity.to_string().parse::<f64>().ok(),
+                        "values": psm.values,
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect()
+}
+
+/// Write `data` as one JSON document, with protein values given by
+/// `protein_values`, and peptide values averaged rather than summed across
+/// PSMs if `average` is true
+pub fn write_nested<W, F>(
+    data: &Dataset,
+    mut out: W,
+    protein_values: F,
+    average: bool,
+) -> std::io::Result<()>
+where
+    W: Write,
+    F: Fn(&Protein) -> Vec<u32>,
+{
+    write!(out, "{{\"channels\":{},\"proteins\":[", data.channels)?;
+    for (idx, prot) in data.proteins.iter().enumerate() {
+        if idx > 0 {
+            out.write_all(b",")?;
+        }
+        let value = json!({
+            "accession": prot.accession,
+            "description": prot.description,
+            "spectral_count": prot.spectral_count,
+            "sequence_count": prot.sequence_count,
+            "values": protein_values(prot),
+            "peptides": peptides(prot, average),
+        });
+        serde_json::to_writer(&mut out, &value)?;
+    }
+    writeln!(out, "]}}")?;
+    out.flush()
+}
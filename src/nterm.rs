@@ -0,0 +1,123 @@
+//! N-terminomics: protein N-terminal and neo-N-terminal peptides
+//!
+//! In TAILS-style experiments, N-termini are labeled before digestion, so
+//! peptides starting at a protein N-terminus, or at a site cleaved by an
+//! endogenous protease, are the readout. A peptide is a protein N-terminus
+//! if it follows `-`, or the initiator methionine at position 1 of its
+//! protein sequence when a FASTA file is given, and a neo-N-terminus if it
+//! does not follow a tryptic cleavage site.
+use crate::coverage::Fasta;
+use census2csv::filter;
+use census2csv::sequence::{self, Cleavage};
+use census_proteomics::Dataset;
+use std::str::FromStr;
+
+/// TMT, TMTpro, and acetyl mass shifts, in Da
+const TMT: f64 = 229.1629;
+const TMTPRO: f64 = 304.2071;
+const ACETYL: f64 = 42.0106;
+/// Tolerance for matching mass shifts, in Da
+const TOLERANCE: f64 = 0.01;
+
+/// N-terminal label a peptide is required to carry
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Label {
+    /// TMT or TMTpro
+    Tmt,
+    Acetyl,
+    /// TMT, TMTpro, or acetyl
+    Any,
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tmt" => Ok(Label::Tmt),
+            "acetyl" => Ok(Label::Acetyl),
+            "any" => Ok(Label::Any),
+            _ => Err(format!("unknown N-terminal label {}", s)),
+        }
+    }
+}
+
+impl Label {
+    fn masses(self) -> &'static [f64] {
+        match self {
+            Label::Tmt => &[TMT, TMTPRO],
+            Label::Acetyl => &[ACETYL],
+            Label::Any => &[TMT, TMTPRO, ACETYL],
+        }
+    }
+
+    /// Does `peptide` carry this label on its N-terminus?
+    fn labels(self, peptide: &sequence::Peptide) -> bool {
+        peptide.modifications().iter().any(|m| {
+            m.is_nterm()
+                && m.mass()
+                    .is_some_and(|mass| self.masses().iter().any(|l| (mass - l).abs() < TOLERANCE))
+        })
+    }
+}
+
+/// Kind of N-terminus a peptide starts at
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Terminus {
+    Protein,
+    Neo,
+}
+
+impl Terminus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Terminus::Protein => "protein",
+            Terminus::Neo => "neo",
+        }
+    }
+}
+
+/// An N-terminal peptide, located in its protein sequence if known
+pub struct Site {
+    pub terminus: Terminus,
+    /// Residues either side of the cleavage site, as `P1.P1'`
+    pub cleavage: String,
+    /// 1-indexed position of the first residue of the peptide
+    pub position: Option<usize>,
+}
+
+/// Classify the peptide `seq` of a protein whose sequence is `protein`, if
+/// known, returning `None` if it is not N-terminal
+pub fn site(seq: &str, protein: Option<&str>) -> Option<Site> {
+    let peptide = sequence::parse(seq);
+    let residues = peptide.residues();
+    let position = protein.and_then(|p| p.find(&residues)).map(|idx| idx + 1);
+    let terminus = match (peptide.before, position) {
+        (Some('-'), _) | (Some('M'), Some(2)) => Terminus::Protein,
+        _ if matches!(peptide.cleavage(), Cleavage::SemiNRagged | Cleavage::Non) => Terminus::Neo,
+        _ => return None,
+    };
+    let cleavage = format!(
+        "{}.{}",
+        peptide.before.unwrap_or('-'),
+        residues.chars().next().unwrap_or('-')
+    );
+    Some(Site {
+        terminus,
+        cleavage,
+        position,
+    })
+}
+
+/// Keep only N-terminal peptides, carrying `label` if given. Proteins left
+/// without peptides are kept, for the caller to remove.
+pub fn apply(data: &mut Dataset, label: Option<Label>, fasta: Option<&Fasta>) {
+    for prot in &mut data.proteins {
+        let protein = fasta.and_then(|f| f.get(&prot.accession));
+        prot.peptides.retain(|pep| {
+            site(&pep.sequence, protein).is_some()
+                && label.map_or(true, |l| l.labels(&sequence::parse(&pep.sequence)))
+        });
+        filter::recount(prot);
+    }
+}
@@ -0,0 +1,79 @@
+//! Allocation-free formatting of numeric fields
+//!
+//! Integers are written with itoa and floating point values with ryu,
+//! appending to a caller-owned buffer. Output is identical to `Display`.
+//!
+//! Formatting never depends on the locale of the machine: the decimal
+//! separator is always `.`, and digits are never grouped.
+use std::fmt::Write;
+
+/// How floating point fields are written
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FloatFormat {
+    /// Number of digits after the decimal point. When `None`, the shortest
+    /// representation that reads back as the same value is written.
+    pub precision: Option<usize>,
+    /// Write values in exponent notation, such as `1.25e3`
+    pub scientific: bool,
+}
+
+impl FloatFormat {
+    /// Append `value` to `buf` in this format
+    pub fn push(&self, buf: &mut String, value: f64) {
+        let start = buf.len();
+        let _ = match (self.precision, self.scientific) {
+            (None, false) => {
+                push_float(buf, value);
+                Ok(())
+            }
+            (None, true) => write!(buf, "{:e}", value),
+            (Some(p), false) => write!(buf, "{:.*}", p, value),
+            (Some(p), true) => write!(buf, "{:.*e}", p, value),
+        };
+        // Small negative values that round to zero would otherwise be
+        // written as "-0.00"
+        let digits = buf[start..].split('e').next().unwrap_or_default();
+        if value.is_finite()
+            && digits.starts_with('-')
+            && digits.bytes().all(|b| matches!(b, b'-' | b'0' | b'.'))
+        {
+            buf.remove(start);
+        }
+    }
+}
+
+/// Append the decimal representation of `value` to `buf`
+pub fn push_int<I: itoa::Integer>(buf: &mut String, value: I) {
+    buf.push_str(itoa::Buffer::new().format(value));
+}
+
+/// Append `value` to `buf`, formatted as `Display` formats an `f64`
+pub fn push_float(buf: &mut String, value: f64) {
+    // ryu switches to exponent notation outside of this range, and writes
+    // "1.0" where `Display` writes "1"
+    let abs = value.abs();
+    if value.is_finite() && (abs == 0.0 || (1e-5..1e16).contains(&abs)) {
+        let mut ryu = ryu::Buffer::new();
+        let s = ryu.format_finite(value);
+        // At 16 or more significant digits two shortest representations may
+        // be equally close, and ryu and `Display` break the tie differently
+        let digits = s.trim_start_matches(['-', '0', '.']);
+        if digits.bytes().filter(u8::is_ascii_digit).count() < 16 {
+            buf.push_str(s.strip_suffix(".0").unwrap_or(s));
+            return;
+        }
+    }
+    let _ = write!(buf, "{}", value);
+}
+
+/// Decimal representation of `value`
+pub fn int<I: itoa::Integer>(value: I) -> String {
+    itoa::Buffer::new().format(value).to_string()
+}
+
+/// `value` formatted as `Display` formats an `f64`
+pub fn float(value: f64) -> String {
+    let mut buf = String::new();
+    push_float(&mut buf, value);
+    buf
+}
@@ -0,0 +1,1700 @@
+//! Conversion of a dataset to output files, and the options controlling
+//! their layout, format, and columns
+use crate::{
+    append, census, checkpoint, coverage, gct, idmap, meta, nested, nterm, perseus, plot, saint,
+    split, terms, uniprot, writer,
+};
+use census2csv::correction::{Noise, Scale};
+use census2csv::filter::Filter;
+use census2csv::impute::{self, Impute};
+use census2csv::number::FloatFormat;
+use census2csv::rollup::{self, Rollup};
+use census2csv::transform::Transform;
+use census2csv::{abpp, duplicates, grouping, labels, paired, parallel, qc, sample, sequence};
+use census_proteomics::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use writer::{RecordWriter, Rows};
+
+/// Output table layout
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Layout {
+    /// One row per protein
+    Protein,
+    /// One row per unique peptide sequence within each protein
+    Peptide,
+    /// One row per peptide-spectrum match
+    Flat,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Protein, Layout::Peptide, Layout::Flat];
+
+    /// Name used to distinguish outputs when several layouts are requested
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Protein => "protein",
+            Layout::Peptide => "peptide",
+            Layout::Flat => "flat",
+        }
+    }
+}
+
+/// Source of the spectral and sequence counts of protein rows
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Counts {
+    /// Recounted from the PSMs passing filters
+    Filtered,
+    /// As reported by census, before filtering
+    Census,
+    /// Recounted values, followed by census values in `census_` columns
+    Both,
+}
+
+impl std::str::FromStr for Counts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "filtered" => Ok(Counts::Filtered),
+            "census" => Ok(Counts::Census),
+            "both" => Ok(Counts::Both),
+            _ => Err(format!("unknown count source {}", s)),
+        }
+    }
+}
+
+/// Statistic combining the channels of a condition into one column
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aggregate {
+    Mean,
+    Median,
+}
+
+impl Aggregate {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregate::Mean => "mean",
+            Aggregate::Median => "median",
+        }
+    }
+
+    fn apply(self, values: &[f64]) -> f64 {
+        let n = values.len();
+        match self {
+            Aggregate::Mean => values.iter().sum::<f64>() / n as f64,
+            Aggregate::Median => {
+                let mut sorted = values.to_vec();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                if n % 2 == 1 {
+                    sorted[n / 2]
+                } else {
+                    (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Aggregate::Mean),
+            "median" => Ok(Aggregate::Median),
+            _ => Err(format!("unknown aggregate {}", s)),
+        }
+    }
+}
+
+/// Output file format
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// Comma separated table using the requested layout
+    Csv,
+    /// Tab separated table using the requested layout
+    Tsv,
+    /// Filtered data written back out in census_out format
+    Census,
+    /// Tab-delimited matrix with Perseus column type annotations
+    Perseus,
+    /// SAINTexpress inter, prey, and bait files
+    Saint,
+    /// Broad GCT 1.3, for Morpheus and ssGSEA
+    Gct,
+    /// SQL text script creating and populating a table, for loading into
+    /// SQLite with sqlite3
+    Sql,
+    /// SQLite database holding a single table
+    Sqlite,
+    /// Arrow IPC file (Feather v2), for pyarrow, polars, and R arrow
+    Arrow,
+    /// Parquet file with a single row group
+    Parquet,
+    /// Single JSON document of proteins, their peptides, and their PSMs
+    NestedJson,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "census" => Ok(Format::Census),
+            "perseus" => Ok(Format::Perseus),
+            "saint" => Ok(Format::Saint),
+            "gct" => Ok(Format::Gct),
+            "sql" => Ok(Format::Sql),
+            "sqlite" => Ok(Format::Sqlite),
+            "arrow" | "feather" => Ok(Format::Arrow),
+            "parquet" => Ok(Format::Parquet),
+            "nested-json" => Ok(Format::NestedJson),
+            _ => Err(format!("unknown output format {}", s)),
+        }
+    }
+}
+
+impl Format {
+    pub const ALL: [Format; 11] = [
+        Format::Csv,
+        Format::Tsv,
+        Format::Census,
+        Format::Perseus,
+        Format::Saint,
+        Format::Gct,
+        Format::Sql,
+        Format::Sqlite,
+        Format::Arrow,
+        Format::Parquet,
+        Format::NestedJson,
+    ];
+
+    /// Name of the format, as given to `--format`
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Census => "census",
+            Format::Perseus => "perseus",
+            Format::Saint => "saint",
+            Format::Gct => "gct",
+            Format::Sql => "sql",
+            Format::Sqlite => "sqlite",
+            Format::Arrow => "arrow",
+            Format::Parquet => "parquet",
+            Format::NestedJson => "nested-json",
+        }
+    }
+
+    /// Contents of outputs in this format, if it is not a table
+    pub fn summary(self) -> Option<&'static str> {
+        match self {
+            Format::Census => Some("Filtered PSMs written back out in census_out format"),
+            Format::Saint => Some("SAINTexpress inter, prey, and bait files, with one inter line per protein and channel"),
+            Format::NestedJson => Some("One JSON document of proteins, with accession, description, spectral_count, sequence_count, and rolled up values, each with its peptides, with sequence, unique, spectral_count, and values, each with its PSMs, with scan, purity, and values"),
+            _ => None,
+        }
+    }
+
+    /// File extension for outputs of this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Census => "filtered.txt",
+            Format::Perseus => "perseus.txt",
+            Format::Saint => "inter.txt",
+            Format::Gct => "gct",
+            Format::Sql => "sql",
+            Format::Sqlite => "sqlite",
+            Format::Arrow => "arrow",
+            Format::Parquet => "parquet",
+            Format::NestedJson => "json",
+        }
+    }
+
+    /// Is this format a table using the requested layout and columns?
+    pub fn is_table(self) -> bool {
+        !matches!(self, Format::Census | Format::Saint | Format::NestedJson)
+    }
+}
+
+/// Options controlling the content of output tables
+pub struct Options {
+    /// Layout of the table currently being written
+    pub layout: Layout,
+    /// Every layout requested. Each is written to its own file, from a
+    /// single parse of the input.
+    pub layouts: Vec<Layout>,
+    /// Write quality control results to a JSON file alongside the output
+    pub qc_report: bool,
+    pub format: Format,
+    /// Average channel values by the number of spectral matches, rather than
+    /// summing them
+    pub average: bool,
+    /// Weight PSMs in protein rollups by the correlation of their channel
+    /// profiles with the rest of the protein
+    pub correlation_weighting: bool,
+    /// Annotate rows with gene name, protein name, and subcellular location
+    pub uniprot: Option<uniprot::UniProt>,
+    /// Rewrite accessions in output tables to another namespace
+    pub idmap: Option<idmap::IdMap>,
+    /// Write only proteins annotated with a selected term, and their terms
+    pub select_term: Option<terms::Selection>,
+    /// Protein sequences, for coverage maps and N-terminal positions
+    pub fasta: Option<coverage::Fasta>,
+    /// Write a map of the residues covered by peptides
+    pub coverage: Option<coverage::Format>,
+    /// Append the rows of each run to this table, rather than writing one
+    /// output per input
+    pub append: Option<append::Target>,
+    /// Keep only protein N-terminal and neo-N-terminal peptides, and
+    /// describe their cleavage sites in peptide and flat rows
+    pub nterm_mode: bool,
+    /// N-terminal label required in `nterm_mode`
+    pub nterm_label: Option<nterm::Label>,
+    /// Append the minimum, maximum, median, and total of each row's channel
+    /// values
+    pub row_stats: bool,
+    /// Formatting of floating point fields
+    pub float_format: FloatFormat,
+    /// Append the abundance rank and percentile of each protein to protein
+    /// rows
+    pub rank: bool,
+    /// Append the sequences of each protein's peptides to protein rows
+    pub list_peptides: bool,
+    /// Classify the tryptic cleavage of each peptide in peptide and flat rows
+    pub cleavage_class: bool,
+    /// Remove PSMs whose sequences cannot be parsed, rather than warning
+    pub skip_unparseable: bool,
+    /// Remove protein blocks identical to an earlier block, so that their
+    /// rows are written once
+    pub dedupe: bool,
+    /// Group peptide rows by residues and modifications, ignoring flanking
+    /// residues, and describe the modifications of peptide and flat rows
+    pub ptm_groups: bool,
+    /// Accession prefixes identifying contaminant proteins
+    pub contaminant_prefixes: Vec<String>,
+    /// Remove contaminants, rather than flagging them in an `is_contaminant`
+    /// column
+    pub exclude_contaminants: bool,
+    /// Keep, flag, or remove proteins identified by a single peptide
+    pub single_hits: qc::SingleHits,
+    /// Source of the spectral and sequence counts of protein rows
+    pub counts: Counts,
+    /// Spectral and sequence counts reported by census for the block that
+    /// each protein of the prepared dataset came from, recorded before
+    /// filtering. Empty if they are not needed.
+    pub census_counts: Vec<(u16, u16)>,
+    /// Warn if the fraction of decoys remaining after filtering exceeds this
+    pub max_decoy_rate: f64,
+    /// How to handle accessions that appear in multiple protein blocks
+    pub duplicates: Option<duplicates::Policy>,
+    /// Identity of protein blocks, deciding which are duplicates
+    pub key: duplicates::Key,
+    /// Use TMT reporter ion labels for channel column headers
+    pub labels: Option<labels::LabelNames>,
+    /// Labeling chemistry declared for the experiment
+    pub chemistry: Option<labels::Chemistry>,
+    /// Write a JSON sidecar describing each column
+    pub meta: bool,
+    /// Write provenance comment lines at the top of CSV outputs
+    pub header_comments: bool,
+    /// Column layout of output tables
+    pub schema: meta::Schema,
+    /// Sync CSV and TSV outputs to disk every this many rows, recording
+    /// their progress so that an interrupted run can be resumed
+    pub checkpoint: Option<usize>,
+    /// Continue outputs from the checkpoints of an interrupted run
+    pub resume: bool,
+    /// Write the output table to stdout rather than to a file
+    pub stdout: bool,
+    /// Append a final row of per-channel sums to CSV outputs
+    pub totals_row: bool,
+    /// Experimental condition of each channel, used to name channel columns
+    pub conditions: Vec<Option<String>>,
+    /// Write one column per condition, combining its channels, rather than
+    /// one per channel
+    pub aggregate: Option<Aggregate>,
+    /// Time point of each channel, ordering channel columns chronologically
+    pub time_points: Vec<Option<f64>>,
+    /// Append the change of each time point from the first, and the slope
+    /// of intensity against time
+    pub time_course: bool,
+    /// Description of any normalization applied to channel values
+    pub normalization: String,
+    /// Variance-stabilizing transform applied to channel values as they are
+    /// written
+    pub transform: Option<Transform>,
+    /// Cofactor of `transform`
+    pub cofactor: f64,
+    /// Write transformed values alongside raw values, rather than in place
+    /// of them
+    pub keep_raw: bool,
+    /// Print the first N rows of each table to stdout, rather than writing
+    /// any files
+    pub preview: Option<usize>,
+    /// Plot distributions of the prepared data
+    pub plot: Option<plot::Plot>,
+    /// Directory to write figures of the prepared data to, and their format
+    pub plot_dir: Option<(PathBuf, plot::Figures)>,
+    /// Fraction of protein blocks to process, and the seed choosing them
+    pub sample: Option<(f64, u64)>,
+    /// Control and treated channel pairs for competition ratio columns
+    pub abpp: Option<abpp::Pairs>,
+    /// Paired channels for log2 ratio and paired t-test columns
+    pub paired: Option<paired::Pairs>,
+    /// Remove proteins whose sequences are all explained by another protein
+    pub group_proteins: bool,
+    /// Inference score and subsumed proteins of each retained protein,
+    /// computed from the prepared dataset
+    pub groups: HashMap<String, grouping::Group>,
+    /// Imputation of missing channel values in output rows
+    pub impute: Option<Impute>,
+    /// Value imputed for each channel, computed from the prepared dataset
+    pub impute_floors: Vec<u32>,
+    /// Remove plex effects across the plexes of a manifest with an empirical
+    /// Bayes batch correction
+    pub batch_correct: bool,
+    /// Noise floor subtracted from PSM values before aggregation
+    pub noise: Option<Noise>,
+    /// Per-channel factors applied to PSM values before aggregation
+    pub scale: Option<Scale>,
+    /// Bait annotation of each channel, for SAINT outputs
+    pub baits: Option<saint::Baits>,
+    /// Condition names of control purifications, for SAINT outputs without
+    /// a bait file
+    pub saint_controls: Vec<String>,
+    /// Interaction quantity of SAINT outputs
+    pub saint_quantity: saint::Quantity,
+    /// Write one CSV per channel or condition, rather than a single table
+    pub split_by: Option<split::SplitBy>,
+    /// Number of threads used to filter proteins and build rows
+    pub threads: usize,
+    /// Approximate limit, in bytes, on memory used for rows built at once
+    pub max_memory: Option<usize>,
+}
+
+impl Options {
+    /// Describe each column of the output table for a dataset with
+    /// `channels` channels
+    pub fn columns(&self, channels: u8) -> Vec<meta::Column> {
+        use meta::Column;
+        let mut cols = vec![
+            Column::new("accession", "string", "Protein accession"),
+            Column::new("description", "string", "Protein description"),
+        ];
+        match self.layout {
+            Layout::Protein => {
+                let (spectral, sequence) = match self.counts {
+                    Counts::Census => (
+                        "Number of PSMs reported by census, before filtering",
+                        "Number of unique peptide sequences reported by census, before filtering",
+                    ),
+                    Counts::Filtered | Counts::Both => (
+                        "Number of PSMs passing filters",
+                        "Number of unique peptide sequences passing filters",
+                    ),
+                };
+                cols.push(Column::new("spectral_count", "integer", spectral).units("PSMs"));
+                cols.push(Column::new("sequence_count", "integer", sequence).units("peptides"));
+                if self.counts == Counts::Both {
+                    cols.push(
+                        Column::new(
+                            "census_spectral_count",
+                            "integer",
+                            "Number of PSMs reported by census, before filtering",
+                        )
+                        .units("PSMs"),
+                    );
+                    cols.push(
+                        Column::new(
+                            "census_sequence_count",
+                            "integer",
+                            "Number of unique peptide sequences reported by census, before filtering",
+                        )
+                        .units("peptides"),
+                    );
+                }
+            }
+            Layout::Peptide => {
+                cols.push(
+                    Column::new(
+                        "spectral_count",
+                        "integer",
+                        "Number of PSMs for this sequence",
+                    )
+                    .units("PSMs"),
+                );
+                cols.push(Column::new("sequence", "string", "Peptide sequence"));
+            }
+            Layout::Flat => {
+                cols.push(Column::new("sequence", "string", "Peptide sequence"));
+                if self.schema >= meta::Schema::V2 {
+                    cols.push(Column::new("scan", "integer", "Scan number of the PSM"));
+                }
+            }
+        }
+        if self.cleavage_class && self.layout != Layout::Protein {
+            cols.push(Column::new(
+                "cleavage_class",
+                "string",
+                "Tryptic cleavage at the peptide termini: full, semi_n_ragged or semi_c_ragged for a non-tryptic N- or C-terminus, or non",
+            ));
+        }
+        if self.nterm_mode && self.layout != Layout::Protein {
+            cols.push(Column::new(
+                "nterm_type",
+                "string",
+                "Whether the peptide starts at a protein N-terminus or a neo-N-terminus",
+            ));
+            cols.push(Column::new(
+                "cleavage_site",
+                "string",
+                "Residues either side of the N-terminal cleavage site, as P1.P1'",
+            ));
+            cols.push(Column::new(
+                "nterm_position",
+                "integer",
+                "1-indexed position of the first residue of the peptide in the protein sequence",
+            ));
+        }
+        if self.ptm_groups && self.layout != Layout::Protein {
+            cols.push(Column::new(
+                "modifications",
+                "string",
+                "Semicolon-separated modifications, each as the residue and its 1-indexed position, or N-term, and the annotated mass shift, such as K9:42.0106",
+            ));
+            cols.push(Column::new(
+                "modification_composition",
+                "string",
+                "Semicolon-separated count of each distinct modification, such as 14.0157:2;42.0106:1",
+            ));
+        }
+
+        let plex = self.labels.and_then(|l| l.resolve(channels).ok());
+        let desc = match (self.layout, self.average) {
+            (Layout::Flat, _) => "Reporter ion intensity",
+            (Layout::Protein, true) if self.correlation_weighting => {
+                "Reporter ion intensity, averaged across PSMs weighted by profile correlation"
+            }
+            (Layout::Protein, false) if self.correlation_weighting => {
+                "Reporter ion intensity, summed across PSMs weighted by profile correlation"
+            }
+            (_, true) => "Reporter ion intensity, averaged across PSMs",
+            (_, false) => "Reporter ion intensity, summed across PSMs",
+        };
+        let kind = if self.average { "number" } else { "integer" };
+        let names = labels::channel_names(plex, channels);
+        let label = |idx: usize| match plex {
+            Some(p) => p.labels()[idx].to_string(),
+            None => format!("{}", idx + 1),
+        };
+        let mut transformed = Vec::new();
+        let mut timed = Vec::new();
+        for (condition, idxs) in self.channel_groups(channels as usize) {
+            let (name, desc, kind) = match (&condition, self.aggregate) {
+                (Some(cond), Some(agg)) => (
+                    cond.clone(),
+                    format!(
+                        "{}, the {} across channels {}",
+                        desc,
+                        agg.name(),
+                        idxs.iter()
+                            .map(|idx| label(*idx))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    "number",
+                ),
+                (Some(cond), None) => (format!("{}_{}", cond, label(idxs[0])), desc.into(), kind),
+                (None, _) => (names[idxs[0]].clone(), desc.into(), kind),
+            };
+            if let Some(t) = self.transform {
+                let desc = format!("{}, {} transformed", desc, t.name());
+                let col = Column::new(format!("{}_{}", name, t.name()), "number", &desc);
+                transformed.push(col.units("intensity"));
+            }
+            if let Some(time) = self.group_time(&idxs) {
+                timed.push((name.clone(), time));
+            }
+            let mut col = Column::new(name, kind, &desc).units("intensity");
+            if let [idx] = idxs[..] {
+                col = col.channel(idx + 1, label(idx));
+            }
+            col.condition = condition;
+            cols.push(col);
+        }
+        match self.transform {
+            Some(_) if self.keep_raw => cols.extend(transformed),
+            // Transformed values take the place of raw values in channel
+            // columns, keeping their names and channel metadata
+            Some(_) => {
+                let channels = cols.len() - transformed.len();
+                for (col, t) in cols[channels..].iter_mut().zip(transformed) {
+                    col.kind = t.kind;
+                    col.description = t.description;
+                }
+            }
+            None => {}
+        }
+        if self.time_course {
+            let t0 = timed.iter().map(|(_, t)| *t).fold(f64::INFINITY, f64::min);
+            for (name, time) in timed.iter().filter(|(_, t)| *t > t0) {
+                let desc = format!(
+                    "Change in intensity of {} at time point {} from time point {}",
+                    name, time, t0
+                );
+                cols.push(
+                    Column::new(format!("delta_{}", name), "number", &desc).units("intensity"),
+                );
+            }
+            cols.push(Column::new(
+                "slope",
+                "number",
+                "Least squares slope of intensity against time point, per unit of time",
+            ));
+        }
+        if self.row_stats {
+            for (name, kind, desc) in [
+                ("row_min", kind, "Minimum intensity across channels"),
+                ("row_max", kind, "Maximum intensity across channels"),
+                ("row_median", "number", "Median intensity across channels"),
+                ("row_total", kind, "Total intensity across channels"),
+            ] {
+                cols.push(Column::new(name, kind, desc).units("intensity"));
+            }
+        }
+        if self.impute.is_some() {
+            cols.push(Column::new(
+                "imputed_count",
+                "integer",
+                "Number of channels with missing values that were imputed",
+            ));
+            cols.push(Column::new(
+                "imputed_channels",
+                "string",
+                "Semicolon-separated 1-indexed channels with imputed values",
+            ));
+        }
+        if let Some(pairs) = &self.abpp {
+            for (c, t) in &pairs.pairs {
+                cols.push(Column::new(
+                    format!("ratio_{}_{}", c, t),
+                    "number",
+                    &format!(
+                        "Competition ratio of control channel {} to treated channel {}, capped at {}",
+                        c, t, pairs.cap
+                    ),
+                ));
+            }
+            cols.push(Column::new(
+                "competition_ratio",
+                "number",
+                "Median competition ratio across channel pairs",
+            ));
+        }
+        if let Some(pairs) = &self.paired {
+            for (name, n, d) in &pairs.pairs {
+                cols.push(Column::new(
+                    format!("log2_{}", name),
+                    "number",
+                    &format!("Log2 ratio of channel {} to channel {}", n, d),
+                ));
+            }
+            for (name, desc) in [
+                ("paired_mean_log2", "Mean log2 ratio across channel pairs"),
+                (
+                    "paired_t",
+                    "Paired t statistic of the log2 ratios against zero",
+                ),
+                ("paired_p", "Two-sided p-value of the paired t-test"),
+            ] {
+                cols.push(Column::new(name, "number", desc));
+            }
+        }
+        if self.rank && self.layout == Layout::Protein {
+            cols.push(Column::new(
+                "abundance_rank",
+                "integer",
+                "Rank of the protein by intensity summed across channels, from 1 for the most abundant",
+            ));
+            cols.push(Column::new(
+                "abundance_percentile",
+                "number",
+                "Percentage of proteins with a summed intensity at or below that of the protein",
+            ));
+        }
+
+        if self.uniprot.is_some() {
+            cols.push(Column::new(
+                "gene_name",
+                "string",
+                "Primary gene name from UniProt",
+            ));
+            cols.push(Column::new(
+                "protein_name",
+                "string",
+                "Recommended protein name from UniProt",
+            ));
+            cols.push(Column::new(
+                "subcellular_location",
+                "string",
+                "Subcellular location from UniProt",
+            ));
+        }
+        if self.flag_contaminants() {
+            cols.push(Column::new(
+                "is_contaminant",
+                "boolean",
+                "Whether the accession has a contaminant prefix",
+            ));
+        }
+        if self.select_term.is_some() {
+            cols.push(Column::new(
+                "term",
+                "string",
+                "Semicolon-separated annotated terms of the protein matching --select-term",
+            ));
+        }
+        if self.single_hits == qc::SingleHits::Flag {
+            cols.push(Column::new(
+                "is_single_hit",
+                "boolean",
+                "Whether the protein is identified by a single distinct peptide sequence passing filters",
+            ));
+        }
+        if self.group_proteins && self.layout == Layout::Protein {
+            cols.push(Column::new(
+                "inference_score",
+                "number",
+                "Number of sequences matching no other retained protein, plus the fraction of PSMs with those sequences",
+            ));
+            cols.push(Column::new(
+                "subsumed_accessions",
+                "string",
+                "Semicolon-separated accessions of proteins whose sequences are all explained by this protein",
+            ));
+        }
+        if self.list_peptides && self.layout == Layout::Protein {
+            cols.push(Column::new(
+                "peptides",
+                "string",
+                "Semicolon-separated sequences of the peptides passing filters",
+            ));
+        }
+        cols
+    }
+
+    /// Description of every correction applied to channel values, for header
+    /// comments
+    pub fn normalization(&self) -> String {
+        let corrections = self
+            .noise
+            .iter()
+            .map(Noise::describe)
+            .chain(self.scale.iter().map(Scale::describe))
+            .chain(
+                self.transform
+                    .map(|t| format!("{} transform, cofactor {}", t.name(), self.cofactor)),
+            );
+        let parts = std::iter::once(self.normalization.clone())
+            .filter(|n| n != "none")
+            .chain(corrections)
+            .collect::<Vec<String>>();
+        if parts.is_empty() {
+            "none".to_string()
+        } else {
+            parts.join("; ")
+        }
+    }
+
+    /// Are contaminants kept and flagged in an `is_contaminant` column?
+    fn flag_contaminants(&self) -> bool {
+        !self.contaminant_prefixes.is_empty() && !self.exclude_contaminants
+    }
+
+    /// Strategy used to combine PSM values into protein values
+    fn rollup(&self) -> &(dyn Rollup + Sync) {
+        match (self.correlation_weighting, self.average) {
+            (true, true) => &rollup::CorrelationWeighted { average: true },
+            (true, false) => &rollup::CorrelationWeighted { average: false },
+            (false, true) => &rollup::Mean,
+            (false, false) => &rollup::Sum,
+        }
+    }
+
+    /// Number of proteins whose rows are built at once, reduced from `BATCH`
+    /// as needed to stay within `max_memory`
+    fn batch_size(&self, data: &Dataset) -> usize {
+        match self.max_memory {
+            Some(max) => {
+                let psms = data
+                    .proteins
+                    .iter()
+                    .map(|p| p.peptides.len())
+                    .sum::<usize>();
+                let per_protein = psms.div_ceil(data.proteins.len().max(1)).max(1) * PSM_BYTES;
+                (max / per_protein).clamp(1, BATCH)
+            }
+            None => BATCH,
+        }
+    }
+
+    /// Append the N-terminal site of the peptide `seq` of `prot`, in
+    /// `nterm_mode`
+    fn push_nterm(&self, prot: &Protein, seq: &str, rows: &mut Rows) {
+        if !self.nterm_mode {
+            return;
+        }
+        let protein = self.fasta.as_ref().and_then(|f| f.get(&prot.accession));
+        match nterm::site(seq, protein) {
+            Some(site) => {
+                rows.push(site.terminus.name());
+                rows.push(&site.cleavage);
+                match site.position {
+                    Some(pos) => rows.push_int(pos),
+                    None => rows.push(""),
+                }
+            }
+            None => (0..3).for_each(|_| rows.push("")),
+        }
+    }
+
+    /// Append the modifications of the peptide `seq`, and their composition,
+    /// with `ptm_groups`
+    fn push_modifications(&self, seq: &str, rows: &mut Rows) {
+        if self.ptm_groups {
+            let peptide = sequence::parse(seq);
+            rows.push(&peptide.describe_modifications());
+            rows.push(&peptide.modification_composition());
+        }
+    }
+
+    /// Append the channel `values` of a row, along with any columns derived
+    /// from them. Missing values are imputed first, if requested.
+    fn push_channels(&self, values: &mut [u32], rows: &mut Rows) {
+        let imputed = match self.impute {
+            Some(_) => impute::impute(values, &self.impute_floors),
+            None => Vec::new(),
+        };
+        self.push_values(values, rows);
+        self.push_row_stats(values, rows);
+        if self.impute.is_some() {
+            rows.push_int(imputed.len());
+            let channels = imputed
+                .iter()
+                .map(|idx| (idx + 1).to_string())
+                .collect::<Vec<String>>();
+            rows.push(&channels.join(";"));
+        }
+        if let Some(pairs) = &self.abpp {
+            let ratios = pairs.ratios(values);
+            for ratio in ratios.iter().chain(std::iter::once(&abpp::median(&ratios))) {
+                match ratio {
+                    Some(r) => rows.push_float(*r, self.float_format),
+                    None => rows.push(""),
+                }
+            }
+        }
+        if let Some(pairs) = &self.paired {
+            let ratios = pairs.log2_ratios(values);
+            for ratio in &ratios {
+                match ratio {
+                    Some(r) => rows.push_float(*r, self.float_format),
+                    None => rows.push(""),
+                }
+            }
+            match paired::t_test(&ratios) {
+                Some(test) => {
+                    rows.push_float(test.mean, self.float_format);
+                    rows.push_float(test.t, self.float_format);
+                    rows.push_float(test.p, self.float_format);
+                }
+                None => (0..3).for_each(|_| rows.push("")),
+            }
+        }
+    }
+
+    /// Time point of the channels of a channel column, from the first
+    fn group_time(&self, idxs: &[usize]) -> Option<f64> {
+        self.time_points.get(idxs[0]).copied().flatten()
+    }
+
+    /// Channels written to each channel column, with their condition. Each
+    /// channel has its own column, unless conditions are aggregated, when
+    /// the channels of each condition share the column of the first.
+    /// Columns with time points are ordered chronologically, followed by
+    /// the rest.
+    fn channel_groups(&self, channels: usize) -> Vec<(Option<String>, Vec<usize>)> {
+        let mut groups: Vec<(Option<String>, Vec<usize>)> = Vec::new();
+        for idx in 0..channels {
+            let condition = self.conditions.get(idx).cloned().flatten();
+            let group = match (&condition, self.aggregate) {
+                (Some(_), Some(_)) => groups.iter().position(|(c, _)| *c == condition),
+                _ => None,
+            };
+            match group {
+                Some(group) => groups[group].1.push(idx),
+                None => groups.push((condition, vec![idx])),
+            }
+        }
+        groups.sort_by(
+            |(_, a), (_, b)| match (self.group_time(a), self.group_time(b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            },
+        );
+        groups
+    }
+
+    /// Append the channel `values` of a row, aggregated by condition,
+    /// transformed, and followed by time course columns if requested
+    fn push_values(&self, values: &[u32], rows: &mut Rows) {
+        let groups = self.channel_groups(values.len());
+        let combined = groups
+            .iter()
+            .map(|(_, idxs)| match self.aggregate {
+                Some(agg) => agg.apply(&idxs.iter().map(|&i| values[i] as f64).collect::<Vec<_>>()),
+                None => values[idxs[0]] as f64,
+            })
+            .collect::<Vec<f64>>();
+        if self.transform.is_none() || self.keep_raw {
+            for (&v, (_, idxs)) in combined.iter().zip(&groups) {
+                match self.aggregate {
+                    Some(_) => rows.push_float(v, self.float_format),
+                    None => rows.push_int(values[idxs[0]]),
+                }
+            }
+        }
+        if let Some(t) = self.transform {
+            for &v in &combined {
+                rows.push_float(t.apply(v, self.cofactor), self.float_format);
+            }
+        }
+        if self.time_course {
+            let timed = groups
+                .iter()
+                .zip(&combined)
+                .filter_map(|((_, idxs), &v)| Some((self.group_time(idxs)?, v)))
+                .collect::<Vec<(f64, f64)>>();
+            self.push_time_course(&timed, rows);
+        }
+    }
+
+    /// Append the change of each later time point from the first, and the
+    /// slope of intensity against time, from `(time, value)` pairs in
+    /// chronological order
+    fn push_time_course(&self, timed: &[(f64, f64)], rows: &mut Rows) {
+        let t0 = timed.first().map(|(t, _)| *t).unwrap_or(0.0);
+        let baseline = timed
+            .iter()
+            .filter(|(t, _)| *t == t0)
+            .map(|(_, v)| *v)
+            .collect::<Vec<_>>();
+        let baseline = baseline.iter().sum::<f64>() / baseline.len().max(1) as f64;
+        for (_, v) in timed.iter().filter(|(t, _)| *t > t0) {
+            rows.push_float(v - baseline, self.float_format);
+        }
+        let n = timed.len() as f64;
+        let (mean_t, mean_v) = (
+            timed.iter().map(|(t, _)| t).sum::<f64>() / n,
+            timed.iter().map(|(_, v)| v).sum::<f64>() / n,
+        );
+        let sxx = timed.iter().map(|(t, _)| (t - mean_t).powi(2)).sum::<f64>();
+        let sxy = timed
+            .iter()
+            .map(|(t, v)| (t - mean_t) * (v - mean_v))
+            .sum::<f64>();
+        if sxx > 0.0 {
+            rows.push_float(sxy / sxx, self.float_format);
+        } else {
+            rows.push("");
+        }
+    }
+
+    /// Append summary statistics of a row's channel `values`, if requested
+    fn push_row_stats(&self, values: &[u32], rows: &mut Rows) {
+        if !self.row_stats {
+            return;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        if n == 0 {
+            (0..4).for_each(|_| rows.push(""));
+            return;
+        }
+        let median = if n % 2 == 1 {
+            sorted[n / 2] as f64
+        } else {
+            (sorted[n / 2 - 1] as f64 + sorted[n / 2] as f64) / 2.0
+        };
+        rows.push_int(sorted[0]);
+        rows.push_int(sorted[n - 1]);
+        rows.push_float(median, self.float_format);
+        rows.push_int(sorted.iter().map(|&v| v as u64).sum::<u64>());
+    }
+
+    /// Values of additional per-protein columns
+    fn extra_columns(&self, prot: &Protein) -> Vec<Cow<'_, str>> {
+        let mut extra = match &self.uniprot {
+            Some(up) => match uniprot::accession(&prot.accession).and_then(|acc| up.get(acc)) {
+                Some(entry) => vec![
+                    Cow::from(entry.gene.as_str()),
+                    Cow::from(entry.name.as_str()),
+                    Cow::from(entry.location.as_str()),
+                ],
+                None => vec![Cow::from(""); 3],
+            },
+            None => Vec::new(),
+        };
+        if self.flag_contaminants() {
+            let contaminant = qc::is_contaminant(prot, &self.contaminant_prefixes);
+            extra.push(Cow::from(if contaminant { "true" } else { "false" }));
+        }
+        if self.single_hits == qc::SingleHits::Flag {
+            extra.push(Cow::from(if qc::is_single_hit(prot) {
+                "true"
+            } else {
+                "false"
+            }));
+        }
+        if let Some(selection) = &self.select_term {
+            extra.push(Cow::from(selection.matching(&prot.accession).join(";")));
+        }
+        extra
+    }
+}
+
+/// Path of `outpath` without the extension of `format`, to which suffixes
+/// are added for additional outputs
+pub fn output_stem(outpath: &Path, format: Format) -> String {
+    let path = outpath.display().to_string();
+    let ext = format!(".{}", format.extension());
+    match path.strip_suffix(&ext) {
+        Some(stem) => stem.to_string(),
+        None => path,
+    }
+}
+
+/// Filter `data` and write it to `outpath` in each requested layout,
+/// returning the results of quality control checks on the filtered data.
+/// The dataset is prepared once, however many outputs are written; when
+/// several layouts are requested, each is written to
+/// `<output>.<layout>.<ext>`.
+pub fn convert<'a, P: AsRef<Path>>(
+    data: Dataset,
+    input: &str,
+    outpath: P,
+    filters: &Filter<'a>,
+    opts: &mut Options,
+) -> std::io::Result<qc::Report> {
+    let outpath = outpath.as_ref();
+    let (data, mut report) = prepare(data, filters, opts)?;
+    if opts.plot == Some(plot::Plot::Terminal) {
+        // Kept off stdout when it carries the output table
+        let mut out: Box<dyn Write> = match opts.stdout {
+            true => Box::new(std::io::stderr().lock()),
+            false => Box::new(std::io::stdout().lock()),
+        };
+        writeln!(out, "{}", input)?;
+        plot::terminal(&data, out)?;
+    }
+    if let Some((dir, format)) = &opts.plot_dir {
+        let stem = output_stem(outpath, opts.format);
+        let name = Path::new(&stem)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(stem);
+        plot::write_figures(&data, dir, &name, *format)?;
+    }
+    if let Some(limit) = opts.preview {
+        let layouts = opts.layouts.clone();
+        for (idx, &layout) in layouts.iter().enumerate() {
+            opts.layout = layout;
+            if layouts.len() > 1 {
+                if idx > 0 {
+                    println!();
+                }
+                println!("{} ({} layout)", input, layout.name());
+            }
+            let stdout = std::io::stdout();
+            write_table(
+                &data,
+                layout,
+                &mut writer::Preview::new(stdout.lock(), limit),
+                opts,
+            )?;
+        }
+        return Ok(report);
+    }
+    if opts.qc_report {
+        let path = format!("{}.qc.json", output_stem(outpath, opts.format));
+        fs::write(&path, report.to_json().to_string())?;
+    }
+    if let (Some(format), Some(fasta)) = (opts.coverage, &opts.fasta) {
+        let path = format!(
+            "{}.{}",
+            output_stem(outpath, opts.format),
+            format.extension()
+        );
+        let file = std::io::BufWriter::new(fs::File::create(&path)?);
+        let missing = coverage::write(&data, fasta, format, file)?;
+        if missing > 0 {
+            report.warnings.push(format!(
+                "{} of {} proteins have no sequence in the FASTA file, and are missing from the coverage map",
+                missing,
+                data.proteins.len()
+            ));
+        }
+    }
+
+    if let Some(mut target) = opts.append.take() {
+        if let Some(&layout) = opts.layouts.first() {
+            opts.layout = layout;
+        }
+        let res = target
+            .writer(&append::run_name(input))
+            .and_then(|mut out| write_table(&data, opts.layout, &mut out, opts));
+        opts.append = Some(target);
+        res?;
+        return Ok(report);
+    }
+    if !opts.format.is_table() || opts.layouts.len() <= 1 {
+        if let Some(&layout) = opts.layouts.first() {
+            opts.layout = layout;
+        }
+        write_output(&data, input, outpath, filters, opts)?;
+        return Ok(report);
+    }
+    for layout in opts.layouts.clone() {
+        opts.layout = layout;
+        let path = PathBuf::from(format!(
+            "{}.{}.{}",
+            output_stem(outpath, opts.format),
+            layout.name(),
+            opts.format.extension()
+        ));
+        write_output(&data, input, &path, filters, opts)?;
+    }
+    Ok(report)
+}
+
+/// Keep the proteins of `data` for which `keep` is true, along with the
+/// matching entries of `blocks`
+fn retain<F: Fn(&Protein) -> bool>(data: &mut Dataset, blocks: &mut Vec<usize>, keep: F) {
+    let kept = data.proteins.iter().map(keep).collect::<Vec<bool>>();
+    let mut flags = kept.iter();
+    data.proteins.retain(|_| *flags.next().unwrap());
+    let mut flags = kept.iter();
+    blocks.retain(|_| *flags.next().unwrap());
+}
+
+/// Check, deduplicate, and filter `data`, and annotate it from UniProt,
+/// returning the dataset to write along with the results of quality control
+/// checks
+fn prepare<'a>(
+    data: Dataset,
+    filters: &Filter<'a>,
+    opts: &mut Options,
+) -> std::io::Result<(Dataset, qc::Report)> {
+    if let Some(l) = opts.labels {
+        l.resolve(data.channels)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    }
+    if let Some(pairs) = &opts.abpp {
+        pairs
+            .check(data.channels)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    }
+    if let Some(pairs) = &opts.paired {
+        pairs
+            .check(data.channels)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    }
+
+    let data = match opts.sample {
+        Some((fraction, seed)) => Dataset {
+            proteins: sample::sample(data.proteins, fraction, seed),
+            channels: data.channels,
+        },
+        None => data,
+    };
+
+    let mut report = qc::Report::default();
+    let data = if opts.dedupe {
+        let (data, removed) = duplicates::dedupe(data);
+        if removed > 0 {
+            report.warnings.push(format!(
+                "removed {} protein blocks identical to an earlier block",
+                removed
+            ));
+        }
+        data
+    } else {
+        data
+    };
+    let mut data = duplicates::apply(data, opts.duplicates, opts.key, &mut report.warnings)?;
+    report.check_channels(&data);
+    report.check_sequences(&mut data, opts.skip_unparseable);
+    if let Some(chemistry) = opts.chemistry.filter(|c| !c.accepts(data.channels)) {
+        report.warnings.push(format!(
+            "the file has {} channels, which does not match the declared {} chemistry ({})",
+            data.channels,
+            chemistry,
+            chemistry
+                .plexes()
+                .iter()
+                .map(|p| format!("{} channels", p.channels()))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        ));
+    }
+    if let Some(noise) = &opts.noise {
+        noise.apply(&mut data)?;
+    }
+    if let Some(scale) = &opts.scale {
+        scale.apply(&mut data)?;
+    }
+    // Counts are kept by block, as duplicated accessions may be kept apart
+    let census = match opts.counts {
+        Counts::Filtered => Vec::new(),
+        Counts::Census | Counts::Both => data
+            .proteins
+            .iter()
+            .map(|prot| (prot.spectral_count, prot.sequence_count))
+            .collect(),
+    };
+    // Block of each remaining protein
+    let (mut data, mut blocks) = parallel::filter_indexed(data, filters, opts.threads);
+    if opts.exclude_contaminants {
+        retain(&mut data, &mut blocks, |prot| {
+            !qc::is_contaminant(prot, &opts.contaminant_prefixes)
+        });
+    }
+    if let Some(selection) = &opts.select_term {
+        retain(&mut data, &mut blocks, |prot| {
+            !selection.matching(&prot.accession).is_empty()
+        });
+    }
+    if opts.nterm_mode {
+        nterm::apply(&mut data, opts.nterm_label, opts.fasta.as_ref());
+        retain(&mut data, &mut blocks, |prot| !prot.peptides.is_empty());
+    }
+    if opts.single_hits == qc::SingleHits::Drop {
+        retain(&mut data, &mut blocks, |prot| !qc::is_single_hit(prot));
+    }
+    if opts.group_proteins {
+        let (grouped, groups, indices) = grouping::group_indexed(data);
+        data = grouped;
+        opts.groups = groups;
+        blocks = indices.into_iter().map(|idx| blocks[idx]).collect();
+    }
+    opts.census_counts = match census.is_empty() {
+        true => Vec::new(),
+        false => blocks.into_iter().map(|idx| census[idx]).collect(),
+    };
+    report.check_decoys(&data, opts.max_decoy_rate);
+    if let Some(impute) = opts.impute {
+        opts.impute_floors = impute.floors(&data);
+    }
+
+    if let Some(up) = &mut opts.uniprot {
+        up.fetch(data.proteins.iter().map(|prot| prot.accession.as_str()));
+        // Fill in missing descriptions from UniProt
+        for prot in data
+            .proteins
+            .iter_mut()
+            .filter(|p| p.description.is_empty())
+        {
+            if let Some(entry) = uniprot::accession(&prot.accession).and_then(|acc| up.get(acc)) {
+                prot.description = entry.name.clone();
+            }
+        }
+    }
+    Ok((data, report))
+}
+
+/// Write a prepared dataset to `outpath` in the current layout and format
+fn write_output<'a>(
+    data: &Dataset,
+    input: &str,
+    outpath: &Path,
+    filters: &Filter<'a>,
+    opts: &Options,
+) -> std::io::Result<()> {
+    if let Some(by) = opts.split_by {
+        let mut table = writer::Table::default();
+        write_table(data, opts.layout, &mut table, opts)?;
+        for (name, part) in split::split(&table, by) {
+            let path = outpath.with_extension(format!("{}.csv", name.replace('/', "_")));
+            let mut file = fs::File::create(&path)?;
+            if opts.meta {
+                meta::write_sidecar(&path, &part.columns, opts.schema)?;
+            }
+            if opts.header_comments {
+                meta::write_comments(
+                    &mut file,
+                    input,
+                    filters,
+                    &opts.normalization(),
+                    opts.schema,
+                )?;
+            }
+            part.write_to(&mut writer::Delimited::csv(file))?;
+        }
+        return Ok(());
+    }
+
+    if let (Some(every), Format::Csv | Format::Tsv) = (opts.checkpoint, opts.format) {
+        let settings = format!(
+            "filter {}, layout {}, schema {}",
+            meta::filter_hash(filters),
+            opts.layout.name(),
+            opts.schema.name()
+        );
+        let tsv = opts.format == Format::Tsv;
+        let mut out = checkpoint::Writer::create(outpath, tsv, &settings, every, opts.resume)?;
+        if opts.meta {
+            meta::write_sidecar(outpath, &opts.columns(data.channels), opts.schema)?;
+        }
+        if opts.header_comments && !out.resumed() {
+            meta::write_comments(
+                out.file(),
+                input,
+                filters,
+                &opts.normalization(),
+                opts.schema,
+            )?;
+        }
+        return write_table(data, opts.layout, &mut out, opts);
+    }
+
+    let mut file: Box<dyn Write> = match opts.stdout {
+        true => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+        false => Box::new(fs::File::create(outpath)?),
+    };
+    if opts.meta && opts.format.is_table() {
+        meta::write_sidecar(outpath, &opts.columns(data.channels), opts.schema)?;
+    }
+    if opts.header_comments && (opts.format == Format::Csv || opts.format == Format::Tsv) {
+        meta::write_comments(
+            &mut file,
+            input,
+            filters,
+            &opts.normalization(),
+            opts.schema,
+        )?;
+    }
+    let layout = opts.layout;
+    match opts.format {
+        Format::Census => census::write_census(data, file)?,
+        Format::Saint => write_saint(data, input, outpath, file, opts)?,
+        Format::Csv => write_table(data, layout, &mut writer::Delimited::csv(file), opts)?,
+        Format::Tsv => write_table(data, layout, &mut writer::Delimited::tsv(file), opts)?,
+        Format::Perseus => write_table(data, layout, &mut perseus::Writer::new(file), opts)?,
+        Format::Gct => write_table(data, layout, &mut gct::Writer::new(file), opts)?,
+        Format::Sql | Format::Sqlite => {
+            let table = match layout {
+                Layout::Protein => "proteins",
+                Layout::Peptide => "peptides",
+                Layout::Flat => "psms",
+            };
+            match opts.format {
+                Format::Sql => write_table(data, layout, &mut writer::Sql::new(file, table), opts)?,
+                _ => write_table(data, layout, &mut writer::Sqlite::new(file, table), opts)?,
+            }
+        }
+        Format::Arrow => write_table(data, layout, &mut writer::Arrow::new(file), opts)?,
+        Format::Parquet => write_table(
+            data,
+            layout,
+            &mut writer::Parquet::new(std::io::BufWriter::new(file)),
+            opts,
+        )?,
+        Format::NestedJson => nested::write_nested(
+            data,
+            std::io::BufWriter::new(file),
+            |prot| protein_values(prot, opts).collect(),
+            opts.average,
+        )?,
+    }
+    Ok(())
+}
+
+/// Write SAINT files for `data`, with the inter file at `outpath` and the
+/// prey and bait files alongside it
+fn write_saint<W: Write>(
+    data: &Dataset,
+    input: &str,
+    outpath: &Path,
+    inter: W,
+    opts: &Options,
+) -> std::io::Result<()> {
+    let baits = match &opts.baits {
+        Some(baits) => baits.clone(),
+        None => saint::from_conditions(&opts.conditions, &opts.saint_controls),
+    };
+    let path = outpath.to_string_lossy();
+    let base = match path.strip_suffix("inter.txt") {
+        Some(base) => base.to_string(),
+        None => format!("{}.", outpath.with_extension("").display()),
+    };
+    let run = input
+        .split(';')
+        .next()
+        .and_then(|f| Path::new(f).file_stem())
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let genes = |prot: &Protein| {
+        let up = opts.uniprot.as_ref()?;
+        uniprot::accession(&prot.accession)
+            .and_then(|acc| up.get(acc))
+            .map(|entry| entry.gene.clone())
+            .filter(|gene| !gene.is_empty())
+    };
+    let exp = saint::Experiment {
+        run: &run,
+        baits: &baits,
+        quantity: opts.saint_quantity,
+        genes: &genes,
+    };
+    saint::write(
+        data,
+        &exp,
+        inter,
+        fs::File::create(format!("{}prey.txt", base))?,
+        fs::File::create(format!("{}bait.txt", base))?,
+    )
+}
+
+/// Write `data` to `out` using `layout`, and finish the table
+fn write_table(
+    data: &Dataset,
+    layout: Layout,
+    out: &mut dyn RecordWriter,
+    opts: &Options,
+) -> std::io::Result<()> {
+    match layout {
+        Layout::Protein => combine_protein(data, out, opts)?,
+        Layout::Peptide => combine_peptide(data, out, opts)?,
+        Layout::Flat => flat_peptide(data, out, opts)?,
+    }
+    out.finish()
+}
+
+/// Number of proteins whose rows are built in parallel before being
+/// written, bounding the number of rows held in memory
+const BATCH: usize = 4096;
+
+/// Rough memory use of a PSM as a built row, used to size batches for
+/// `--max-memory`
+pub const PSM_BYTES: usize = 256;
+
+/// Running per-channel sums of the values written to an output table
+struct Totals {
+    rows: usize,
+    sums: Vec<u64>,
+}
+
+impl Totals {
+    fn new(channels: u8) -> Totals {
+        Totals {
+            rows: 0,
+            sums: vec![0; channels as usize],
+        }
+    }
+
+    fn add(&mut self, values: &[u32]) {
+        self.rows += 1;
+        for (sum, val) in self.sums.iter_mut().zip(values.iter()) {
+            *sum += *val as u64;
+        }
+    }
+
+    /// Add the rows and sums of `other`
+    fn merge(&mut self, other: &Totals) {
+        self.rows += other.rows;
+        for (sum, val) in self.sums.iter_mut().zip(other.sums.iter()) {
+            *sum += *val;
+        }
+    }
+
+    /// A final row containing the per-channel sums, with the row count in
+    /// the description column
+    fn record(&self, columns: &[meta::Column]) -> Rows {
+        let mut rows = Rows::default();
+        for col in columns {
+            match (col.name.as_str(), &col.channel) {
+                ("accession", _) => rows.push("TOTAL"),
+                ("description", _) => rows.push(&format!("{} rows", self.rows)),
+                (_, Some((chan, _))) => rows.push_int(self.sums[chan - 1]),
+                _ => rows.push(""),
+            }
+        }
+        rows.end();
+        rows
+    }
+}
+
+/// Leading fields of a row: accession and description
+fn protein_fields(prot: &Protein, rows: &mut Rows, opts: &Options) {
+    match &opts.idmap {
+        Some(idmap) => rows.push(idmap.map(&prot.accession)),
+        None => rows.push(&prot.accession),
+    }
+    rows.push(&prot.description);
+}
+
+/// Build the rows for each batch of proteins in parallel, with `build`
+/// appending the rows for a slice of proteins, given the index of its first
+/// protein in `data`, and write them in order, followed by the totals row
+/// if requested
+fn write_rows<F>(
+    data: &Dataset,
+    out: &mut dyn RecordWriter,
+    opts: &Options,
+    build: F,
+) -> std::io::Result<()>
+where
+    F: Fn(usize, &[Protein], &mut Rows, &mut Totals) + Sync,
+{
+    let columns = opts.columns(data.channels);
+    let mut totals = Totals::new(data.channels);
+    out.header(&columns)?;
+
+    let size = opts.batch_size(data);
+    for (idx, batch) in data.proteins.chunks(size).enumerate() {
+        let parts = parallel::map_chunks(batch, opts.threads, |start, prots| {
+            let mut rows = Rows::default();
+            let mut part = Totals::new(data.channels);
+            build(idx * size + start, prots, &mut rows, &mut part);
+            (rows, part)
+        });
+        for (rows, part) in parts {
+            totals.merge(&part);
+            for row in rows.iter() {
+                out.record(row)?;
+            }
+        }
+    }
+
+    if opts.totals_row {
+        out.record(totals.record(&columns).get(0))?;
+    }
+    Ok(())
+}
+
+/// Channel values of the row for `prot` in the protein layout
+fn protein_values<'a>(prot: &Protein, opts: &'a Options) -> impl Iterator<Item = u32> + 'a {
+    rollup::protein(prot, opts.rollup())
+        .into_iter()
+        .map(|v| v as u32)
+}
+
+/// Summed intensity of every protein row, for ranking proteins by abundance
+#[derive(Default)]
+struct Ranks {
+    /// Sorted in ascending order
+    totals: Vec<u64>,
+}
+
+impl Ranks {
+    fn new(data: &Dataset, opts: &Options) -> Ranks {
+        let mut totals = parallel::map(&data.proteins, opts.threads, |prot| {
+            protein_values(prot, opts).map(u64::from).sum::<u64>()
+        });
+        totals.sort_unstable();
+        Ranks { totals }
+    }
+
+    /// Rank and percentile of a row with channel `values`. Tied proteins
+    /// share the highest rank among them.
+    fn get(&self, values: &[u32]) -> (usize, f64) {
+        let total = values.iter().map(|&v| u64::from(v)).sum::<u64>();
+        let n = self.totals.len();
+        let above = n - self.totals.partition_point(|&t| t <= total);
+        let at_or_below = n - above;
+        (above + 1, 100.0 * at_or_below as f64 / n.max(1) as f64)
+    }
+}
+
+fn combine_protein(
+    data: &Dataset,
+    out: &mut dyn RecordWriter,
+    opts: &Options,
+) -> std::io::Result<()> {
+    let ranks = if opts.rank {
+        Ranks::new(data, opts)
+    } else {
+        Ranks::default()
+    };
+    write_rows(data, out, opts, |start, prots, rows, totals| {
+        let mut values = Vec::new();
+        for (idx, prot) in prots.iter().enumerate() {
+            values.clear();
+            values.extend(protein_values(prot, opts));
+            protein_fields(prot, rows, opts);
+            let census = opts.census_counts.get(start + idx).copied();
+            let census = census.unwrap_or((prot.spectral_count, prot.sequence_count));
+            let counts = match opts.counts {
+                Counts::Filtered => vec![(prot.spectral_count, prot.sequence_count)],
+                Counts::Census => vec![census],
+                Counts::Both => vec![(prot.spectral_count, prot.sequence_count), census],
+            };
+            for (spectral, sequence) in counts {
+                rows.push_int(spectral);
+                rows.push_int(sequence);
+            }
+            let (rank, percentile) = ranks.get(&values);
+            opts.push_channels(&mut values, rows);
+            if opts.rank {
+                rows.push_int(rank);
+                rows.push_float(percentile, opts.float_format);
+            }
+            for field in opts.extra_columns(prot) {
+                rows.push(&field);
+            }
+            if opts.group_proteins {
+                match opts.groups.get(&prot.accession) {
+                    Some(group) => {
+                        rows.push_float(group.score, opts.float_format);
+                        rows.push(&group.subsumed.join(";"));
+                    }
+                    None => (0..2).for_each(|_| rows.push("")),
+                }
+            }
+            if opts.list_peptides {
+                let mut seen = HashSet::new();
+                let sequences = prot
+                    .peptides
+                    .iter()
+                    .map(|pep| pep.sequence.as_str())
+                    .filter(|seq| seen.insert(*seq))
+                    .collect::<Vec<&str>>();
+                rows.push(&sequences.join(";"));
+            }
+            rows.end();
+            totals.add(&values);
+        }
+    })
+}
+
+fn flat_peptide(data: &Dataset, out: &mut dyn RecordWriter, opts: &Options) -> std::io::Result<()> {
+    write_rows(data, out, opts, |_, prots, rows, totals| {
+        let mut values = Vec::new();
+        for prot in prots {
+            let extra = opts.extra_columns(prot);
+            for peptide in &prot.peptides {
+                protein_fields(prot, rows, opts);
+                rows.push(&peptide.sequence);
+                if opts.schema >= meta::Schema::V2 {
+                    rows.push_int(peptide.scan);
+                }
+                if opts.cleavage_class {
+                    rows.push(sequence::parse(&peptide.sequence).cleavage().name());
+                }
+                opts.push_nterm(prot, &peptide.sequence, rows);
+                opts.push_modifications(&peptide.sequence, rows);
+                values.clear();
+                values.extend_from_slice(&peptide.values);
+                opts.push_channels(&mut values, rows);
+                for field in &extra {
+                    rows.push(field);
+                }
+                rows.end();
+                totals.add(&values);
+            }
+        }
+    })
+}
+
+fn combine_peptide(
+    data: &Dataset,
+    out: &mut dyn RecordWriter,
+    opts: &Options,
+) -> std::io::Result<()> {
+    let average = opts.average;
+    write_rows(data, out, opts, |_, prots, rows, totals| {
+        for prot in prots {
+            let extra = opts.extra_columns(prot);
+            // Key, first full sequence, PSM count, and values of each group, in
+            // order of first appearance so that rows are written in the same
+            // order by every run
+            let mut groups: Vec<(&str, &str, u32, Vec<u32>)> = Vec::new();
+            let mut index: HashMap<&str, usize> = HashMap::new();
+            for peptide in &prot.peptides {
+                let key = if opts.ptm_groups {
+                    sequence::parse(&peptide.sequence).modified
+                } else {
+                    &peptide.sequence
+                };
+                let idx = *index.entry(key).or_insert_with(|| {
+                    groups.push((key, &peptide.sequence, 0, vec![0; data.channels as usize]));
+                    groups.len() - 1
+                });
+                let (_, _, spec, entry) = &mut groups[idx];
+                for (idx, val) in peptide.values.iter().enumerate() {
+                    entry[idx] += *val;
+                }
+                *spec += 1;
+            }
+
+            for (key, sequence, spec, mut values) in groups {
+                if average {
+                    values.iter_mut().for_each(|v| *v /= spec);
+                }
+                protein_fields(prot, rows, opts);
+                rows.push_int(spec);
+                rows.push(key);
+                if opts.cleavage_class {
+                    rows.push(sequence::parse(sequence).cleavage().name());
+                }
+                opts.push_nterm(prot, sequence, rows);
+                opts.push_modifications(sequence, rows);
+                opts.push_channels(&mut values, rows);
+                for field in &extra {
+                    rows.push(field);
+                }
+                rows.end();
+                totals.add(&values);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn totals_row() {
+        let columns = vec![
+            meta::Column::new("accession", "string", ""),
+            meta::Column::new("description", "string", ""),
+            meta::Column::new("spectral_count", "integer", ""),
+            meta::Column::new("channel_1", "integer", "").channel(1, "channel_1"),
+            meta::Column::new("channel_2", "integer", "").channel(2, "channel_2"),
+        ];
+        let mut totals = Totals::new(2);
+        totals.add(&[1, 2]);
+        let mut part = Totals::new(2);
+        part.add(&[u32::MAX, 3]);
+        part.add(&[u32::MAX, 4]);
+        totals.merge(&part);
+
+        let rows = totals.record(&columns);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows.get(0).iter().collect::<Vec<_>>(),
+            ["TOTAL", "3 rows", "", "8589934591", "9"]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Log2 ratios and paired t-tests for paired-sample designs
+//!
+//! In paired designs, such as a tumor and normal sample from each patient,
+//! each pair of channels is compared within itself, and the log2 ratios of
+//! the pairs are tested against zero with a paired t-test, so that
+//! differences between patients do not mask a consistent change.
+use std::path::Path;
+use std::str::FromStr;
+
+/// Named pairs of 1-indexed numerator and denominator channels
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pairs {
+    pub pairs: Vec<(String, usize, usize)>,
+}
+
+fn channel(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(c) if c > 0 => Ok(c),
+        _ => Err(format!("invalid channel {}", s.trim())),
+    }
+}
+
+/// Parse a `numerator:denominator` pair
+fn parse_pair(s: &str) -> Result<(usize, usize), String> {
+    let (num, den) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected numerator:denominator, found {}", s))?;
+    Ok((channel(num)?, channel(den)?))
+}
+
+/// Read a tab-delimited file with a pair name, numerator channel, and
+/// denominator channel on each line. Blank lines, lines beginning with `#`,
+/// and a header line are skipped.
+fn read_pairs<P: AsRef<Path>>(path: P) -> Result<Vec<(String, usize, usize)>, String> {
+    let path = path.as_ref();
+    let file = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut pairs = Vec::new();
+    for (idx, line) in file.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').map(str::trim).collect::<Vec<&str>>();
+        let (name, num, den) = match fields[..] {
+            [name, num, den, ..] => (name, num, den),
+            _ => ("", "", ""),
+        };
+        match (channel(num), channel(den)) {
+            (Ok(num), Ok(den)) if !name.is_empty() => pairs.push((name.to_string(), num, den)),
+            _ if pairs.is_empty() && num.parse::<usize>().is_err() => {}
+            _ => {
+                return Err(format!(
+                    "{} line {}: expected a name, numerator channel, and denominator channel",
+                    path.display(),
+                    idx + 1
+                ))
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+impl FromStr for Pairs {
+    type Err = String;
+
+    /// Parse `name=numerator:denominator` pairs separated by commas, such as
+    /// `p1=1:2,p2=3:4`, where names are optional, or the path of a
+    /// tab-delimited file of pairs if `s` contains no `:`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = if s.contains(':') {
+            s.split(',')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+                .enumerate()
+                .map(|(idx, pair)| {
+                    let (name, pair) = match pair.split_once('=') {
+                        Some((name, pair)) => (name.trim().to_string(), pair),
+                        None => (format!("pair_{}", idx + 1), pair),
+                    };
+                    let (num, den) = parse_pair(pair)?;
+                    Ok((name, num, den))
+                })
+                .collect::<Result<Vec<_>, String>>()?
+        } else {
+            read_pairs(s)?
+        };
+        if pairs.is_empty() {
+            return Err("no channel pairs given".into());
+        }
+        Ok(Pairs { pairs })
+    }
+}
+
+impl Pairs {
+    /// Check that every channel exists in a dataset with `channels` channels
+    pub fn check(&self, channels: u8) -> Result<(), String> {
+        match self
+            .pairs
+            .iter()
+            .flat_map(|&(_, n, d)| [n, d])
+            .find(|&c| c > channels as usize)
+        {
+            Some(c) => Err(format!(
+                "channel {} is out of range for {} channels",
+                c, channels
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Log2 ratio of numerator to denominator of each pair, or `None` if
+    /// either channel has no intensity
+    pub fn log2_ratios(&self, values: &[u32]) -> Vec<Option<f64>> {
+        self.pairs
+            .iter()
+            .map(|&(_, n, d)| {
+                let num = *values.get(n - 1)? as f64;
+                let den = *values.get(d - 1)? as f64;
+                if num > 0.0 && den > 0.0 {
+                    Some((num / den).log2())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Result of a paired t-test
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TTest {
+    /// Mean of the paired differences
+    pub mean: f64,
+    pub t: f64,
+    /// Two-sided p-value
+    pub p: f64,
+}
+
+/// Paired t-test of the defined `differences` against zero, which requires
+/// at least 2 differences that are not all equal
+pub fn t_test(differences: &[Option<f64>]) -> Option<TTest> {
+    let d = differences.iter().flatten().copied().collect::<Vec<f64>>();
+    let n = d.len() as f64;
+    if d.len() < 2 {
+        return None;
+    }
+    let mean = d.iter().sum::<f64>() / n;
+    let var = d.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    if var <= 0.0 {
+        return None;
+    }
+    let t = mean / (var / n).sqrt();
+    let df = n - 1.0;
+    let p = incomplete_beta(df / 2.0, 0.5, df / (df + t * t));
+    Some(TTest { mean, t, p })
+}
+
+/// Natural log of the gamma function, by the Lanczos approximation, for
+/// `x` >= 0.5
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let a = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |a, (i, c)| a + c / (x + i as f64 + 1.0));
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// Continued fraction of the incomplete beta function, by the modified
+/// Lentz method
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - qab * x / qap);
+    let mut h = d;
+    for m in 1..=300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        let aa = m * (b - m) * x / ((qam + m2) * (a + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        h *= d * c;
+        let aa = -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn special_functions() {
+        assert!(close(ln_gamma(5.0), 24f64.ln()));
+        assert!(close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln()));
+        assert!(close(incomplete_beta(1.0, 1.0, 0.3), 0.3));
+        assert!(close(incomplete_beta(2.0, 3.0, 0.4), 0.5248));
+        assert_eq!(incomplete_beta(2.0, 3.0, 0.0), 0.0);
+        assert_eq!(incomplete_beta(2.0, 3.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn paired_t_test() {
+        let test = t_test(&[Some(1.0), Some(2.0), None, Some(3.0), Some(4.0), Some(5.0)]).unwrap();
+        assert!(close(test.mean, 3.0));
+        assert!(close(test.t, 4.242_640_687));
+        assert!(close(test.p, 0.013_235_600));
+
+        let test = t_test(&[Some(0.5), Some(-0.1), Some(0.3), Some(0.8)]).unwrap();
+        assert!(close(test.t, 1.986_798_536));
+        assert!(close(test.p, 0.141_121_940));
+
+        // With one degree of freedom, p = 1 - 2 atan(|t|) / pi
+        let test = t_test(&[Some(-1.0), Some(-3.0)]).unwrap();
+        assert!(close(
+            test.p,
+            1.0 - 2.0 * test.t.abs().atan() / std::f64::consts::PI
+        ));
+
+        assert!(t_test(&[Some(1.0), None]).is_none());
+        assert!(t_test(&[Some(1.0), Some(1.0), Some(1.0)]).is_none());
+    }
+
+    #[test]
+    fn parse_pairs() {
+        let pairs = "tumor=1:2, 3:4".parse::<Pairs>().unwrap();
+        assert_eq!(
+            pairs.pairs,
+            vec![("tumor".to_string(), 1, 2), ("pair_2".to_string(), 3, 4)]
+        );
+        assert!(pairs.check(4).is_ok());
+        assert!(pairs.check(3).is_err());
+        assert!("1:0".parse::<Pairs>().is_err());
+        assert!("1-2:3".parse::<Pairs>().is_err());
+
+        let dir = fixture::dir(
+            "pairs",
+            &[(
+                "pairs.tsv",
+                "name\tnumerator\tdenominator\n# patient 1\np1\t1\t2\n\np2\t4\t3\n",
+            )],
+        );
+        let path = dir.join("pairs.tsv");
+        let pairs = path.to_str().unwrap().parse::<Pairs>().unwrap();
+        assert_eq!(pairs.pairs[1], ("p2".to_string(), 4, 3));
+        std::fs::write(&path, "p1\t1\t2\np2\tx\t3\n").unwrap();
+        let e = path.to_str().unwrap().parse::<Pairs>().err().unwrap();
+        assert!(e.ends_with("line 2: expected a name, numerator channel, and denominator channel"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ratios() {
+        let pairs = "1:2,3:1,2:9".parse::<Pairs>().unwrap();
+        assert_eq!(
+            pairs.log2_ratios(&[400, 100, 0]),
+            vec![Some(2.0), None, None]
+        );
+    }
+}
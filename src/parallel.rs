@@ -0,0 +1,153 @@
+//! Ordered parallel processing of protein entries
+//!
+//! Work is split into one contiguous chunk per thread using scoped threads,
+//! and results are concatenated in chunk order, so that outputs are
+//! identical to sequential processing regardless of the thread count.
+use crate::filter::Filter;
+use census_proteomics::Dataset;
+
+/// Number of threads to use by default: the available parallelism of the
+/// machine, or 1 if it cannot be determined
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Apply `f` to each of `items` on up to `threads` threads, returning the
+/// results in the order of `items`
+pub fn map<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    map_chunks(items, threads, |_, c| c.iter().map(&f).collect::<Vec<R>>())
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Apply `f` to one contiguous chunk of `items` per thread, and the index
+/// of its first item, on up to `threads` threads, returning the result for
+/// each chunk in order
+pub fn map_chunks<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(usize, &[T]) -> R + Sync,
+{
+    let threads = threads.min(items.len());
+    if threads <= 1 {
+        return vec![f(0, items)];
+    }
+    let chunk = items.len().div_ceil(threads);
+    std::thread::scope(|s| {
+        let f = &f;
+        items
+            .chunks(chunk)
+            .enumerate()
+            .map(|(idx, c)| s.spawn(move || f(idx * chunk, c)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|h| h.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// As `map`, taking ownership of each item
+pub fn map_owned<T, R, F>(mut items: Vec<T>, threads: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let threads = threads.min(items.len());
+    if threads <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+    let chunk = items.len().div_ceil(threads);
+    let mut chunks = Vec::with_capacity(threads);
+    while items.len() > chunk {
+        let rest = items.split_off(chunk);
+        chunks.push(std::mem::replace(&mut items, rest));
+    }
+    chunks.push(items);
+
+    std::thread::scope(|s| {
+        let f = &f;
+        chunks
+            .into_iter()
+            .map(|c| s.spawn(move || c.into_iter().map(f).collect::<Vec<R>>()))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|h| h.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Apply `filter` to each protein of `data` on up to `threads` threads. The
+/// result is the same as `filter.filter_dataset(data)`.
+pub fn filter(data: Dataset, filter: &Filter, threads: usize) -> Dataset {
+    filter_indexed(data, filter, threads).0
+}
+
+/// As `filter`, also returning the index in `data` of each remaining
+/// protein, so that proteins sharing an accession can be told apart
+pub fn filter_indexed(data: Dataset, filter: &Filter, threads: usize) -> (Dataset, Vec<usize>) {
+    let regex = Filter::tryptic_regex();
+    let filter = filter.clone().with_protein_matches(&data);
+    let channels = data.channels;
+    let items = data.proteins.into_iter().enumerate().collect();
+    let (indices, proteins) = map_owned(items, threads, |(idx, prot)| {
+        filter.filter_protein(prot, &regex).map(|prot| (idx, prot))
+    })
+    .into_iter()
+    .flatten()
+    .unzip();
+    (Dataset { proteins, channels }, indices)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter::ProteinFilter;
+    use census_proteomics::{Peptide, Protein};
+
+    #[test]
+    fn chunks_start_at_their_index() {
+        let items = (0..10).collect::<Vec<usize>>();
+        for threads in 1..=4 {
+            for (start, first) in map_chunks(&items, threads, |start, c| (start, c[0])) {
+                assert_eq!(start, first);
+            }
+            assert_eq!(map(&items, threads, |x| x * 2)[9], 18);
+        }
+    }
+
+    #[test]
+    fn filter_keeps_indices() {
+        let blocks = [("A", 1), ("A", 2), ("B", 3), ("A", 4)];
+        let data = || Dataset {
+            proteins: blocks
+                .iter()
+                .map(|&(accession, psms)| Protein {
+                    accession: accession.to_string(),
+                    spectral_count: psms as u16,
+                    peptides: vec![Peptide::default(); psms],
+                    ..Protein::default()
+                })
+                .collect(),
+            channels: 0,
+        };
+        let filter = Filter::default().add_protein_filter(ProteinFilter::SpectralCounts(2));
+        for threads in 1..=4 {
+            let (kept, indices) = filter_indexed(data(), &filter, threads);
+            assert_eq!(indices, vec![1, 2, 3]);
+            for (prot, idx) in kept.proteins.iter().zip(indices) {
+                assert_eq!(prot.accession, blocks[idx].0);
+                assert_eq!(prot.peptides.len(), blocks[idx].1);
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! Write Parquet files with a single row group
+//!
+//! Columns are optional and written with PLAIN encoding and no compression,
+//! in data pages of at most `PAGE_ROWS` values, which every Parquet reader
+//! supports. File and page metadata use the Thrift compact protocol, which is
+//! encoded here directly.
+use std::io::prelude::*;
+
+const MAGIC: &[u8] = b"PAR1";
+
+/// Values in each data page
+const PAGE_ROWS: usize = 64 * 1024;
+
+/// Thrift compact protocol type codes
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+/// Parquet encodings
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+/// Values of a column, `None` for nulls
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column<'a> {
+    Int64(Vec<Option<i64>>),
+    Double(Vec<Option<f64>>),
+    Boolean(Vec<Option<bool>>),
+    Utf8(Vec<Option<&'a str>>),
+}
+
+impl Column<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Column::Int64(v) => v.len(),
+            Column::Double(v) => v.len(),
+            Column::Boolean(v) => v.len(),
+            Column::Utf8(v) => v.len(),
+        }
+    }
+
+    /// Parquet physical type
+    fn physical(&self) -> i32 {
+        match self {
+            Column::Boolean(_) => 0,
+            Column::Int64(_) => 2,
+            Column::Double(_) => 5,
+            Column::Utf8(_) => 6,
+        }
+    }
+
+    fn is_null(&self, idx: usize) -> bool {
+        match self {
+            Column::Int64(v) => v[idx].is_none(),
+            Column::Double(v) => v[idx].is_none(),
+            Column::Boolean(v) => v[idx].is_none(),
+            Column::Utf8(v) => v[idx].is_none(),
+        }
+    }
+
+    /// PLAIN encoding of the values in `range` that are not null
+    fn plain(&self, range: std::ops::Range<usize>, buf: &mut Vec<u8>) {
+        match self {
+            Column::Int64(v) => v[range]
+                .iter()
+                .flatten()
+                .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+            Column::Double(v) => v[range]
+                .iter()
+                .flatten()
+                .for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+            Column::Utf8(v) => {
+                for s in v[range].iter().flatten() {
+                    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(s.as_bytes());
+                }
+            }
+            Column::Boolean(v) => {
+                // Bit-packed, least significant bit first
+                let bits = v[range].iter().flatten().collect::<Vec<_>>();
+                for chunk in bits.chunks(8) {
+                    let byte = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u8, |acc, (i, b)| acc | (u8::from(**b) << i));
+                    buf.push(byte);
+                }
+            }
+        }
+    }
+}
+
+fn uvarint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Writer for a Thrift compact protocol struct, tracking the last field id
+/// of each nested struct
+struct Thrift {
+    buf: Vec<u8>,
+    last: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Thrift {
+        Thrift {
+            buf: Vec::new(),
+            last: vec![0],
+        }
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last.last_mut().unwrap();
+        let delta = id - *last;
+        if (1..=15).contains(&delta) {
+            self.buf.push(((delta as u8) << 4) | kind);
+        } else {
+            self.buf.push(kind);
+            uvarint(&mut self.buf, ((id << 1) ^ (id >> 15)) as u16 as u64);
+        }
+        *last = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        uvarint(&mut self.buf, ((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        uvarint(&mut self.buf, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        uvarint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.buf.push(((len as u8) << 4) | kind);
+        } else {
+            self.buf.push(0xf0 | kind);
+            uvarint(&mut self.buf, len as u64);
+        }
+    }
+
+    /// Write an i32 list element
+    fn element_i32(&mut self, value: i32) {
+        uvarint(&mut self.buf, ((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    /// Begin a struct field, or a struct list element if `id` is `None`
+    fn begin(&mut self, id: Option<i16>) {
+        if let Some(id) = id {
+            self.field(id, STRUCT);
+        }
+        self.last.push(0);
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last.pop();
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.end();
+        self.buf
+    }
+}
+
+/// Definition levels of the values in `range`, RLE encoded as runs of
+/// nulls and values, preceded by their length
+fn definition_levels(column: &Column, range: std::ops::Range<usize>, buf: &mut Vec<u8>) {
+    let mut runs = Vec::new();
+    let mut idx = range.start;
+    while idx < range.end {
+        let null = column.is_null(idx);
+        let start = idx;
+        while idx < range.end && column.is_null(idx) == null {
+            idx += 1;
+        }
+        uvarint(&mut runs, ((idx - start) as u64) << 1);
+        runs.push(u8::from(!null));
+    }
+    buf.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&runs);
+}
+
+/// Location of a column chunk written to the file
+struct Chunk {
+    name: String,
+    physical: i32,
+    utf8: bool,
+    offset: u64,
+    size: u64,
+}
+
+/// Parquet file being written to `out`. Columns are written one at a time,
+/// and must all have the same number of rows.
+pub struct Writer<W: Write> {
+    out: W,
+    offset: u64,
+    rows: Option<usize>,
+    chunks: Vec<Chunk>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut out: W) -> std::io::Result<Writer<W>> {
+        out.write_all(MAGIC)?;
+        Ok(Writer {
+            out,
+            offset: MAGIC.len() as u64,
+            rows: None,
+            chunks: Vec::new(),
+        })
+    }
+
+    /// Write the values of the next column
+    pub fn column(&mut self, name: &str, column: &Column) -> std::io::Result<()> {
+        let rows = *self.rows.get_or_insert(column.len());
+        if column.len() != rows {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "parquet column {} has {} rows, but the table has {}",
+                    name,
+                    column.len(),
+                    rows
+                ),
+            ));
+        }
+        let offset = self.offset;
+        let mut body = Vec::new();
+        for start in (0..rows).step_by(PAGE_ROWS) {
+            let range = start..rows.min(start + PAGE_ROWS);
+            body.clear();
+            definition_levels(column, range.clone(), &mut body);
+            column.plain(range.clone(), &mut body);
+
+            let mut header = Thrift::new();
+            // Data page, uncompressed
+            header.i32(1, 0);
+            header.i32(2, body.len() as i32);
+            header.i32(3, body.len() as i32);
+            header.begin(Some(5));
+            header.i32(1, range.len() as i32);
+            header.i32(2, PLAIN);
+            header.i32(3, RLE);
+            header.i32(4, RLE);
+            header.end();
+            let header = header.finish();
+
+            self.out.write_all(&header)?;
+            self.out.write_all(&body)?;
+            self.offset += (header.len() + body.len()) as u64;
+        }
+        self.chunks.push(Chunk {
+            name: name.into(),
+            physical: column.physical(),
+            utf8: matches!(column, Column::Utf8(_)),
+            offset,
+            size: self.offset - offset,
+        });
+        Ok(())
+    }
+
+    /// Write the file metadata and footer
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let rows = self.rows.unwrap_or(0) as i64;
+        let mut meta = Thrift::new();
+        meta.i32(1, 1);
+
+        meta.list(2, STRUCT, self.chunks.len() + 1);
+        meta.begin(None);
+        meta.binary(4, b"schema");
+        meta.i32(5, self.chunks.len() as i32);
+        meta.end();
+        for chunk in &self.chunks {
+            meta.begin(None);
+            meta.i32(1, chunk.physical);
+            // Optional
+            meta.i32(3, 1);
+            meta.binary(4, chunk.name.as_bytes());
+            if chunk.utf8 {
+                meta.i32(6, 0);
+            }
+            meta.end();
+        }
+        meta.i64(3, rows);
+
+        // A table without rows has no row groups, since a column chunk
+        // must hold at least one page
+        let groups = if rows > 0 { 1 } else { 0 };
+        meta.list(4, STRUCT, groups);
+        if groups > 0 {
+            meta.begin(None);
+            meta.list(1, STRUCT, self.chunks.len());
+            for chunk in &self.chunks {
+                meta.begin(None);
+                meta.i64(2, chunk.offset as i64);
+                meta.begin(Some(3));
+                meta.i32(1, chunk.physical);
+                meta.list(2, I32, 2);
+                meta.element_i32(PLAIN);
+                meta.element_i32(RLE);
+                meta.list(3, BINARY, 1);
+                uvarint(&mut meta.buf, chunk.name.len() as u64);
+                meta.buf.extend_from_slice(chunk.name.as_bytes());
+                // Uncompressed
+                meta.i32(4, 0);
+                meta.i64(5, rows);
+                meta.i64(6, chunk.size as i64);
+                meta.i64(7, chunk.size as i64);
+                meta.i64(9, chunk.offset as i64);
+                meta.end();
+                meta.end();
+            }
+            let size = self.chunks.iter().map(|c| c.size as i64).sum();
+            meta.i64(2, size);
+            meta.i64(3, rows);
+            meta.end();
+        }
+        meta.binary(
+            6,
+            format!("census2csv version {}", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        let meta = meta.finish();
+
+        self.out.write_all(&meta)?;
+        self.out.write_all(&(meta.len() as u32).to_le_bytes())?;
+        self.out.write_all(MAGIC)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn thrift_fields() {
+        let mut t = Thrift::new();
+        t.i32(1, -1);
+        t.i64(3, 300);
+        t.binary(20, b"ab");
+        t.begin(Some(21));
+        t.i32(1, 2);
+        t.end();
+        t.list(22, I32, 2);
+        t.element_i32(0);
+        t.element_i32(3);
+        assert_eq!(
+            t.finish(),
+            [
+                0x15, 0x01, // field 1, i32 -1
+                0x26, 0xd8, 0x04, // field 3, i64 300
+                0x08, 0x28, 2, b'a', b'b', // field 20 in long form
+                0x1c, 0x15, 0x04, 0x00, // field 21, struct { 1: 2 }
+                0x19, 0x25, 0x00, 0x06, // field 22, list<i32> [0, 3]
+                0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn page_contents() {
+        let column = Column::Int64(vec![Some(1), None, None, Some(-2)]);
+        let mut buf = Vec::new();
+        definition_levels(&column, 0..4, &mut buf);
+        assert_eq!(buf, [6, 0, 0, 0, 2, 1, 4, 0, 2, 1]);
+
+        let column = Column::Boolean(vec![Some(true), None, Some(false), Some(true)]);
+        let mut buf = Vec::new();
+        column.plain(0..4, &mut buf);
+        assert_eq!(buf, [0b101]);
+
+        let column = Column::Utf8(vec![Some("ab"), None]);
+        let mut buf = Vec::new();
+        column.plain(0..2, &mut buf);
+        assert_eq!(buf, [2, 0, 0, 0, b'a', b'b']);
+    }
+
+    #[test]
+    fn file_layout() {
+        let mut out = Vec::new();
+        let mut writer = Writer::new(&mut out).unwrap();
+        writer
+            .column("a", &Column::Double(vec![Some(1.0), None]))
+            .unwrap();
+        let err = writer
+            .column("b", &Column::Int64(vec![Some(1)]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "parquet column b has 1 rows, but the table has 2"
+        );
+        writer.finish().unwrap();
+
+        assert_eq!(&out[..4], MAGIC);
+        assert_eq!(&out[out.len() - 4..], MAGIC);
+        let len = out.len();
+        let meta = u32::from_le_bytes([out[len - 8], out[len - 7], out[len - 6], out[len - 5]]);
+        assert!((meta as usize) < len - 12);
+        // A single data page follows the magic number
+        assert_eq!(out[4], 0x15);
+    }
+}
@@ -0,0 +1,110 @@
+//! Write output tables as Perseus matrices
+//!
+//! Perseus reads tab-delimited text, and uses `#!{Type}` annotation rows
+//! following the header to assign a type to each column: `E` for the main
+//! expression columns, `N` for other numeric columns, and `T` for text. If
+//! channel conditions are known, a `#!{C:Condition}` categorical row is
+//! written as well, so that samples can be grouped without manual annotation.
+use crate::meta::Column;
+use crate::writer::{Delimited, RecordWriter, Row, Rows};
+use std::io::prelude::*;
+
+/// Perseus type code for a column
+fn type_code(col: &Column) -> &'static str {
+    match (col.channel.is_some(), col.kind) {
+        (true, _) => "E",
+        (false, "integer") | (false, "number") => "N",
+        _ => "T",
+    }
+}
+
+/// Tab-delimited writer that adds Perseus annotation rows after the header
+pub struct Writer<W: Write> {
+    tsv: Delimited<W>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Writer<W> {
+        Writer {
+            tsv: Delimited::tsv(out),
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for Writer<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.tsv.header(columns)?;
+
+        let mut types = columns
+            .iter()
+            .map(|c| type_code(c).to_string())
+            .collect::<Vec<String>>();
+        types[0] = format!("#!{{Type}}{}", types[0]);
+        let mut rows = Rows::default();
+        rows.record(&types);
+
+        if columns.iter().any(|c| c.condition.is_some()) {
+            let mut conditions = columns
+                .iter()
+                .map(|c| c.condition.clone().unwrap_or_default())
+                .collect::<Vec<String>>();
+            conditions[0] = format!("#!{{C:Condition}}{}", conditions[0]);
+            rows.record(&conditions);
+        }
+        for row in rows.iter() {
+            self.tsv.record(row)?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        self.tsv.record(row)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.tsv.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("accession", "string", ""),
+            Column::new("spectral_count", "integer", ""),
+            Column::new("126", "number", "").channel(1, "126"),
+            Column::new("127", "number", "").channel(2, "127"),
+        ]
+    }
+
+    fn write(columns: &[Column]) -> String {
+        let mut writer = Writer::new(Vec::new());
+        writer.header(columns).unwrap();
+        let mut rows = Rows::default();
+        rows.record(["P12345", "2", "100", "200"]);
+        writer.record(rows.get(0)).unwrap();
+        writer.finish().unwrap();
+        String::from_utf8(writer.tsv.get_mut().clone()).unwrap()
+    }
+
+    #[test]
+    fn type_annotation_row() {
+        assert_eq!(
+            write(&columns()),
+            "accession\tspectral_count\t126\t127\n#!{Type}T\tN\tE\tE\nP12345\t2\t100\t200\n"
+        );
+    }
+
+    #[test]
+    fn condition_annotation_row() {
+        let mut columns = columns();
+        columns[2].condition = Some("control".into());
+        columns[3].condition = Some("treated".into());
+        assert_eq!(
+            write(&columns),
+            "accession\tspectral_count\t126\t127\n#!{Type}T\tN\tE\tE\n#!{C:Condition}\t\tcontrol\ttreated\nP12345\t2\t100\t200\n"
+        );
+    }
+}
@@ -0,0 +1,850 @@
+//! Distribution plots of a prepared dataset, for sanity checking a run
+//! before the outputs are used
+//!
+//! Intensities are plotted per channel on a log10 scale, with the same bins
+//! for every channel so that the channels can be compared by eye, and zero
+//! intensities are counted separately. Figures for the QC report, a
+//! boxplot of each channel's intensities, a heatmap of missing values, and
+//! the channels on the first two principal components, are built as a list
+//! of marks and written as either SVG or PNG.
+use crate::raster::{self, Canvas, Rgb};
+use census2csv::rollup::{self, Sum};
+use census_proteomics::Dataset;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Width of each intensity bin, in log10 units
+const BIN_WIDTH: f64 = 0.5;
+
+/// Width of the longest histogram bar, in characters
+const WIDTH: usize = 40;
+
+/// Peptide counts above this are binned together
+const MAX_PEPTIDES: usize = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Plot {
+    /// ASCII histograms written to stdout
+    Terminal,
+}
+
+impl std::str::FromStr for Plot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "terminal" => Ok(Plot::Terminal),
+            _ => Err(format!("unknown plot {}, expected terminal", s)),
+        }
+    }
+}
+
+/// File format of the figures written with `--plot-dir`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Figures {
+    Svg,
+    Png,
+}
+
+impl std::str::FromStr for Figures {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "svg" => Ok(Figures::Svg),
+            "png" => Ok(Figures::Png),
+            _ => Err(format!("unknown figure format {}, expected svg or png", s)),
+        }
+    }
+}
+
+/// Counts of `values` falling in each of `bins` bins of `width`, starting
+/// at `lo`
+fn histogram(values: &[f64], lo: f64, width: f64, bins: usize) -> Vec<usize> {
+    let mut counts = vec![0; bins];
+    for &v in values {
+        let bin = ((v - lo) / width).floor() as usize;
+        counts[bin.min(bins - 1)] += 1;
+    }
+    counts
+}
+
+/// Log10 intensities of each channel, and the number of zero intensities
+pub fn channel_intensities(data: &Dataset) -> Vec<(Vec<f64>, usize)> {
+    let mut channels = vec![(Vec::new(), 0); data.channels as usize];
+    for pep in data.proteins.iter().flat_map(|p| &p.peptides) {
+        for ((logs, zeros), &v) in channels.iter_mut().zip(&pep.values) {
+            if v == 0 {
+                *zeros += 1;
+            } else {
+                logs.push((v as f64).log10());
+            }
+        }
+    }
+    channels
+}
+
+/// Number of proteins with each count of distinct peptide sequences, from 1
+/// to `MAX_PEPTIDES`, the last including all larger counts
+pub fn peptide_counts(data: &Dataset) -> Vec<usize> {
+    let mut counts = vec![0; MAX_PEPTIDES];
+    for prot in &data.proteins {
+        let n = (prot.sequence_count as usize).clamp(1, MAX_PEPTIDES);
+        counts[n - 1] += 1;
+    }
+    counts
+}
+
+/// Range of the bins shared by every channel, rounded out to whole bins
+pub fn intensity_range(channels: &[(Vec<f64>, usize)]) -> Option<(f64, f64)> {
+    let logs = channels.iter().flat_map(|(logs, _)| logs);
+    let lo = logs.clone().cloned().fold(f64::INFINITY, f64::min);
+    let hi = logs.cloned().fold(f64::NEG_INFINITY, f64::max);
+    if lo.is_finite() {
+        let lo = (lo / BIN_WIDTH).floor() * BIN_WIDTH;
+        let hi = ((hi / BIN_WIDTH).floor() + 1.0) * BIN_WIDTH;
+        Some((lo, hi))
+    } else {
+        None
+    }
+}
+
+fn bars<W: Write>(labels: &[String], counts: &[usize], out: &mut W) -> std::io::Result<()> {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    let pad = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+    for (label, &count) in labels.iter().zip(counts) {
+        let len = (count * WIDTH).div_ceil(max);
+        writeln!(
+            out,
+            "  {:>pad$} | {:<width$} {}",
+            label,
+            "#".repeat(len),
+            count,
+            pad = pad,
+            width = WIDTH
+        )?;
+    }
+    Ok(())
+}
+
+/// Write ASCII histograms of the intensities of each channel, and of the
+/// number of peptides per protein
+pub fn terminal<W: Write>(data: &Dataset, mut out: W) -> std::io::Result<()> {
+    let channels = channel_intensities(data);
+    match intensity_range(&channels) {
+        Some((lo, hi)) => {
+            let bins = ((hi - lo) / BIN_WIDTH).round() as usize;
+            let labels = (0..bins)
+                .map(|i| {
+                    let start = lo + i as f64 * BIN_WIDTH;
+                    format!("{:.1}-{:.1}", start, start + BIN_WIDTH)
+                })
+                .collect::<Vec<String>>();
+            for (idx, (logs, zeros)) in channels.iter().enumerate() {
+                writeln!(
+                    out,
+                    "channel {}: log10 intensity of {} PSMs, {} zero",
+                    idx + 1,
+                    logs.len() + zeros,
+                    zeros
+                )?;
+                bars(&labels, &histogram(logs, lo, BIN_WIDTH, bins), &mut out)?;
+            }
+        }
+        None => writeln!(out, "no PSMs with nonzero intensity")?,
+    }
+
+    writeln!(
+        out,
+        "distinct peptides per protein, {} proteins",
+        data.proteins.len()
+    )?;
+    let labels = (1..=MAX_PEPTIDES)
+        .map(|n| {
+            if n == MAX_PEPTIDES {
+                format!("{}+", n)
+            } else {
+                n.to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+    bars(&labels, &peptide_counts(data), &mut out)?;
+    out.flush()
+}
+
+/// Quartiles and whiskers of `sorted`, with whiskers at the most extreme
+/// values within 1.5 interquartile ranges of the box
+fn box_stats(sorted: &[f64]) -> [f64; 5] {
+    let q = |p: f64| {
+        let pos = p * (sorted.len() - 1) as f64;
+        let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+        sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+    };
+    let (q1, median, q3) = (q(0.25), q(0.5), q(0.75));
+    let iqr = q3 - q1;
+    let low = sorted
+        .iter()
+        .copied()
+        .find(|&v| v >= q1 - 1.5 * iqr)
+        .unwrap_or(q1);
+    let high = sorted
+        .iter()
+        .rev()
+        .copied()
+        .find(|&v| v <= q3 + 1.5 * iqr)
+        .unwrap_or(q3);
+    [low, q1, median, q3, high]
+}
+
+/// Eigenvectors of the symmetric matrix `m`, with their eigenvalues, in
+/// decreasing order of eigenvalue, by cyclic Jacobi rotation
+fn eigen(mut m: Vec<Vec<f64>>) -> Vec<(f64, Vec<f64>)> {
+    let n = m.len();
+    let mut v = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect::<Vec<Vec<f64>>>();
+    for _ in 0..100 {
+        let off = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| m[i][j] * m[i][j])
+            .sum::<f64>();
+        if off < 1e-18 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if m[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (m[q][q] - m[p][p]) / (2.0 * m[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let (c, s) = (1.0 / (t * t + 1.0).sqrt(), t / (t * t + 1.0).sqrt());
+                for row in m.iter_mut() {
+                    let (mkp, mkq) = (row[p], row[q]);
+                    row[p] = c * mkp - s * mkq;
+                    row[q] = s * mkp + c * mkq;
+                }
+                let (rp, rq) = (m[p].clone(), m[q].clone());
+                for (k, (mpk, mqk)) in rp.into_iter().zip(rq).enumerate() {
+                    m[p][k] = c * mpk - s * mqk;
+                    m[q][k] = s * mpk + c * mqk;
+                }
+                for row in v.iter_mut() {
+                    let (vp, vq) = (row[p], row[q]);
+                    row[p] = c * vp - s * vq;
+                    row[q] = s * vp + c * vq;
+                }
+            }
+        }
+    }
+    let mut pairs = (0..n)
+        .map(|i| (m[i][i], v.iter().map(|row| row[i]).collect()))
+        .collect::<Vec<(f64, Vec<f64>)>>();
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+    pairs
+}
+
+/// Channels on the first two principal components
+pub struct Pca {
+    /// Coordinates of each channel
+    pub points: Vec<(f64, f64)>,
+    /// Fraction of variance explained by each component
+    pub explained: (f64, f64),
+}
+
+/// Principal components of the centered log2 intensities of proteins
+/// quantified in every channel
+pub fn pca(data: &Dataset) -> Option<Pca> {
+    let n = data.channels as usize;
+    let rows = data
+        .proteins
+        .iter()
+        .filter(|p| !p.peptides.is_empty())
+        .map(|p| rollup::protein(p, &Sum))
+        .filter(|v| v.iter().all(|&x| x > 0.0))
+        .map(|v| {
+            let logs = v.iter().map(|x| x.log2()).collect::<Vec<f64>>();
+            let mean = logs.iter().sum::<f64>() / n as f64;
+            logs.into_iter().map(|x| x - mean).collect::<Vec<f64>>()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    if n < 2 || rows.len() < 2 {
+        return None;
+    }
+    // Channels are the observations, and proteins the variables, so the
+    // channel by channel Gram matrix gives the scores directly
+    let means = (0..n)
+        .map(|c| rows.iter().map(|r| r[c]).sum::<f64>() / rows.len() as f64)
+        .collect::<Vec<f64>>();
+    let gram = (0..n)
+        .map(|a| {
+            (0..n)
+                .map(|b| {
+                    rows.iter()
+                        .map(|r| (r[a] - means[a]) * (r[b] - means[b]))
+                        .sum::<f64>()
+                })
+                .collect()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    let total = (0..n).map(|i| gram[i][i]).sum::<f64>();
+    if total <= 0.0 {
+        return None;
+    }
+    let pcs = eigen(gram);
+    let score = |pc: &(f64, Vec<f64>)| {
+        let scale = pc.0.max(0.0).sqrt();
+        pc.1.iter().map(|x| x * scale).collect::<Vec<f64>>()
+    };
+    let (x, y) = (score(&pcs[0]), score(&pcs[1]));
+    Some(Pca {
+        points: x.into_iter().zip(y).collect(),
+        explained: (pcs[0].0.max(0.0) / total, pcs[1].0.max(0.0) / total),
+    })
+}
+
+/// Fraction of PSMs with zero intensity, in each channel of each protein
+/// with any such PSM, most incomplete proteins first
+pub fn missing(data: &Dataset) -> Vec<(&str, Vec<f64>)> {
+    let mut rows = data
+        .proteins
+        .iter()
+        .filter(|p| !p.peptides.is_empty())
+        .map(|p| {
+            let fractions = (0..data.channels as usize)
+                .map(|c| {
+                    let zeros = p.peptides.iter().filter(|pep| pep.values[c] == 0).count();
+                    zeros as f64 / p.peptides.len() as f64
+                })
+                .collect::<Vec<f64>>();
+            (p.accession.as_str(), fractions)
+        })
+        .filter(|(_, f)| f.iter().any(|&x| x > 0.0))
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        let sum = |f: &[f64]| f.iter().sum::<f64>();
+        sum(&b.1).total_cmp(&sum(&a.1))
+    });
+    rows
+}
+
+/// Escape text for inclusion in SVG
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Font size of figure text, other than titles
+const FONT_SIZE: f64 = 11.0;
+
+const BLACK: Rgb = [0, 0, 0];
+const WHITE: Rgb = [255, 255, 255];
+
+/// Which end or middle of a text is at its position
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Something drawn in a figure
+#[derive(Clone, Debug, PartialEq)]
+enum Mark {
+    Line {
+        from: (f64, f64),
+        to: (f64, f64),
+        width: f64,
+        stroke: Rgb,
+    },
+    Rect {
+        at: (f64, f64),
+        size: (f64, f64),
+        fill: Option<Rgb>,
+        stroke: Rgb,
+    },
+    Circle {
+        center: (f64, f64),
+        radius: f64,
+        fill: Rgb,
+    },
+    /// Text with its baseline at `at`, turned to run upwards if `vertical`
+    Text {
+        at: (f64, f64),
+        text: String,
+        size: f64,
+        anchor: Anchor,
+        vertical: bool,
+    },
+}
+
+/// A figure on a white background, drawn in the same coordinates whether
+/// it is written as SVG or PNG
+pub struct Figure {
+    width: f64,
+    height: f64,
+    marks: Vec<Mark>,
+}
+
+impl Figure {
+    fn new(width: f64, height: f64, title: &str) -> Figure {
+        let mut figure = Figure {
+            width,
+            height,
+            marks: Vec::new(),
+        };
+        figure.marks.push(Mark::Text {
+            at: (width / 2.0, 18.0),
+            text: title.to_string(),
+            size: 14.0,
+            anchor: Anchor::Middle,
+            vertical: false,
+        });
+        figure
+    }
+
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), stroke: Rgb) {
+        self.marks.push(Mark::Line {
+            from,
+            to,
+            width: 1.0,
+            stroke,
+        });
+    }
+
+    fn text<S: Into<String>>(&mut self, at: (f64, f64), text: S, anchor: Anchor) {
+        self.marks.push(Mark::Text {
+            at,
+            text: text.into(),
+            size: FONT_SIZE,
+            anchor,
+            vertical: false,
+        });
+    }
+
+    /// The figure as an SVG document
+    pub fn svg(&self) -> String {
+        let color = |c: Rgb| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"{s}\">\n<rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n",
+            w = self.width,
+            h = self.height,
+            s = FONT_SIZE
+        );
+        for mark in &self.marks {
+            let element = match mark {
+                Mark::Line {
+                    from,
+                    to,
+                    width,
+                    stroke,
+                } => format!(
+                    "<line x1=\"{}\" x2=\"{}\" y1=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                    from.0,
+                    to.0,
+                    from.1,
+                    to.1,
+                    color(*stroke),
+                    width
+                ),
+                Mark::Rect {
+                    at,
+                    size,
+                    fill,
+                    stroke,
+                } => format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"{}\"/>",
+                    at.0,
+                    at.1,
+                    size.0,
+                    size.1,
+                    fill.map(color).unwrap_or_else(|| "none".to_string()),
+                    color(*stroke)
+                ),
+                Mark::Circle {
+                    center,
+                    radius,
+                    fill,
+                } => format!(
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>",
+                    center.0,
+                    center.1,
+                    radius,
+                    color(*fill)
+                ),
+                Mark::Text {
+                    at: (x, y),
+                    text,
+                    size,
+                    anchor,
+                    vertical,
+                } => {
+                    let mut attrs = format!("x=\"{}\" y=\"{}\"", x, y);
+                    match anchor {
+                        Anchor::Start => (),
+                        Anchor::Middle => attrs.push_str(" text-anchor=\"middle\""),
+                        Anchor::End => attrs.push_str(" text-anchor=\"end\""),
+                    }
+                    if *size != FONT_SIZE {
+                        attrs.push_str(&format!(" font-size=\"{}\"", size));
+                    }
+                    if *vertical {
+                        attrs.push_str(&format!(" transform=\"rotate(-90 {} {})\"", x, y));
+                    }
+                    format!("<text {}>{}</text>", attrs, escape(text))
+                }
+            };
+            out.push_str(&element);
+            out.push('\n');
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    /// The figure drawn at one pixel per unit, encoded as PNG
+    pub fn png(&self) -> Vec<u8> {
+        self.draw().png()
+    }
+
+    /// The figure drawn at one pixel per unit. Text is drawn in a bitmap
+    /// font rather than the sans-serif font of the SVG, so it takes up
+    /// somewhat different space.
+    fn draw(&self) -> Canvas {
+        let mut canvas = Canvas::new(
+            self.width.ceil() as usize,
+            self.height.ceil() as usize,
+            WHITE,
+        );
+        for mark in &self.marks {
+            match mark {
+                Mark::Line {
+                    from,
+                    to,
+                    width,
+                    stroke,
+                } => canvas.line(*from, *to, *width, *stroke),
+                Mark::Rect {
+                    at,
+                    size,
+                    fill,
+                    stroke,
+                } => {
+                    if let Some(fill) = fill {
+                        canvas.fill_rect(at.0, at.1, size.0, size.1, *fill);
+                    }
+                    canvas.stroke_rect(at.0, at.1, size.0, size.1, *stroke);
+                }
+                Mark::Circle {
+                    center,
+                    radius,
+                    fill,
+                } => canvas.circle(*center, *radius, *fill),
+                Mark::Text {
+                    at: (x, y),
+                    text,
+                    size,
+                    anchor,
+                    vertical,
+                } => {
+                    let shift = match anchor {
+                        Anchor::Start => 0.0,
+                        Anchor::Middle => raster::text_width(text, *size) / 2.0,
+                        Anchor::End => raster::text_width(text, *size),
+                    };
+                    // The start of vertical text is below its anchor
+                    let at = match vertical {
+                        false => (x - shift, *y),
+                        true => (*x, y + shift),
+                    };
+                    canvas.text(at, text, *size, *vertical, BLACK);
+                }
+            }
+        }
+        canvas
+    }
+}
+
+/// Boxplot of the log10 intensities of each channel
+pub fn boxplot(data: &Dataset, title: &str) -> Figure {
+    let channels = channel_intensities(data);
+    let (left, top, plot_h, step) = (50.0, 30.0, 300.0, 40.0);
+    let width = left + step * channels.len().max(1) as f64 + 20.0;
+    let mut fig = Figure::new(width, top + plot_h + 40.0, title);
+    let (lo, hi) = intensity_range(&channels).unwrap_or((0.0, 1.0));
+    let y = |v: f64| top + plot_h * (1.0 - (v - lo) / (hi - lo));
+    let mut tick = lo;
+    while tick <= hi + 1e-9 {
+        fig.line((left, y(tick)), (width - 20.0, y(tick)), [0xdd; 3]);
+        fig.text(
+            (left - 4.0, y(tick) + 4.0),
+            format!("{:.1}", tick),
+            Anchor::End,
+        );
+        tick += BIN_WIDTH;
+    }
+    for (idx, (logs, _)) in channels.iter().enumerate() {
+        let x = left + step * (idx as f64 + 0.5);
+        fig.text(
+            (x, top + plot_h + 16.0),
+            (idx + 1).to_string(),
+            Anchor::Middle,
+        );
+        if logs.is_empty() {
+            continue;
+        }
+        let mut sorted = logs.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let [low, q1, median, q3, high] = box_stats(&sorted);
+        fig.line((x, y(low)), (x, y(high)), BLACK);
+        fig.marks.push(Mark::Rect {
+            at: (x - step * 0.3, y(q3)),
+            size: (step * 0.6, y(q1) - y(q3)),
+            fill: Some([0x9e, 0xca, 0xe1]),
+            stroke: BLACK,
+        });
+        fig.marks.push(Mark::Line {
+            from: (x - step * 0.3, y(median)),
+            to: (x + step * 0.3, y(median)),
+            width: 2.0,
+            stroke: BLACK,
+        });
+    }
+    fig.text(
+        (left + (width - left - 20.0) / 2.0, top + plot_h + 34.0),
+        "channel",
+        Anchor::Middle,
+    );
+    fig.marks.push(Mark::Text {
+        at: (12.0, top + plot_h / 2.0),
+        text: "log10 intensity".to_string(),
+        size: FONT_SIZE,
+        anchor: Anchor::Middle,
+        vertical: true,
+    });
+    fig
+}
+
+/// Proteins shown in the missing value heatmap
+const MISSING_ROWS: usize = 100;
+
+/// Heatmap of the fraction of PSMs with zero intensity, for the proteins
+/// with the most missing values
+pub fn missing_values(data: &Dataset, title: &str) -> Figure {
+    let rows = missing(data);
+    let shown = &rows[..rows.len().min(MISSING_ROWS)];
+    let (left, top, cell_w, cell_h) = (120.0, 30.0, 24.0, 10.0);
+    let width = left + cell_w * data.channels as f64 + 20.0;
+    let height = top + cell_h * shown.len().max(1) as f64 + 40.0;
+    let mut fig = Figure::new(width, height, title);
+    if shown.is_empty() {
+        fig.text(
+            (width / 2.0, top + 10.0),
+            "no missing values",
+            Anchor::Middle,
+        );
+    }
+    for (row, (accession, fractions)) in shown.iter().enumerate() {
+        let y = top + cell_h * row as f64;
+        fig.marks.push(Mark::Text {
+            at: (left - 4.0, y + cell_h - 2.0),
+            text: accession.to_string(),
+            size: 8.0,
+            anchor: Anchor::End,
+            vertical: false,
+        });
+        for (c, &f) in fractions.iter().enumerate() {
+            let shade = (255.0 * (1.0 - f)).round() as u8;
+            fig.marks.push(Mark::Rect {
+                at: (left + cell_w * c as f64, y),
+                size: (cell_w, cell_h),
+                fill: Some([255, shade, shade]),
+                stroke: [0xee; 3],
+            });
+        }
+    }
+    let bottom = top + cell_h * shown.len().max(1) as f64;
+    for c in 0..data.channels as usize {
+        fig.text(
+            (left + cell_w * (c as f64 + 0.5), bottom + 14.0),
+            (c + 1).to_string(),
+            Anchor::Middle,
+        );
+    }
+    fig.text(
+        (width / 2.0, bottom + 30.0),
+        format!(
+            "{} of {} proteins with missing values shown",
+            shown.len(),
+            rows.len()
+        ),
+        Anchor::Middle,
+    );
+    fig
+}
+
+/// Scatter plot of the channels on the first two principal components
+pub fn pca_scatter(data: &Dataset, title: &str) -> Figure {
+    let (size, margin) = (360.0, 50.0);
+    let mut fig = Figure::new(size + 2.0 * margin, size + 2.0 * margin, title);
+    let center = margin + size / 2.0;
+    let Pca {
+        points,
+        explained: (var1, var2),
+    } = match pca(data) {
+        Some(pca) => pca,
+        None => {
+            fig.text(
+                (center, center),
+                "too few complete proteins for PCA",
+                Anchor::Middle,
+            );
+            return fig;
+        }
+    };
+    let extent = points
+        .iter()
+        .map(|&(x, y)| x.abs().max(y.abs()))
+        .fold(0.0, f64::max)
+        .max(1e-9)
+        * 1.1;
+    let pos = |v: f64| center + v / extent * size / 2.0;
+    fig.marks.push(Mark::Rect {
+        at: (margin, margin),
+        size: (size, size),
+        fill: None,
+        stroke: [0xcc; 3],
+    });
+    fig.line((margin, center), (margin + size, center), [0xee; 3]);
+    fig.line((center, margin), (center, margin + size), [0xee; 3]);
+    for (idx, &(x, y)) in points.iter().enumerate() {
+        let (px, py) = (pos(x), 2.0 * margin + size - pos(y));
+        fig.marks.push(Mark::Circle {
+            center: (px, py),
+            radius: 4.0,
+            fill: [0x31, 0x82, 0xbd],
+        });
+        fig.text((px + 6.0, py - 4.0), (idx + 1).to_string(), Anchor::Start);
+    }
+    fig.text(
+        (center, margin + size + 30.0),
+        format!("PC1 ({:.1}%)", var1 * 100.0),
+        Anchor::Middle,
+    );
+    fig.marks.push(Mark::Text {
+        at: (14.0, center),
+        text: format!("PC2 ({:.1}%)", var2 * 100.0),
+        size: FONT_SIZE,
+        anchor: Anchor::Middle,
+        vertical: true,
+    });
+    fig
+}
+
+/// Write the boxplot, missing value, and PCA figures for `data` to `dir`,
+/// as `<name>.boxplot.<ext>`, `<name>.missing.<ext>`, and `<name>.pca.<ext>`
+/// with the extension of `format`
+pub fn write_figures<P: AsRef<Path>>(
+    data: &Dataset,
+    dir: P,
+    name: &str,
+    format: Figures,
+) -> std::io::Result<()> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let figures = [
+        (
+            "boxplot",
+            boxplot(data, &format!("{}: channel intensities", name)),
+        ),
+        (
+            "missing",
+            missing_values(data, &format!("{}: missing values", name)),
+        ),
+        (
+            "pca",
+            pca_scatter(data, &format!("{}: channels by PCA", name)),
+        ),
+    ];
+    for (kind, figure) in figures.iter() {
+        let (ext, bytes) = match format {
+            Figures::Svg => ("svg", figure.svg().into_bytes()),
+            Figures::Png => ("png", figure.png()),
+        };
+        std::fs::write(dir.join(format!("{}.{}.{}", name, kind, ext)), bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use census2csv::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t3\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t200\t0.5
+S\t\tK.SHAREDK.L\t1000\t0.5\t2000\t0.5
+S\tU\tR.SEQUENCER.A\t300\t0.5\t400\t0.5
+P\tQ67890\t1\t1\t10.0%\t100\t10000\tKeratin
+S\tU\tK.KERATINK.R\t5\t0.5\t0\t0.5";
+
+    #[test]
+    fn figure_formats() {
+        assert_eq!("svg".parse(), Ok(Figures::Svg));
+        assert_eq!("PNG".parse(), Ok(Figures::Png));
+        assert!("jpeg".parse::<Figures>().is_err());
+    }
+
+    #[test]
+    fn missing_value_figure() {
+        let data = input::parse_census(FILE).unwrap();
+        let fig = missing_values(&data, "a & b");
+        let svg = fig.svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">a &amp; b</text>"));
+        assert!(svg.contains(">1 of 1 proteins with missing values shown</text>"));
+        // Keratin has no intensity in the second channel
+        assert!(svg.contains(
+            "<rect x=\"144\" y=\"30\" width=\"24\" height=\"10\" fill=\"#ff0000\" stroke=\"#eeeeee\"/>"
+        ));
+        assert!(svg.contains(
+            "<rect x=\"120\" y=\"30\" width=\"24\" height=\"10\" fill=\"#ffffff\" stroke=\"#eeeeee\"/>"
+        ));
+
+        // The same cells, drawn as pixels
+        let canvas = fig.draw();
+        assert_eq!(canvas.pixel(156, 35), [255, 0, 0]);
+        assert_eq!(canvas.pixel(132, 35), [255, 255, 255]);
+        // The stroke between the cells
+        assert_eq!(canvas.pixel(143, 35), [0xee; 3]);
+        // The accession is drawn, ending left of the cells
+        let (left, right) = (116 - raster::text_width("Q67890", 8.0) as usize, 116);
+        let inked =
+            |x0: usize, x1: usize| (x0..x1).any(|x| (25..40).any(|y| canvas.pixel(x, y) == BLACK));
+        assert!(inked(left, right));
+        assert!(!inked(right, 120));
+        assert!(!inked(0, left));
+    }
+
+    #[test]
+    fn write_both_formats() {
+        let data = input::parse_census(FILE).unwrap();
+        let dir = crate::fixture::dir("plot-figures", &[]);
+        write_figures(&data, &dir, "a", Figures::Svg).unwrap();
+        write_figures(&data, &dir, "a", Figures::Png).unwrap();
+        for kind in &["boxplot", "missing", "pca"] {
+            let svg = std::fs::read_to_string(dir.join(format!("a.{}.svg", kind))).unwrap();
+            assert!(svg.starts_with("<svg "), "{}", kind);
+            let png = std::fs::read(dir.join(format!("a.{}.png", kind))).unwrap();
+            assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n", "{}", kind);
+        }
+        // Two channels are too few for PCA
+        let pca = std::fs::read_to_string(dir.join("a.pca.svg")).unwrap();
+        assert!(pca.contains(">too few complete proteins for PCA</text>"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Curated lists of common contaminant proteins, for `ExcludePreset`
+//!
+//! Proteins are identified by UniProt accession, which is matched against
+//! each `|`-separated part of a protein's accession, ignoring isoform
+//! suffixes, so that `sp|P04264|K2C1_HUMAN` and `P04264-2` both match.
+use census_proteomics::Protein;
+use serde::{Deserialize, Serialize};
+
+/// Human keratins, from skin and hair introduced during sample handling
+const KERATINS: &[&str] = &[
+    "P04264", // KRT1
+    "P35908", // KRT2
+    "P12035", // KRT3
+    "P19013", // KRT4
+    "P13647", // KRT5
+    "P02538", // KRT6A
+    "P04259", // KRT6B
+    "P08729", // KRT7
+    "P05787", // KRT8
+    "P35527", // KRT9
+    "P13645", // KRT10
+    "P13646", // KRT13
+    "P02533", // KRT14
+    "P19012", // KRT15
+    "P08779", // KRT16
+    "Q04695", // KRT17
+    "P05783", // KRT18
+    "P08727", // KRT19
+    "Q01546", // KRT76
+    "Q7Z794", // KRT77
+];
+
+/// Affinity reagents and the proteins of common affinity tags
+const AFFINITY_TAGS: &[&str] = &[
+    "P22629", // Streptavidin
+    "P02701", // Avidin
+    "P06709", // BirA biotin ligase
+    "P38507", // Protein A
+    "P19909", // Protein G
+    "P08515", // Glutathione S-transferase (GST tag)
+    "P0AEX9", // Maltose-binding protein (MBP tag)
+    "P42212", // Green fluorescent protein
+];
+
+/// Proteases used for tag cleavage and digestion
+const PROTEASES: &[&str] = &[
+    "P04517", // TEV protease, within the TEV polyprotein
+    "P00761", // Porcine trypsin
+];
+
+/// A curated list of contaminant proteins
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    /// Human keratins
+    Keratins,
+    /// Streptavidin, avidin, protein A and G, and the GST, MBP, and GFP tags
+    AffinityTags,
+    /// Keratins, affinity tags, and the TEV and trypsin proteases, for
+    /// affinity purification-mass spectrometry
+    AffinityPurification,
+}
+
+impl Preset {
+    /// UniProt accessions of the proteins in the preset
+    pub fn accessions(self) -> Vec<&'static str> {
+        let lists: &[&[&str]] = match self {
+            Preset::Keratins => &[KERATINS],
+            Preset::AffinityTags => &[AFFINITY_TAGS],
+            Preset::AffinityPurification => &[KERATINS, AFFINITY_TAGS, PROTEASES],
+        };
+        lists.iter().flat_map(|list| list.iter().copied()).collect()
+    }
+
+    /// Is `prot` one of the proteins of the preset?
+    pub fn matches(self, prot: &Protein) -> bool {
+        let accessions = self.accessions();
+        prot.accession
+            .split('|')
+            .map(|part| part.split('-').next().unwrap_or(part))
+            .any(|part| accessions.contains(&part))
+    }
+}
@@ -0,0 +1,42 @@
+//! Write PSMs as a flat tab-delimited table, without any aggregation, for
+//! tools that perform their own quantification
+use crate::labels::channel_names;
+use census2csv::number;
+use census_proteomics::*;
+use std::io::prelude::*;
+
+/// Write one row per PSM of `data`
+pub fn write_tsv<W: Write>(data: &Dataset, mut file: W) -> std::io::Result<()> {
+    let channels = channel_names(None, data.channels)
+        .iter()
+        .map(|c| format!("\t{}", c))
+        .collect::<String>();
+    writeln!(
+        file,
+        "accession\tdescription\tsequence\tunique\tpurity\tscan{}",
+        channels
+    )?;
+
+    let mut values = String::new();
+    for prot in &data.proteins {
+        for pep in &prot.peptides {
+            values.clear();
+            for &v in &pep.values {
+                values.push('\t');
+                number::push_int(&mut values, v);
+            }
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}{}",
+                prot.accession,
+                prot.description.replace('\t', " "),
+                pep.sequence,
+                pep.unique,
+                pep.purity,
+                pep.scan,
+                values
+            )?;
+        }
+    }
+    Ok(())
+}
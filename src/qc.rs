@@ -0,0 +1,242 @@
+//! Quality control checks run on parsed and filtered datasets
+use crate::sequence;
+use census_proteomics::*;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Default maximum fraction of decoy proteins allowed after filtering
+pub const MAX_DECOY_RATE: f64 = 0.01;
+
+/// Channels whose total intensity is below this fraction of the median
+/// channel total are considered to have failed
+pub const DEAD_CHANNEL_RATIO: f64 = 0.1;
+
+/// Is this protein a decoy? Matches the behavior of `ExcludeReverse`
+pub fn is_decoy(prot: &Protein) -> bool {
+    prot.accession.contains("Reverse")
+}
+
+/// Is this protein a contaminant, with an accession starting with any of
+/// `prefixes`?
+pub fn is_contaminant<S: AsRef<str>>(prot: &Protein, prefixes: &[S]) -> bool {
+    prefixes
+        .iter()
+        .any(|p| prot.accession.starts_with(p.as_ref()))
+}
+
+/// Is this protein a single-hit wonder, identified by only one distinct
+/// peptide sequence?
+pub fn is_single_hit(prot: &Protein) -> bool {
+    let sequences = prot
+        .peptides
+        .iter()
+        .map(|pep| pep.sequence.as_str())
+        .collect::<HashSet<&str>>();
+    sequences.len() == 1
+}
+
+/// How to handle single-hit proteins
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SingleHits {
+    /// Write them like any other protein
+    Keep,
+    /// Write them, marked in an `is_single_hit` column
+    Flag,
+    /// Remove them
+    Drop,
+}
+
+impl FromStr for SingleHits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(SingleHits::Keep),
+            "flag" => Ok(SingleHits::Flag),
+            "drop" => Ok(SingleHits::Drop),
+            _ => Err(format!("unknown single-hit policy {}", s)),
+        }
+    }
+}
+
+/// Results of quality control checks for a single dataset
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Number of proteins remaining after filtering
+    pub proteins: usize,
+    /// Number of decoy proteins remaining after filtering
+    pub decoys: usize,
+    /// Total intensity of each channel, prior to filtering
+    pub channel_totals: Vec<u64>,
+    /// Channels (1-indexed) with near zero, or drastically lower total
+    /// intensity than the other channels
+    pub dead_channels: Vec<usize>,
+    /// Human readable descriptions of each failed check
+    pub warnings: Vec<String>,
+}
+
+impl Report {
+    /// Fraction of proteins that are decoys
+    pub fn decoy_rate(&self) -> f64 {
+        if self.proteins == 0 {
+            0.0
+        } else {
+            self.decoys as f64 / self.proteins as f64
+        }
+    }
+
+    /// Summary of the checks, as written by `--qc-report`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "passed": self.passed(),
+            "proteins": self.proteins,
+            "decoys": self.decoys,
+            "decoy_rate": self.decoy_rate(),
+            "channel_totals": self.channel_totals,
+            "dead_channels": self.dead_channels,
+            "warnings": self.warnings,
+        })
+    }
+
+    /// Did all checks pass?
+    pub fn passed(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Check for failed channels in an unfiltered dataset
+    pub fn check_channels(&mut self, data: &Dataset) {
+        let mut totals = vec![0u64; data.channels as usize];
+        for prot in &data.proteins {
+            for pep in &prot.peptides {
+                for (total, val) in totals.iter_mut().zip(pep.values.iter()) {
+                    *total += *val as u64;
+                }
+            }
+        }
+
+        let mut sorted = totals.clone();
+        sorted.sort_unstable();
+        let median = sorted.get(sorted.len() / 2).copied().unwrap_or(0) as f64;
+
+        self.dead_channels = totals
+            .iter()
+            .enumerate()
+            .filter(|(_, &t)| t == 0 || (t as f64) < median * DEAD_CHANNEL_RATIO)
+            .map(|(idx, _)| idx + 1)
+            .collect();
+        for &chan in &self.dead_channels {
+            self.warnings.push(format!(
+                "channel {} has a total intensity of {}, compared to a median of {} across channels. Ratios involving this channel will be misleading",
+                chan,
+                totals[chan - 1],
+                median
+            ));
+        }
+        self.channel_totals = totals;
+    }
+
+    /// Check that every PSM sequence can be parsed, removing PSMs that
+    /// cannot if `remove` is true, along with proteins left without PSMs
+    pub fn check_sequences(&mut self, data: &mut Dataset, remove: bool) {
+        let mut count = 0;
+        let mut example = None;
+        for prot in &mut data.proteins {
+            prot.peptides
+                .retain(|pep| match sequence::check(&pep.sequence) {
+                    Ok(()) => true,
+                    Err(reason) => {
+                        count += 1;
+                        example.get_or_insert_with(|| format!("{} ({})", pep.sequence, reason));
+                        !remove
+                    }
+                });
+        }
+        let example = match example {
+            Some(example) => example,
+            None => return,
+        };
+        if remove {
+            data.proteins.retain(|prot| !prot.peptides.is_empty());
+            self.warnings.push(format!(
+                "removed {} PSMs whose sequences cannot be parsed, such as {}",
+                count, example
+            ));
+        } else {
+            self.warnings.push(format!(
+                "{} PSMs have sequences that cannot be parsed, such as {}. Sequence filters and columns may be wrong for them; use --skip-unparseable-sequences to remove them",
+                count, example
+            ));
+        }
+    }
+
+    /// Check the fraction of decoy proteins remaining in a filtered dataset
+    pub fn check_decoys(&mut self, data: &Dataset, max_decoy_rate: f64) {
+        self.proteins = data.proteins.len();
+        self.decoys = data.proteins.iter().filter(|p| is_decoy(p)).count();
+
+        if self.decoy_rate() > max_decoy_rate {
+            self.warnings.push(format!(
+                "{} of {} proteins ({:.2}%) remaining after filtering are decoys, exceeding the {:.2}% threshold. Upstream filtering may have failed",
+                self.decoys,
+                self.proteins,
+                self.decoy_rate() * 100.0,
+                max_decoy_rate * 100.0
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input;
+
+    fn dataset(accessions: &[&str]) -> Dataset {
+        let mut text =
+            String::from("H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\n");
+        for acc in accessions {
+            text.push_str(&format!(
+                "P\t{}\t1\t1\t10.0%\t100\t10000\tProtein\nS\tU\tK.PEPTIDEK.R\t100\t0.5\n",
+                acc
+            ));
+        }
+        input::parse_census(&text).unwrap()
+    }
+
+    #[test]
+    fn decoy_rate_within_threshold() {
+        let mut accessions = vec!["P1"; 99];
+        accessions.push("Reverse_P1");
+        let mut report = Report::default();
+        report.check_decoys(&dataset(&accessions), MAX_DECOY_RATE);
+        assert_eq!((report.proteins, report.decoys), (100, 1));
+        assert_eq!(report.decoy_rate(), 0.01);
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn decoy_rate_exceeding_threshold() {
+        let mut report = Report::default();
+        report.check_decoys(&dataset(&["P1", "P2", "P3", "Reverse_P1"]), 0.2);
+        assert_eq!(report.decoy_rate(), 0.25);
+        assert!(!report.passed());
+        assert_eq!(
+            report.warnings,
+            ["1 of 4 proteins (25.00%) remaining after filtering are decoys, exceeding the 20.00% threshold. Upstream filtering may have failed"]
+        );
+        assert_eq!(report.to_json()["passed"], false);
+        assert_eq!(report.to_json()["decoys"], 1);
+    }
+
+    #[test]
+    fn empty_dataset_has_no_decoys() {
+        let mut report = Report::default();
+        let empty = Dataset {
+            proteins: Vec::new(),
+            channels: 1,
+        };
+        report.check_decoys(&empty, 0.0);
+        assert_eq!(report.decoy_rate(), 0.0);
+        assert!(report.passed());
+    }
+}
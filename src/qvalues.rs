@@ -0,0 +1,66 @@
+//! PSM q-values from Percolator or crux rescoring results
+//!
+//! Results are tab-delimited with a header line. PSMs are identified by a
+//! `scan` column, as written by crux, or otherwise by the `PSMId` column
+//! written by Percolator, whose last three `_`-separated parts are the scan,
+//! charge, and rank (`target_0_13456_2_1`). The q-value is read from a
+//! `q-value` or `percolator q-value` column.
+use std::collections::HashMap;
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Scan number of a Percolator `PSMId`
+fn psm_id_scan(id: &str) -> Option<usize> {
+    let parts = id.rsplit('_').collect::<Vec<&str>>();
+    parts.get(2)?.parse().ok()
+}
+
+/// Read the q-value of each PSM by scan number. A scan appearing more than
+/// once, as when results of several runs are combined, is given its
+/// largest q-value, and the number of such scans is returned.
+pub fn read(text: &str) -> std::io::Result<(HashMap<usize, f64>, usize)> {
+    let mut lines = (text.lines().enumerate()).filter(|(_, l)| !l.trim().is_empty());
+    let (_, header) = lines
+        .next()
+        .ok_or_else(|| invalid("q-value file is empty"))?;
+    let header = header
+        .split('\t')
+        .map(|s| s.trim().to_lowercase())
+        .collect::<Vec<String>>();
+    let col = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let qvalue = col(&["q-value", "percolator q-value", "q_value", "qvalue"])
+        .ok_or_else(|| invalid("q-value file has no q-value column"))?;
+    let (scan, psm_id) = (col(&["scan"]), col(&["psmid"]));
+    if scan.is_none() && psm_id.is_none() {
+        return Err(invalid("q-value file has no scan or PSMId column"));
+    }
+
+    let mut qvalues = HashMap::new();
+    let mut repeated = 0;
+    for (idx, line) in lines {
+        let row = line.split('\t').collect::<Vec<&str>>();
+        let field = |col: usize| row.get(col).map(|s| s.trim()).unwrap_or("");
+        let id = match (scan, psm_id) {
+            (Some(col), _) => field(col).parse().ok(),
+            (None, Some(col)) => psm_id_scan(field(col)),
+            (None, None) => None,
+        };
+        let q = field(qvalue).parse::<f64>().ok();
+        let (id, q) = match (id, q) {
+            (Some(id), Some(q)) => (id, q),
+            _ => {
+                return Err(invalid(format!(
+                    "line {} of the q-value file has no valid scan and q-value",
+                    idx + 1
+                )))
+            }
+        };
+        if let Some(prev) = qvalues.insert(id, q) {
+            repeated += 1;
+            qvalues.insert(id, q.max(prev));
+        }
+    }
+    Ok((qvalues, repeated))
+}
@@ -0,0 +1,756 @@
+//! Drawing of figures into RGB images, and encoding of those images as PNG
+//!
+//! This covers what the QC figures need: lines, rectangles, circles, and
+//! text in a built-in 5x7 pixel font with descenders, drawn without
+//! anti-aliasing. Images are compressed with LZ77 and the fixed Huffman
+//! codes of deflate, which handles the large areas of flat color in a
+//! figure well without the tables of dynamic codes.
+
+/// A color, as red, green, and blue intensities
+pub type Rgb = [u8; 3];
+
+/// Rows of each printable ASCII character from ' ' to '~', top first, with
+/// the leftmost of the five columns in the highest bit. The last two rows
+/// are below the baseline.
+const FONT: [[u8; 9]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00], // '!'
+    [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a, 0x00, 0x00], // '#'
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04, 0x00, 0x00], // '$'
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03, 0x00, 0x00], // '%'
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d, 0x00, 0x00], // '&'
+    [0x04, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '\''
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02, 0x00, 0x00], // '('
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08, 0x00, 0x00], // ')'
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00, 0x00, 0x00], // '*'
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x04, 0x08], // ','
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00, 0x00], // '.'
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00, 0x00, 0x00], // '/'
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e, 0x00, 0x00], // '0'
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00, 0x00], // '1'
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f, 0x00, 0x00], // '2'
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e, 0x00, 0x00], // '3'
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02, 0x00, 0x00], // '4'
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e, 0x00, 0x00], // '5'
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e, 0x00, 0x00], // '6'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08, 0x00, 0x00], // '7'
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e, 0x00, 0x00], // '8'
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c, 0x00, 0x00], // '9'
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00, 0x00, 0x00], // ':'
+    [0x00, 0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x04, 0x08], // ';'
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02, 0x00, 0x00], // '<'
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x00], // '='
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08, 0x00, 0x00], // '>'
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00, 0x00], // '?'
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e, 0x00, 0x00], // '@'
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11, 0x00, 0x00], // 'A'
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e, 0x00, 0x00], // 'B'
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e, 0x00, 0x00], // 'C'
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c, 0x00, 0x00], // 'D'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f, 0x00, 0x00], // 'E'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10, 0x00, 0x00], // 'F'
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f, 0x00, 0x00], // 'G'
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11, 0x00, 0x00], // 'H'
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00, 0x00], // 'I'
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c, 0x00, 0x00], // 'J'
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11, 0x00, 0x00], // 'K'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f, 0x00, 0x00], // 'L'
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11, 0x00, 0x00], // 'M'
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11, 0x00, 0x00], // 'N'
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e, 0x00, 0x00], // 'O'
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10, 0x00, 0x00], // 'P'
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d, 0x00, 0x00], // 'Q'
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11, 0x00, 0x00], // 'R'
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e, 0x00, 0x00], // 'S'
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x00], // 'T'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e, 0x00, 0x00], // 'U'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04, 0x00, 0x00], // 'V'
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a, 0x00, 0x00], // 'W'
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11, 0x00, 0x00], // 'X'
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04, 0x00, 0x00], // 'Y'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f, 0x00, 0x00], // 'Z'
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e, 0x00, 0x00], // '['
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00, 0x00, 0x00], // '\\'
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e, 0x00, 0x00], // ']'
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f, 0x00, 0x00], // '_'
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f, 0x00, 0x00], // 'a'
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1e, 0x00, 0x00], // 'b'
+    [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e, 0x00, 0x00], // 'c'
+    [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f, 0x00, 0x00], // 'd'
+    [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e, 0x00, 0x00], // 'e'
+    [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08, 0x00, 0x00], // 'f'
+    [0x00, 0x00, 0x0f, 0x11, 0x11, 0x11, 0x0f, 0x01, 0x0e], // 'g'
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11, 0x00, 0x00], // 'h'
+    [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e, 0x00, 0x00], // 'i'
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // 'j'
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12, 0x00, 0x00], // 'k'
+    [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00, 0x00], // 'l'
+    [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11, 0x00, 0x00], // 'm'
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11, 0x00, 0x00], // 'n'
+    [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e, 0x00, 0x00], // 'o'
+    [0x00, 0x00, 0x1e, 0x11, 0x11, 0x11, 0x1e, 0x10, 0x10], // 'p'
+    [0x00, 0x00, 0x0f, 0x11, 0x11, 0x11, 0x0f, 0x01, 0x01], // 'q'
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10, 0x00, 0x00], // 'r'
+    [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e, 0x00, 0x00], // 's'
+    [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06, 0x00, 0x00], // 't'
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0d, 0x00, 0x00], // 'u'
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0a, 0x04, 0x00, 0x00], // 'v'
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0a, 0x00, 0x00], // 'w'
+    [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x00, 0x00], // 'x'
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x11, 0x0f, 0x01, 0x0e], // 'y'
+    [0x00, 0x00, 0x1f, 0x02, 0x04, 0x08, 0x1f, 0x00, 0x00], // 'z'
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02, 0x00, 0x00], // '{'
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x00], // '|'
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08, 0x00, 0x00], // '}'
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+/// Rows above the baseline, and columns, of each character of the font
+const GLYPH_HEIGHT: usize = 7;
+const GLYPH_WIDTH: usize = 5;
+
+/// Pixels each font pixel is drawn as, for text of `size`
+fn scale(size: f64) -> f64 {
+    (size / GLYPH_HEIGHT as f64).floor().max(1.0)
+}
+
+/// Width of `text` drawn at `size`, in pixels
+pub fn text_width(text: &str, size: f64) -> f64 {
+    match text.chars().count() {
+        0 => 0.0,
+        n => ((GLYPH_WIDTH + 1) * n - 1) as f64 * scale(size),
+    }
+}
+
+/// First pixel whose center is at or after `x`
+fn pixel(x: f64) -> i64 {
+    (x - 0.5).ceil() as i64
+}
+
+/// An RGB image, drawn on in the coordinates of a figure: pixel `(x, y)`
+/// covers the unit square from `(x, y)` to `(x + 1, y + 1)`
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, background: Rgb) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: background.repeat(width * height),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb {
+        let idx = 3 * (y * self.width + x);
+        [self.pixels[idx], self.pixels[idx + 1], self.pixels[idx + 2]]
+    }
+
+    /// Set a pixel, ignoring those outside of the image
+    fn set(&mut self, x: i64, y: i64, color: Rgb) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            let idx = 3 * (y as usize * self.width + x as usize);
+            self.pixels[idx..idx + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Fill the pixels whose centers lie within a rectangle
+    pub fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Rgb) {
+        for py in pixel(y)..pixel(y + height) {
+            for px in pixel(x)..pixel(x + width) {
+                self.set(px, py, color);
+            }
+        }
+    }
+
+    /// Outline a rectangle with a stroke one pixel wide, centered on its
+    /// edges
+    pub fn stroke_rect(&mut self, x: f64, y: f64, width: f64, height: f64, color: Rgb) {
+        let (outer, inner) = (width + 1.0, height - 1.0);
+        self.fill_rect(x - 0.5, y - 0.5, outer, 1.0, color);
+        self.fill_rect(x - 0.5, y + height - 0.5, outer, 1.0, color);
+        self.fill_rect(x - 0.5, y + 0.5, 1.0, inner, color);
+        self.fill_rect(x + width - 0.5, y + 0.5, 1.0, inner, color);
+    }
+
+    /// Draw a line `width` pixels wide, ending flat at its end points. The
+    /// width is measured across the line, though the ends of a diagonal
+    /// line are cut along a row or column of pixels.
+    pub fn line(&mut self, from: (f64, f64), to: (f64, f64), width: f64, color: Rgb) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let major = dx.abs().max(dy.abs());
+        if major == 0.0 {
+            return;
+        }
+        // Half the width, measured along a column for lines closer to
+        // horizontal, or along a row otherwise
+        let half = width / 2.0 * dx.hypot(dy) / major;
+        let (x0, x1) = (from.0.min(to.0) - half, from.0.max(to.0) + half);
+        let (y0, y1) = (from.1.min(to.1) - half, from.1.max(to.1) + half);
+        for py in pixel(y0)..pixel(y1) {
+            for px in pixel(x0)..pixel(x1) {
+                let (x, y) = (px as f64 + 0.5, py as f64 + 0.5);
+                let (t, offset) = match dx.abs() >= dy.abs() {
+                    true => {
+                        let t = (x - from.0) / dx;
+                        (t, y - from.1 - dy * t)
+                    }
+                    false => {
+                        let t = (y - from.1) / dy;
+                        (t, x - from.0 - dx * t)
+                    }
+                };
+                if (0.0..1.0).contains(&t) && (-half..half).contains(&offset) {
+                    self.set(px, py, color);
+                }
+            }
+        }
+    }
+
+    /// Fill the pixels whose centers lie within a circle
+    pub fn circle(&mut self, center: (f64, f64), radius: f64, color: Rgb) {
+        let (cx, cy) = center;
+        for py in pixel(cy - radius)..pixel(cy + radius) {
+            for px in pixel(cx - radius)..pixel(cx + radius) {
+                let (x, y) = (px as f64 + 0.5 - cx, py as f64 + 0.5 - cy);
+                if x * x + y * y <= radius * radius {
+                    self.set(px, py, color);
+                }
+            }
+        }
+    }
+
+    /// Draw `text` at `size`, starting at `(x, y)` on its baseline. Vertical
+    /// text runs upwards, turned a quarter turn counterclockwise about
+    /// `(x, y)`. Characters outside of printable ASCII are drawn as '?'.
+    pub fn text(&mut self, (x, y): (f64, f64), text: &str, size: f64, vertical: bool, color: Rgb) {
+        let s = scale(size);
+        for (idx, ch) in text.chars().enumerate() {
+            let glyph = match ch {
+                ' '..='~' => &FONT[ch as usize - ' ' as usize],
+                _ => &FONT['?' as usize - ' ' as usize],
+            };
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                        continue;
+                    }
+                    // Offsets along the text, and down from the baseline
+                    let u = ((GLYPH_WIDTH + 1) * idx + col) as f64 * s;
+                    let v = (row as f64 - GLYPH_HEIGHT as f64) * s;
+                    match vertical {
+                        false => self.fill_rect(x + u, y + v, s, s, color),
+                        true => self.fill_rect(x + v, y - u - s, s, s, color),
+                    }
+                }
+            }
+        }
+    }
+
+    /// The image encoded as an 8-bit RGB PNG
+    pub fn png(&self) -> Vec<u8> {
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, truecolor, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        chunk(&mut out, b"IHDR", &header);
+        // Each row is preceded by its filter type, which is always none
+        let mut rows = Vec::with_capacity((3 * self.width + 1) * self.height);
+        for row in self.pixels.chunks(3 * self.width.max(1)).take(self.height) {
+            rows.push(0);
+            rows.extend_from_slice(row);
+        }
+        chunk(&mut out, b"IDAT", &zlib(&rows));
+        chunk(&mut out, b"IEND", &[]);
+        out
+    }
+}
+
+/// Append a PNG chunk, with its length and checksum
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 of `data`, as used by PNG and gzip
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = match c & 1 {
+                1 => 0xedb8_8320 ^ (c >> 1),
+                _ => c >> 1,
+            };
+        }
+        *entry = c;
+    }
+    !data.iter().fold(!0u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Adler-32 of `data`, as used by zlib
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums are reduced often enough that they cannot overflow
+    for block in data.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// `data` in a zlib stream
+fn zlib(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, and no preset dictionary
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Smallest length of each length code, from 257, and its extra bits
+const LENGTHS: [(usize, u32); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+/// Smallest distance of each distance code, and its extra bits
+const DISTANCES: [(usize, u32); 30] = [
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 1),
+    (7, 1),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 4),
+    (49, 4),
+    (65, 5),
+    (97, 5),
+    (129, 6),
+    (193, 6),
+    (257, 7),
+    (385, 7),
+    (513, 8),
+    (769, 8),
+    (1025, 9),
+    (1537, 9),
+    (2049, 10),
+    (3073, 10),
+    (4097, 11),
+    (6145, 11),
+    (8193, 12),
+    (12289, 12),
+    (16385, 13),
+    (24577, 13),
+];
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+/// Earlier positions tried for each match, which bounds the time spent on
+/// data that repeats
+const MAX_CHAIN: usize = 64;
+
+/// Bits packed into bytes, least significant first
+#[derive(Default)]
+struct Bits {
+    out: Vec<u8>,
+    acc: u32,
+    len: u32,
+}
+
+impl Bits {
+    fn put(&mut self, value: u32, len: u32) {
+        self.acc |= value << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit
+    fn code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len)
+    }
+
+    /// A literal byte, a length code, or the end of the block, with the
+    /// fixed literal/length codes
+    fn symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    /// A copy of `len` bytes from `dist` bytes back
+    fn copy(&mut self, len: usize, dist: usize) {
+        let idx = LENGTHS.iter().rposition(|&(base, _)| base <= len).unwrap();
+        let (base, extra) = LENGTHS[idx];
+        self.symbol(257 + idx as u32);
+        self.put((len - base) as u32, extra);
+        let idx = DISTANCES
+            .iter()
+            .rposition(|&(base, _)| base <= dist)
+            .unwrap();
+        let (base, extra) = DISTANCES[idx];
+        self.code(idx as u32, 5);
+        self.put((dist - base) as u32, extra);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let h = (data[0] as u32) << 10 ^ (data[1] as u32) << 5 ^ data[2] as u32;
+    (h.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Record that the bytes at `pos` can be matched
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(&data[pos..]);
+        prev[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// `data` compressed as a single deflate block with fixed Huffman codes
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = Bits::default();
+    // Final block, fixed codes
+    bits.put(1, 1);
+    bits.put(1, 2);
+    // Latest position of each hash, and the previous position of the same
+    // hash before each position
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut len, mut dist) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let limit = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(&data[pos..])];
+            let mut tries = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW && tries < MAX_CHAIN {
+                let n = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + limit])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if n > len {
+                    len = n;
+                    dist = pos - candidate;
+                    if n == limit {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                tries += 1;
+            }
+        }
+        if len >= MIN_MATCH {
+            bits.copy(len, dist);
+            for p in pos..pos + len {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += len;
+        } else {
+            bits.symbol(data[pos] as u32);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    bits.symbol(256);
+    bits.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    /// Bits of a deflate stream, least significant first
+    struct Reader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl Reader<'_> {
+        fn bits(&mut self, len: u32) -> usize {
+            (0..len).fold(0, |value, i| {
+                let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+                self.pos += 1;
+                value | (bit as usize) << i
+            })
+        }
+
+        /// A Huffman code, which starts from its most significant bit
+        fn code(&mut self, len: u32) -> usize {
+            (0..len).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn symbol(&mut self) -> usize {
+            let code = self.code(7);
+            if code < 0x18 {
+                return 256 + code;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => 280 + code - 0xc0,
+                _ => 144 + (code << 1 | self.bits(1)) - 0x190,
+            }
+        }
+    }
+
+    /// Decompress a single deflate block with fixed codes
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut bits = Reader { data, pos: 0 };
+        assert_eq!(bits.bits(1), 1, "final block");
+        assert_eq!(bits.bits(2), 1, "fixed codes");
+        let mut out = Vec::new();
+        loop {
+            match bits.symbol() {
+                byte @ 0..=255 => out.push(byte as u8),
+                256 => return out,
+                symbol => {
+                    let (base, extra) = LENGTHS[symbol - 257];
+                    let len = base + bits.bits(extra);
+                    let (base, extra) = DISTANCES[bits.code(5)];
+                    let dist = base + bits.bits(extra);
+                    for _ in 0..len {
+                        out.push(out[out.len() - dist]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&[0xff; 100_000]), {
+            let a = (1 + 255 * 100_000u64) % 65521;
+            let b = (100_000 + 255 * 100_000 * 100_001 / 2) % 65521;
+            (b << 16 | a) as u32
+        });
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let mut noise = Vec::new();
+        let mut x = 1u32;
+        for _ in 0..5000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            noise.push((x >> 16) as u8);
+        }
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabc".to_vec(),
+            vec![7; 100_000],
+            noise.clone(),
+            noise.repeat(4),
+            (0..=255).collect(),
+        ];
+        for input in &inputs {
+            let compressed = deflate(input);
+            assert_eq!(&inflate(&compressed), input, "{} bytes", input.len());
+        }
+        // Runs of the same color are stored as copies
+        assert!(deflate(&[7; 100_000]).len() < 1000);
+        assert!(deflate(&noise.repeat(4)).len() < noise.len() * 2);
+
+        let stream = zlib(b"hello hello hello");
+        assert_eq!(&stream[..2], &[0x78, 0x01]);
+        assert_eq!((0x78 << 8 | 0x01) % 31, 0);
+        let (body, sum) = stream[2..].split_at(stream.len() - 6);
+        assert_eq!(inflate(body), b"hello hello hello");
+        assert_eq!(sum, adler32(b"hello hello hello").to_be_bytes());
+    }
+
+    #[test]
+    fn encode_png() {
+        let mut canvas = Canvas::new(3, 2, [255, 255, 255]);
+        canvas.fill_rect(1.0, 0.0, 1.0, 1.0, [255, 0, 0]);
+        canvas.fill_rect(0.0, 1.0, 3.0, 1.0, [0, 0, 255]);
+        let png = canvas.png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            chunks.push((&body[..4], &body[4..]));
+            pos += len + 12;
+        }
+        assert_eq!(pos, png.len());
+        let kinds = chunks.iter().map(|(kind, _)| *kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        let idat = chunks[1].1;
+        assert_eq!(
+            inflate(&idat[2..idat.len() - 4]),
+            [
+                0, 255, 255, 255, 255, 0, 0, 255, 255, 255, //
+                0, 0, 0, 255, 0, 0, 255, 0, 0, 255
+            ]
+        );
+        assert!(chunks[2].1.is_empty());
+    }
+
+    #[test]
+    fn shapes() {
+        let black = [0, 0, 0];
+        let inked = |canvas: &Canvas| {
+            let mut pixels = Vec::new();
+            for y in 0..canvas.height {
+                for x in 0..canvas.width {
+                    if canvas.pixel(x, y) == black {
+                        pixels.push((x, y));
+                    }
+                }
+            }
+            pixels
+        };
+
+        // Pixels whose centers are covered, so a line on a pixel boundary
+        // is drawn on one side of it
+        let mut canvas = Canvas::new(8, 8, [255; 3]);
+        canvas.fill_rect(1.4, 1.6, 2.0, 1.0, black);
+        assert_eq!(inked(&canvas), [(1, 2), (2, 2)]);
+
+        let mut canvas = Canvas::new(8, 8, [255; 3]);
+        canvas.line((1.0, 3.0), (4.0, 3.0), 1.0, black);
+        assert_eq!(inked(&canvas), [(1, 2), (2, 2), (3, 2)]);
+        let mut canvas = Canvas::new(8, 8, [255; 3]);
+        canvas.line((2.0, 3.0), (2.0, 1.0), 2.0, black);
+        assert_eq!(inked(&canvas), [(1, 1), (2, 1), (1, 2), (2, 2)]);
+        // A diagonal line has one pixel in each column, or three when wide
+        // enough across to cover them
+        let mut canvas = Canvas::new(8, 8, [255; 3]);
+        canvas.line((0.0, 0.0), (8.0, 8.0), 1.0, black);
+        assert_eq!(inked(&canvas), (0..8).map(|i| (i, i)).collect::<Vec<_>>());
+        canvas.line((0.0, 0.0), (8.0, 8.0), 1.5, black);
+        assert_eq!(inked(&canvas).len(), 8 + 7 * 2);
+        // A line without length is not drawn
+        canvas.line((6.0, 1.0), (6.0, 1.0), 1.0, black);
+        assert_eq!(canvas.pixel(5, 0), [255; 3]);
+
+        let mut canvas = Canvas::new(8, 8, [255; 3]);
+        canvas.stroke_rect(1.0, 1.0, 3.0, 3.0, black);
+        assert_eq!(inked(&canvas).len(), 12);
+        assert_eq!(canvas.pixel(0, 0), black);
+        assert_ne!(canvas.pixel(1, 1), black);
+
+        // Drawing off the edge of the image is clipped
+        let mut canvas = Canvas::new(8, 8, [255; 3]);
+        canvas.circle((0.0, 0.0), 1.5, black);
+        assert_eq!(inked(&canvas), [(0, 0)]);
+        canvas.circle((5.0, 5.0), 1.0, black);
+        assert_eq!(inked(&canvas).len(), 5);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(text_width("", 11.0), 0.0);
+        assert_eq!(text_width("1", 11.0), 5.0);
+        assert_eq!(text_width("10", 11.0), 11.0);
+        assert_eq!(text_width("10", 14.0), 22.0);
+
+        let black = [0, 0, 0];
+        // The stem of an 'I' is in its middle column
+        let mut canvas = Canvas::new(10, 12, [255; 3]);
+        canvas.text((2.0, 9.0), "I", 11.0, false, black);
+        for y in 2..9 {
+            assert_eq!(canvas.pixel(4, y), black);
+        }
+        assert_eq!(canvas.pixel(4, 9), [255; 3]);
+        // The tail of a 'g' is below the baseline
+        let mut canvas = Canvas::new(10, 12, [255; 3]);
+        canvas.text((2.0, 9.0), "g", 11.0, false, black);
+        assert_eq!(canvas.pixel(6, 9), black);
+        assert_eq!(canvas.pixel(3, 10), black);
+
+        // Vertical text runs upwards from its start, with its tops to the left
+        let mut canvas = Canvas::new(12, 10, [255; 3]);
+        canvas.text((9.0, 8.0), "I", 11.0, true, black);
+        for x in 2..9 {
+            assert_eq!(canvas.pixel(x, 5), black);
+        }
+        assert_eq!(canvas.pixel(9, 5), [255; 3]);
+        // Characters without a glyph are drawn as '?'
+        let mut drawn = Canvas::new(10, 12, [255; 3]);
+        drawn.text((2.0, 9.0), "é", 11.0, false, black);
+        let mut expected = Canvas::new(10, 12, [255; 3]);
+        expected.text((2.0, 9.0), "?", 11.0, false, black);
+        assert_eq!(drawn.pixels, expected.pixels);
+    }
+}
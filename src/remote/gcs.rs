@@ -0,0 +1,435 @@
+//! Google Cloud Storage, over the JSON API
+//!
+//! Access tokens are found as Google's client libraries find them: the
+//! `GOOGLE_OAUTH_ACCESS_TOKEN` environment variable, then the service
+//! account or user credentials in `GOOGLE_APPLICATION_CREDENTIALS` or the
+//! gcloud application default credentials, and then the metadata server of
+//! a Compute Engine instance. Without any, requests are sent anonymously,
+//! which is enough for public objects. `STORAGE_EMULATOR_HOST` selects an
+//! emulator, which is used without authentication.
+use super::http::{self, Body, Response, Url};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const API: &str = "https://storage.googleapis.com";
+
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// Metadata server of a Compute Engine instance, which is only reachable
+/// there. The address is used rather than metadata.google.internal, which
+/// is slow to fail to resolve elsewhere.
+const METADATA: &str =
+    "http://169.254.169.254/computeMetadata/v1/instance/service-accounts/default/token";
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Tokens are renewed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(300);
+
+/// Contents of a credentials file
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Key {
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        token_uri: Option<String>,
+    },
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+}
+
+/// Where access tokens come from
+enum Source {
+    Anonymous,
+    Token(String),
+    Key(Key),
+    Metadata,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+pub struct Config {
+    base: String,
+    source: Source,
+    /// Current access token, if there is one, and when it expires
+    token: Mutex<Option<(Option<String>, Instant)>>,
+}
+
+impl Config {
+    /// Configuration from the environment and gcloud credentials
+    pub fn from_env() -> std::io::Result<Config> {
+        Config::from_vars(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> std::io::Result<Config> {
+        if let Some(host) = var("STORAGE_EMULATOR_HOST") {
+            let base = match host.contains("://") {
+                true => host,
+                false => format!("http://{}", host),
+            };
+            return Ok(Config::new(base.trim_end_matches('/'), Source::Anonymous));
+        }
+        if let Some(token) =
+            var("GOOGLE_OAUTH_ACCESS_TOKEN").or_else(|| var("CLOUDSDK_AUTH_ACCESS_TOKEN"))
+        {
+            return Ok(Config::new(API, Source::Token(token)));
+        }
+        let (path, explicit) = match var("GOOGLE_APPLICATION_CREDENTIALS") {
+            Some(path) => (Some(PathBuf::from(path)), true),
+            None => (
+                var("HOME").map(|home| {
+                    Path::new(&home).join(".config/gcloud/application_default_credentials.json")
+                }),
+                false,
+            ),
+        };
+        let text = match path.as_ref().map(std::fs::read_to_string) {
+            Some(Ok(text)) => Some(text),
+            Some(Err(e)) if explicit || e.kind() != ErrorKind::NotFound => {
+                let path = path.unwrap_or_default();
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "unable to read Google credentials {}: {}",
+                        path.display(),
+                        e
+                    ),
+                ));
+            }
+            _ => None,
+        };
+        let source = match text {
+            Some(text) => Source::Key(serde_json::from_str(&text).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported Google credentials: {}", e),
+                )
+            })?),
+            None => Source::Metadata,
+        };
+        Ok(Config::new(API, source))
+    }
+
+    fn new(base: &str, source: Source) -> Config {
+        Config {
+            base: base.to_string(),
+            source,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Access token to send, if any, fetching a new one when needed
+    fn token(&self) -> std::io::Result<Option<String>> {
+        let key = match &self.source {
+            Source::Anonymous => return Ok(None),
+            Source::Token(token) => return Ok(Some(token.clone())),
+            Source::Key(key) => Some(key),
+            Source::Metadata => None,
+        };
+        let mut current = self.token.lock().unwrap();
+        if let Some((token, expiry)) = &*current {
+            if Instant::now() + EXPIRY_MARGIN < *expiry {
+                return Ok(token.clone());
+            }
+        }
+
+        let resp = match key {
+            Some(key) => {
+                let (url, body) = key.request()?;
+                let form = [(
+                    "Content-Type".to_string(),
+                    "application/x-www-form-urlencoded".to_string(),
+                )];
+                http::request(
+                    "POST",
+                    &Url::parse(&url)?,
+                    &form,
+                    Body::Bytes(body.as_bytes()),
+                )?
+            }
+            None => {
+                let flavor = [("Metadata-Flavor".to_string(), "Google".to_string())];
+                let url = Url::parse(METADATA)?;
+                match http::request_with_timeout(
+                    "GET",
+                    &url,
+                    &flavor,
+                    Body::Empty,
+                    METADATA_TIMEOUT,
+                ) {
+                    Ok(resp) => resp,
+                    // Not being on Compute Engine is the usual reason, so
+                    // requests are sent anonymously from then on
+                    Err(_) => {
+                        *current = Some((None, Instant::now() + Duration::from_secs(86400 * 365)));
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+        if !resp.is_success() {
+            let status = resp.status;
+            let text = resp.text().unwrap_or_default();
+            return Err(http::status_error(
+                status,
+                format!(
+                    "unable to get a Google access token: HTTP {}: {}",
+                    status,
+                    text.trim()
+                ),
+            ));
+        }
+        let token: Token = serde_json::from_str(&resp.text()?).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid Google access token: {}", e),
+            )
+        })?;
+        let expiry = Instant::now() + Duration::from_secs(token.expires_in.unwrap_or(3600));
+        *current = Some((Some(token.access_token.clone()), expiry));
+        Ok(Some(token.access_token))
+    }
+
+    fn headers(&self) -> std::io::Result<Vec<(String, String)>> {
+        Ok(self
+            .token()?
+            .map(|token| ("Authorization".to_string(), format!("Bearer {}", token)))
+            .into_iter()
+            .collect())
+    }
+
+    /// Error for an unsuccessful response
+    fn error(&self, resp: Response) -> Error {
+        #[derive(Deserialize)]
+        struct Body {
+            error: Message,
+        }
+        #[derive(Deserialize)]
+        struct Message {
+            message: String,
+        }
+        let status = resp.status;
+        let text = resp.text().unwrap_or_default();
+        let mut msg = match serde_json::from_str::<Body>(&text) {
+            Ok(body) => body.error.message,
+            Err(_) => format!("HTTP {}", status),
+        };
+        let anonymous = matches!(&*self.token.lock().unwrap(), Some((None, _)));
+        if anonymous && matches!(status, 401 | 403) {
+            msg.push_str(" (no Google credentials were found)");
+        }
+        http::status_error(status, msg)
+    }
+
+    /// Start downloading `object`, returning the response to read it from
+    pub fn get(&self, bucket: &str, object: &str) -> std::io::Result<Response> {
+        let url = Url::parse(&format!(
+            "{}/storage/v1/b/{}/o/{}?alt=media",
+            self.base,
+            http::encode(bucket, false),
+            http::encode(object, false)
+        ))?;
+        let resp = http::request("GET", &url, &self.headers()?, Body::Empty)?;
+        match resp.is_success() {
+            true => Ok(resp),
+            false => Err(self.error(resp)),
+        }
+    }
+
+    /// Upload the file at `path` as `object`
+    pub fn put(&self, bucket: &str, object: &str, path: &Path) -> std::io::Result<()> {
+        let url = Url::parse(&format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            self.base,
+            http::encode(bucket, false),
+            http::encode(object, false)
+        ))?;
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut headers = self.headers()?;
+        headers.push((
+            "Content-Type".to_string(),
+            "application/octet-stream".to_string(),
+        ));
+        let resp = http::request("POST", &url, &headers, Body::Reader(&mut file, len))?;
+        match resp.is_success() {
+            true => Ok(()),
+            false => Err(self.error(resp)),
+        }
+    }
+}
+
+impl Key {
+    /// URL and form body of a request for an access token
+    fn request(&self) -> std::io::Result<(String, String)> {
+        match self {
+            Key::ServiceAccount {
+                client_email,
+                private_key,
+                token_uri,
+            } => {
+                let token_uri = token_uri
+                    .clone()
+                    .unwrap_or_else(|| "https://oauth2.googleapis.com/token".into());
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let claims = serde_json::json!({
+                    "iss": client_email,
+                    "scope": SCOPE,
+                    "aud": token_uri,
+                    "iat": now,
+                    "exp": now + 3600,
+                });
+                let signed = format!(
+                    "{}.{}",
+                    base64url(br#"{"alg":"RS256","typ":"JWT"}"#),
+                    base64url(claims.to_string().as_bytes())
+                );
+                let signature = sign(private_key, signed.as_bytes())?;
+                let assertion = format!("{}.{}", signed, base64url(&signature));
+                Ok((
+                    token_uri,
+                    format!(
+                        "grant_type={}&assertion={}",
+                        http::encode("urn:ietf:params:oauth:grant-type:jwt-bearer", false),
+                        assertion
+                    ),
+                ))
+            }
+            Key::AuthorizedUser {
+                client_id,
+                client_secret,
+                refresh_token,
+            } => Ok((
+                "https://oauth2.googleapis.com/token".into(),
+                format!(
+                    "grant_type=refresh_token&client_id={}&client_secret={}&refresh_token={}",
+                    http::encode(client_id, false),
+                    http::encode(client_secret, false),
+                    http::encode(refresh_token, false)
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn sign(pem: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    super::tls::sign_rs256(pem, data)
+}
+
+#[cfg(not(unix))]
+fn sign(_: &str, _: &[u8]) -> std::io::Result<Vec<u8>> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "service account credentials are only supported on Unix systems",
+    ))
+}
+
+/// Unpadded URL-safe base64, as used in a JWT
+fn base64url(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut out = String::with_capacity((data.len() * 4).div_ceil(3));
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn with(vars: &[(&str, &str)]) -> std::io::Result<Config> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64url(b""), "");
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(b"foob"), "Zm9vYg");
+        assert_eq!(base64url(&[0xfb, 0xff, 0xbf]), "-_-_");
+        assert_eq!(
+            base64url(br#"{"alg":"RS256","typ":"JWT"}"#),
+            "eyJhbGciOiJSUzI1NiIsInR5cCI6IkpXVCJ9"
+        );
+    }
+
+    #[test]
+    fn configuration() {
+        let config = with(&[("STORAGE_EMULATOR_HOST", "localhost:4443")]).unwrap();
+        assert_eq!(config.base, "http://localhost:4443");
+        assert_eq!(config.token().unwrap(), None);
+        let config = with(&[("GOOGLE_OAUTH_ACCESS_TOKEN", "ya29.token")]).unwrap();
+        assert_eq!(config.base, API);
+        assert_eq!(
+            config.headers().unwrap(),
+            [("Authorization".to_string(), "Bearer ya29.token".to_string())]
+        );
+
+        let dir = crate::fixture::dir(
+            "gcs-config",
+            &[
+                (
+                    "user.json",
+                    r#"{"type": "authorized_user", "client_id": "id", "client_secret": "s/e",
+                        "refresh_token": "1//refresh"}"#,
+                ),
+                ("external.json", r#"{"type": "external_account"}"#),
+            ],
+        );
+        let path = |name: &str| dir.join(name).display().to_string();
+        let config = with(&[("GOOGLE_APPLICATION_CREDENTIALS", &path("user.json"))]).unwrap();
+        match &config.source {
+            Source::Key(key) => assert_eq!(
+                key.request().unwrap(),
+                (
+                    "https://oauth2.googleapis.com/token".to_string(),
+                    "grant_type=refresh_token&client_id=id&client_secret=s%2Fe&refresh_token=1%2F%2Frefresh".to_string()
+                )
+            ),
+            _ => panic!("expected user credentials"),
+        }
+        let err = with(&[("GOOGLE_APPLICATION_CREDENTIALS", &path("external.json"))])
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("unsupported Google credentials"));
+        let err = with(&[("GOOGLE_APPLICATION_CREDENTIALS", &path("missing.json"))])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        // Without a credentials file the metadata server is tried
+        let config = with(&[("HOME", &path("nowhere"))]).unwrap();
+        assert!(matches!(config.source, Source::Metadata));
+    }
+}
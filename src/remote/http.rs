@@ -0,0 +1,536 @@
+//! Minimal HTTP/1.1 client for object storage requests
+//!
+//! Each request uses its own connection, closed once the response has been
+//! read. Response bodies are streamed, either delimited by Content-Length,
+//! whose length is checked, or chunked.
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Longest to wait for a connection to be established
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest a read or write may stall
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Most bytes of an error response body that are kept
+const MAX_ERROR_BODY: u64 = 64 << 10;
+
+/// An http:// or https:// URL
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, beginning with a slash
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> std::io::Result<Url> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid URL {}", url));
+        let (tls, rest) = match url.split_once("://") {
+            Some(("https", rest)) => (true, rest),
+            Some(("http", rest)) => (false, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !host.ends_with(']') || authority.starts_with('[') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Url {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Value of the Host header
+    pub fn authority(&self) -> String {
+        match (self.tls, self.port) {
+            (true, 443) | (false, 80) => self.host.clone(),
+            (_, port) => format!("{}:{}", self.host, port),
+        }
+    }
+
+    /// Address to connect to, without the brackets of an IPv6 literal
+    fn address(&self) -> (&str, u16) {
+        (
+            self.host.trim_start_matches('[').trim_end_matches(']'),
+            self.port,
+        )
+    }
+}
+
+/// Percent-encode `s`, leaving unreserved characters, and slashes if
+/// `slash` is set
+pub fn encode(s: &str, slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+enum Stream {
+    Plain(TcpStream),
+    #[cfg(unix)]
+    Tls(Box<super::tls::TlsStream>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+fn connect(url: &Url, timeout: Duration) -> std::io::Result<Stream> {
+    let mut last = None;
+    for addr in url.address().to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(tcp) => {
+                tcp.set_read_timeout(Some(TIMEOUT))?;
+                tcp.set_write_timeout(Some(TIMEOUT))?;
+                if !url.tls {
+                    return Ok(Stream::Plain(tcp));
+                }
+                #[cfg(unix)]
+                return super::tls::TlsStream::connect(tcp, url.address().0)
+                    .map(|s| Stream::Tls(Box::new(s)));
+                #[cfg(not(unix))]
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "https:// object storage is only supported on Unix systems",
+                ));
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{} has no addresses", url.host),
+        )
+    }))
+}
+
+/// Body of a request
+pub enum Body<'a> {
+    Empty,
+    Bytes(&'a [u8]),
+    /// `len` bytes read from a reader
+    Reader(&'a mut dyn Read, u64),
+}
+
+/// Response whose body has not been read yet
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    body: Box<dyn Read + Send>,
+}
+
+impl Response {
+    /// Value of the header `name`, which is matched ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Copy the body into `out`, returning the number of bytes copied
+    pub fn copy_to(&mut self, out: &mut dyn Write) -> std::io::Result<u64> {
+        std::io::copy(&mut self.body, out)
+    }
+
+    /// The body as text, keeping at most the first 64 KiB of an error
+    pub fn text(mut self) -> std::io::Result<String> {
+        let mut buf = Vec::new();
+        if self.is_success() {
+            self.body.read_to_end(&mut buf)?;
+        } else {
+            self.body.take(MAX_ERROR_BODY).read_to_end(&mut buf)?;
+        }
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+/// Marks an error response that may succeed if the request is repeated
+#[derive(Debug)]
+struct Transient(String);
+
+impl std::fmt::Display for Transient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Error for a response with an unsuccessful `status`
+pub fn status_error(status: u16, msg: String) -> Error {
+    match status {
+        404 => Error::new(ErrorKind::NotFound, msg),
+        401 | 403 => Error::new(ErrorKind::PermissionDenied, msg),
+        408 | 429 | 500..=599 => Error::other(Transient(msg)),
+        _ => Error::other(msg),
+    }
+}
+
+/// Whether repeating the request that failed with `err` may succeed
+pub fn is_transient(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe
+        | ErrorKind::TimedOut
+        | ErrorKind::WouldBlock
+        | ErrorKind::UnexpectedEof
+        | ErrorKind::Interrupted => true,
+        _ => err.get_ref().is_some_and(|e| e.is::<Transient>()),
+    }
+}
+
+/// Reads exactly `left` bytes, failing if the stream ends first
+struct Sized<R> {
+    inner: R,
+    left: u64,
+}
+
+impl<R: Read> Read for Sized<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.left == 0 {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.left.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("response ended {} bytes early", self.left),
+            ));
+        }
+        self.left -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads a chunked transfer encoding body
+struct Chunked<R> {
+    inner: R,
+    left: u64,
+    done: bool,
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> std::io::Result<String> {
+    let mut line = String::new();
+    // A bounded line, so that a malformed response cannot grow it forever
+    reader.take(8 << 10).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "malformed HTTP response",
+        ));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+impl<R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.left == 0 {
+            let line = read_line(&mut self.inner)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            self.left = u64::from_str_radix(size, 16)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "malformed chunked response"))?;
+            if self.left == 0 {
+                // Skip any trailers
+                while !read_line(&mut self.inner)?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let max = buf.len().min(self.left.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "chunked response ended early",
+            ));
+        }
+        self.left -= n as u64;
+        if self.left == 0 {
+            read_line(&mut self.inner)?;
+        }
+        Ok(n)
+    }
+}
+
+/// Send a request, returning the response once its headers have been read
+pub fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: Body,
+) -> std::io::Result<Response> {
+    request_with_timeout(method, url, headers, body, CONNECT_TIMEOUT)
+}
+
+/// As `request`, waiting at most `timeout` for the connection
+pub fn request_with_timeout(
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: Body,
+    timeout: Duration,
+) -> std::io::Result<Response> {
+    let mut stream = std::io::BufWriter::new(connect(url, timeout)?);
+    let len = match &body {
+        Body::Empty => 0,
+        Body::Bytes(b) => b.len() as u64,
+        Body::Reader(_, len) => *len,
+    };
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: census2csv/{}\r\n",
+        method,
+        url.path,
+        url.authority(),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    if len > 0 || !matches!(method, "GET" | "HEAD") {
+        write!(stream, "Content-Length: {}\r\n", len)?;
+    }
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    stream.write_all(b"\r\n")?;
+    match body {
+        Body::Empty => {}
+        Body::Bytes(b) => stream.write_all(b)?,
+        Body::Reader(reader, len) => {
+            let sent = std::io::copy(&mut reader.take(len), &mut stream)?;
+            if sent != len {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("request body ended after {} of {} bytes", sent, len),
+                ));
+            }
+        }
+    }
+    stream.flush()?;
+    let stream = stream.into_inner().map_err(|e| e.into_error())?;
+    read_response(BufReader::new(stream), method == "HEAD")
+}
+
+fn read_response<R: BufRead + Send + 'static>(
+    mut reader: R,
+    head: bool,
+) -> std::io::Result<Response> {
+    let malformed = || Error::new(ErrorKind::InvalidData, "malformed HTTP response");
+    let mut status;
+    let mut headers;
+    loop {
+        let line = read_line(&mut reader)?;
+        let mut parts = line.splitn(3, ' ');
+        if !parts.next().unwrap_or("").starts_with("HTTP/1.") {
+            return Err(malformed());
+        }
+        status = parts
+            .next()
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(malformed)?;
+        headers = Vec::new();
+        loop {
+            let line = read_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+            if headers.len() > 256 {
+                return Err(malformed());
+            }
+        }
+        // Skip interim responses, such as 100 Continue
+        if !(100..200).contains(&status) {
+            break;
+        }
+    }
+    let mut response = Response {
+        status,
+        headers,
+        body: Box::new(std::io::empty()),
+    };
+    if head || status == 204 || status == 304 {
+        return Ok(response);
+    }
+    let chunked = response
+        .header("transfer-encoding")
+        .is_some_and(|t| t.to_ascii_lowercase().contains("chunked"));
+    response.body = if chunked {
+        Box::new(Chunked {
+            inner: reader,
+            left: 0,
+            done: false,
+        })
+    } else {
+        match response.header("content-length") {
+            Some(len) => Box::new(Sized {
+                inner: reader,
+                left: len.parse().map_err(|_| malformed())?,
+            }),
+            None => Box::new(reader),
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(text: &str) -> std::io::Result<(u16, String)> {
+        let reader = BufReader::new(std::io::Cursor::new(text.as_bytes().to_vec()));
+        let mut resp = read_response(reader, false)?;
+        let mut body = Vec::new();
+        resp.copy_to(&mut body)?;
+        Ok((resp.status, String::from_utf8(body).unwrap()))
+    }
+
+    #[test]
+    fn urls() {
+        let url = Url::parse("https://bucket.s3.amazonaws.com/a/b%20c?x=1").unwrap();
+        assert_eq!(
+            url,
+            Url {
+                tls: true,
+                host: "bucket.s3.amazonaws.com".into(),
+                port: 443,
+                path: "/a/b%20c?x=1".into(),
+            }
+        );
+        assert_eq!(url.authority(), "bucket.s3.amazonaws.com");
+        let url = Url::parse("http://localhost:9000").unwrap();
+        assert_eq!((url.port, url.path.as_str()), (9000, "/"));
+        assert_eq!(url.authority(), "localhost:9000");
+        let url = Url::parse("http://[::1]:9000/x").unwrap();
+        assert_eq!(url.address(), ("::1", 9000));
+        assert!(Url::parse("ftp://host/x").is_err());
+        assert!(Url::parse("http://host:port/x").is_err());
+        assert!(Url::parse("http:///x").is_err());
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(encode("runs/a b+c~.txt", true), "runs/a%20b%2Bc~.txt");
+        assert_eq!(encode("runs/a", false), "runs%2Fa");
+    }
+
+    #[test]
+    fn response_bodies() {
+        assert_eq!(
+            response("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, extra").unwrap(),
+            (200, "hello".into())
+        );
+        assert_eq!(
+            response(
+                "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 404 Not Found\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r\n2\r\nde\r\n0\r\nTrailer: 1\r\n\r\n"
+            )
+            .unwrap(),
+            (404, "abcde".into())
+        );
+        assert_eq!(
+            response("HTTP/1.0 200 OK\r\n\r\nto the end").unwrap(),
+            (200, "to the end".into())
+        );
+        let err = response("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err =
+            response("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(response("SSH-2.0\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn request_over_tcp() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            let mut body = [0u8; 4];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 201 Created\r\nETag: \"x\"\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+            (head, body)
+        });
+        let url = Url::parse(&format!("http://127.0.0.1:{}/b/k?uploads", port)).unwrap();
+        let headers = [("X-Test".to_string(), "1".to_string())];
+        let resp = request(
+            "PUT",
+            &url,
+            &headers,
+            Body::Reader(&mut &b"data, and more"[..], 4),
+        )
+        .unwrap();
+        assert_eq!(resp.status, 201);
+        assert_eq!(resp.header("etag"), Some("\"x\""));
+        assert_eq!(resp.text().unwrap(), "ok");
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("PUT /b/k?uploads HTTP/1.1\r\n"));
+        assert!(head.contains(&format!("\r\nHost: 127.0.0.1:{}\r\n", port)));
+        assert!(head.contains("\r\nContent-Length: 4\r\nX-Test: 1\r\n"));
+        assert_eq!(&body, b"data");
+    }
+}
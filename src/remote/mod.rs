@@ -0,0 +1,511 @@
+//! Read inputs from, and write outputs to, object storage
+//!
+//! `s3://bucket/key` and `gs://bucket/key` URIs are staged through a local
+//! temporary directory, talking to S3 and Google Cloud Storage directly
+//! over HTTPS with the credentials that the AWS and Google Cloud tools
+//! would use (see the `s3` and `gcs` modules). HTTPS uses the system
+//! OpenSSL, which is loaded when it is first needed.
+//! Each remote input is downloaded into its own directory; once processing
+//! has finished, every file written alongside it (outputs, sidecars, and
+//! split parts) is uploaded next to the original object.
+mod digest;
+mod gcs;
+mod http;
+mod s3;
+#[cfg(unix)]
+mod tls;
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Attempts at each transfer, which is repeated after transient failures
+const ATTEMPTS: u32 = 3;
+
+/// Delay before the second attempt, which doubles for each after that
+const BACKOFF: Duration = Duration::from_millis(200);
+/// Whether `path` refers to an object in S3 or Google Cloud Storage
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("s3://") || path.starts_with("gs://")
+}
+
+/// Split a URI into the prefix of its parent "directory", including the
+/// trailing slash, and the object name
+fn split(uri: &str) -> std::io::Result<(&str, &str)> {
+    let bucket = uri.find("://").map(|i| i + 3).unwrap_or(0);
+    match uri[bucket..].rfind('/') {
+        Some(idx) if idx + bucket + 1 < uri.len() => {
+            Ok((&uri[..=idx + bucket], &uri[idx + bucket + 1..]))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} does not name an object", uri),
+        )),
+    }
+}
+
+/// Bucket and key of an object URI
+fn object(uri: &str) -> std::io::Result<(&str, &str)> {
+    let rest = uri
+        .strip_prefix("s3://")
+        .or_else(|| uri.strip_prefix("gs://"))
+        .unwrap_or(uri);
+    match rest.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok((bucket, key)),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} does not name an object", uri),
+        )),
+    }
+}
+
+/// Call `f` until it succeeds, fails with an error that will not go away
+/// if it is repeated, or has been attempted `ATTEMPTS` times
+fn retry<T>(mut f: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut delay = BACKOFF;
+    for _ in 1..ATTEMPTS {
+        match f() {
+            Err(e) if http::is_transient(&e) => std::thread::sleep(delay),
+            result => return result,
+        }
+        delay *= 2;
+    }
+    f()
+}
+
+/// Configuration for each object store, read from the environment when
+/// the store is first used
+#[derive(Default)]
+struct Client {
+    s3: OnceLock<Result<s3::Config, (ErrorKind, String)>>,
+    gcs: OnceLock<Result<gcs::Config, (ErrorKind, String)>>,
+}
+
+static CLIENT: Client = Client {
+    s3: OnceLock::new(),
+    gcs: OnceLock::new(),
+};
+
+fn configured<T>(
+    cell: &OnceLock<Result<T, (ErrorKind, String)>>,
+    init: fn() -> std::io::Result<T>,
+) -> std::io::Result<&T> {
+    cell.get_or_init(|| init().map_err(|e| (e.kind(), e.to_string())))
+        .as_ref()
+        .map_err(|(kind, msg)| Error::new(*kind, msg.clone()))
+}
+
+impl Client {
+    /// Download `uri` into the writer returned by `open`, which is called
+    /// again should the download need to be repeated
+    fn download<W: Write>(
+        &self,
+        uri: &str,
+        mut open: impl FnMut() -> std::io::Result<W>,
+    ) -> std::io::Result<W> {
+        let (bucket, key) = object(uri)?;
+        retry(|| {
+            let mut resp = match uri.starts_with("s3://") {
+                true => configured(&self.s3, s3::Config::from_env)?.get(bucket, key)?,
+                false => configured(&self.gcs, gcs::Config::from_env)?.get(bucket, key)?,
+            };
+            let mut out = open()?;
+            resp.copy_to(&mut out)?;
+            out.flush()?;
+            Ok(out)
+        })
+        .map_err(|e| Error::new(e.kind(), format!("unable to download {}: {}", uri, e)))
+    }
+
+    /// Upload the file at `path` to `uri`
+    fn upload(&self, path: &Path, uri: &str) -> std::io::Result<()> {
+        let (bucket, key) = object(uri)?;
+        retry(|| match uri.starts_with("s3://") {
+            true => configured(&self.s3, s3::Config::from_env)?.put(bucket, key, path),
+            false => configured(&self.gcs, gcs::Config::from_env)?.put(bucket, key, path),
+        })
+        .map_err(|e| Error::new(e.kind(), format!("unable to upload {}: {}", uri, e)))
+    }
+}
+
+/// Run `task` on each of `items` concurrently, returning the first error
+fn concurrently<T: Sync>(
+    items: &[T],
+    task: impl Fn(&T) -> std::io::Result<()> + Sync,
+) -> std::io::Result<()> {
+    let task = &task;
+    std::thread::scope(|s| {
+        let handles = items
+            .iter()
+            .map(|item| s.spawn(move || task(item)))
+            .collect::<Vec<_>>();
+        // Every thread is joined, so that a panic in one is returned as an
+        // error rather than propagated out of the scope
+        let results = handles
+            .into_iter()
+            .map(|h| {
+                h.join()
+                    .unwrap_or_else(|_| Err(Error::other("transfer thread panicked")))
+            })
+            .collect::<Vec<_>>();
+        results.into_iter().collect()
+    })
+}
+
+/// Random number unique to each call, to name temporary directories
+fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+/// Create a directory, which must not already exist, readable only by the
+/// current user
+fn create_dir(path: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)
+}
+
+/// Read a small remote object, such as a filter file, directly into memory.
+/// Local paths are read as usual.
+pub fn read_to_string(path: &str) -> std::io::Result<String> {
+    if !is_remote(path) {
+        return fs::read_to_string(path);
+    }
+    let bytes = CLIENT.download(path, || Ok(Vec::new()))?;
+    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Temporary directory holding downloaded inputs and pending outputs. The
+/// directory is removed when this is dropped.
+#[derive(Default)]
+pub struct Staging {
+    root: Option<PathBuf>,
+    /// (local directory, remote prefix, downloaded file) for each staged
+    /// input or output
+    dirs: Vec<(PathBuf, String, Option<PathBuf>)>,
+    /// Client to use instead of the one configured from the environment
+    client: Option<Client>,
+}
+
+impl Staging {
+    fn client(&self) -> &Client {
+        self.client.as_ref().unwrap_or(&CLIENT)
+    }
+
+    /// Create a fresh directory for the next staged object. The root is
+    /// named uniquely, so that nothing another process left behind in a
+    /// directory is mistaken for an output of this one.
+    fn dir(&mut self) -> std::io::Result<PathBuf> {
+        let root = match &self.root {
+            Some(root) => root.clone(),
+            None => {
+                let tmp = std::env::temp_dir();
+                let root = loop {
+                    let name = format!("census2csv-{}-{:016x}", std::process::id(), random());
+                    match create_dir(&tmp.join(&name)) {
+                        Ok(()) => break tmp.join(name),
+                        Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                        Err(e) => return Err(e),
+                    }
+                };
+                self.root = Some(root.clone());
+                root
+            }
+        };
+        let dir = root.join(self.dirs.len().to_string());
+        create_dir(&dir)?;
+        Ok(dir)
+    }
+
+    /// Download any remote entries of `paths` concurrently, returning the
+    /// local path to read for each. Local paths are returned unchanged.
+    pub fn fetch(&mut self, paths: &[&str]) -> std::io::Result<Vec<String>> {
+        let mut local = Vec::with_capacity(paths.len());
+        let mut downloads = Vec::new();
+        for path in paths {
+            if !is_remote(path) {
+                local.push(path.to_string());
+                continue;
+            }
+            let (prefix, name) = split(path)?;
+            let file = self.dir()?.join(name);
+            self.dirs.push((
+                file.parent().unwrap().into(),
+                prefix.into(),
+                Some(file.clone()),
+            ));
+            local.push(file.display().to_string());
+            downloads.push((path.to_string(), file));
+        }
+
+        let client = self.client();
+        concurrently(&downloads, |(uri, file)| {
+            client.download(uri, || File::create(file)).map(drop)
+        })?;
+        Ok(local)
+    }
+
+    /// Local path to write to in place of a remote output location. Local
+    /// paths are returned unchanged.
+    pub fn output(&mut self, path: &str) -> std::io::Result<PathBuf> {
+        if !is_remote(path) {
+            return Ok(PathBuf::from(path));
+        }
+        let (prefix, name) = split(path)?;
+        let dir = self.dir()?;
+        self.dirs.push((dir.clone(), prefix.into(), None));
+        Ok(dir.join(name))
+    }
+
+    /// Upload every file written into a staging directory, other than the
+    /// input downloaded there, to the remote prefix it was staged from,
+    /// concurrently
+    pub fn publish(&self) -> std::io::Result<()> {
+        let mut uploads = Vec::new();
+        for (dir, prefix, input) in &self.dirs {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if Some(&path) == input.as_ref() || !path.is_file() {
+                    continue;
+                }
+                let name = path.file_name().unwrap().to_string_lossy();
+                uploads.push((path.clone(), format!("{}{}", prefix, name)));
+            }
+        }
+
+        let client = self.client();
+        concurrently(&uploads, |(file, uri)| client.upload(file, uri))
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Some(root) = &self.root {
+            let _ = fs::remove_dir_all(root);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read};
+    use std::sync::{Arc, Mutex};
+
+    type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Serve GET and PUT requests for objects path-style, as an
+    /// S3-compatible store does, failing the first request for any key
+    /// containing "flaky". Returns the endpoint and the requests made.
+    fn store(objects: Objects) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let log = Arc::new(Mutex::new(Vec::<String>::new()));
+        let requests = log.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split(' ');
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap().to_string());
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((k, v)) => headers.insert(k.to_ascii_lowercase(), v.to_string()),
+                        None => break,
+                    };
+                }
+                assert!(headers["authorization"].starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+                let first = !log.lock().unwrap().iter().any(|r| r.ends_with(&path));
+                log.lock().unwrap().push(format!("{} {}", method, path));
+                let mut objects = objects.lock().unwrap();
+                let (status, body) = match method {
+                    _ if path.contains("flaky") && first => ("500 Internal Server Error", Vec::new()),
+                    "PUT" => {
+                        let len = headers["content-length"].parse().unwrap();
+                        let mut body = vec![0; len];
+                        reader.read_exact(&mut body).unwrap();
+                        assert_eq!(headers["x-amz-content-sha256"], digest::hex(&digest::sha256(&body)));
+                        objects.insert(path, body);
+                        ("200 OK", Vec::new())
+                    }
+                    _ => match objects.get(&path) {
+                        Some(body) => ("200 OK", body.clone()),
+                        None => (
+                            "404 Not Found",
+                            b"<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>"
+                                .to_vec(),
+                        ),
+                    },
+                };
+                write!(
+                    &stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                (&stream).write_all(&body).unwrap();
+            }
+        });
+        (endpoint, requests)
+    }
+
+    fn staging(endpoint: &str) -> Staging {
+        let credentials = s3::Credentials {
+            access_key: "AKID".into(),
+            secret_key: "secret".into(),
+            token: None,
+        };
+        let config = s3::Config::new(Some(endpoint.into()), "us-east-1".into(), Some(credentials));
+        Staging {
+            root: None,
+            dirs: Vec::new(),
+            client: Some(Client {
+                s3: OnceLock::from(Ok(config)),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn uris() {
+        assert!(is_remote("s3://bucket/a.txt") && is_remote("gs://bucket/a.txt"));
+        assert!(!is_remote("a.txt") && !is_remote("file://a.txt"));
+        assert_eq!(
+            split("s3://bucket/runs/a.txt").unwrap(),
+            ("s3://bucket/runs/", "a.txt")
+        );
+        assert_eq!(
+            split("gs://bucket/a.txt").unwrap(),
+            ("gs://bucket/", "a.txt")
+        );
+        for uri in ["s3://bucket", "s3://bucket/", "gs://bucket/runs/"] {
+            assert_eq!(split(uri).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(
+            object("gs://bucket/runs/a.txt").unwrap(),
+            ("bucket", "runs/a.txt")
+        );
+        assert!(object("s3:///a.txt").is_err());
+    }
+
+    #[test]
+    fn retries() {
+        let mut calls = 0;
+        let result = retry(|| {
+            calls += 1;
+            match calls {
+                1 => Err(http::status_error(503, "slow down".into())),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+        let mut calls = 0;
+        let err = retry::<()>(|| {
+            calls += 1;
+            Err(Error::new(ErrorKind::ConnectionReset, "reset"))
+        })
+        .unwrap_err();
+        assert_eq!((calls, err.kind()), (ATTEMPTS, ErrorKind::ConnectionReset));
+        let mut calls = 0;
+        let err = retry::<()>(|| {
+            calls += 1;
+            Err(http::status_error(403, "denied".into()))
+        })
+        .unwrap_err();
+        assert_eq!((calls, err.kind()), (1, ErrorKind::PermissionDenied));
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let err = concurrently(&[1, 2, 3], |&i| match i {
+            2 => panic!("transfer {} failed", i),
+            _ => Ok(()),
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "transfer thread panicked");
+    }
+
+    #[test]
+    fn stage_through_s3() {
+        let objects = Objects::default();
+        objects.lock().unwrap().extend([
+            ("/bucket/runs/a.txt".to_string(), b"census".to_vec()),
+            (
+                "/bucket/runs/flaky%20b.txt".to_string(),
+                b"retried".to_vec(),
+            ),
+        ]);
+        let (endpoint, requests) = store(objects.clone());
+        let mut staging = staging(&endpoint);
+        let local = staging
+            .fetch(&[
+                "local.txt",
+                "s3://bucket/runs/a.txt",
+                "s3://bucket/runs/flaky b.txt",
+            ])
+            .unwrap();
+        assert_eq!(local[0], "local.txt");
+        assert_eq!(fs::read_to_string(&local[1]).unwrap(), "census");
+        assert_eq!(fs::read_to_string(&local[2]).unwrap(), "retried");
+
+        // Each staging area has its own root
+        let root = Path::new(&local[1])
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let name = root.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with(&format!("census2csv-{}-", std::process::id())));
+        let mut other = Staging::default();
+        assert!(!other.dir().unwrap().starts_with(&root));
+
+        fs::write(Path::new(&local[1]).with_extension("csv"), "accession\n").unwrap();
+        let output = staging.output("s3://bucket/out/all.csv").unwrap();
+        fs::write(&output, "combined\n").unwrap();
+        staging.publish().unwrap();
+        {
+            let objects = objects.lock().unwrap();
+            assert_eq!(objects["/bucket/runs/a.csv"], b"accession\n");
+            assert_eq!(objects["/bucket/out/all.csv"], b"combined\n");
+        }
+        let puts = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.starts_with("PUT"))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(puts.len(), 2, "only outputs are uploaded: {:?}", puts);
+
+        let err = staging
+            .fetch(&["s3://bucket/runs/missing.txt"])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "unable to download s3://bucket/runs/missing.txt: NoSuchKey: The specified key does not exist."
+        );
+        drop(staging);
+        assert!(!root.exists());
+    }
+}
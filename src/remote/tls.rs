@@ -0,0 +1,354 @@
+//! TLS connections and RSA signatures using the system OpenSSL
+//!
+//! OpenSSL (1.1.1 or 3) is loaded when it is first needed rather than
+//! linked, so that census2csv builds and runs without it, and only HTTPS
+//! object storage requires it. Certificates are verified against the
+//! system trust store, which `SSL_CERT_FILE` and `SSL_CERT_DIR` override.
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
+use std::os::unix::io::AsRawFd;
+use std::sync::OnceLock;
+
+const LIBSSL: &[&str] = &[
+    "libssl.so.3",
+    "libssl.so.1.1",
+    "libssl.so",
+    "libssl.3.dylib",
+    "libssl.dylib",
+];
+const LIBCRYPTO: &[&str] = &[
+    "libcrypto.so.3",
+    "libcrypto.so.1.1",
+    "libcrypto.so",
+    "libcrypto.3.dylib",
+    "libcrypto.dylib",
+];
+
+const SSL_VERIFY_PEER: c_int = 1;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+const SSL_ERROR_SSL: c_int = 1;
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+type Ptr = *mut c_void;
+
+/// Functions used from libssl and libcrypto
+struct OpenSsl {
+    ctx: Ptr,
+    ssl_new: unsafe extern "C" fn(Ptr) -> Ptr,
+    ssl_free: unsafe extern "C" fn(Ptr),
+    ssl_set_fd: unsafe extern "C" fn(Ptr, c_int) -> c_int,
+    ssl_ctrl: unsafe extern "C" fn(Ptr, c_int, c_long, Ptr) -> c_long,
+    ssl_set1_host: unsafe extern "C" fn(Ptr, *const c_char) -> c_int,
+    ssl_connect: unsafe extern "C" fn(Ptr) -> c_int,
+    ssl_read: unsafe extern "C" fn(Ptr, Ptr, c_int) -> c_int,
+    ssl_write: unsafe extern "C" fn(Ptr, *const c_void, c_int) -> c_int,
+    ssl_shutdown: unsafe extern "C" fn(Ptr) -> c_int,
+    ssl_get_error: unsafe extern "C" fn(Ptr, c_int) -> c_int,
+    ssl_get_verify_result: unsafe extern "C" fn(Ptr) -> c_long,
+    verify_error_string: unsafe extern "C" fn(c_long) -> *const c_char,
+    err_get_error: unsafe extern "C" fn() -> c_ulong,
+    err_error_string_n: unsafe extern "C" fn(c_ulong, *mut c_char, usize),
+    bio_new_mem_buf: unsafe extern "C" fn(*const c_void, c_int) -> Ptr,
+    bio_free: unsafe extern "C" fn(Ptr) -> c_int,
+    pem_read_bio_private_key: unsafe extern "C" fn(Ptr, *mut Ptr, Ptr, Ptr) -> Ptr,
+    evp_pkey_free: unsafe extern "C" fn(Ptr),
+    evp_md_ctx_new: unsafe extern "C" fn() -> Ptr,
+    evp_md_ctx_free: unsafe extern "C" fn(Ptr),
+    evp_sha256: unsafe extern "C" fn() -> Ptr,
+    evp_digest_sign_init: unsafe extern "C" fn(Ptr, *mut Ptr, Ptr, Ptr, Ptr) -> c_int,
+    evp_digest_sign: unsafe extern "C" fn(Ptr, *mut u8, *mut usize, *const u8, usize) -> c_int,
+}
+
+// The context is only used to create connections, which OpenSSL allows from
+// any thread
+unsafe impl Send for OpenSsl {}
+unsafe impl Sync for OpenSsl {}
+
+unsafe fn open(names: &[&str]) -> Result<Ptr, String> {
+    for name in names {
+        let name = CString::new(*name).unwrap();
+        let lib = libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL);
+        if !lib.is_null() {
+            return Ok(lib);
+        }
+    }
+    Err(format!(
+        "{} could not be loaded; OpenSSL 1.1.1 or later is needed for https:// object storage",
+        names[0]
+    ))
+}
+
+unsafe fn symbol(lib: Ptr, name: &str) -> Result<Ptr, String> {
+    let cname = CString::new(name).unwrap();
+    let sym = libc::dlsym(lib, cname.as_ptr());
+    if sym.is_null() {
+        Err(format!("OpenSSL does not provide {}", name))
+    } else {
+        Ok(sym)
+    }
+}
+
+/// The function `name` in `lib`, which must have the signature `F`
+unsafe fn function<F: Copy>(lib: Ptr, name: &str) -> Result<F, String> {
+    assert_eq!(std::mem::size_of::<F>(), std::mem::size_of::<Ptr>());
+    let sym = symbol(lib, name)?;
+    Ok(std::mem::transmute_copy::<Ptr, F>(&sym))
+}
+
+impl OpenSsl {
+    unsafe fn load() -> Result<OpenSsl, String> {
+        let crypto = open(LIBCRYPTO)?;
+        let ssl = open(LIBSSL)?;
+        macro_rules! f {
+            ($lib:expr, $name:literal) => {
+                function($lib, $name)?
+            };
+        }
+        let init: unsafe extern "C" fn(u64, Ptr) -> c_int = f!(ssl, "OPENSSL_init_ssl");
+        let method: unsafe extern "C" fn() -> Ptr = f!(ssl, "TLS_client_method");
+        let ctx_new: unsafe extern "C" fn(Ptr) -> Ptr = f!(ssl, "SSL_CTX_new");
+        let verify_paths: unsafe extern "C" fn(Ptr) -> c_int =
+            f!(ssl, "SSL_CTX_set_default_verify_paths");
+        let set_verify: unsafe extern "C" fn(Ptr, c_int, Ptr) = f!(ssl, "SSL_CTX_set_verify");
+
+        let lib = OpenSsl {
+            ctx: std::ptr::null_mut(),
+            ssl_new: f!(ssl, "SSL_new"),
+            ssl_free: f!(ssl, "SSL_free"),
+            ssl_set_fd: f!(ssl, "SSL_set_fd"),
+            ssl_ctrl: f!(ssl, "SSL_ctrl"),
+            ssl_set1_host: f!(ssl, "SSL_set1_host"),
+            ssl_connect: f!(ssl, "SSL_connect"),
+            ssl_read: f!(ssl, "SSL_read"),
+            ssl_write: f!(ssl, "SSL_write"),
+            ssl_shutdown: f!(ssl, "SSL_shutdown"),
+            ssl_get_error: f!(ssl, "SSL_get_error"),
+            ssl_get_verify_result: f!(ssl, "SSL_get_verify_result"),
+            verify_error_string: f!(crypto, "X509_verify_cert_error_string"),
+            err_get_error: f!(crypto, "ERR_get_error"),
+            err_error_string_n: f!(crypto, "ERR_error_string_n"),
+            bio_new_mem_buf: f!(crypto, "BIO_new_mem_buf"),
+            bio_free: f!(crypto, "BIO_free"),
+            pem_read_bio_private_key: f!(crypto, "PEM_read_bio_PrivateKey"),
+            evp_pkey_free: f!(crypto, "EVP_PKEY_free"),
+            evp_md_ctx_new: f!(crypto, "EVP_MD_CTX_new"),
+            evp_md_ctx_free: f!(crypto, "EVP_MD_CTX_free"),
+            evp_sha256: f!(crypto, "EVP_sha256"),
+            evp_digest_sign_init: f!(crypto, "EVP_DigestSignInit"),
+            evp_digest_sign: f!(crypto, "EVP_DigestSign"),
+        };
+        if init(0, std::ptr::null_mut()) != 1 {
+            return Err(format!(
+                "unable to initialize OpenSSL: {}",
+                lib.last_error()
+            ));
+        }
+        let ctx = ctx_new(method());
+        if ctx.is_null() {
+            return Err(format!(
+                "unable to create a TLS context: {}",
+                lib.last_error()
+            ));
+        }
+        verify_paths(ctx);
+        set_verify(ctx, SSL_VERIFY_PEER, std::ptr::null_mut());
+        Ok(OpenSsl { ctx, ..lib })
+    }
+
+    /// Description of the most recent OpenSSL error on this thread
+    fn last_error(&self) -> String {
+        let code = unsafe { (self.err_get_error)() };
+        if code == 0 {
+            return "unknown error".into();
+        }
+        let mut buf = [0 as c_char; 256];
+        unsafe {
+            (self.err_error_string_n)(code, buf.as_mut_ptr(), buf.len());
+            CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+        }
+    }
+}
+
+fn openssl() -> std::io::Result<&'static OpenSsl> {
+    static LIB: OnceLock<Result<OpenSsl, String>> = OnceLock::new();
+    LIB.get_or_init(|| unsafe { OpenSsl::load() })
+        .as_ref()
+        .map_err(|e| Error::new(ErrorKind::Unsupported, e.clone()))
+}
+
+/// A TLS connection over a TCP stream. Timeouts set on the TCP stream apply
+/// to reads and writes.
+pub struct TlsStream {
+    lib: &'static OpenSsl,
+    ssl: Ptr,
+    // Closed after the TLS connection that uses it
+    tcp: TcpStream,
+}
+
+// An OpenSSL connection may be moved between threads, as long as it is used
+// by one at a time
+unsafe impl Send for TlsStream {}
+
+impl TlsStream {
+    /// Connect over `tcp` to `host`, verifying its certificate
+    pub fn connect(tcp: TcpStream, host: &str) -> std::io::Result<TlsStream> {
+        let lib = openssl()?;
+        let name = CString::new(host).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let ssl = unsafe { (lib.ssl_new)(lib.ctx) };
+        if ssl.is_null() {
+            return Err(Error::other(lib.last_error()));
+        }
+        let stream = TlsStream { lib, ssl, tcp };
+        unsafe {
+            (lib.ssl_set_fd)(ssl, stream.tcp.as_raw_fd());
+            // Server name indication, and the name the certificate must match
+            (lib.ssl_ctrl)(
+                ssl,
+                SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                TLSEXT_NAMETYPE_HOST_NAME,
+                name.as_ptr() as Ptr,
+            );
+            (lib.ssl_set1_host)(ssl, name.as_ptr());
+            let ret = (lib.ssl_connect)(ssl);
+            if ret != 1 {
+                let verify = (lib.ssl_get_verify_result)(ssl);
+                let msg = if verify != 0 {
+                    let reason = CStr::from_ptr((lib.verify_error_string)(verify));
+                    format!(
+                        "certificate verification failed: {}",
+                        reason.to_string_lossy()
+                    )
+                } else {
+                    stream.error(ret).to_string()
+                };
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("TLS handshake with {} failed: {}", host, msg),
+                ));
+            }
+        }
+        Ok(stream)
+    }
+
+    /// Error for the return value `ret` of a failed OpenSSL call
+    fn error(&self, ret: c_int) -> Error {
+        match unsafe { (self.lib.ssl_get_error)(self.ssl, ret) } {
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => {
+                Error::new(ErrorKind::TimedOut, "TLS connection timed out")
+            }
+            SSL_ERROR_SYSCALL => match Error::last_os_error() {
+                e if e.raw_os_error() == Some(0) => {
+                    Error::new(ErrorKind::UnexpectedEof, "TLS connection closed")
+                }
+                e => e,
+            },
+            SSL_ERROR_SSL => Error::other(self.lib.last_error()),
+            code => Error::other(format!("TLS error {}", code)),
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        let ret = unsafe { (self.lib.ssl_read)(self.ssl, buf.as_mut_ptr() as Ptr, len) };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        match unsafe { (self.lib.ssl_get_error)(self.ssl, ret) } {
+            SSL_ERROR_ZERO_RETURN => Ok(0),
+            _ => match self.error(ret) {
+                // Servers commonly close without a TLS close notification,
+                // which is detected by checking the length of what was read
+                e if e.kind() == ErrorKind::UnexpectedEof => Ok(0),
+                e => Err(e),
+            },
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        let ret = unsafe { (self.lib.ssl_write)(self.ssl, buf.as_ptr() as *const c_void, len) };
+        if ret > 0 {
+            Ok(ret as usize)
+        } else {
+            Err(self.error(ret))
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.tcp.flush()
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.ssl_shutdown)(self.ssl);
+            (self.lib.ssl_free)(self.ssl);
+        }
+    }
+}
+
+/// RSASSA-PKCS1-v1_5 signature of `data` with SHA-256 (RS256), using the
+/// PEM-encoded private key `pem`
+pub fn sign_rs256(pem: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let lib = openssl()?;
+    let fail = |what: &str| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", what, lib.last_error()),
+        )
+    };
+    unsafe {
+        let bio = (lib.bio_new_mem_buf)(pem.as_ptr() as *const c_void, pem.len() as c_int);
+        if bio.is_null() {
+            return Err(fail("unable to read private key"));
+        }
+        let key = (lib.pem_read_bio_private_key)(
+            bio,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        (lib.bio_free)(bio);
+        if key.is_null() {
+            return Err(fail("invalid private key"));
+        }
+        let ctx = (lib.evp_md_ctx_new)();
+        let mut sig = vec![0u8; 1024];
+        let mut len = sig.len();
+        let ok = !ctx.is_null()
+            && (lib.evp_digest_sign_init)(
+                ctx,
+                std::ptr::null_mut(),
+                (lib.evp_sha256)(),
+                std::ptr::null_mut(),
+                key,
+            ) == 1
+            && (lib.evp_digest_sign)(ctx, sig.as_mut_ptr(), &mut len, data.as_ptr(), data.len())
+                == 1;
+        let res = if ok {
+            sig.truncate(len);
+            Ok(sig)
+        } else {
+            Err(fail("unable to sign"))
+        };
+        if !ctx.is_null() {
+            (lib.evp_md_ctx_free)(ctx);
+        }
+        (lib.evp_pkey_free)(key);
+        res
+    }
+}
@@ -0,0 +1,116 @@
+//! State of a batch conversion, so that an interrupted run can be resumed
+//!
+//! State is only recorded by runs given `--resume` or `--state`, so that
+//! other runs leave nothing behind in the working directory.
+//! The state file starts with a line recording the settings of the run,
+//! followed by one line per completed input, with the input and its output
+//! separated by a tab. Each line is flushed as soon as its output is
+//! written, so that the file is accurate however the run is interrupted.
+//! Resuming skips inputs that were completed by a run with the same
+//! settings and whose outputs still exist.
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Default location of the state file, in the working directory
+pub const DEFAULT_PATH: &str = "census2csv.state";
+
+const HEADER: &str = "# census2csv state";
+
+pub struct State {
+    path: PathBuf,
+    file: fs::File,
+    /// Output written for each completed input
+    done: HashMap<String, PathBuf>,
+}
+
+impl State {
+    /// Start recording a run with `settings` at `path`. If `resume` is true,
+    /// inputs completed by an earlier run with the same settings are kept;
+    /// otherwise any earlier state is discarded.
+    pub fn open<P: AsRef<Path>>(path: P, settings: &str, resume: bool) -> std::io::Result<State> {
+        let path = path.as_ref().to_path_buf();
+        let header = format!("{} {}", HEADER, settings);
+        let mut done = HashMap::new();
+        if resume && path.exists() {
+            let text = fs::read_to_string(&path)?;
+            let mut lines = text.lines();
+            if lines.next() != Some(header.as_str()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "{} was written by a run with different settings, and cannot be resumed",
+                        path.display()
+                    ),
+                ));
+            }
+            for line in lines {
+                if let Some((input, output)) = line.split_once('\t') {
+                    done.insert(input.to_string(), PathBuf::from(output));
+                }
+            }
+        }
+
+        let mut file = fs::File::create(&path)?;
+        writeln!(file, "{}", header)?;
+        for (input, output) in &done {
+            writeln!(file, "{}\t{}", input, output.display())?;
+        }
+        file.flush()?;
+        Ok(State { path, file, done })
+    }
+
+    /// Was `input` completed, with its output still present?
+    pub fn is_done(&self, input: &str) -> bool {
+        self.done.get(input).is_some_and(|output| output.exists())
+    }
+
+    /// Record that `input` was converted to `output`
+    pub fn complete<P: AsRef<Path>>(&mut self, input: &str, output: P) -> std::io::Result<()> {
+        let output = output.as_ref();
+        writeln!(self.file, "{}\t{}", input, output.display())?;
+        self.file.sync_data()?;
+        self.done.insert(input.to_string(), output.to_path_buf());
+        Ok(())
+    }
+
+    /// Remove the state file, once every input has been converted
+    pub fn remove(self) -> std::io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn resume_completed_inputs() {
+        let dir = fixture::dir("resume", &[("a.csv", "")]);
+        let path = dir.join("state");
+        let output = dir.join("a.csv");
+
+        let mut state = State::open(&path, "test", true).unwrap();
+        assert!(!state.is_done("a.txt"));
+        state.complete("a.txt", &output).unwrap();
+        state.complete("b.txt", dir.join("b.csv")).unwrap();
+        drop(state);
+
+        // Inputs whose outputs were removed are converted again
+        let state = State::open(&path, "test", true).unwrap();
+        assert!(state.is_done("a.txt"));
+        assert!(!state.is_done("b.txt"));
+        drop(state);
+
+        assert!(State::open(&path, "other", true).is_err());
+        let state = State::open(&path, "other", false).unwrap();
+        assert!(!state.is_done("a.txt"));
+        state.remove().unwrap();
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
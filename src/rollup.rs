@@ -0,0 +1,233 @@
+//! Protein rollup strategies
+//!
+//! A rollup combines the channel values of a protein's PSMs into a single
+//! value per channel. The `Sum`, `Mean`, and `CorrelationWeighted` strategies
+//! used by census2csv are provided, and other strategies can be supplied by
+//! implementing `Rollup`.
+use census_proteomics::{Peptide, Protein};
+
+/// Channel values and evidence for a single PSM
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PeptideValues<'a> {
+    pub sequence: &'a str,
+    /// Reporter ion intensity of each channel
+    pub values: &'a [u32],
+    pub unique: bool,
+    /// Precursor isolation purity
+    pub purity: f32,
+}
+
+impl<'a> From<&'a Peptide> for PeptideValues<'a> {
+    fn from(pep: &'a Peptide) -> PeptideValues<'a> {
+        PeptideValues {
+            sequence: &pep.sequence,
+            values: &pep.values,
+            unique: pep.unique,
+            purity: pep.purity,
+        }
+    }
+}
+
+/// Strategy for combining PSM values into protein values
+pub trait Rollup {
+    /// Combine `peptides`, which contains at least one PSM, returning one
+    /// value per channel
+    fn aggregate(&self, peptides: &[PeptideValues]) -> Vec<f64>;
+}
+
+/// Sum of each channel across PSMs
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sum;
+
+/// Mean of each channel across PSMs
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Mean;
+
+fn sums(peptides: &[PeptideValues]) -> Vec<f64> {
+    let mut sums = vec![0.0; peptides[0].values.len()];
+    for pep in peptides {
+        for (sum, val) in sums.iter_mut().zip(pep.values) {
+            *sum += *val as f64;
+        }
+    }
+    sums
+}
+
+impl Rollup for Sum {
+    fn aggregate(&self, peptides: &[PeptideValues]) -> Vec<f64> {
+        sums(peptides)
+    }
+}
+
+impl Rollup for Mean {
+    fn aggregate(&self, peptides: &[PeptideValues]) -> Vec<f64> {
+        let n = peptides.len() as f64;
+        sums(peptides).into_iter().map(|s| s / n).collect()
+    }
+}
+
+/// Sum or mean of each channel, with each PSM weighted by the correlation of
+/// its channel profile with the summed profile of the protein's other PSMs
+///
+/// Reporter ion interference flattens the profile of a PSM towards that of
+/// co-isolated peptides, so PSMs that disagree with the rest of the protein
+/// are down-weighted rather than removed. Weights are clamped to the range
+/// 0 to 1, and rescaled to sum to the number of PSMs, so that summed values
+/// remain comparable to `Sum`. A protein with a single PSM, or whose PSMs
+/// all have a weight of zero, is rolled up without weights.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CorrelationWeighted {
+    /// Average the weighted PSMs, rather than summing them
+    pub average: bool,
+}
+
+/// Pearson correlation of `a` and `b`, or `None` if either is constant
+fn correlation(a: &[f64], b: &[f64]) -> Option<f64> {
+    let n = a.len() as f64;
+    let (ma, mb) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut cov, mut va, mut vb) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - ma) * (y - mb);
+        va += (x - ma).powi(2);
+        vb += (y - mb).powi(2);
+    }
+    if va > 0.0 && vb > 0.0 {
+        Some(cov / (va * vb).sqrt())
+    } else {
+        None
+    }
+}
+
+impl CorrelationWeighted {
+    /// Weight of each PSM, from 0 to 1
+    pub fn weights(&self, peptides: &[PeptideValues]) -> Vec<f64> {
+        if peptides.len() < 2 {
+            return vec![1.0; peptides.len()];
+        }
+        let total = sums(peptides);
+        peptides
+            .iter()
+            .map(|pep| {
+                let values = pep.values.iter().map(|&v| v as f64).collect::<Vec<f64>>();
+                let others = total
+                    .iter()
+                    .zip(&values)
+                    .map(|(t, v)| t - v)
+                    .collect::<Vec<f64>>();
+                correlation(&values, &others).map_or(0.0, |r| r.clamp(0.0, 1.0))
+            })
+            .collect()
+    }
+}
+
+impl Rollup for CorrelationWeighted {
+    fn aggregate(&self, peptides: &[PeptideValues]) -> Vec<f64> {
+        let weights = self.weights(peptides);
+        let weight = weights.iter().sum::<f64>();
+        if weight <= 0.0 {
+            return if self.average {
+                Mean.aggregate(peptides)
+            } else {
+                Sum.aggregate(peptides)
+            };
+        }
+        let mut sums = vec![0.0; peptides[0].values.len()];
+        for (pep, w) in peptides.iter().zip(&weights) {
+            for (sum, val) in sums.iter_mut().zip(pep.values) {
+                *sum += w * *val as f64;
+            }
+        }
+        let scale = if self.average {
+            1.0 / weight
+        } else {
+            peptides.len() as f64 / weight
+        };
+        sums.into_iter().map(|s| s * scale).collect()
+    }
+}
+
+/// Apply `rollup` to the PSMs of `prot`. Proteins without any PSMs have a
+/// value of zero in every channel.
+pub fn protein<R: Rollup + ?Sized>(prot: &Protein, rollup: &R) -> Vec<f64> {
+    if prot.peptides.is_empty() {
+        return vec![0.0; prot.channels as usize];
+    }
+    let peptides = prot
+        .peptides
+        .iter()
+        .map(PeptideValues::from)
+        .collect::<Vec<PeptideValues>>();
+    rollup.aggregate(&peptides)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int\tm/z_128.1_int\tnorm_m/z_128.1_int
+P\tP12345\t4\t4\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t200\t0.5\t300\t0.5
+S\tU\tK.SECONDK.L\t200\t0.5\t400\t0.5\t600\t0.5
+S\tU\tK.THIRDK.L\t300\t0.5\t600\t0.5\t900\t0.5
+S\tU\tK.OUTLIERK.L\t300\t0.5\t150\t0.5\t0\t0.5";
+
+    fn protein_of(text: &str) -> Protein {
+        input::parse_census(text).unwrap().proteins.remove(0)
+    }
+
+    /// Largest value of each channel, as an example of a custom strategy
+    struct Max;
+
+    impl Rollup for Max {
+        fn aggregate(&self, peptides: &[PeptideValues]) -> Vec<f64> {
+            (0..peptides[0].values.len())
+                .map(|i| peptides.iter().map(|p| p.values[i]).max().unwrap() as f64)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn sum_and_mean() {
+        let prot = protein_of(FILE);
+        assert_eq!(protein(&prot, &Sum), [900.0, 1350.0, 1800.0]);
+        assert_eq!(protein(&prot, &Mean), [225.0, 337.5, 450.0]);
+        let custom: &dyn Rollup = &Max;
+        assert_eq!(protein(&prot, custom), [300.0, 600.0, 900.0]);
+    }
+
+    #[test]
+    fn correlation_weights() {
+        let prot = protein_of(FILE);
+        let peptides = prot
+            .peptides
+            .iter()
+            .map(PeptideValues::from)
+            .collect::<Vec<_>>();
+        let rollup = CorrelationWeighted::default();
+        let weights = rollup.weights(&peptides);
+        for w in &weights[..3] {
+            assert!((w - 1.0).abs() < 1e-9, "{:?}", weights);
+        }
+        assert_eq!(weights[3], 0.0);
+
+        // The outlier is dropped, and the others rescaled to four PSMs
+        let values = protein(&prot, &rollup);
+        let expected = [800.0, 1600.0, 2400.0];
+        for (v, e) in values.iter().zip(&expected) {
+            assert!((v - e).abs() < 1e-6, "{:?}", values);
+        }
+        let average = CorrelationWeighted { average: true };
+        let values = protein(&prot, &average);
+        assert!((values[0] - 200.0).abs() < 1e-6, "{:?}", values);
+    }
+
+    #[test]
+    fn single_psm_is_unweighted() {
+        let text = FILE.lines().take(3).collect::<Vec<_>>().join("\n");
+        let prot = protein_of(&text);
+        let rollup = CorrelationWeighted::default();
+        assert_eq!(protein(&prot, &rollup), [100.0, 200.0, 300.0]);
+    }
+}
@@ -0,0 +1,322 @@
+//! Write inputs for SAINTexpress interactomics scoring
+//!
+//! Each channel of a TMT affinity purification experiment is treated as one
+//! IP. Three files are written: `inter`, with one row per IP and prey; `prey`,
+//! with one row per protein; and `bait`, with one row per IP, marking it as a
+//! test (T) or control (C) purification.
+//!
+//! By default the interaction quantity is a spectral count, for use with
+//! SAINTexpress-spc: for TMT data, the number of the protein's PSMs with
+//! reporter ion signal in the channel. With `Quantity::Intensity`, it is the
+//! protein's reporter ion intensity in the channel instead, for use with
+//! SAINTexpress-int. Files without reporter ions (such as DTASelect spectral
+//! count data) are treated as a single IP, described by channel 1 of the bait
+//! file, and the quantity is always the spectral count.
+use census_proteomics::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Average residue mass used to estimate protein length, in daltons
+const RESIDUE_MASS: u32 = 110;
+
+/// Condition names treated as control purifications, unless others are
+/// given
+pub const CONTROLS: &[&str] = &[
+    "control", "ctrl", "ctl", "igg", "mock", "empty", "negative", "neg",
+];
+
+/// Interaction quantity written to the inter file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Quantity {
+    /// Number of PSMs, for SAINTexpress-spc
+    Spectral,
+    /// Summed reporter ion intensity, for SAINTexpress-int
+    Intensity,
+}
+
+impl FromStr for Quantity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spc" => Ok(Quantity::Spectral),
+            "int" => Ok(Quantity::Intensity),
+            _ => Err(format!("unknown SAINT quantity {}", s)),
+        }
+    }
+}
+
+/// Bait name, and whether it is a control purification
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bait {
+    pub name: String,
+    pub control: bool,
+}
+
+/// Bait annotations, keyed on 1-indexed channel
+pub type Baits = HashMap<usize, Bait>;
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Read a tab-delimited bait file with the columns channel, bait, and type
+/// (T or C). A header row is permitted.
+pub fn read_baits<P: AsRef<Path>>(path: P) -> std::io::Result<Baits> {
+    let file = fs::read_to_string(path)?;
+    let mut baits = HashMap::new();
+    for line in file.lines().filter(|l| !l.trim().is_empty()) {
+        let row = line.split('\t').map(str::trim).collect::<Vec<&str>>();
+        let channel = match row[0].parse::<usize>() {
+            Ok(ch) => ch,
+            Err(_) if baits.is_empty() => continue,
+            Err(_) => return Err(invalid(format!("invalid channel {}", row[0]))),
+        };
+        let name = row
+            .get(1)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| invalid(format!("missing bait for channel {}", channel)))?;
+        let control = match row.get(2).copied().unwrap_or("T") {
+            "T" | "t" => false,
+            "C" | "c" => true,
+            other => return Err(invalid(format!("invalid bait type {}", other))),
+        };
+        baits.insert(
+            channel,
+            Bait {
+                name: name.to_string(),
+                control,
+            },
+        );
+    }
+    Ok(baits)
+}
+
+/// Is the condition `name` one of `controls`, or of `CONTROLS` if none are
+/// given? Names are compared ignoring case and a trailing replicate number,
+/// so that Ctrl_2 and IgG-1 are controls.
+pub fn is_control<S: AsRef<str>>(name: &str, controls: &[S]) -> bool {
+    let base = |name: &str| {
+        name.trim()
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .trim_end_matches(['_', '-', '.', ' '])
+            .to_lowercase()
+    };
+    let name = base(name);
+    if controls.is_empty() {
+        CONTROLS.contains(&name.as_str())
+    } else {
+        controls.iter().any(|c| base(c.as_ref()) == name)
+    }
+}
+
+/// Bait annotations derived from channel conditions, with conditions
+/// matching `controls` (as for `is_control`) treated as control
+/// purifications
+pub fn from_conditions<S: AsRef<str>>(conditions: &[Option<String>], controls: &[S]) -> Baits {
+    conditions
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, cond)| {
+            cond.as_ref().map(|name| {
+                (
+                    idx + 1,
+                    Bait {
+                        name: name.clone(),
+                        control: is_control(name, controls),
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+/// Description of the experiment written to SAINT files
+pub struct Experiment<'a> {
+    /// Name of the experiment, used as a prefix for IP names
+    pub run: &'a str,
+    pub baits: &'a Baits,
+    pub quantity: Quantity,
+    /// Gene name to report for each protein, if known
+    pub genes: &'a dyn Fn(&Protein) -> Option<String>,
+}
+
+/// Number of PSMs of `prot` with nonzero intensity in each channel
+fn spectral_counts(prot: &Protein, channels: usize) -> Vec<u32> {
+    let mut counts = vec![0; channels];
+    for pep in &prot.peptides {
+        for (count, val) in counts.iter_mut().zip(&pep.values) {
+            *count += (*val > 0) as u32;
+        }
+    }
+    counts
+}
+
+/// Write SAINT inter, prey, and bait files for the experiment `exp`
+pub fn write<I: Write, P: Write, B: Write>(
+    data: &Dataset,
+    exp: &Experiment,
+    mut inter: I,
+    mut prey: P,
+    mut bait: B,
+) -> std::io::Result<()> {
+    let (run, baits) = (exp.run, exp.baits);
+    // (column index into the protein totals, IP name, bait)
+    let ips = if data.channels == 0 {
+        baits
+            .get(&1)
+            .map(|b| vec![(None, run.to_string(), b)])
+            .unwrap_or_default()
+    } else {
+        (1..=data.channels as usize)
+            .filter_map(|ch| {
+                baits
+                    .get(&ch)
+                    .map(|b| (Some(ch - 1), format!("{}_{}", run, ch), b))
+            })
+            .collect::<Vec<_>>()
+    };
+    if ips.is_empty() {
+        return Err(invalid("no channels have a bait annotation"));
+    }
+
+    for (_, ip, b) in &ips {
+        writeln!(
+            bait,
+            "{}\t{}\t{}",
+            ip,
+            b.name,
+            if b.control { "C" } else { "T" }
+        )?;
+    }
+
+    for prot in &data.proteins {
+        let totals = match exp.quantity {
+            Quantity::Spectral => spectral_counts(prot, data.channels as usize),
+            Quantity::Intensity => prot.total(),
+        };
+        for (idx, ip, b) in &ips {
+            let quantity = match idx {
+                Some(i) => totals[*i],
+                None => prot.spectral_count as u32,
+            };
+            if quantity > 0 {
+                writeln!(
+                    inter,
+                    "{}\t{}\t{}\t{}",
+                    ip, b.name, prot.accession, quantity
+                )?;
+            }
+        }
+        writeln!(
+            prey,
+            "{}\t{}\t{}",
+            prot.accession,
+            (prot.molecular_weight / RESIDUE_MASS).max(1),
+            (exp.genes)(prot).unwrap_or_else(|| prot.accession.clone())
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+    use census2csv::input;
+
+    const FILE: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t3\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t0\t0.5
+S\tU\tK.PEPTIDEK.L\t300\t0.5\t50\t0.5
+S\tU\tR.SEQUENCER.A\t500\t0.5\t0\t0.5";
+
+    fn conditions(names: &[&str]) -> Vec<Option<String>> {
+        names.iter().map(|name| Some(name.to_string())).collect()
+    }
+
+    fn write_files(baits: &Baits, quantity: Quantity) -> (String, String, String) {
+        let data = input::parse_census(FILE).unwrap();
+        let genes = |prot: &Protein| Some(format!("GENE_{}", prot.accession));
+        let exp = Experiment {
+            run: "ap",
+            baits,
+            quantity,
+            genes: &genes,
+        };
+        let (mut inter, mut prey, mut bait) = (Vec::new(), Vec::new(), Vec::new());
+        write(&data, &exp, &mut inter, &mut prey, &mut bait).unwrap();
+        let text = |buf: Vec<u8>| String::from_utf8(buf).unwrap();
+        (text(inter), text(prey), text(bait))
+    }
+
+    #[test]
+    fn control_names() {
+        let none: &[&str] = &[];
+        for name in ["control", "Control", "ctrl_2", "IgG", "igg-1", "Mock 3"] {
+            assert!(is_control(name, none), "{}", name);
+        }
+        for name in ["treated", "BAIT1", "controlled"] {
+            assert!(!is_control(name, none), "{}", name);
+        }
+        assert!(is_control("GFP_1", &["gfp"]));
+        assert!(!is_control("control", &["gfp"]));
+
+        let baits = from_conditions(&conditions(&["BAIT", "Ctrl1"]), none);
+        assert!(!baits[&1].control);
+        assert!(baits[&2].control);
+        assert_eq!(baits[&2].name, "Ctrl1");
+    }
+
+    #[test]
+    fn spectral_counts_by_default() {
+        let baits = from_conditions(&conditions(&["BAIT", "IgG"]), &[] as &[&str]);
+        let (inter, prey, bait) = write_files(&baits, Quantity::Spectral);
+        assert_eq!(inter, "ap_1\tBAIT\tP12345\t3\nap_2\tIgG\tP12345\t1\n");
+        assert_eq!(prey, "P12345\t3\tGENE_P12345\n");
+        assert_eq!(bait, "ap_1\tBAIT\tT\nap_2\tIgG\tC\n");
+    }
+
+    #[test]
+    fn intensities() {
+        let baits = from_conditions(&conditions(&["BAIT", "IgG"]), &[] as &[&str]);
+        let (inter, _, _) = write_files(&baits, Quantity::Intensity);
+        assert_eq!(inter, "ap_1\tBAIT\tP12345\t900\nap_2\tIgG\tP12345\t50\n");
+    }
+
+    #[test]
+    fn bait_file() {
+        let dir = fixture::dir(
+            "saint",
+            &[
+                ("baits.tsv", "channel\tbait\ttype\n1\tBAIT\tT\n2\tIgG\tc\n"),
+                ("bad.tsv", "1\tBAIT\tX\n"),
+            ],
+        );
+        let baits = read_baits(dir.join("baits.tsv")).unwrap();
+        assert_eq!(baits.len(), 2);
+        assert!(!baits[&1].control && baits[&2].control);
+        let err = read_baits(dir.join("bad.tsv")).err().unwrap();
+        assert_eq!(err.to_string(), "invalid bait type X");
+
+        let err = write(
+            &input::parse_census(FILE).unwrap(),
+            &Experiment {
+                run: "ap",
+                baits: &Baits::new(),
+                quantity: Quantity::Spectral,
+                genes: &|_| None,
+            },
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "no channels have a bait annotation");
+    }
+}
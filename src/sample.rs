@@ -0,0 +1,25 @@
+//! Deterministic random subsampling of protein blocks
+//!
+//! Whether a block is kept depends only on the seed and the position of the
+//! block in the input, so that the same subset is selected on every run, and
+//! on every machine.
+
+/// Random number for the block at `idx`, uniformly distributed in [0, 1),
+/// from the splitmix64 mixing function
+fn uniform(seed: u64, idx: u64) -> f64 {
+    let mut z = seed.wrapping_add(idx.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Keep approximately `fraction` of `items`, in their original order
+pub fn sample<T>(items: Vec<T>, fraction: f64, seed: u64) -> Vec<T> {
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| uniform(seed, *idx as u64) < fraction)
+        .map(|(_, item)| item)
+        .collect()
+}
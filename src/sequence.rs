@@ -0,0 +1,287 @@
+//! Parsing of census peptide sequences
+//!
+//! Census writes peptides with their flanking residues, such as
+//! `K.PEPTM(15.9949)IDEK.L`, where `-` marks a protein terminus and
+//! modifications follow the residue they modify, in parentheses or square
+//! brackets, or as symbols such as `*` in older SEQUEST-style searches.
+//! Crosslinking searches may join several peptides into one sequence with a
+//! separator such as `--`, and each is parsed on its own by `links`.
+
+/// Separators between the peptides of crosslinked or concatenated sequences
+const SEPARATORS: &[&str] = &["--", "~", "|"];
+
+/// Symbols marking modified residues, outside of parentheses or brackets
+const MOD_SYMBOLS: &str = "*#@^$%&!?'";
+
+/// Whether the termini of a peptide result from tryptic cleavage, after K or
+/// R, or are protein termini
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cleavage {
+    /// Both termini are tryptic
+    Full,
+    /// The C-terminus is tryptic, and the N-terminus is not
+    SemiNRagged,
+    /// The N-terminus is tryptic, and the C-terminus is not
+    SemiCRagged,
+    /// Neither terminus is tryptic
+    Non,
+}
+
+impl Cleavage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Cleavage::Full => "full",
+            Cleavage::SemiNRagged => "semi_n_ragged",
+            Cleavage::SemiCRagged => "semi_c_ragged",
+            Cleavage::Non => "non",
+        }
+    }
+}
+
+/// A modification annotated in a peptide sequence
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Modification<'a> {
+    /// 0-indexed residue the modification follows, or `None` for a
+    /// modification written before the first residue
+    pub residue: Option<usize>,
+    /// Text between the parentheses or brackets, usually a mass shift
+    pub annotation: &'a str,
+}
+
+impl Modification<'_> {
+    /// Mass shift of the modification, if the annotation is a number such
+    /// as `15.9949` or `+42.0106`
+    pub fn mass(&self) -> Option<f64> {
+        self.annotation
+            .trim()
+            .trim_start_matches('+')
+            .parse::<f64>()
+            .ok()
+            .filter(|m| m.is_finite())
+    }
+
+    /// Is the modification on the peptide N-terminus, either before or on
+    /// the first residue?
+    pub fn is_nterm(&self) -> bool {
+        matches!(self.residue, None | Some(0))
+    }
+}
+
+/// A peptide sequence split into its flanking residues and the modified
+/// sequence between them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Peptide<'a> {
+    /// Residue preceding the peptide, or `-` at the protein N-terminus
+    pub before: Option<char>,
+    /// Peptide sequence, including any modifications
+    pub modified: &'a str,
+    /// Residue following the peptide, or `-` at the protein C-terminus
+    pub after: Option<char>,
+}
+
+/// Is `c` a flanking residue, or `-` for a protein terminus?
+fn is_flank(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'-'
+}
+
+/// `sequence` with its modification annotations removed, keeping flanking
+/// residues, so that mass shifts such as `(+2026.07)` cannot be mistaken for
+/// residues or flanking periods when matching patterns
+pub fn unmodified(sequence: &str) -> std::borrow::Cow<'_, str> {
+    if !sequence.contains(['(', '[']) {
+        return sequence.into();
+    }
+    let mut depth = 0usize;
+    sequence
+        .chars()
+        .filter(|&c| {
+            match c {
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth = depth.saturating_sub(1),
+                _ => return depth == 0,
+            }
+            false
+        })
+        .collect::<String>()
+        .into()
+}
+
+/// Split a crosslinked or concatenated `sequence` into its peptides, at
+/// separators outside of modification annotations. Other sequences are
+/// returned as a single peptide.
+pub fn links(sequence: &str) -> Vec<Peptide<'_>> {
+    let mut links = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut idx = 0;
+    while idx < sequence.len() {
+        let rest = &sequence[idx..];
+        match rest.as_bytes()[0] {
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => {
+                if let Some(sep) = SEPARATORS.iter().find(|s| rest.starts_with(*s)) {
+                    links.push(parse(&sequence[start..idx]));
+                    idx += sep.len();
+                    start = idx;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        idx += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    links.push(parse(&sequence[start..]));
+    links
+}
+
+/// Check that every peptide of `sequence` has residues and balanced
+/// modification annotations, and no characters other than residues,
+/// modification symbols, flanking periods, and terminus markers outside of
+/// them, returning the reason it cannot be parsed otherwise
+pub fn check(sequence: &str) -> Result<(), String> {
+    for link in links(sequence) {
+        let mut depth = 0usize;
+        for c in link.modified.chars() {
+            match c {
+                '(' | '[' => depth += 1,
+                ')' | ']' if depth == 0 => return Err(format!("unmatched {:?}", c)),
+                ')' | ']' => depth -= 1,
+                _ if depth > 0 => {}
+                c if c.is_ascii_alphabetic() || MOD_SYMBOLS.contains(c) || c == '.' || c == '-' => {
+                }
+                c => return Err(format!("unexpected character {:?}", c)),
+            }
+        }
+        if depth > 0 {
+            return Err("unclosed modification annotation".into());
+        }
+        if link.residues().is_empty() {
+            return Err("no residues".into());
+        }
+    }
+    Ok(())
+}
+
+/// Split `sequence` into its flanking residues and modified sequence.
+/// Sequences without flanking residues are returned whole.
+pub fn parse(sequence: &str) -> Peptide<'_> {
+    let bytes = sequence.as_bytes();
+    let n = bytes.len();
+    let (before, start) = if n > 2 && bytes[1] == b'.' && is_flank(bytes[0]) {
+        (Some(bytes[0] as char), 2)
+    } else {
+        (None, 0)
+    };
+    let (after, end) = if n >= start + 2 && bytes[n - 2] == b'.' && is_flank(bytes[n - 1]) {
+        (Some(bytes[n - 1] as char), n - 2)
+    } else {
+        (None, n)
+    };
+    Peptide {
+        before,
+        modified: &sequence[start..end.max(start)],
+        after,
+    }
+}
+
+impl Peptide<'_> {
+    /// Classify the termini of the peptide. A terminus is tryptic if it
+    /// follows K or R, or is a protein terminus; termini are not checked
+    /// when the flanking residues are unknown.
+    pub fn cleavage(&self) -> Cleavage {
+        let nterm = matches!(self.before, None | Some('K' | 'R' | '-'));
+        let cterm = match self.after {
+            None | Some('-') => true,
+            Some(_) => matches!(self.residues().chars().last(), Some('K' | 'R')),
+        };
+        match (nterm, cterm) {
+            (true, true) => Cleavage::Full,
+            (false, true) => Cleavage::SemiNRagged,
+            (true, false) => Cleavage::SemiCRagged,
+            (false, false) => Cleavage::Non,
+        }
+    }
+
+    /// Does the peptide carry a modification with a mass shift from `min` to
+    /// `max`, inclusive?
+    pub fn has_mod_mass(&self, min: f64, max: f64) -> bool {
+        self.modifications()
+            .iter()
+            .filter_map(Modification::mass)
+            .any(|mass| mass >= min && mass <= max)
+    }
+
+    /// Modifications annotated in the sequence, in order
+    pub fn modifications(&self) -> Vec<Modification<'_>> {
+        let mut mods = Vec::new();
+        let mut residues = 0usize;
+        let mut open = None;
+        for (idx, c) in self.modified.char_indices() {
+            match (c, open) {
+                ('(' | '[', None) => open = Some(idx + 1),
+                (')' | ']', Some(start)) => {
+                    mods.push(Modification {
+                        residue: residues.checked_sub(1),
+                        annotation: &self.modified[start..idx],
+                    });
+                    open = None;
+                }
+                (c, None) if c.is_ascii_alphabetic() => residues += 1,
+                _ => {}
+            }
+        }
+        mods
+    }
+
+    /// Modifications as a semicolon-separated list of the residue and its
+    /// 1-indexed position, or `N-term` before the first residue, and the
+    /// annotation, such as `N-term:42.0106;K9:14.0157`
+    pub fn describe_modifications(&self) -> String {
+        let residues = self.residues();
+        self.modifications()
+            .iter()
+            .map(|m| match m.residue {
+                Some(idx) => format!(
+                    "{}{}:{}",
+                    residues.chars().nth(idx).unwrap_or('X'),
+                    idx + 1,
+                    m.annotation
+                ),
+                None => format!("N-term:{}", m.annotation),
+            })
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    /// Number of each distinct modification annotation, as a
+    /// semicolon-separated list such as `14.0157:2;42.0106:1`
+    pub fn modification_composition(&self) -> String {
+        let mut counts = std::collections::BTreeMap::new();
+        for m in self.modifications() {
+            *counts.entry(m.annotation).or_insert(0) += 1;
+        }
+        counts
+            .iter()
+            .map(|(annotation, n)| format!("{}:{}", annotation, n))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    /// Unmodified residues of the peptide, in upper case
+    pub fn residues(&self) -> String {
+        let mut depth = 0usize;
+        self.modified
+            .chars()
+            .filter(|&c| {
+                match c {
+                    '(' | '[' => depth += 1,
+                    ')' | ']' => depth = depth.saturating_sub(1),
+                    _ => return depth == 0 && c.is_ascii_alphabetic(),
+                }
+                false
+            })
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    }
+}
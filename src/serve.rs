@@ -0,0 +1,645 @@
+//! HTTP server exposing conversion to other services
+//!
+//! `POST /convert` accepts either a raw census file as the request body, or
+//! a multipart/form-data upload with a `census` part and an optional
+//! `filter` part containing filter.json. The query parameters `table`
+//! (protein, protein-average, or psm) and `format` (csv or json) select the
+//! output. `GET /health` reports that the server is running.
+//!
+//! Each connection is handled on its own thread, and served with a single
+//! response before the connection is closed. `Limits` bounds what a client
+//! can cost the server: at most `max_connections` are handled at once, and
+//! further connections are refused with 503 by a separate thread until one
+//! finishes. Reads and writes that stall for longer than `timeout`, and
+//! connections that are not complete within `deadline` however steadily the
+//! client sends, are aborted. The request head and body are read
+//! incrementally up to fixed limits, so that a client cannot hold memory it
+//! has not sent.
+use crate::diagnostics;
+use census2csv::convert::{self, Table};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Largest request line and headers that will be accepted, in bytes
+const MAX_HEAD: u64 = 64 << 10;
+
+/// Longest spent writing a 503 response to a refused connection
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest spent, and most bytes read, discarding what a client is still
+/// sending once its response has been written
+const LINGER: Duration = Duration::from_secs(1);
+const LINGER_BYTES: u64 = 1 << 20;
+
+/// Limits on the resources a client can use
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Largest request body that will be accepted, in bytes
+    pub max_body: usize,
+    /// Connections handled at once
+    pub max_connections: usize,
+    /// Longest a read or write of a connection may stall
+    pub timeout: Duration,
+    /// Longest a connection may take, from being accepted to the end of its
+    /// response
+    pub deadline: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_body: 64 << 20,
+            max_connections: 16,
+            timeout: Duration::from_secs(30),
+            deadline: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Slot of a connection counted in `active`, released when it is dropped
+struct Slot {
+    active: Arc<AtomicUsize>,
+}
+
+impl Slot {
+    fn acquire(active: &Arc<AtomicUsize>, max: usize) -> Option<Slot> {
+        active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Slot {
+                active: Arc::clone(active),
+            })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A connection whose reads and writes fail once `deadline` has passed, as
+/// well as when a single read or write stalls for `timeout`
+struct Connection<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+    timeout: Duration,
+}
+
+impl Connection<'_> {
+    /// Time left for the next read or write
+    fn remaining(&self) -> std::io::Result<Duration> {
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(left.min(self.timeout)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "connection deadline passed",
+            )),
+        }
+    }
+}
+
+impl Read for Connection<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Connection<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn error(status: &'static str, msg: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", msg).into_bytes(),
+        }
+    }
+}
+
+/// Listen on `host`:`port` until the process is terminated
+pub fn serve(host: &str, port: u16, limits: Limits) -> std::io::Result<()> {
+    let listener = TcpListener::bind((host, port))?;
+    diagnostics::info(format!("census2csv listening on http://{}:{}", host, port));
+    run(listener, limits);
+    Ok(())
+}
+
+/// Handle connections to `listener`
+fn run(listener: TcpListener, limits: Limits) {
+    let active = Arc::new(AtomicUsize::new(0));
+    // Refusals are written by their own thread, so that a client that does
+    // not read its 503 cannot stall the accept loop. If too many are waiting,
+    // connections are closed without a response.
+    let (refuse, refused) = mpsc::sync_channel::<TcpStream>(limits.max_connections);
+    std::thread::spawn(move || {
+        for stream in refused {
+            let mut conn = Connection {
+                stream: &stream,
+                deadline: Instant::now() + REFUSE_TIMEOUT,
+                timeout: REFUSE_TIMEOUT,
+            };
+            let busy = Response::error("503 Service Unavailable", "server is busy");
+            match respond(&mut conn, busy) {
+                Ok(()) => close(&stream),
+                Err(e) => diagnostics::error(format!("Error while refusing connection: {}", e)),
+            }
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                diagnostics::error(format!("Error accepting connection: {}", e));
+                continue;
+            }
+        };
+        let slot = match Slot::acquire(&active, limits.max_connections) {
+            Some(slot) => slot,
+            None => {
+                // Dropping the stream closes it
+                let _ = refuse.try_send(stream);
+                continue;
+            }
+        };
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, limits) {
+                diagnostics::error(format!("Error while handling request: {}", e));
+            }
+            drop(slot);
+        });
+    }
+}
+
+fn handle(stream: TcpStream, limits: Limits) -> std::io::Result<()> {
+    let mut conn = Connection {
+        stream: &stream,
+        deadline: Instant::now() + limits.deadline,
+        timeout: limits.timeout,
+    };
+    let response = match read_request(&mut BufReader::new(&mut conn), limits.max_body) {
+        Ok(req) => route(&req),
+        Err(resp) => resp,
+    };
+    // Allow a short time to send the response, which is a 408 if the
+    // deadline passed while reading the request
+    conn.deadline = conn.deadline.max(Instant::now() + LINGER);
+    respond(&mut conn, response)?;
+    close(&stream);
+    Ok(())
+}
+
+/// Close `stream` once its response has been written. Closing a socket with
+/// unread data resets the connection, which can discard the response before
+/// the client reads it, so anything the client is still sending is read
+/// first, within `LINGER`.
+fn close(stream: &TcpStream) {
+    let _ = stream.shutdown(Shutdown::Write);
+    let conn = Connection {
+        stream,
+        deadline: Instant::now() + LINGER,
+        timeout: LINGER,
+    };
+    let _ = std::io::copy(&mut conn.take(LINGER_BYTES), &mut std::io::sink());
+}
+
+fn respond<W: Write>(mut stream: W, response: Response) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Read a line of the request head, which must end within the limit of
+/// `head`
+fn head_line<R: BufRead>(head: &mut std::io::Take<R>, what: &str) -> Result<String, Response> {
+    let mut line = String::new();
+    match head.read_line(&mut line) {
+        Ok(_) if line.ends_with('\n') => Ok(line),
+        Ok(_) if head.limit() == 0 => Err(Response::error(
+            "431 Request Header Fields Too Large",
+            "request head is too large",
+        )),
+        Err(e) if timed_out(&e) => Err(timeout()),
+        _ => Err(Response::error(
+            "400 Bad Request",
+            &format!("invalid {}", what),
+        )),
+    }
+}
+
+fn timed_out(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+fn timeout() -> Response {
+    Response::error("408 Request Timeout", "request was not received in time")
+}
+
+fn read_request<R: BufRead>(reader: &mut R, max_body: usize) -> Result<Request, Response> {
+    let bad = |msg: &str| Response::error("400 Bad Request", msg);
+    let mut head = reader.take(MAX_HEAD);
+
+    let line = head_line(&mut head, "request line")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let target = parts.next().unwrap_or("/");
+    let (path, query) = match target.find('?') {
+        Some(idx) => (&target[..idx], &target[idx + 1..]),
+        None => (target, ""),
+    };
+    let path = path.to_string();
+    let query = query
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let mut it = kv.splitn(2, '=');
+            (
+                it.next().unwrap_or("").to_string(),
+                it.next().unwrap_or("").to_string(),
+            )
+        })
+        .collect();
+
+    let mut content_length = 0;
+    let mut content_type = String::new();
+    loop {
+        let line = head_line(&mut head, "header")?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(idx) = header.find(':') {
+            let (name, value) = (header[..idx].to_lowercase(), header[idx + 1..].trim());
+            match name.as_str() {
+                "content-length" => {
+                    content_length = value
+                        .parse::<usize>()
+                        .map_err(|_| bad("invalid Content-Length"))?
+                }
+                "content-type" => content_type = value.to_string(),
+                _ => {}
+            }
+        }
+    }
+    if content_length > max_body {
+        return Err(Response::error(
+            "413 Payload Too Large",
+            "request body is too large",
+        ));
+    }
+
+    // Read incrementally rather than allocating Content-Length up front
+    let mut body = Vec::new();
+    let read = head
+        .into_inner()
+        .take(content_length as u64)
+        .read_to_end(&mut body);
+    match read {
+        Err(e) if timed_out(&e) => return Err(timeout()),
+        Err(_) => return Err(bad("request body is shorter than Content-Length")),
+        Ok(_) if body.len() < content_length => {
+            return Err(bad("request body is shorter than Content-Length"))
+        }
+        Ok(_) => {}
+    }
+    Ok(Request {
+        method,
+        path,
+        query,
+        content_type,
+        body,
+    })
+}
+
+fn route(req: &Request) -> Response {
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/health") => Response {
+            status: "200 OK",
+            content_type: "text/plain",
+            body: b"ok\n".to_vec(),
+        },
+        ("POST", "/convert") => convert_request(req),
+        (_, "/convert") | (_, "/health") => {
+            Response::error("405 Method Not Allowed", "method not allowed")
+        }
+        _ => Response::error("404 Not Found", "not found"),
+    }
+}
+
+fn convert_request(req: &Request) -> Response {
+    let param = |name: &str| {
+        req.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+    let table = match param("table").unwrap_or("protein").parse::<Table>() {
+        Ok(table) => table,
+        Err(e) => return Response::error("400 Bad Request", &e),
+    };
+    let json = match param("format").unwrap_or("csv") {
+        "csv" => false,
+        "json" => true,
+        other => return Response::error("400 Bad Request", &format!("unknown format {}", other)),
+    };
+
+    let (census, filter) = if req.content_type.starts_with("multipart/form-data") {
+        let parts = match multipart(&req.content_type, &req.body) {
+            Some(parts) => parts,
+            None => return Response::error("400 Bad Request", "invalid multipart body"),
+        };
+        let get = |name: &str| parts.iter().find(|(n, _)| n == name).map(|(_, v)| *v);
+        match get("census") {
+            Some(census) => (census, get("filter")),
+            None => return Response::error("400 Bad Request", "missing census part"),
+        }
+    } else {
+        (req.body.as_slice(), None)
+    };
+    let filter = match filter.map(std::str::from_utf8) {
+        Some(Ok(f)) if !f.trim().is_empty() => Some(f),
+        Some(Err(_)) => return Response::error("400 Bad Request", "filter is not valid UTF-8"),
+        _ => None,
+    };
+
+    match convert::frame(census, filter, table) {
+        Ok(frame) if json => Response {
+            status: "200 OK",
+            content_type: "application/json",
+            body: frame.to_json().to_string().into_bytes(),
+        },
+        Ok(frame) => Response {
+            status: "200 OK",
+            content_type: "text/csv",
+            body: frame.to_csv().into_bytes(),
+        },
+        Err(e) => Response::error("422 Unprocessable Entity", &e),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|idx| idx + from)
+}
+
+/// Split a multipart/form-data body into (name, contents) pairs
+fn multipart<'a>(content_type: &str, body: &'a [u8]) -> Option<Vec<(String, &'a [u8])>> {
+    let boundary = content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|p| p.strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut parts = Vec::new();
+    let mut pos = find(body, &delimiter, 0)? + delimiter.len();
+    // The final delimiter is followed by "--"
+    while !body[pos..].starts_with(b"--") {
+        let headers_end = find(body, b"\r\n\r\n", pos)?;
+        let headers = String::from_utf8_lossy(&body[pos..headers_end]);
+        let name = headers
+            .split(';')
+            .map(str::trim)
+            .find_map(|p| p.strip_prefix("name="))
+            .map(|n| n.trim_matches('"').to_string())
+            .unwrap_or_default();
+
+        let start = headers_end + 4;
+        let mut end_delim = b"\r\n".to_vec();
+        end_delim.extend_from_slice(&delimiter);
+        let end = find(body, &end_delim, start)?;
+        parts.push((name, &body[start..end]));
+        pos = end + end_delim.len();
+    }
+    Some(parts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(request: &[u8]) -> Result<Request, Response> {
+        read_request(&mut BufReader::new(request), Limits::default().max_body)
+    }
+
+    fn status(request: &[u8]) -> &'static str {
+        match parse(request) {
+            Ok(_) => "ok",
+            Err(resp) => resp.status,
+        }
+    }
+
+    #[test]
+    fn parse_request() {
+        let req = parse(
+            b"POST /convert?table=psm&format=json HTTP/1.1\r\n\
+              Host: localhost\r\n\
+              content-type: text/plain\r\n\
+              Content-Length: 5\r\n\r\nhello",
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/convert");
+        assert_eq!(
+            req.query,
+            vec![
+                ("table".to_string(), "psm".to_string()),
+                ("format".to_string(), "json".to_string())
+            ]
+        );
+        assert_eq!(req.content_type, "text/plain");
+        assert_eq!(req.body, b"hello");
+
+        let req = parse(b"GET /health HTTP/1.1\r\n\r\n").ok().unwrap();
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("GET", "/health"));
+        assert!(req.query.is_empty() && req.body.is_empty());
+    }
+
+    #[test]
+    fn reject_malformed_requests() {
+        assert_eq!(status(b""), "400 Bad Request");
+        assert_eq!(status(b"GET /health HTTP/1.1\r\n"), "400 Bad Request");
+        assert_eq!(
+            status(b"POST /convert HTTP/1.1\r\nContent-Length: x\r\n\r\n"),
+            "400 Bad Request"
+        );
+        assert_eq!(
+            status(b"POST /convert HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            "400 Bad Request"
+        );
+    }
+
+    #[test]
+    fn enforce_limits() {
+        let body = format!(
+            "POST /convert HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            Limits::default().max_body + 1
+        );
+        assert_eq!(status(body.as_bytes()), "413 Payload Too Large");
+        let small = b"POST /convert HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let res = read_request(&mut BufReader::new(&small[..]), 4);
+        assert_eq!(res.err().unwrap().status, "413 Payload Too Large");
+
+        let mut head = b"GET /health HTTP/1.1\r\nX-Padding: ".to_vec();
+        head.resize(MAX_HEAD as usize + 1, b'a');
+        head.extend_from_slice(b"\r\n\r\n");
+        assert_eq!(status(&head), "431 Request Header Fields Too Large");
+    }
+
+    /// Start a server with `limits` on a free port, returning its address
+    fn start(limits: Limits) -> std::net::SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || run(listener, limits));
+        addr
+    }
+
+    fn exchange(addr: std::net::SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn convert_over_http() {
+        let addr = start(Limits::default());
+        let census = "H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\n\
+            P\tP12345\t1\t1\t30.3%\t335\t82944\tSerum albumin\n\
+            S\tU\tK.PEPTIDEK.L\t100\t1.0\n";
+        let request = format!(
+            "POST /convert HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            census.len(),
+            census
+        );
+        let response = exchange(addr, request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(
+            response.ends_with("P12345,Serum albumin,1,1,100\n"),
+            "{}",
+            response
+        );
+
+        let response = exchange(addr, b"GET /missing HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn refuse_when_busy() {
+        let addr = start(Limits {
+            max_connections: 1,
+            ..Limits::default()
+        });
+        // Hold the only slot by sending nothing
+        let idle = TcpStream::connect(addr).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let response = exchange(addr, b"GET /health HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        drop(idle);
+    }
+
+    #[test]
+    fn enforce_deadline() {
+        let addr = start(Limits {
+            deadline: Duration::from_millis(300),
+            ..Limits::default()
+        });
+        // Headers sent steadily, each well within the stall timeout, still
+        // cannot hold the connection past its deadline
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /health HTTP/1.1\r\n").unwrap();
+        let started = Instant::now();
+        for _ in 0..10 {
+            std::thread::sleep(Duration::from_millis(50));
+            if stream.write_all(b"X-Slow: 1\r\n").is_err() {
+                break;
+            }
+        }
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{}",
+            response
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn split_multipart() {
+        let body = b"--xyz\r\n\
+            Content-Disposition: form-data; name=\"census\"\r\n\r\n\
+            H\tcensus\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"filter\"\r\n\r\n\
+            {}\r\n\
+            --xyz--\r\n";
+        let parts = multipart("multipart/form-data; boundary=xyz", body).unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                ("census".to_string(), &b"H\tcensus"[..]),
+                ("filter".to_string(), &b"{}"[..])
+            ]
+        );
+        assert!(multipart("multipart/form-data", body).is_none());
+
+        // Quoted boundaries, other headers, and file names are accepted
+        let body = b"preamble\r\n--a b\r\n\
+            Content-Disposition: form-data; name=\"census\"; filename=\"x.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line one\r\nline two\r\n\
+            --a b--";
+        let parts = multipart("multipart/form-data; boundary=\"a b\"", body).unwrap();
+        assert_eq!(
+            parts,
+            vec![("census".to_string(), &b"line one\r\nline two"[..])]
+        );
+        assert!(multipart("multipart/form-data; boundary=xyz", b"--xyz\r\nname=\"a\"").is_none());
+    }
+}
@@ -0,0 +1,107 @@
+//! Report peptide sequences assigned to more than one protein
+//!
+//! Census lists a shared peptide under every protein it matches, so its PSMs
+//! are counted once per protein. The report gives each shared sequence with
+//! the proteins it is assigned to, and its intensity split evenly between
+//! them, to show how much of the signal in a file is ambiguous before
+//! choosing between unique-only and razor quantification.
+use crate::diagnostics;
+use census_proteomics::Dataset;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::prelude::*;
+
+/// A peptide sequence found under more than one protein
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shared {
+    pub sequence: String,
+    pub accessions: BTreeSet<String>,
+    /// Distinct PSMs of the sequence
+    pub psms: usize,
+    /// Summed reporter ion intensity of the distinct PSMs
+    pub intensity: u64,
+}
+
+impl Shared {
+    /// Intensity attributed to each protein, if split evenly between them
+    pub fn split_intensity(&self) -> f64 {
+        self.intensity as f64 / self.accessions.len() as f64
+    }
+}
+
+/// Find shared sequences in `data`, returned with the summed intensity of
+/// every distinct PSM in the file. A PSM listed under several proteins is
+/// counted once, by its sequence and scan.
+pub fn find(data: &Dataset) -> (Vec<Shared>, u64) {
+    let mut seen = HashSet::new();
+    let mut total = 0;
+    let mut sequences: BTreeMap<&str, Shared> = BTreeMap::new();
+    for prot in &data.proteins {
+        for pep in &prot.peptides {
+            let entry = sequences
+                .entry(pep.sequence.as_str())
+                .or_insert_with(|| Shared {
+                    sequence: pep.sequence.clone(),
+                    accessions: BTreeSet::new(),
+                    psms: 0,
+                    intensity: 0,
+                });
+            entry.accessions.insert(prot.accession.clone());
+            if seen.insert((pep.sequence.as_str(), pep.scan)) {
+                let intensity = pep.values.iter().map(|&v| v as u64).sum::<u64>();
+                entry.psms += 1;
+                entry.intensity += intensity;
+                total += intensity;
+            }
+        }
+    }
+    let shared = sequences
+        .into_values()
+        .filter(|s| s.accessions.len() > 1)
+        .collect();
+    (shared, total)
+}
+
+/// Write the shared sequences of `data` as CSV, most intense first, and
+/// print a summary of the ambiguous signal to stderr
+pub fn write_report<W: Write>(data: &Dataset, mut out: W) -> std::io::Result<()> {
+    let (mut shared, total) = find(data);
+    shared.sort_by_key(|s| std::cmp::Reverse(s.intensity));
+    writeln!(
+        out,
+        "sequence,proteins,accessions,psms,intensity,split_intensity,fraction_of_total"
+    )?;
+    let fraction = |v: u64| {
+        if total > 0 {
+            v as f64 / total as f64
+        } else {
+            0.0
+        }
+    };
+    for s in &shared {
+        writeln!(
+            out,
+            "{},{},{},{},{},{:.1},{:.4}",
+            s.sequence,
+            s.accessions.len(),
+            s.accessions.iter().cloned().collect::<Vec<_>>().join(";"),
+            s.psms,
+            s.intensity,
+            s.split_intensity(),
+            fraction(s.intensity)
+        )?;
+    }
+    out.flush()?;
+
+    let sequences = data
+        .proteins
+        .iter()
+        .flat_map(|prot| prot.peptides.iter().map(|pep| pep.sequence.as_str()))
+        .collect::<HashSet<_>>();
+    diagnostics::info(format!(
+        "{} of {} peptide sequences are shared between proteins, carrying {:.1}% of the reporter ion intensity",
+        shared.len(),
+        sequences.len(),
+        100.0 * fraction(shared.iter().map(|s| s.intensity).sum())
+    ));
+    Ok(())
+}
@@ -0,0 +1,291 @@
+//! SILAC census files, with heavy/light (and medium) peptide pairs
+//!
+//! SILAC census files share the block structure of TMT census files, but
+//! each S line describes a light/heavy pair: its `SAM_INT` (light) and
+//! `REF_INT` (heavy) intensities, the `RATIO` of light to heavy given by a
+//! regression across the chromatographic profile, and the quality of the fit
+//! in `REGRESSION_FACTOR`. A medium channel is read from `MED_INT` when
+//! present. Columns are found by name, from the H lines declaring the P and
+//! S line layouts.
+use crate::meta::Column;
+use crate::writer::{RecordWriter, Rows};
+use census2csv::number::FloatFormat;
+use std::fs;
+use std::path::Path;
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A single light/heavy peptide pair
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pair {
+    pub sequence: String,
+    pub unique: bool,
+    pub light: f64,
+    pub medium: Option<f64>,
+    pub heavy: f64,
+    /// Light to heavy ratio, from regression
+    pub ratio: f64,
+    pub area_ratio: Option<f64>,
+    /// Correlation of the light and heavy profiles, from 0 to 1
+    pub regression: f64,
+    pub determinant: Option<f64>,
+    pub profile_score: Option<f64>,
+    pub file: String,
+    pub scan: String,
+    pub charge: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Protein {
+    pub accession: String,
+    pub description: String,
+    pub pairs: Vec<Pair>,
+}
+
+/// Field named `name` of an S or P line, given its header
+fn named<'a>(header: &[&str], fields: &[&'a str], name: &str) -> Option<&'a str> {
+    let idx = header.iter().position(|h| *h == name)?;
+    fields.get(idx).copied().filter(|f| !f.is_empty())
+}
+
+fn number(header: &[&str], fields: &[&str], name: &str) -> Option<f64> {
+    named(header, fields, name).and_then(|f| f.parse().ok())
+}
+
+/// Read the proteins of a SILAC census file
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Protein>> {
+    let file = fs::read_to_string(path)?;
+    let mut pline = Vec::new();
+    let mut sline = Vec::new();
+    let mut proteins: Vec<Protein> = Vec::new();
+    for (idx, line) in file.lines().enumerate() {
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        match fields[0] {
+            "H" if fields.get(1) == Some(&"PLINE") => pline = fields[1..].to_vec(),
+            "H" if fields.get(1) == Some(&"SLINE") => {
+                sline = fields[1..].to_vec();
+                if !sline.contains(&"SAM_INT") || !sline.contains(&"REF_INT") {
+                    return Err(invalid(
+                        "input has no SAM_INT and REF_INT columns, and is not a SILAC census file",
+                    ));
+                }
+            }
+            "P" => proteins.push(Protein {
+                accession: named(&pline, &fields, "LOCUS")
+                    .ok_or_else(|| invalid(format!("line {}: missing LOCUS", idx + 1)))?
+                    .to_string(),
+                description: named(&pline, &fields, "DESCRIPTION")
+                    .unwrap_or("")
+                    .to_string(),
+                pairs: Vec::new(),
+            }),
+            "S" => {
+                let prot = proteins
+                    .last_mut()
+                    .ok_or_else(|| invalid(format!("line {}: S line before P line", idx + 1)))?;
+                let require = |name: &str| {
+                    number(&sline, &fields, name).ok_or_else(|| {
+                        invalid(format!("line {}: missing or invalid {}", idx + 1, name))
+                    })
+                };
+                let text = |name: &str| named(&sline, &fields, name).unwrap_or("").to_string();
+                prot.pairs.push(Pair {
+                    sequence: named(&sline, &fields, "SEQUENCE")
+                        .ok_or_else(|| invalid(format!("line {}: missing SEQUENCE", idx + 1)))?
+                        .to_string(),
+                    unique: named(&sline, &fields, "UNIQUE").is_some(),
+                    light: require("SAM_INT")?,
+                    medium: number(&sline, &fields, "MED_INT"),
+                    heavy: require("REF_INT")?,
+                    ratio: require("RATIO")?,
+                    area_ratio: number(&sline, &fields, "AREA_RATIO"),
+                    regression: require("REGRESSION_FACTOR")?,
+                    determinant: number(&sline, &fields, "DETERMINANT_FACTOR"),
+                    profile_score: number(&sline, &fields, "PROFILE_SCORE"),
+                    file: text("FILE_NAME"),
+                    scan: text("SCAN"),
+                    charge: text("CS"),
+                });
+            }
+            _ => {}
+        }
+    }
+    if sline.is_empty() {
+        return Err(invalid("no SLINE header found"));
+    }
+    Ok(proteins)
+}
+
+/// Remove pairs with a regression factor below `min_regression`, and then
+/// proteins without any remaining pairs
+pub fn filter(proteins: &mut Vec<Protein>, min_regression: f64) {
+    for prot in proteins.iter_mut() {
+        prot.pairs.retain(|p| p.regression >= min_regression);
+    }
+    proteins.retain(|prot| !prot.pairs.is_empty());
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len();
+    if n % 2 == 1 {
+        values[n / 2]
+    } else {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    }
+}
+
+fn push_opt(rows: &mut Rows, value: Option<f64>, format: FloatFormat) {
+    match value {
+        Some(v) => rows.push_float(v, format),
+        None => rows.push(""),
+    }
+}
+
+/// Write one row per pair
+pub fn write_pairs(
+    proteins: &[Protein],
+    out: &mut dyn RecordWriter,
+    format: FloatFormat,
+) -> std::io::Result<()> {
+    let medium = proteins
+        .iter()
+        .flat_map(|p| &p.pairs)
+        .any(|p| p.medium.is_some());
+    let mut cols = vec![
+        Column::new("accession", "string", "Protein accession"),
+        Column::new("description", "string", "Protein description"),
+        Column::new("sequence", "string", "Peptide sequence"),
+        Column::new(
+            "unique",
+            "boolean",
+            "Whether the peptide is unique to the protein",
+        ),
+        Column::new("light", "number", "Light intensity").units("intensity"),
+    ];
+    if medium {
+        cols.push(Column::new("medium", "number", "Medium intensity").units("intensity"));
+    }
+    cols.extend(vec![
+        Column::new("heavy", "number", "Heavy intensity").units("intensity"),
+        Column::new("ratio", "number", "Light to heavy ratio, from regression"),
+        Column::new("area_ratio", "number", "Light to heavy ratio of peak areas"),
+        Column::new(
+            "regression_factor",
+            "number",
+            "Correlation of the light and heavy chromatographic profiles",
+        ),
+        Column::new("determinant_factor", "number", "Census determinant factor"),
+        Column::new("profile_score", "number", "Census profile score"),
+        Column::new("file", "string", "Spectrum file"),
+        Column::new("scan", "string", "Scan number"),
+        Column::new("charge", "string", "Precursor charge state"),
+    ]);
+    out.header(&cols)?;
+
+    let mut rows = Rows::default();
+    for prot in proteins {
+        for pair in &prot.pairs {
+            rows.push(&prot.accession);
+            rows.push(&prot.description);
+            rows.push(&pair.sequence);
+            rows.push(if pair.unique { "true" } else { "false" });
+            rows.push_float(pair.light, format);
+            if medium {
+                push_opt(&mut rows, pair.medium, format);
+            }
+            rows.push_float(pair.heavy, format);
+            rows.push_float(pair.ratio, format);
+            push_opt(&mut rows, pair.area_ratio, format);
+            rows.push_float(pair.regression, format);
+            push_opt(&mut rows, pair.determinant, format);
+            push_opt(&mut rows, pair.profile_score, format);
+            rows.push(&pair.file);
+            rows.push(&pair.scan);
+            rows.push(&pair.charge);
+            rows.end();
+        }
+    }
+    for row in rows.iter() {
+        out.record(row)?;
+    }
+    out.finish()
+}
+
+/// Write one row per protein, summarizing the ratios of its pairs
+pub fn write_proteins(
+    proteins: &[Protein],
+    out: &mut dyn RecordWriter,
+    format: FloatFormat,
+) -> std::io::Result<()> {
+    let cols = vec![
+        Column::new("accession", "string", "Protein accession"),
+        Column::new("description", "string", "Protein description"),
+        Column::new("pairs", "integer", "Number of peptide pairs"),
+        Column::new("sequence_count", "integer", "Number of distinct sequences"),
+        Column::new("light", "number", "Light intensity, summed across pairs").units("intensity"),
+        Column::new("heavy", "number", "Heavy intensity, summed across pairs").units("intensity"),
+        Column::new(
+            "ratio_mean",
+            "number",
+            "Mean light to heavy ratio across pairs",
+        ),
+        Column::new(
+            "ratio_median",
+            "number",
+            "Median light to heavy ratio across pairs",
+        ),
+        Column::new(
+            "log2_ratio_median",
+            "number",
+            "Median log2 light to heavy ratio across pairs with a positive ratio",
+        ),
+        Column::new(
+            "regression_factor_mean",
+            "number",
+            "Mean regression factor across pairs",
+        ),
+    ];
+    out.header(&cols)?;
+
+    let mut rows = Rows::default();
+    for prot in proteins {
+        let n = prot.pairs.len();
+        let mut ratios = prot.pairs.iter().map(|p| p.ratio).collect::<Vec<f64>>();
+        let mut logs = ratios
+            .iter()
+            .filter(|&&r| r > 0.0)
+            .map(|r| r.log2())
+            .collect::<Vec<f64>>();
+        let mut sequences = prot
+            .pairs
+            .iter()
+            .map(|p| p.sequence.as_str())
+            .collect::<Vec<&str>>();
+        sequences.sort_unstable();
+        sequences.dedup();
+
+        rows.push(&prot.accession);
+        rows.push(&prot.description);
+        rows.push_int(n);
+        rows.push_int(sequences.len());
+        rows.push_float(prot.pairs.iter().map(|p| p.light).sum(), format);
+        rows.push_float(prot.pairs.iter().map(|p| p.heavy).sum(), format);
+        rows.push_float(ratios.iter().sum::<f64>() / n as f64, format);
+        rows.push_float(median(&mut ratios), format);
+        if logs.is_empty() {
+            rows.push("");
+        } else {
+            rows.push_float(median(&mut logs), format);
+        }
+        let regression = prot.pairs.iter().map(|p| p.regression).sum::<f64>() / n as f64;
+        rows.push_float(regression, format);
+        rows.end();
+    }
+    for row in rows.iter() {
+        out.record(row)?;
+    }
+    out.finish()
+}
@@ -0,0 +1,141 @@
+//! Split a wide output table into one table per channel, or per group of
+//! channels sharing a condition, for downstream tools that expect a single
+//! sample per file
+use crate::writer::{Rows, Table};
+use std::str::FromStr;
+
+/// How to split channel columns between output files
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SplitBy {
+    Channel,
+    /// Channels with the same condition are written together. Channels
+    /// without a condition are written to separate files.
+    Condition,
+}
+
+impl FromStr for SplitBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "channel" => Ok(SplitBy::Channel),
+            "condition" => Ok(SplitBy::Condition),
+            _ => Err(format!("unknown split {}", s)),
+        }
+    }
+}
+
+/// Split `table` into named parts that each retain every non-channel column,
+/// in the order the channel or condition is first encountered. Names are
+/// used to name the output files.
+pub fn split(table: &Table, by: SplitBy) -> Vec<(String, Table)> {
+    let columns = &table.columns;
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (idx, col) in columns.iter().enumerate() {
+        if col.channel.is_none() {
+            continue;
+        }
+        let name = match (by, &col.condition) {
+            (SplitBy::Condition, Some(cond)) => cond.clone(),
+            _ => col.name.clone(),
+        };
+        match groups.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, group)) => group.push(idx),
+            None => groups.push((name, vec![idx])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(name, group)| {
+            let keep = (0..columns.len())
+                .filter(|i| columns[*i].channel.is_none() || group.contains(i))
+                .collect::<Vec<usize>>();
+            let mut rows = Rows::default();
+            for row in table.rows.iter() {
+                rows.record(keep.iter().map(|&i| row.get(i).unwrap_or("")));
+            }
+            let part = Table {
+                columns: keep.iter().map(|&i| columns[i].clone()).collect(),
+                rows,
+            };
+            (name, part)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::Column;
+
+    fn table() -> Table {
+        let mut columns = vec![
+            Column::new("accession", "string", ""),
+            Column::new("126", "number", "").channel(1, "126"),
+            Column::new("127", "number", "").channel(2, "127"),
+            Column::new("128", "number", "").channel(3, "128"),
+            Column::new("spectral_count", "integer", ""),
+        ];
+        columns[1].condition = Some("treated".into());
+        columns[3].condition = Some("treated".into());
+        let mut rows = Rows::default();
+        rows.record(["P1", "1", "2", "3", "4"]);
+        rows.record(["P2", "5", "6", "7", "8"]);
+        Table { columns, rows }
+    }
+
+    fn summary(parts: &[(String, Table)]) -> Vec<(String, Vec<String>, Vec<Vec<String>>)> {
+        parts
+            .iter()
+            .map(|(name, part)| {
+                (
+                    name.clone(),
+                    part.columns.iter().map(|c| c.name.clone()).collect(),
+                    part.rows
+                        .iter()
+                        .map(|row| row.iter().map(String::from).collect())
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn by_channel() {
+        let parts = split(&table(), SplitBy::Channel);
+        let names = parts.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["126", "127", "128"]);
+        assert_eq!(
+            summary(&parts)[1],
+            (
+                "127".to_string(),
+                strings(&["accession", "127", "spectral_count"]),
+                vec![strings(&["P1", "2", "4"]), strings(&["P2", "6", "8"])],
+            )
+        );
+    }
+
+    #[test]
+    fn by_condition() {
+        let parts = summary(&split(&table(), SplitBy::Condition));
+        assert_eq!(parts.len(), 2);
+        assert_eq!(
+            parts[0],
+            (
+                "treated".to_string(),
+                strings(&["accession", "126", "128", "spectral_count"]),
+                vec![
+                    strings(&["P1", "1", "3", "4"]),
+                    strings(&["P2", "5", "7", "8"])
+                ],
+            )
+        );
+        // Channels without a condition are written alone
+        assert_eq!(parts[1].0, "127");
+    }
+}
@@ -0,0 +1,375 @@
+//! Write SQLite database files holding a single table
+//!
+//! No SQLite library is linked, so the database file format is encoded here
+//! directly: rows are packed into the leaf pages of a table b-tree as they
+//! arrive, and the interior pages, and the schema on the first page, are
+//! added once every row has been written. Rows whose records do not fit on
+//! a page spill into overflow pages, as SQLite itself does.
+use std::io::prelude::*;
+
+/// Size of every page. This is the SQLite default, and leaves room for
+/// rows of a few hundred columns on a page.
+const PAGE_SIZE: usize = 4096;
+
+/// Largest payload stored entirely on a table leaf page
+const MAX_LOCAL: usize = PAGE_SIZE - 35;
+
+/// Smallest payload stored on a table leaf page when the rest overflows
+const MIN_LOCAL: usize = (PAGE_SIZE - 12) * 32 / 255 - 23;
+
+/// Most child pointers on an interior page, allowing for the largest
+/// possible rowid in each cell
+const MAX_CHILDREN: usize = (PAGE_SIZE - 12) / (4 + 9 + 2) + 1;
+
+/// Value of a single field of a row
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+}
+
+/// Append `value` as a SQLite variable length integer
+fn varint(buf: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        // Nine bytes, the last of which holds eight bits
+        let mut bytes = [0u8; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest & 0x7f) as u8 | 0x80;
+            rest >>= 7;
+        }
+        buf.extend_from_slice(&bytes);
+        return;
+    }
+    let mut groups = [0u8; 8];
+    let mut len = 0;
+    let mut rest = value;
+    loop {
+        groups[len] = (rest & 0x7f) as u8;
+        len += 1;
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    for idx in (0..len).rev() {
+        buf.push(groups[idx] | if idx > 0 { 0x80 } else { 0 });
+    }
+}
+
+fn varint_len(value: u64) -> usize {
+    let mut buf = Vec::with_capacity(9);
+    varint(&mut buf, value);
+    buf.len()
+}
+
+/// Serial type and big-endian bytes of an integer, in the fewest bytes
+fn integer(value: i64) -> (u64, Vec<u8>) {
+    let bytes = value.to_be_bytes();
+    match value {
+        0 => (8, Vec::new()),
+        1 => (9, Vec::new()),
+        -0x80..=0x7f => (1, bytes[7..].to_vec()),
+        -0x8000..=0x7fff => (2, bytes[6..].to_vec()),
+        -0x80_0000..=0x7f_ffff => (3, bytes[5..].to_vec()),
+        -0x8000_0000..=0x7fff_ffff => (4, bytes[4..].to_vec()),
+        -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, bytes[2..].to_vec()),
+        _ => (6, bytes.to_vec()),
+    }
+}
+
+/// Encode `values` in the SQLite record format
+fn record(values: &[Value]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        match value {
+            Value::Null => varint(&mut header, 0),
+            // SQLite does not store NaN, and reads it back as NULL
+            Value::Real(v) if v.is_nan() => varint(&mut header, 0),
+            Value::Integer(v) => {
+                let (kind, bytes) = integer(*v);
+                varint(&mut header, kind);
+                body.extend_from_slice(&bytes);
+            }
+            Value::Real(v) => {
+                varint(&mut header, 7);
+                body.extend_from_slice(&v.to_be_bytes());
+            }
+            Value::Text(s) => {
+                varint(&mut header, s.len() as u64 * 2 + 13);
+                body.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+    // The header length includes its own varint
+    let mut len = header.len() + 1;
+    while varint_len(len as u64) + header.len() != len {
+        len += 1;
+    }
+    let mut out = Vec::with_capacity(len + body.len());
+    varint(&mut out, len as u64);
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Page of a b-tree: a leaf if `right` is `None`, and otherwise an interior
+/// page whose right-most child is `right`. `offset` is the position of the
+/// page header, after the database header on the first page.
+fn btree_page(cells: &[Vec<u8>], right: Option<u32>, offset: usize) -> Vec<u8> {
+    let mut page = vec![0u8; PAGE_SIZE];
+    let header = if right.is_some() { 12 } else { 8 };
+    let mut content = PAGE_SIZE;
+    for (idx, cell) in cells.iter().enumerate() {
+        content -= cell.len();
+        page[content..content + cell.len()].copy_from_slice(cell);
+        let ptr = offset + header + 2 * idx;
+        page[ptr..ptr + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    }
+    page[offset] = if right.is_some() { 0x05 } else { 0x0d };
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    // A content area starting at 65536 is written as 0, which cannot occur
+    // with 4096 byte pages
+    page[offset + 5..offset + 7].copy_from_slice(&(content as u16).to_be_bytes());
+    if let Some(right) = right {
+        page[offset + 8..offset + 12].copy_from_slice(&right.to_be_bytes());
+    }
+    page
+}
+
+/// A SQLite database being built in memory, holding the table `name`
+pub struct Database {
+    name: String,
+    /// CREATE TABLE statement of the table
+    sql: String,
+    /// Every page, with the first page left empty until the schema is written
+    pages: Vec<Vec<u8>>,
+    /// Cells of the leaf page being filled, and the bytes they use
+    cells: Vec<Vec<u8>>,
+    used: usize,
+    /// Page number and largest rowid of each complete leaf page
+    leaves: Vec<(u32, i64)>,
+    rowid: i64,
+}
+
+impl Database {
+    pub fn new<S: Into<String>>(name: S, sql: String) -> Database {
+        Database {
+            name: name.into(),
+            sql,
+            pages: vec![Vec::new()],
+            cells: Vec::new(),
+            used: 0,
+            leaves: Vec::new(),
+            rowid: 0,
+        }
+    }
+
+    /// Add `page`, returning its page number
+    fn push_page(&mut self, page: Vec<u8>) -> u32 {
+        self.pages.push(page);
+        self.pages.len() as u32
+    }
+
+    /// Cell of a table leaf page holding `payload` for `rowid`, writing any
+    /// part of the payload that does not fit on the page to overflow pages
+    fn leaf_cell(&mut self, rowid: i64, payload: &[u8]) -> Vec<u8> {
+        let local = if payload.len() <= MAX_LOCAL {
+            payload.len()
+        } else {
+            let size = MIN_LOCAL + (payload.len() - MIN_LOCAL) % (PAGE_SIZE - 4);
+            if size <= MAX_LOCAL {
+                size
+            } else {
+                MIN_LOCAL
+            }
+        };
+        let mut cell = Vec::with_capacity(local + 22);
+        varint(&mut cell, payload.len() as u64);
+        varint(&mut cell, rowid as u64);
+        cell.extend_from_slice(&payload[..local]);
+        if local < payload.len() {
+            let chunks = payload[local..].chunks(PAGE_SIZE - 4).collect::<Vec<_>>();
+            let first = self.pages.len() as u32 + 1;
+            cell.extend_from_slice(&first.to_be_bytes());
+            for (idx, chunk) in chunks.iter().enumerate() {
+                let next = if idx + 1 < chunks.len() {
+                    first + idx as u32 + 1
+                } else {
+                    0
+                };
+                let mut page = vec![0u8; PAGE_SIZE];
+                page[..4].copy_from_slice(&next.to_be_bytes());
+                page[4..4 + chunk.len()].copy_from_slice(chunk);
+                self.push_page(page);
+            }
+        }
+        cell
+    }
+
+    /// Complete the leaf page being filled
+    fn flush(&mut self) {
+        let cells = std::mem::take(&mut self.cells);
+        let page = self.push_page(btree_page(&cells, None, 0));
+        self.leaves.push((page, self.rowid));
+        self.used = 0;
+    }
+
+    /// Append a row to the table
+    pub fn insert(&mut self, values: &[Value]) {
+        self.rowid += 1;
+        let payload = record(values);
+        let cell = self.leaf_cell(self.rowid, &payload);
+        if self.used + cell.len() + 2 > PAGE_SIZE - 8 {
+            // The row belongs on the next page
+            self.rowid -= 1;
+            self.flush();
+            self.rowid += 1;
+        }
+        self.used += cell.len() + 2;
+        self.cells.push(cell);
+    }
+
+    /// Add interior pages above the leaves, returning the root page number
+    fn build_tree(&mut self) -> u32 {
+        if !self.cells.is_empty() || self.leaves.is_empty() {
+            self.flush();
+        }
+        let mut level = std::mem::take(&mut self.leaves);
+        while level.len() > 1 {
+            // Spread children evenly, so that every page has at least two
+            let pages = level.len().div_ceil(MAX_CHILDREN);
+            let mut next = Vec::with_capacity(pages);
+            let mut children = level.as_slice();
+            for idx in 0..pages {
+                let count = children.len().div_ceil(pages - idx);
+                let (group, rest) = children.split_at(count);
+                children = rest;
+                let (last, left) = group.split_last().unwrap();
+                let cells = left
+                    .iter()
+                    .map(|(page, key)| {
+                        let mut cell = page.to_be_bytes().to_vec();
+                        varint(&mut cell, *key as u64);
+                        cell
+                    })
+                    .collect::<Vec<_>>();
+                let page = self.push_page(btree_page(&cells, Some(last.0), 0));
+                next.push((page, last.1));
+            }
+            level = next;
+        }
+        level[0].0
+    }
+
+    /// Write the database to `out`
+    pub fn write<W: Write>(mut self, mut out: W) -> std::io::Result<()> {
+        let root = self.build_tree();
+        let schema = record(&[
+            Value::Text("table"),
+            Value::Text(&self.name),
+            Value::Text(&self.name),
+            Value::Integer(root as i64),
+            Value::Text(&self.sql),
+        ]);
+        let cell = self.leaf_cell(1, &schema);
+        let first = if cell.len() + 2 <= PAGE_SIZE - 100 - 8 {
+            btree_page(&[cell], None, 100)
+        } else {
+            // The schema does not fit after the database header, so the
+            // first page points to a leaf page holding it
+            let leaf = self.push_page(btree_page(&[cell], None, 0));
+            btree_page(&[], Some(leaf), 100)
+        };
+        self.pages[0] = first;
+
+        let count = self.pages.len() as u32;
+        let header = &mut self.pages[0][..100];
+        header[..16].copy_from_slice(b"SQLite format 3\0");
+        header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        // File format versions, reserved bytes, and payload fractions
+        header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        for (offset, value) in [
+            // File change counter
+            (24, 1),
+            (28, count),
+            // Schema cookie and schema format
+            (40, 1),
+            (44, 4),
+            // UTF-8 text encoding
+            (56, 1),
+            // Version valid for, matching the change counter
+            (92, 1),
+            (96, 3_045_000),
+        ] {
+            header[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        for page in &self.pages {
+            out.write_all(page)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn varints() {
+        let encode = |value| {
+            let mut buf = Vec::new();
+            varint(&mut buf, value);
+            buf
+        };
+        assert_eq!(encode(0), [0]);
+        assert_eq!(encode(127), [0x7f]);
+        assert_eq!(encode(128), [0x81, 0x00]);
+        assert_eq!(encode(16384), [0x81, 0x80, 0x00]);
+        assert_eq!(encode(u64::MAX), [0xff; 9]);
+        assert_eq!(
+            encode(1 << 56),
+            [0x80, 0xc0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]
+        );
+    }
+
+    #[test]
+    fn records() {
+        assert_eq!(
+            record(&[
+                Value::Null,
+                Value::Integer(1),
+                Value::Integer(-2),
+                Value::Integer(300),
+                Value::Text("ab"),
+            ]),
+            [6, 0, 9, 1, 2, 17, 0xfe, 0x01, 0x2c, b'a', b'b']
+        );
+        assert_eq!(
+            record(&[Value::Real(1.5), Value::Real(f64::NAN)]),
+            [3, 7, 0, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn pages() {
+        let mut db = Database::new("t", "CREATE TABLE t (a TEXT)".into());
+        let long = "x".repeat(10_000);
+        for _ in 0..3 {
+            db.insert(&[Value::Text(&long)]);
+        }
+        for _ in 0..2000 {
+            db.insert(&[Value::Text("short")]);
+        }
+        let mut out = Vec::new();
+        db.write(&mut out).unwrap();
+        assert_eq!(out.len() % PAGE_SIZE, 0);
+        assert_eq!(&out[..16], b"SQLite format 3\0");
+        let pages = u32::from_be_bytes([out[28], out[29], out[30], out[31]]);
+        assert_eq!(pages as usize, out.len() / PAGE_SIZE);
+    }
+}
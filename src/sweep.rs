@@ -0,0 +1,130 @@
+//! Report how many proteins and peptides are retained as the threshold of a
+//! single filter is varied, for choosing thresholds from the data
+use census2csv::filter::{Filter, PeptideFilter, ProteinFilter};
+use census2csv::parallel;
+use census_proteomics::Dataset;
+use std::collections::HashSet;
+use std::io::prelude::*;
+
+/// Thresholds from `start` to `stop`, inclusive, in increments of `step`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub stop: f64,
+    pub step: f64,
+}
+
+impl std::str::FromStr for Range {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(':')
+            .map(|p| p.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("expected start:stop:step, found {}", s))?;
+        match *parts.as_slice() {
+            [start, stop, step] if step > 0.0 && stop >= start => Ok(Range { start, stop, step }),
+            [_, _, _] => Err("step must be positive, and stop at least start".into()),
+            _ => Err(format!("expected start:stop:step, found {}", s)),
+        }
+    }
+}
+
+impl Range {
+    pub fn values(&self) -> Vec<f64> {
+        let steps = ((self.stop - self.start) / self.step + 1e-9).floor() as usize;
+        (0..=steps)
+            .map(|i| self.start + i as f64 * self.step)
+            .collect()
+    }
+}
+
+/// Names of the filters that can be swept
+pub const RULES: &[&str] = &[
+    "TotalIntensity",
+    "MeanIntensity",
+    "MaxChannelIntensity",
+    "Purity",
+    "SpectralCounts",
+    "SequenceCounts",
+    "ProteinTotalIntensity",
+    "TopNPeptidesByIntensity",
+];
+
+/// Add the filter named `rule`, with a threshold of `value`, to `base`.
+/// `TotalIntensity` is the peptide filter, and `ProteinTotalIntensity` the
+/// protein filter of the same name.
+fn with_rule<'a>(base: &Filter<'a>, rule: &str, value: f64) -> Result<Filter<'a>, String> {
+    let base = base.clone();
+    Ok(match rule {
+        "TotalIntensity" => base.add_peptide_filter(PeptideFilter::TotalIntensity(value as u32)),
+        "MeanIntensity" => base.add_peptide_filter(PeptideFilter::MeanIntensity(value)),
+        "MaxChannelIntensity" => {
+            base.add_peptide_filter(PeptideFilter::MaxChannelIntensity(value as u32))
+        }
+        "Purity" => base.add_peptide_filter(PeptideFilter::Purity(value as f32)),
+        "SpectralCounts" => base.add_protein_filter(ProteinFilter::SpectralCounts(value as u16)),
+        "SequenceCounts" => base.add_protein_filter(ProteinFilter::SequenceCounts(value as u16)),
+        "ProteinTotalIntensity" => {
+            base.add_protein_filter(ProteinFilter::TotalIntensity(value as u64))
+        }
+        "TopNPeptidesByIntensity" => {
+            base.add_protein_filter(ProteinFilter::TopNPeptidesByIntensity(value as usize))
+        }
+        _ => {
+            return Err(format!(
+                "cannot sweep {}, expected one of {}",
+                rule,
+                RULES.join(", ")
+            ))
+        }
+    })
+}
+
+/// Apply `rule` to `data` at each threshold of `range`, on top of the rules
+/// of `base`, and write the proteins, peptides, and PSMs retained at each
+/// threshold as CSV
+pub fn sweep<'a, W: Write>(
+    data: &Dataset,
+    base: &Filter<'a>,
+    rule: &str,
+    range: Range,
+    threads: usize,
+    mut out: W,
+) -> std::io::Result<()> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+    with_rule(base, rule, range.start).map_err(invalid)?;
+    writeln!(out, "{},proteins,peptides,psms", rule)?;
+    for value in range.values() {
+        let filter = with_rule(base, rule, value).map_err(invalid)?;
+        let copy = Dataset {
+            proteins: data.proteins.clone(),
+            channels: data.channels,
+        };
+        let kept = parallel::filter(copy, &filter, threads);
+        let peptides = kept
+            .proteins
+            .iter()
+            .flat_map(|prot| {
+                prot.peptides
+                    .iter()
+                    .map(move |pep| (prot.accession.as_str(), pep.sequence.as_str()))
+            })
+            .collect::<HashSet<_>>();
+        let psms = kept
+            .proteins
+            .iter()
+            .map(|p| p.peptides.len())
+            .sum::<usize>();
+        writeln!(
+            out,
+            "{},{},{},{}",
+            value,
+            kept.proteins.len(),
+            peptides.len(),
+            psms
+        )?;
+    }
+    Ok(())
+}
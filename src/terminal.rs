@@ -0,0 +1,210 @@
+//! Keyboard input and full-screen output for the terminal explorer
+//!
+//! On Unix the terminal is switched into raw mode with termios, so that
+//! keys arrive as they are pressed, and the alternate screen is used, so
+//! that the shell's scrollback is restored on exit. Output is drawn with
+//! ANSI escape sequences, which every terminal emulator in use supports.
+use std::io::prelude::*;
+
+/// A key press
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    /// Ctrl-C, which raw mode delivers as input rather than a signal
+    Interrupt,
+    Char(char),
+}
+
+/// Decode the keys in `bytes`, as read from a terminal in raw mode.
+/// Unrecognized escape sequences are skipped.
+pub fn keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let key = match ch {
+            '\x1b' => match chars.peek() {
+                Some('[') | Some('O') => {
+                    chars.next();
+                    // Parameters, then the final character of the sequence
+                    let mut params = String::new();
+                    let last = loop {
+                        match chars.next() {
+                            Some(c) if c.is_ascii_digit() || c == ';' => params.push(c),
+                            other => break other,
+                        }
+                    };
+                    match (last, params.split(';').next().unwrap_or("")) {
+                        (Some('A'), _) => Key::Up,
+                        (Some('B'), _) => Key::Down,
+                        (Some('C'), _) => Key::Right,
+                        (Some('D'), _) => Key::Left,
+                        (Some('H'), _) => Key::Home,
+                        (Some('F'), _) => Key::End,
+                        (Some('~'), "1") | (Some('~'), "7") => Key::Home,
+                        (Some('~'), "4") | (Some('~'), "8") => Key::End,
+                        (Some('~'), "5") => Key::PageUp,
+                        (Some('~'), "6") => Key::PageDown,
+                        _ => continue,
+                    }
+                }
+                _ => Key::Esc,
+            },
+            '\r' | '\n' => Key::Enter,
+            '\t' => Key::Tab,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\x03' => Key::Interrupt,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+/// `text` cut or padded with spaces to exactly `width` characters
+pub fn fit(text: &str, width: usize) -> String {
+    let mut out = text.chars().take(width).collect::<String>();
+    let len = out.chars().count();
+    out.extend(std::iter::repeat(' ').take(width - len));
+    out
+}
+
+/// Draw `lines` over the whole screen. Lines given as `(true, text)` are
+/// highlighted in reverse video.
+pub fn draw<W: Write>(out: &mut W, lines: &[(bool, String)], width: usize) -> std::io::Result<()> {
+    let mut frame = String::from("\x1b[H");
+    for (idx, (highlight, line)) in lines.iter().enumerate() {
+        if idx > 0 {
+            frame.push_str("\r\n");
+        }
+        if *highlight {
+            frame.push_str("\x1b[7m");
+            frame.push_str(&fit(line, width));
+            frame.push_str("\x1b[0m");
+        } else {
+            frame.push_str(&fit(line, width));
+        }
+    }
+    frame.push_str("\x1b[J");
+    out.write_all(frame.as_bytes())?;
+    out.flush()
+}
+
+#[cfg(unix)]
+pub use self::unix::{size, Raw};
+
+#[cfg(unix)]
+mod unix {
+    use std::io::prelude::*;
+
+    /// Raw mode on the terminal of stdin, with output on the alternate
+    /// screen, until this is dropped
+    pub struct Raw {
+        original: libc::termios,
+    }
+
+    impl Raw {
+        pub fn enable() -> std::io::Result<Raw> {
+            let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut raw = original;
+            unsafe { libc::cfmakeraw(&mut raw) };
+            // Reads wait at most 100ms, so that resizing is noticed
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let guard = Raw { original };
+            // Alternate screen, with the cursor hidden
+            let mut out = std::io::stdout();
+            out.write_all(b"\x1b[?1049h\x1b[?25l")?;
+            out.flush()?;
+            Ok(guard)
+        }
+    }
+
+    impl Drop for Raw {
+        fn drop(&mut self) {
+            let mut out = std::io::stdout();
+            let _ = out.write_all(b"\x1b[?25h\x1b[?1049l");
+            let _ = out.flush();
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original) };
+        }
+    }
+
+    /// Width and height of the terminal of stdout, in characters
+    pub fn size() -> Option<(usize, usize)> {
+        let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+        match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => {
+                Some((size.ws_col as usize, size.ws_row as usize))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_keys() {
+        assert_eq!(
+            keys(b"\x1b[A\x1b[B\x1bOC\x1b[D\x1b[5~\x1b[6~\x1b[H\x1b[4~\x1b[1;5A"),
+            [
+                Key::Up,
+                Key::Down,
+                Key::Right,
+                Key::Left,
+                Key::PageUp,
+                Key::PageDown,
+                Key::Home,
+                Key::End,
+                Key::Up
+            ]
+        );
+        assert_eq!(
+            keys("q/é\r\t\x7f\x03\x1b".as_bytes()),
+            [
+                Key::Char('q'),
+                Key::Char('/'),
+                Key::Char('é'),
+                Key::Enter,
+                Key::Tab,
+                Key::Backspace,
+                Key::Interrupt,
+                Key::Esc
+            ]
+        );
+        // An unknown sequence is skipped, and so are other control bytes
+        assert_eq!(keys(b"\x1b[99z\x01x"), [Key::Char('x')]);
+    }
+
+    #[test]
+    fn draw_screen() {
+        assert_eq!(fit("abcdef", 4), "abcd");
+        assert_eq!(fit("▁█", 4), "▁█  ");
+        let mut out = Vec::new();
+        draw(&mut out, &[(false, "one".into()), (true, "two".into())], 5).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[Hone  \r\n\x1b[7mtwo  \x1b[0m\x1b[J"
+        );
+    }
+}
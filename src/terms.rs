@@ -0,0 +1,72 @@
+//! Restrict output to proteins annotated with a term, such as a GO term or
+//! CORUM complex
+//!
+//! Annotations are tab-delimited, with an accession and a term on each
+//! line. Several terms may be given for an accession, on separate lines or
+//! separated by semicolons. Terms match a selection if they contain it,
+//! ignoring case, so that `proteasome` selects proteins annotated with
+//! `proteasome complex` or `26S proteasome`.
+use crate::uniprot;
+use std::collections::HashMap;
+
+fn invalid<S: Into<String>>(msg: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Annotated terms, and the selection to match against them
+pub struct Selection {
+    terms: HashMap<String, Vec<String>>,
+    /// Lowercased selection
+    query: String,
+}
+
+impl Selection {
+    /// Read annotations from `text`, selecting proteins with a term
+    /// containing `query`
+    pub fn read(text: &str, query: &str) -> std::io::Result<Selection> {
+        let mut terms: HashMap<String, Vec<String>> = HashMap::new();
+        for line in text.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split('\t').map(str::trim);
+            let accession = fields.next().unwrap_or("");
+            let annotated = fields.next().unwrap_or("");
+            if accession.is_empty() || annotated.is_empty() {
+                continue;
+            }
+            let entry = terms.entry(accession.to_string()).or_default();
+            for term in annotated
+                .split(';')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+            {
+                if !entry.iter().any(|t| t == term) {
+                    entry.push(term.to_string());
+                }
+            }
+        }
+        if terms.is_empty() {
+            return Err(invalid("no annotated terms"));
+        }
+        Ok(Selection {
+            terms,
+            query: query.to_lowercase(),
+        })
+    }
+
+    /// Terms of `accession` matching the selection, looked up as written
+    /// and then as the UniProt accession of a locus
+    pub fn matching(&self, accession: &str) -> Vec<&str> {
+        let annotated = self
+            .terms
+            .get(accession)
+            .or_else(|| uniprot::accession(accession).and_then(|acc| self.terms.get(acc)));
+        annotated
+            .into_iter()
+            .flatten()
+            .filter(|term| term.to_lowercase().contains(&self.query))
+            .map(String::as_str)
+            .collect()
+    }
+}
@@ -0,0 +1,46 @@
+//! Variance-stabilizing transforms of channel values
+//!
+//! Reporter ion intensities have a variance that grows with their mean, which
+//! violates the assumptions of linear models. Both transforms are close to a
+//! logarithm for values much larger than the cofactor, but remain finite and
+//! approximately linear near zero, so that missing values stay defined.
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Inverse hyperbolic sine, `asinh(x / c)`
+    Asinh,
+    /// Generalized base-2 logarithm, `log2((x + sqrt(x^2 + c^2)) / 2)`
+    Glog,
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "asinh" | "arcsinh" | "vsn" => Ok(Transform::Asinh),
+            "glog" => Ok(Transform::Glog),
+            _ => Err(format!("unknown transform {}", s)),
+        }
+    }
+}
+
+impl Transform {
+    /// Transform `value`, using the positive `cofactor` to choose where the
+    /// transform changes from linear to logarithmic
+    pub fn apply(self, value: f64, cofactor: f64) -> f64 {
+        match self {
+            Transform::Asinh => (value / cofactor).asinh(),
+            Transform::Glog => ((value + value.hypot(cofactor)) / 2.0).log2(),
+        }
+    }
+
+    /// Short name, used as a suffix for transformed columns
+    pub fn name(self) -> &'static str {
+        match self {
+            Transform::Asinh => "asinh",
+            Transform::Glog => "glog",
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Enrich output with gene names, protein names, and subcellular location
+//! retrieved from the UniProt REST API
+//!
+//! Results are cached on disk in a tab-delimited file, so that repeated runs
+//! only query the API for accessions that have not been seen before. If the
+//! API cannot be reached, previously cached entries are used and any
+//! remaining accessions are left blank.
+use crate::diagnostics;
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::Command;
+
+const ENDPOINT: &str = "https://rest.uniprot.org/uniprotkb/accessions";
+const FIELDS: &str = "accession,gene_primary,protein_name,cc_subcellular_location";
+/// Maximum number of accessions to request in a single query
+const BATCH: usize = 100;
+
+/// Annotation retrieved from UniProt for a single accession
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Entry {
+    pub gene: String,
+    pub name: String,
+    pub location: String,
+}
+
+pub struct UniProt {
+    entries: HashMap<String, Entry>,
+    cache: PathBuf,
+    /// Set once the API could not be reached, so that it is not retried
+    offline: bool,
+}
+
+/// Default location of the on-disk cache
+pub fn default_cache() -> PathBuf {
+    let mut path = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".cache"),
+            None => PathBuf::from("."),
+        },
+    };
+    path.push("census2csv");
+    path.push("uniprot.tsv");
+    path
+}
+
+/// Extract a UniProt accession from a census locus, which may be a bare
+/// accession (P12345) or a FASTA-style identifier (sp|P12345|ALB_HUMAN).
+/// Returns `None` for decoy and contaminant entries.
+pub fn accession(locus: &str) -> Option<&str> {
+    if locus.contains("Reverse") || locus.to_lowercase().starts_with("contaminant") {
+        return None;
+    }
+    match locus.split('|').nth(1) {
+        Some(acc) => Some(acc),
+        None => Some(locus),
+    }
+}
+
+/// Strip evidence codes and the leading label from a UniProt comment field
+fn clean_location(s: &str) -> String {
+    let s = s.trim_start_matches("SUBCELLULAR LOCATION: ");
+    let mut out = String::with_capacity(s.len());
+    let mut depth = 0;
+    for c in s.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 => out.push(c),
+            _ => {}
+        }
+    }
+    out.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace(" .", ".")
+        .replace(" ;", ";")
+}
+
+impl UniProt {
+    /// Open the cache at `path`, which need not exist yet
+    pub fn open(cache: PathBuf) -> UniProt {
+        let mut entries = HashMap::new();
+        if let Ok(file) = fs::read_to_string(&cache) {
+            for line in file.lines() {
+                let f = line.split('\t').collect::<Vec<&str>>();
+                if f.len() == 4 {
+                    entries.insert(
+                        f[0].to_string(),
+                        Entry {
+                            gene: f[1].into(),
+                            name: f[2].into(),
+                            location: f[3].into(),
+                        },
+                    );
+                }
+            }
+        }
+        UniProt {
+            entries,
+            cache,
+            offline: false,
+        }
+    }
+
+    pub fn get(&self, accession: &str) -> Option<&Entry> {
+        self.entries.get(accession)
+    }
+
+    fn query(accessions: &[&str]) -> std::io::Result<String> {
+        let url = format!(
+            "{}?accessions={}&fields={}&format=tsv",
+            ENDPOINT,
+            accessions.join(","),
+            FIELDS
+        );
+        let output = Command::new("curl")
+            .args(["-sSf", "--max-time", "30", &url])
+            .output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Query UniProt for any accessions that are not already cached. Failure
+    /// to reach the API is reported, but is not an error.
+    pub fn fetch<'a, I: Iterator<Item = &'a str>>(&mut self, loci: I) {
+        let mut missing = loci
+            .filter_map(accession)
+            .filter(|acc| !self.entries.contains_key(*acc))
+            .collect::<Vec<&str>>();
+        missing.sort();
+        missing.dedup();
+        if missing.is_empty() || self.offline {
+            return;
+        }
+
+        for batch in missing.chunks(BATCH) {
+            let response = match Self::query(batch) {
+                Ok(response) => response,
+                Err(e) => {
+                    diagnostics::warning(
+                        None,
+                        format!(
+                            "Unable to reach UniProt ({}), using cached annotations only",
+                            e
+                        ),
+                    );
+                    self.offline = true;
+                    break;
+                }
+            };
+            for line in response.lines().skip(1) {
+                let f = line.split('\t').collect::<Vec<&str>>();
+                if f.len() < 4 {
+                    continue;
+                }
+                let name = match f[2].find(" (") {
+                    Some(idx) => &f[2][..idx],
+                    None => f[2],
+                };
+                self.entries.insert(
+                    f[0].to_string(),
+                    Entry {
+                        gene: f[1].split_whitespace().next().unwrap_or("").to_string(),
+                        name: name.to_string(),
+                        location: clean_location(f[3]),
+                    },
+                );
+            }
+            // Remember accessions that UniProt does not know about, so that
+            // they are not queried again
+            for acc in batch {
+                self.entries.entry(acc.to_string()).or_default();
+            }
+        }
+
+        if let Err(e) = self.save() {
+            diagnostics::warning(
+                None,
+                format!(
+                    "Unable to write UniProt cache {}: {}",
+                    self.cache.display(),
+                    e
+                ),
+            );
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.cache.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut keys = self.entries.keys().collect::<Vec<&String>>();
+        keys.sort();
+        let mut file = fs::File::create(&self.cache)?;
+        for key in keys {
+            let e = &self.entries[key];
+            writeln!(file, "{}\t{}\t{}\t{}", key, e.gene, e.name, e.location)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn accessions() {
+        assert_eq!(accession("P12345"), Some("P12345"));
+        assert_eq!(accession("sp|P12345|ALB_HUMAN"), Some("P12345"));
+        assert_eq!(accession("Reverse_sp|P12345|ALB_HUMAN"), None);
+        assert_eq!(accession("contaminant_KERATIN"), None);
+    }
+
+    #[test]
+    fn locations() {
+        assert_eq!(
+            clean_location(
+                "SUBCELLULAR LOCATION: Secreted {ECO:0000269|PubMed:123} . Cytoplasm {ECO:0000250} ;"
+            ),
+            "Secreted. Cytoplasm;"
+        );
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let dir = fixture::dir(
+            "uniprot",
+            &[(
+                "uniprot.tsv",
+                "P12345\tALB\tSerum albumin\tSecreted\nmalformed line\nQ00000\t\t\t\n",
+            )],
+        );
+        let mut uniprot = UniProt::open(dir.join("uniprot.tsv"));
+        let albumin = Entry {
+            gene: "ALB".into(),
+            name: "Serum albumin".into(),
+            location: "Secreted".into(),
+        };
+        assert_eq!(uniprot.get("P12345"), Some(&albumin));
+        assert_eq!(uniprot.get("Q00000"), Some(&Entry::default()));
+        assert_eq!(uniprot.get("malformed line"), None);
+
+        // Every accession is cached, so nothing is queried or rewritten
+        uniprot.fetch(
+            ["sp|P12345|ALB_HUMAN", "Q00000", "Reverse_X"]
+                .iter()
+                .copied(),
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("uniprot.tsv")).unwrap(),
+            "P12345\tALB\tSerum albumin\tSecreted\nmalformed line\nQ00000\t\t\t\n"
+        );
+
+        uniprot.cache = dir.join("nested").join("uniprot.tsv");
+        uniprot.save().unwrap();
+        let reopened = UniProt::open(dir.join("nested").join("uniprot.tsv"));
+        assert_eq!(reopened.entries, uniprot.entries);
+        assert_eq!(
+            fs::read_to_string(dir.join("nested").join("uniprot.tsv")).unwrap(),
+            "P12345\tALB\tSerum albumin\tSecreted\nQ00000\t\t\t\n"
+        );
+    }
+
+    #[test]
+    fn offline_fetch_keeps_cache() {
+        let dir = fixture::dir("uniprot-offline", &[]);
+        let mut uniprot = UniProt::open(dir.join("uniprot.tsv"));
+        uniprot.offline = true;
+        uniprot.fetch(["P12345"].iter().copied());
+        assert_eq!(uniprot.get("P12345"), None);
+        assert!(!dir.join("uniprot.tsv").exists());
+    }
+}
@@ -0,0 +1,587 @@
+//! Output sinks for tables of records
+//!
+//! The protein, peptide, and flat layouts produce rows of string fields, and
+//! a `RecordWriter` decides how those rows are serialized. Fields are passed
+//! unescaped; each writer is responsible for making them safe for its
+//! format.
+use crate::meta::Column;
+use crate::parquet;
+use crate::sqlite::{self, Value};
+use census2csv::arrow::{Array, RecordBatch};
+use census2csv::number::{self, FloatFormat};
+use std::io::prelude::*;
+
+/// Destination for the rows of an output table
+pub trait RecordWriter {
+    /// Begin a table with the given columns
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()>;
+
+    /// Write a row containing one field per column
+    fn record(&mut self, row: Row) -> std::io::Result<()>;
+
+    /// Complete the table, after all records have been written
+    fn finish(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records stored contiguously, with the text of every field in a single
+/// buffer, so that building rows does not allocate per field. Fields are
+/// appended with `push`, and each record is completed with `end`.
+#[derive(Clone, Debug, Default)]
+pub struct Rows {
+    text: String,
+    /// End offset in `text` of each field
+    fields: Vec<usize>,
+    /// End index in `fields` of each record
+    records: Vec<usize>,
+}
+
+impl Rows {
+    /// Append a field to the record being built
+    pub fn push(&mut self, field: &str) {
+        self.text.push_str(field);
+        self.fields.push(self.text.len());
+    }
+
+    /// Append an integer field to the record being built
+    pub fn push_int<I: itoa::Integer>(&mut self, value: I) {
+        number::push_int(&mut self.text, value);
+        self.fields.push(self.text.len());
+    }
+
+    /// Append a floating point field to the record being built
+    pub fn push_float(&mut self, value: f64, format: FloatFormat) {
+        format.push(&mut self.text, value);
+        self.fields.push(self.text.len());
+    }
+
+    /// Complete the record being built
+    pub fn end(&mut self) {
+        self.records.push(self.fields.len());
+    }
+
+    /// Append a complete record
+    pub fn record<S: AsRef<str>, I: IntoIterator<Item = S>>(&mut self, fields: I) {
+        for field in fields {
+            self.push(field.as_ref());
+        }
+        self.end();
+    }
+
+    /// Remove every record, keeping the allocated buffers
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.fields.clear();
+        self.records.clear();
+    }
+
+    /// Number of complete records
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Record `idx`
+    pub fn get(&self, idx: usize) -> Row<'_> {
+        let first = if idx == 0 { 0 } else { self.records[idx - 1] };
+        Row {
+            text: &self.text,
+            start: if first == 0 {
+                0
+            } else {
+                self.fields[first - 1]
+            },
+            ends: &self.fields[first..self.records[idx]],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Row<'_>> {
+        (0..self.len()).map(move |idx| self.get(idx))
+    }
+}
+
+/// A single record of `Rows`
+#[derive(Copy, Clone, Debug)]
+pub struct Row<'a> {
+    text: &'a str,
+    /// Offset in `text` at which the first field begins
+    start: usize,
+    ends: &'a [usize],
+}
+
+impl<'a> Row<'a> {
+    /// Field `idx`, if the record has that many fields
+    pub fn get(&self, idx: usize) -> Option<&'a str> {
+        let end = *self.ends.get(idx)?;
+        let start = if idx == 0 {
+            self.start
+        } else {
+            self.ends[idx - 1]
+        };
+        Some(&self.text[start..end])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> {
+        let text = self.text;
+        let mut start = self.start;
+        self.ends.iter().map(move |&end| {
+            let field = &text[start..end];
+            start = end;
+            field
+        })
+    }
+}
+
+/// Delimited text, such as CSV or TSV. Delimiters appearing within a field
+/// are replaced, rather than quoted, so that outputs remain trivially
+/// splittable: commas become semicolons in CSV, and tabs become spaces in
+/// TSV.
+pub struct Delimited<W: Write> {
+    out: W,
+    sep: u8,
+    /// Line being written, reused between records
+    line: Vec<u8>,
+}
+
+impl<W: Write> Delimited<W> {
+    pub fn csv(out: W) -> Delimited<W> {
+        Delimited {
+            out,
+            sep: b',',
+            line: Vec::new(),
+        }
+    }
+
+    pub fn tsv(out: W) -> Delimited<W> {
+        Delimited {
+            out,
+            sep: b'\t',
+            line: Vec::new(),
+        }
+    }
+
+    /// The underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    fn line<'a, I: Iterator<Item = &'a str>>(&mut self, fields: I) -> std::io::Result<()> {
+        let sep = self.sep;
+        let replacement = if sep == b',' { b';' } else { b' ' };
+        self.line.clear();
+        for (idx, field) in fields.enumerate() {
+            if idx > 0 {
+                self.line.push(sep);
+            }
+            self.line.extend(
+                field
+                    .bytes()
+                    .map(|b| if b == sep { replacement } else { b }),
+            );
+        }
+        self.line.push(b'\n');
+        self.out.write_all(&self.line)
+    }
+}
+
+impl<W: Write> RecordWriter for Delimited<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.line(columns.iter().map(|c| c.name.as_str()))
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        self.line(row.iter())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// SQL script creating and populating a single table, which can be loaded
+/// into SQLite with `sqlite3 out.db < out.sql`
+pub struct Sql<W: Write> {
+    out: W,
+    /// Quoted table name
+    table: String,
+    columns: Vec<Column>,
+    /// Statement being written, reused between records
+    line: String,
+}
+
+impl<W: Write> Sql<W> {
+    pub fn new<S: Into<String>>(out: W, table: S) -> Sql<W> {
+        Sql {
+            out,
+            table: sql_ident(&table.into()),
+            columns: Vec::new(),
+            line: String::new(),
+        }
+    }
+}
+
+fn sql_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// CREATE TABLE statement for the quoted table name `table`
+fn create_table(table: &str, columns: &[Column]) -> String {
+    let defs = columns
+        .iter()
+        .map(|c| {
+            let kind = match c.kind {
+                "integer" | "boolean" => "INTEGER",
+                "number" => "REAL",
+                _ => "TEXT",
+            };
+            format!("{} {}", sql_ident(&c.name), kind)
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("CREATE TABLE {} ({})", table, defs)
+}
+
+impl<W: Write> RecordWriter for Sql<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.columns = columns.to_vec();
+        writeln!(self.out, "BEGIN TRANSACTION;")?;
+        writeln!(self.out, "{};", create_table(&self.table, columns))
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        let line = &mut self.line;
+        line.clear();
+        line.push_str("INSERT INTO ");
+        line.push_str(&self.table);
+        line.push_str(" VALUES (");
+        for (idx, (f, c)) in row.iter().zip(&self.columns).enumerate() {
+            if idx > 0 {
+                line.push_str(", ");
+            }
+            match c.kind {
+                _ if f.is_empty() => line.push_str("NULL"),
+                "boolean" if f == "true" => line.push('1'),
+                "boolean" if f == "false" => line.push('0'),
+                "integer" | "number" | "boolean" if f.parse::<f64>().is_ok() => line.push_str(f),
+                _ => {
+                    line.push('\'');
+                    for ch in f.chars() {
+                        if ch == '\'' {
+                            line.push('\'');
+                        }
+                        line.push(ch);
+                    }
+                    line.push('\'');
+                }
+            }
+        }
+        line.push_str(");\n");
+        self.out.write_all(line.as_bytes())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        writeln!(self.out, "COMMIT;")?;
+        self.out.flush()
+    }
+}
+
+/// Arrow IPC file with typed columns. Empty fields, and numeric fields that
+/// cannot be parsed, are written as nulls.
+pub struct Arrow<W: Write> {
+    out: W,
+    table: Table,
+}
+
+impl<W: Write> Arrow<W> {
+    pub fn new(out: W) -> Arrow<W> {
+        Arrow {
+            out,
+            table: Table::default(),
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for Arrow<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.table.header(columns)
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        self.table.record(row)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        let rows = &self.table.rows;
+        let columns = self
+            .table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(idx, c)| {
+                let fields = rows.iter().map(|r| r.get(idx).filter(|f| !f.is_empty()));
+                let array = match c.kind {
+                    "integer" => Array::int64(fields.map(|f| f.and_then(|f| f.parse().ok()))),
+                    "number" => Array::float64(fields.map(|f| f.and_then(|f| f.parse().ok()))),
+                    "boolean" => Array::boolean(fields.map(|f| f.and_then(|f| f.parse().ok()))),
+                    _ => Array::utf8(fields),
+                };
+                (c.name.clone(), array)
+            })
+            .collect();
+        RecordBatch { columns }.write_ipc(&mut self.out)
+    }
+}
+
+/// SQLite database holding a single table, with the same column types as
+/// the `Sql` script. Empty fields are stored as NULL, and booleans as 1 or 0.
+pub struct Sqlite<W: Write> {
+    out: W,
+    table: String,
+    columns: Vec<Column>,
+    db: Option<sqlite::Database>,
+}
+
+impl<W: Write> Sqlite<W> {
+    pub fn new<S: Into<String>>(out: W, table: S) -> Sqlite<W> {
+        Sqlite {
+            out,
+            table: table.into(),
+            columns: Vec::new(),
+            db: None,
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for Sqlite<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.columns = columns.to_vec();
+        let sql = create_table(&sql_ident(&self.table), columns);
+        self.db = Some(sqlite::Database::new(self.table.clone(), sql));
+        Ok(())
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        let values = row
+            .iter()
+            .zip(&self.columns)
+            .map(|(f, c)| match c.kind {
+                _ if f.is_empty() => Value::Null,
+                "boolean" if f == "true" => Value::Integer(1),
+                "boolean" if f == "false" => Value::Integer(0),
+                "integer" | "number" | "boolean" => match (f.parse(), f.parse()) {
+                    (Ok(v), _) if c.kind != "number" => Value::Integer(v),
+                    (_, Ok(v)) => Value::Real(v),
+                    _ => Value::Text(f),
+                },
+                _ => Value::Text(f),
+            })
+            .collect::<Vec<_>>();
+        self.db
+            .as_mut()
+            .expect("header is written before records")
+            .insert(&values);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self.db.take() {
+            Some(db) => db.write(&mut self.out),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parquet file with typed columns. Empty fields, and numeric fields that
+/// cannot be parsed, are written as nulls.
+pub struct Parquet<W: Write> {
+    out: W,
+    table: Table,
+}
+
+impl<W: Write> Parquet<W> {
+    pub fn new(out: W) -> Parquet<W> {
+        Parquet {
+            out,
+            table: Table::default(),
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for Parquet<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.table.header(columns)
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        self.table.record(row)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        let rows = &self.table.rows;
+        let mut file = parquet::Writer::new(&mut self.out)?;
+        // Columns are converted one at a time, so only one is held in its
+        // typed form at once
+        for (idx, c) in self.table.columns.iter().enumerate() {
+            let fields = rows.iter().map(|r| r.get(idx).filter(|f| !f.is_empty()));
+            let column = match c.kind {
+                "integer" => {
+                    parquet::Column::Int64(fields.map(|f| f.and_then(|f| f.parse().ok())).collect())
+                }
+                "number" => parquet::Column::Double(
+                    fields.map(|f| f.and_then(|f| f.parse().ok())).collect(),
+                ),
+                "boolean" => parquet::Column::Boolean(
+                    fields.map(|f| f.and_then(|f| f.parse().ok())).collect(),
+                ),
+                _ => parquet::Column::Utf8(fields.collect()),
+            };
+            file.column(&c.name, &column)?;
+        }
+        file.finish()
+    }
+}
+
+/// The first rows of a table, printed as aligned columns when the table is
+/// finished, for previewing an output in a terminal
+pub struct Preview<W: Write> {
+    out: W,
+    limit: usize,
+    table: Table,
+}
+
+impl<W: Write> Preview<W> {
+    pub fn new(out: W, limit: usize) -> Preview<W> {
+        Preview {
+            out,
+            limit,
+            table: Table::default(),
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for Preview<W> {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.table.header(columns)
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        if self.table.rows.len() < self.limit {
+            self.table.record(row)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        let names = self
+            .table
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<&str>>();
+        let mut widths = names.iter().map(|n| n.chars().count()).collect::<Vec<_>>();
+        for row in self.table.rows.iter() {
+            for (width, field) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(field.chars().count());
+            }
+        }
+        let rule = widths.iter().map(|&w| "-".repeat(w)).collect::<Vec<_>>();
+        let lines = std::iter::once(names)
+            .chain(std::iter::once(rule.iter().map(String::as_str).collect()))
+            .chain(self.table.rows.iter().map(|row| row.iter().collect()));
+        for fields in lines {
+            let mut line = String::new();
+            for (idx, (field, &width)) in fields.iter().zip(&widths).enumerate() {
+                if idx > 0 {
+                    line.push_str("  ");
+                }
+                line.push_str(field);
+                if idx + 1 < widths.len() {
+                    let pad = width - field.chars().count();
+                    line.extend(std::iter::repeat(' ').take(pad));
+                }
+            }
+            writeln!(self.out, "{}", line)?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Records buffered in memory, for outputs that must see the whole table
+/// before writing
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    pub columns: Vec<Column>,
+    pub rows: Rows,
+}
+
+impl RecordWriter for Table {
+    fn header(&mut self, columns: &[Column]) -> std::io::Result<()> {
+        self.columns = columns.to_vec();
+        Ok(())
+    }
+
+    fn record(&mut self, row: Row) -> std::io::Result<()> {
+        self.rows.record(row.iter());
+        Ok(())
+    }
+}
+
+impl Table {
+    /// Replay the buffered table into `out`
+    pub fn write_to(&self, out: &mut dyn RecordWriter) -> std::io::Result<()> {
+        out.header(&self.columns)?;
+        for row in self.rows.iter() {
+            out.record(row)?;
+        }
+        out.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arrow_columns() {
+        let columns = [
+            Column::new("accession", "string", "Protein accession"),
+            Column::new("count", "integer", "Spectral count"),
+            Column::new("ratio", "number", "Ratio"),
+            Column::new("unique", "boolean", "Unique peptide"),
+        ];
+        let mut rows = Rows::default();
+        rows.record(["P12345", "3", "0.5", "true"]);
+        rows.record(["", "n/a", "", "false"]);
+        rows.record(["Q6", "-1", "2e3", "maybe"]);
+
+        let mut out = Vec::new();
+        let mut arrow = Arrow::new(&mut out);
+        arrow.header(&columns).unwrap();
+        for row in rows.iter() {
+            arrow.record(row).unwrap();
+        }
+        arrow.finish().unwrap();
+
+        // Empty and unparseable fields become nulls
+        let batch = RecordBatch {
+            columns: vec![
+                (
+                    "accession".into(),
+                    Array::utf8([Some("P12345"), None, Some("Q6")]),
+                ),
+                ("count".into(), Array::int64([Some(3), None, Some(-1)])),
+                (
+                    "ratio".into(),
+                    Array::float64([Some(0.5), None, Some(2000.0)]),
+                ),
+                (
+                    "unique".into(),
+                    Array::boolean([Some(true), Some(false), None]),
+                ),
+            ],
+        };
+        let mut expected = Vec::new();
+        batch.write_ipc(&mut expected).unwrap();
+        assert_eq!(out, expected);
+    }
+}
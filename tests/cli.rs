@@ -0,0 +1,88 @@
+//! Tests running the census2csv binary
+use std::path::Path;
+use std::process::{Command, Output};
+
+#[path = "../src/fixture.rs"]
+mod fixture;
+
+const CENSUS: &str = "\
+H\tSLINE\tUNIQUE\tSEQUENCE\tm/z_126.1_int\tnorm_m/z_126.1_int\tm/z_127.1_int\tnorm_m/z_127.1_int
+P\tP12345\t3\t2\t30.3%\t335\t82944\tSerum albumin
+S\tU\tK.PEPTIDEK.L\t100\t0.5\t200\t0.5
+S\t\tK.SHAREDK.L\t1000\t0.5\t2000\t0.5
+S\tU\tR.SEQUENCER.A\t300\t0.5\t400\t0.5
+P\tQ67890\t1\t1\t10.0%\t100\t10000\tOther protein
+S\tU\tK.OTHERK.R\t5\t0.5\t6\t0.5
+";
+
+fn census2csv(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_census2csv"))
+        .current_dir(dir)
+        .args(args)
+        .env_remove("CENSUS2CSV_FILTER")
+        .env_remove("CENSUS2CSV_OUTDIR")
+        .env_remove("CENSUS2CSV_THREADS")
+        .output()
+        .unwrap()
+}
+
+/// Standard output of a successful run
+fn stdout(dir: &Path, args: &[&str]) -> String {
+    let out = census2csv(dir, args);
+    assert!(
+        out.status.success(),
+        "census2csv {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn filtered_output_reads_back() {
+    let dir = fixture::dir(
+        "cli-filtered",
+        &[
+            ("a.txt", CENSUS),
+            (
+                "filter.json",
+                r#"{"peptide_filters": ["Unique"], "protein_filters": []}"#,
+            ),
+        ],
+    );
+    stdout(&dir, &["filter", "--filter", "filter.json", "a.txt"]);
+    assert!(dir.join("a.filtered.txt").exists());
+
+    let filtered = stdout(&dir, &["--stdout", "a.filtered.txt"]);
+    assert_eq!(
+        filtered,
+        "accession,description,spectral_count,sequence_count,channel_1,channel_2
+P12345,Serum albumin,2,2,400,600
+Q67890,Other protein,1,1,5,6
+"
+    );
+    // Filtering again on conversion changes nothing
+    assert_eq!(
+        stdout(&dir, &["--stdout", "--filter", "filter.json", "a.txt"]),
+        filtered
+    );
+}
+
+#[test]
+fn inputs_named_like_subcommands() {
+    let names = [
+        "diff.txt",
+        "filters.txt",
+        "silac.txt",
+        "serve.txt",
+        "explore.txt",
+    ];
+    let files = names.iter().map(|name| (*name, CENSUS)).collect::<Vec<_>>();
+    let dir = fixture::dir("cli-names", &files);
+    let expected = stdout(&dir, &["--stdout", "diff.txt"]);
+    for name in &names {
+        assert_eq!(stdout(&dir, &["--stdout", name]), expected);
+    }
+    stdout(&dir, &names);
+    assert!(dir.join("filters.csv").exists());
+}
@@ -0,0 +1,57 @@
+// JavaScript interface to the census2csv WebAssembly build
+//
+// Build the module with
+//   cargo build --lib --release --target wasm32-unknown-unknown
+// and serve target/wasm32-unknown-unknown/release/census2csv.wasm alongside
+// this file. Conversion happens entirely in the browser.
+
+const TABLES = { protein: 0, "protein-average": 1, psm: 2 };
+
+export class Census2Csv {
+  constructor(instance) {
+    this.exports = instance.exports;
+  }
+
+  // Instantiate from a fetch Response, ArrayBuffer, or typed array
+  static async load(source) {
+    const resolved = await source;
+    const { instance } =
+      typeof Response !== "undefined" && resolved instanceof Response
+        ? await WebAssembly.instantiateStreaming(resolved)
+        : await WebAssembly.instantiate(resolved);
+    return new Census2Csv(instance);
+  }
+
+  // Copy `bytes` into a buffer allocated in module memory
+  copyIn(bytes) {
+    const ptr = this.exports.census_alloc(bytes.length);
+    new Uint8Array(this.exports.memory.buffer, ptr, bytes.length).set(bytes);
+    return ptr;
+  }
+
+  // Convert census file contents (a string or Uint8Array) to CSV text.
+  // `filter` is filter.json contents, or null. `table` is protein,
+  // protein-average, or psm. Throws an Error if conversion fails.
+  convert(input, filter = null, table = "protein") {
+    const encoder = new TextEncoder();
+    const data = typeof input === "string" ? encoder.encode(input) : input;
+    const filt = filter ? encoder.encode(filter) : new Uint8Array(0);
+    if (!(table in TABLES)) {
+      throw new Error(`unknown table ${table}`);
+    }
+
+    const dataPtr = this.copyIn(data);
+    const filtPtr = this.copyIn(filt);
+    const status = this.exports.census_convert(dataPtr, data.length, filtPtr, filt.length, TABLES[table]);
+    this.exports.census_dealloc(dataPtr, data.length);
+    this.exports.census_dealloc(filtPtr, filt.length);
+
+    const result = new TextDecoder().decode(
+      new Uint8Array(this.exports.memory.buffer, this.exports.census_result_ptr(), this.exports.census_result_len())
+    );
+    if (status !== 0) {
+      throw new Error(result);
+    }
+    return result;
+  }
+}